@@ -1,13 +1,33 @@
 #include <stdio.h>
 #include <stdlib.h>
 
+#define OLD_SIZE 100
+#define NEW_SIZE 5000
+
 int main() {
-  void *mptr = malloc(4294967296);
-  printf("malloc(4 GB %p\n", mptr);
-  
-  /* printf("beginning of program\n"); */
-  /* void *ptr = alligator_alloc(10); */
-  /* printf("allocated address\n"); */
-  /* alligator_dealloc(ptr); */
-  /* printf("freed address\n"); */
+  // Write a pattern, grow the allocation into a big allocation, and check the pattern survived
+  unsigned char *ptr = (unsigned char *) alligator_alloc(OLD_SIZE);
+  if (ptr == NULL) {
+    printf("alligator_alloc(%d) failed\n", OLD_SIZE);
+    return 1;
+  }
+  for (int i = 0; i < OLD_SIZE; i++) {
+    ptr[i] = (unsigned char) i;
+  }
+
+  unsigned char *new_ptr = (unsigned char *) alligator_realloc(ptr, NEW_SIZE);
+  if (new_ptr == NULL) {
+    printf("alligator_realloc(%p, %d) failed\n", ptr, NEW_SIZE);
+    return 1;
+  }
+  for (int i = 0; i < OLD_SIZE; i++) {
+    if (new_ptr[i] != (unsigned char) i) {
+      printf("alligator_realloc(%p, %d) lost byte %d: expected %d, found %d\n", ptr, NEW_SIZE, i, i, new_ptr[i]);
+      return 1;
+    }
+  }
+
+  alligator_dealloc(new_ptr);
+  printf("realloc kept all %d bytes\n", OLD_SIZE);
+  return 0;
 }
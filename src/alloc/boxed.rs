@@ -0,0 +1,84 @@
+use core::alloc::{GlobalAlloc, Layout};
+use core::fmt;
+use core::mem::size_of;
+use core::ops::{Deref, DerefMut};
+use core::ptr::{self, NonNull};
+use std::alloc::handle_alloc_error;
+
+use super::AlligatorAlloc;
+use super::heap::HostHeap;
+
+/// A value allocated by an AlligatorAlloc, like a Box which is not tied to the global allocator. Frees the value with the same allocator and Layout when dropped. Created by alloc_value().
+pub struct AllocBox<'a, T, H> where H: HostHeap {
+    /// Allocator which owns the value's memory.
+    alloc: &'a AlligatorAlloc<H>,
+
+    /// The value. Dangling if T is zero sized, the allocator is never called for those.
+    ptr: NonNull<T>,
+}
+
+/// Move value into memory allocated by alloc. The memory uses Layout::new::<T>(), so it is aligned for T.
+///
+/// # Panics
+/// Like Box::new(), calls handle_alloc_error() if the allocation fails.
+pub fn alloc_value<T, H>(alloc: &AlligatorAlloc<H>, value: T) -> AllocBox<'_, T, H> where H: HostHeap {
+    let layout = Layout::new::<T>();
+
+    // GlobalAlloc::alloc() must not be called with a size of 0
+    let ptr = if size_of::<T>() == 0 {
+        NonNull::dangling()
+    } else {
+        match NonNull::new(unsafe { alloc.alloc(layout) } as *mut T) {
+            Some(ptr) => ptr,
+            None => handle_alloc_error(layout),
+        }
+    };
+
+    unsafe {
+        ptr::write(ptr.as_ptr(), value);
+    }
+
+    AllocBox{
+        alloc: alloc,
+        ptr: ptr,
+    }
+}
+
+impl<'a, T, H> AllocBox<'a, T, H> where H: HostHeap {
+    /// Pointer to the value, in the allocator's heap.
+    pub fn as_ptr(&self) -> *const T {
+        self.ptr.as_ptr()
+    }
+}
+
+impl<'a, T, H> Deref for AllocBox<'a, T, H> where H: HostHeap {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { self.ptr.as_ref() }
+    }
+}
+
+impl<'a, T, H> DerefMut for AllocBox<'a, T, H> where H: HostHeap {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { self.ptr.as_mut() }
+    }
+}
+
+impl<'a, T, H> fmt::Debug for AllocBox<'a, T, H> where T: fmt::Debug, H: HostHeap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+impl<'a, T, H> Drop for AllocBox<'a, T, H> where H: HostHeap {
+    fn drop(&mut self) {
+        unsafe {
+            ptr::drop_in_place(self.ptr.as_ptr());
+
+            if size_of::<T>() != 0 {
+                self.alloc.dealloc(self.ptr.as_ptr() as *mut u8, Layout::new::<T>());
+            }
+        }
+    }
+}
@@ -23,6 +23,16 @@ pub trait HostHeap {
     /// if error.
     unsafe fn memory_grow(&mut self, delta_pages: usize) -> usize;
 
+    /// Shrink the heap by a number of pages, giving
+    /// them back to the host. Pages grown again later
+    /// must read as zero, like newly grown memory.
+    /// Returns Err if the host cannot shrink, in which
+    /// case the heap size does not change. The default
+    /// does nothing and returns Err.
+    unsafe fn memory_shrink(&mut self, _delta_pages: usize) -> Result<(), ()> {
+        Err(())
+    }
+
     /// Returns the base address of the specified
     /// Addresses will be guaranteed contiguous
     /// for the following memory_size() bytes.
@@ -38,6 +48,33 @@ cfg_if! {
         /// always 0.
         const WASM_MEMORY_IDX: u32 = 0;
 
+        cfg_if! {
+            if #[cfg(feature = "js")] {
+                use wasm_bindgen::prelude::*;
+
+                /// JavaScript function called with the new heap size in pages every time WASMHostHeap grows the WASM memory. None if one has not been registered. WASM is single threaded so a static mut is okay.
+                static mut ON_MEMORY_GROW_JS: Option<js_sys::Function> = None;
+
+                /// Registers a JavaScript callback which is called with the new size of the WASM memory, in pages, every time Alligator grows it. Growing the memory detaches the WebAssembly.Memory buffer, so JS should use this to re-create any Uint8Array or DataView views over it.
+                ///
+                /// The callback is called from inside the allocator, it must not call back into the WASM module in a way which allocates.
+                #[wasm_bindgen]
+                pub fn on_memory_grow_js(cb: js_sys::Function) {
+                    unsafe {
+                        ON_MEMORY_GROW_JS = Some(cb);
+                    }
+                }
+
+                /// Calls the registered JavaScript memory grow callback, if there is one.
+                unsafe fn notify_memory_grow_js(pages: usize) {
+                    if let Some(cb) = (*core::ptr::addr_of!(ON_MEMORY_GROW_JS)).as_ref() {
+                        // Errors thrown by the callback are ignored, the memory has already grown
+                        let _ = cb.call1(&JsValue::NULL, &JsValue::from(pages as u32));
+                    }
+                }
+            }
+        }
+
         /// Implements a heap inside the WASM environment.
         pub struct WASMHostHeap {
             /// Stores the base memory address of the heap space which the allocator will manage. See ensure_base_found() for more details.
@@ -69,10 +106,25 @@ cfg_if! {
                 memory_size(WASM_MEMORY_IDX)
             }
 
-            /// Grows the heap by a number of pages.
+            /// Grows the heap by a number of pages. If the js feature is enabled and a callback was registered with on_memory_grow_js() it is called after the memory has grown.
             unsafe fn memory_grow(&mut self, delta_pages: usize) -> usize {
                 self.ensure_base_found();
-                memory_grow(WASM_MEMORY_IDX, delta_pages)
+                let grow_res = memory_grow(WASM_MEMORY_IDX, delta_pages);
+
+                cfg_if! {
+                    if #[cfg(feature = "js")] {
+                        if grow_res != usize::MAX {
+                            notify_memory_grow_js(self.memory_size());
+                        }
+                    }
+                }
+
+                grow_res
+            }
+
+            /// WebAssembly memory can only grow, so this does nothing and returns Err.
+            unsafe fn memory_shrink(&mut self, _delta_pages: usize) -> Result<(), ()> {
+                Err(())
             }
 
             /// Returns a pointer to the beginning of the allocators heap segment.
@@ -88,12 +140,12 @@ cfg_if! {
 
         pub type HeapType = WASMHostHeap;
     } else if #[cfg(all(unix, target_pointer_width = "32"))] {
-        use libc::malloc;
+        use libc::calloc;
 
 	   /// The number of pages which can actually be used. This number is currently limited because malloc calls for the full 4 GB don't succeed in Rust (but I can get them to work in a C program). So for now just limit size of LibC HostHeap implementation.
 	   const ACTUAL_EMULATED_PAGES: u32 = 10;
 
-        /// Implements a heap using libc malloc.
+        /// Implements a heap using libc calloc. Memory starts zeroed, like newly grown WebAssembly memory.
 	   ///
 	   /// Limited
         pub struct LibCHostHeap {
@@ -110,9 +162,9 @@ cfg_if! {
                 match self.host_base_ptr {
 				Some(ptr) => Ok(ptr),
 				None => {
-                        let ptr = malloc((ACTUAL_EMULATED_PAGES * PAGE_BYTES) as usize) as *mut u8;
+                        let ptr = calloc((ACTUAL_EMULATED_PAGES * PAGE_BYTES) as usize, 1) as *mut u8;
 				    if ptr.is_null() {
-					   // Failed to malloc
+					   // Failed to calloc
 					   return Err(());
 				    }
 				    
@@ -151,6 +203,17 @@ cfg_if! {
                 return old_guest_page;
             }
 
+            /// Shrinks the heap by a number of pages. The calloc'd memory is not freed, only guest_end_page moves. The allocator never writes past the end of its used pages, so the pages are still zero if they are grown again.
+            unsafe fn memory_shrink(&mut self, delta_pages: usize) -> Result<(), ()> {
+                if delta_pages > self.guest_end_page {
+                    return Err(());
+                }
+
+                self.guest_end_page -= delta_pages;
+
+                Ok(())
+            }
+
             /// Returns a pointer to the base of the heap segment the allocator will manage.
             unsafe fn base_ptr(&mut self) -> *mut u8 {
                 // Lazy allocate the host memory, then return base ptr
@@ -168,5 +231,185 @@ cfg_if! {
         };
 
         pub type HeapType = LibCHostHeap;
+    } else if #[cfg(all(unix, target_pointer_width = "64"))] {
+        use libc::{madvise,mmap,MADV_DONTNEED,MAP_ANON,MAP_FAILED,MAP_NORESERVE,MAP_PRIVATE,PROT_READ,PROT_WRITE};
+
+        /// Implements a heap using one anonymous mmap. Memory starts zeroed, like newly grown WebAssembly memory.
+        ///
+        /// The whole MAX_PAGES WebAssembly address space is mapped up front, without reserving swap, so the host only provides memory for pages which are touched. The mapping never moves, so every address in the heap is a 32 bit offset from base_ptr(), the same as on WebAssembly.
+        pub struct MmapHostHeap {
+            /// The host memory region pointer. None if not mapped.
+            host_base_ptr: Option<*mut u8>,
+
+            /// The current end of the guest's memory in pages.
+            guest_end_page: usize,
+        }
+
+        impl MmapHostHeap {
+            /// Ensure that the host memory has been mapped. Returns the host_base_ptr value.
+            unsafe fn ensure_host_base_ptr(&mut self) -> Result<*mut u8, ()> {
+                match self.host_base_ptr {
+                    Some(ptr) => Ok(ptr),
+                    None => {
+                        let ptr = mmap(null_mut(), (MAX_PAGES as usize) * (PAGE_BYTES as usize), PROT_READ | PROT_WRITE, MAP_ANON | MAP_PRIVATE | MAP_NORESERVE, -1, 0);
+                        if ptr == MAP_FAILED {
+                            // Failed to mmap
+                            return Err(());
+                        }
+
+                        self.host_base_ptr = Some(ptr as *mut u8);
+                        Ok(ptr as *mut u8)
+                    },
+                }
+            }
+        }
+
+        impl HostHeap for MmapHostHeap {
+            /// Returns the heap's size in pages.
+            fn memory_size(&mut self) -> usize {
+                self.guest_end_page
+            }
+
+            /// Grows the heap by a number of pages. The pages are already mapped, this only moves the end of the heap.
+            unsafe fn memory_grow(&mut self, delta_pages: usize) -> usize {
+                // Lazy map the host memory
+                if self.ensure_host_base_ptr().is_err() {
+                    return usize::MAX;
+                }
+
+                // Ensure not oversize
+                let new_guest_end_page = self.guest_end_page + delta_pages;
+                if new_guest_end_page > MAX_PAGES as usize {
+                    return usize::MAX;
+                }
+
+                let old_guest_page = self.guest_end_page;
+                self.guest_end_page = new_guest_end_page;
+
+                old_guest_page
+            }
+
+            /// Shrinks the heap by a number of pages. The pages stay mapped, but the host is told it can drop them with MADV_DONTNEED, so they read as zero if they are grown again.
+            unsafe fn memory_shrink(&mut self, delta_pages: usize) -> Result<(), ()> {
+                let host_base_ptr = match self.host_base_ptr {
+                    Some(ptr) => ptr,
+                    None => return Err(()),
+                };
+
+                if delta_pages > self.guest_end_page {
+                    return Err(());
+                }
+
+                let new_guest_end_page = self.guest_end_page - delta_pages;
+                let start_ptr = host_base_ptr.add(new_guest_end_page * (PAGE_BYTES as usize));
+                if madvise(start_ptr as *mut libc::c_void, delta_pages * (PAGE_BYTES as usize), MADV_DONTNEED) != 0 {
+                    return Err(());
+                }
+
+                self.guest_end_page = new_guest_end_page;
+
+                Ok(())
+            }
+
+            /// Returns a pointer to the base of the heap segment the allocator will manage.
+            unsafe fn base_ptr(&mut self) -> *mut u8 {
+                // Lazy map the host memory, then return base ptr
+                match self.ensure_host_base_ptr() {
+                    Ok(ptr) => ptr,
+                    Err(_) => null_mut(),
+                }
+            }
+        }
+
+        /// Pre-initialized 64-bit mmap HostHeap.
+        pub const INIT: MmapHostHeap = MmapHostHeap{
+            host_base_ptr: None,
+            guest_end_page: 0,
+        };
+
+        pub type HeapType = MmapHostHeap;
+    }
+}
+
+cfg_if! {
+    if #[cfg(feature = "mock")] {
+        /// HostHeap for tests which need the host to misbehave. Backed by a Vec allocated up front, so its memory never moves. memory_grow() succeeds for a set number of calls, then fails by returning usize::MAX, which lets tests deterministically reach the HostGrowFail handling.
+        pub struct MockHostHeap {
+            /// The host memory region. u64s so the MetaPage is aligned.
+            memory: Vec<u64>,
+
+            /// Current size of the heap in pages.
+            pages: usize,
+
+            /// Number of future memory_grow() calls which will succeed.
+            grows_left: usize,
+
+            /// Most pages one memory_grow() call grows the heap by. A call asking for more still succeeds, but only grows by this many pages.
+            max_grow_pages: usize,
+        }
+
+        impl MockHostHeap {
+            /// Creates a mock heap which can grow up to max_pages, and whose first successful_grows calls to memory_grow() succeed. Every call after fails.
+            pub fn new(max_pages: usize, successful_grows: usize) -> MockHostHeap {
+                MockHostHeap{
+                    memory: vec![0; max_pages * (PAGE_BYTES as usize) / 8],
+                    pages: 0,
+                    grows_left: successful_grows,
+                    max_grow_pages: usize::MAX,
+                }
+            }
+
+            /// Makes each memory_grow() call grow the heap by at most max_grow_pages, like a host which cannot grant all the pages asked for.
+            pub fn with_max_grow_pages(mut self, max_grow_pages: usize) -> MockHostHeap {
+                self.max_grow_pages = max_grow_pages;
+                self
+            }
+
+            /// Number of pages the heap can grow to.
+            fn max_pages(&self) -> usize {
+                self.memory.len() * 8 / (PAGE_BYTES as usize)
+            }
+        }
+
+        impl HostHeap for MockHostHeap {
+            /// Returns the heap's size in pages.
+            fn memory_size(&mut self) -> usize {
+                self.pages
+            }
+
+            /// Grows the heap by a number of pages, at most max_grow_pages, if this call is one of the successful_grows and the heap stays within its max pages.
+            unsafe fn memory_grow(&mut self, delta_pages: usize) -> usize {
+                let grow_pages = delta_pages.min(self.max_grow_pages);
+                if self.grows_left == 0 || self.pages + grow_pages > self.max_pages() {
+                    return usize::MAX;
+                }
+                self.grows_left -= 1;
+
+                let old_pages = self.pages;
+                self.pages += grow_pages;
+
+                old_pages
+            }
+
+            /// Shrinks the heap by a number of pages, zeroing them so they read as zero if grown again.
+            unsafe fn memory_shrink(&mut self, delta_pages: usize) -> Result<(), ()> {
+                if delta_pages > self.pages {
+                    return Err(());
+                }
+
+                self.pages -= delta_pages;
+                let start = self.pages * (PAGE_BYTES as usize) / 8;
+                for word in self.memory[start..].iter_mut() {
+                    *word = 0;
+                }
+
+                Ok(())
+            }
+
+            /// Returns a pointer to the start of the Vec.
+            unsafe fn base_ptr(&mut self) -> *mut u8 {
+                self.memory.as_mut_ptr() as *mut u8
+            }
+        }
     }
 }
@@ -1,38 +1,121 @@
+// With the strict feature AllocFail sites panic, leaving the graceful return after them unreachable
+#![cfg_attr(feature = "strict", allow(unreachable_code))]
+
 use core::alloc::{GlobalAlloc, Layout};
 use core::cell::UnsafeCell;
-use core::ptr::null_mut;
-use std::mem::{size_of,transmute};
-use std::convert::{TryFrom,TryInto};
+use core::ptr::{null_mut,copy_nonoverlapping};
+use core::slice::from_raw_parts_mut;
+use core::mem::{size_of,align_of};
+use core::convert::{TryFrom,TryInto};
 use cfg_if::cfg_if;
 
+#[cfg(feature = "sync")]
+use core::sync::atomic::{AtomicBool, Ordering};
+
+/// Checks an invariant of the allocator's state on a hot path. Like assert!(), unless the no-panic feature is enabled: then a broken invariant makes the enclosing function return $fallback, ie., null_mut(), and is only checked with debug_assert!(). A panic inside the global allocator can abort the process, or recurse if the panic handler allocates.
+macro_rules! assert_or_return {
+    ($cond:expr, $fallback:expr, $($arg:tt)+) => {
+        cfg_if! {
+            if #[cfg(feature = "no-panic")] {
+                let holds = $cond;
+                debug_assert!(holds, $($arg)+);
+                if !holds {
+                    return $fallback;
+                }
+            } else {
+                assert!($cond, $($arg)+);
+            }
+        }
+    };
+}
+
 pub mod heap;
 use heap::{HostHeap,HeapType};
 
-/// The number of host memory pages to allocate for all memory allocations. If these fill up then all future allocations will fail.
+#[cfg(feature = "std")]
+pub mod boxed;
+
+#[cfg(feature = "std")]
+pub mod snapshot;
+
+/// The largest number of host memory pages the allocator can use. If these fill up then all future allocations will fail. The MetaPage tables are sized for this many pages. AlligatorAlloc::with_max_pages() can set a lower limit.
 /// TODO Remove and use max values in heap:: and do growing
 const MAX_HOST_PAGES: usize = 200;
 
-/// MAX_HOST_PAGES as an isize.
-/// TODO Remove and just cast inline
-const MAX_HOST_PAGES_ISIZE: isize = MAX_HOST_PAGES as isize;
+/// Host pages the heap is grown by past what an allocation needs, so the host is not asked to grow for every new MiniPage. The first grow is the pages the MetaPage needs plus this many. Never grows past the allocator's max pages.
+const HEAP_GROW_PAGES: usize = 4;
 
 /// Number of bytes which can be allocated from one MiniPage.
 const MINI_PAGE_ALLOC_BYTES: u32 = 2048;
 
+/// Number of bytes of heap one MiniPage interval takes up. Big allocations are sized in these intervals, interval i of the heap has big_alloc_flags[i]. MiniPageHeaders live in the MetaPage, not inline before the MiniPage, so an interval does not include header bytes and this is the same as MINI_PAGE_ALLOC_BYTES. A big allocation's BigAllocHeader is inside its first interval.
+const MINI_PAGE_TOTAL_BYTES: u32 = MINI_PAGE_ALLOC_BYTES;
+
+/// Default number of allocations from a fresh MiniPage per allocation from a reused MiniPage the allocator aims for in each size class. Once a size class has had an allocation from a reused MiniPage, alloc takes a new MiniPage whenever total_alloc_fresh / total_alloc_reused is below this ratio, otherwise it uses the most recently freed into MiniPage. Lower ratios fill in freed segments first, keeping fewer MiniPages and less fragmentation. Higher ratios take new MiniPages more often, so allocations made close together in time end up close together in memory. AlligatorAlloc::with_fresh_reused_ratio() can use another ratio.
 const FRESH_REUSED_RATIO: f64 = 1_f64;
 
-/// Size of the MiniPageHeader.free_segments array
+/// Default policy used to pick which free big allocation to reuse. AlligatorAlloc::with_big_alloc_policy() can use another.
+const BIG_ALLOC_POLICY: BigAllocPolicy = BigAllocPolicy::BestFit;
+
+/// Size of the MiniPageHeader.free_segments array in bytes. Must hold one bit for every segment of the size class with the most segments, checked at compile time after SizeClass.
 const MINI_PAGE_FREE_SEGMENTS_SIZE: usize = 257;
 
+/// Length of the MiniPageHeader.requested_bytes array, the most segments a MiniPage of any size class has. Checked at compile time after SizeClass.
+#[cfg(feature = "requested-size")]
+const MINI_PAGE_MAX_SEGMENTS: usize = (MINI_PAGE_ALLOC_BYTES >> MIN_SIZE_CLASS) as usize;
+
+/// Big allocations of at least this many bytes start on a host page boundary and are rounded up to a whole number of host pages, when the big-page-align feature is enabled. Whole pages can later be handed back to the host.
+const BIG_PAGE_ALIGN_MIN_BYTES: usize = heap::PAGE_BYTES as usize;
+
+/// Number of bytes at the end of the heap kept free for failure handling code by the emergency-reserve feature. Must be a multiple of MINI_PAGE_ALLOC_BYTES. See AlligatorAlloc::release_reserve().
+#[cfg(feature = "emergency-reserve")]
+const EMERGENCY_RESERVE_BYTES: u32 = 4 * MINI_PAGE_ALLOC_BYTES;
+
+/// Returns the (byte index, bit index) of a segment's bit in a MiniPage bitmap. Bits are little-endian within each byte: segment i is stored in byte i / 8 at bit i % 8, so its mask is 1 << (i % 8). Segment 7 is the most significant bit of byte 0 and segment 8 is the least significant bit of byte 1. Every reader and writer of free_segments must agree with this.
+const fn bitmap_position(segment_idx: usize) -> (usize, usize) {
+    (segment_idx / 8, segment_idx % 8)
+}
+
+// Lock in the bitmap bit ordering across byte boundaries. Segments 0..=16 cover a whole byte on either side of byte 1
+const _: () = {
+    const EXPECTED: [(usize, usize); 17] = [
+        (0, 0), (0, 1), (0, 2), (0, 3), (0, 4), (0, 5), (0, 6), (0, 7),
+        (1, 0), (1, 1), (1, 2), (1, 3), (1, 4), (1, 5), (1, 6), (1, 7),
+        (2, 0),
+    ];
+
+    let mut segment_idx = 0;
+    while segment_idx < EXPECTED.len() {
+        let (byte_idx, bit_idx) = bitmap_position(segment_idx);
+        assert!(byte_idx == EXPECTED[segment_idx].0 && bit_idx == EXPECTED[segment_idx].1);
+        segment_idx += 1;
+    }
+};
+
 /// The smallest size class we will allocate.
 pub const MIN_SIZE_CLASS: u8 = 3;
 
-/// The largest size class we can allocate right now.
-/// Multi-page allocations are not supported yet.
-pub const MAX_SIZE_CLASS: u8 = 11;
+/// The largest size class allocated from MiniPages. Larger allocations are big allocations. Not 16 because a 64 KB segment size does not fit in the u16 sizes SizeClass uses.
+pub const MAX_SIZE_CLASS: u8 = 15;
 
-/// MAX_SIZE_CLASS but a usize
-const MAX_SIZE_CLASS_USIZE: usize = 11;
+/// The largest size class whose MiniPages are one MINI_PAGE_ALLOC_BYTES interval. MiniPages of larger size classes span several contiguous intervals, see SizeClass::minipage_intervals().
+const MAX_SINGLE_INTERVAL_SIZE_CLASS: u8 = 11;
+
+/// Number of segments in a MiniPage of a size class larger than MAX_SINGLE_INTERVAL_SIZE_CLASS.
+const LARGE_MINI_PAGE_SEGMENTS: u32 = 4;
+
+/// Number of intervals the MiniPages of MAX_SIZE_CLASS span, the most of any size class.
+const MAX_MINI_PAGE_INTERVALS: usize = ((LARGE_MINI_PAGE_SEGMENTS << MAX_SIZE_CLASS) / MINI_PAGE_ALLOC_BYTES) as usize;
+
+cfg_if! {
+    if #[cfg(feature = "cache-align")] {
+        /// The smallest size class allocations are made from. Segments of this size class are 64 bytes, one cache line, so every allocation is cache line aligned.
+        const MIN_ALLOC_SIZE_CLASS: u8 = 6;
+    } else {
+        /// The smallest size class allocations are made from.
+        const MIN_ALLOC_SIZE_CLASS: u8 = MIN_SIZE_CLASS;
+    }
+}
 
 /// The total number of size classes allocated. The + 1 is needed because MIN_SIZE_CLASS and MAX_SIZE_CLASS both start at 0. So to determine the count of this we need to add one.
 const NUM_SIZE_CLASSES: u8 = (MAX_SIZE_CLASS - MIN_SIZE_CLASS) + 1;
@@ -43,8 +126,50 @@ const NUM_SIZE_CLASSES_USIZE: usize = NUM_SIZE_CLASSES as usize;
 /// The number of MiniPages which can be allocated in one WASM page.
 const MINI_PAGES_PER_WASM_PAGE: u32 = heap::PAGE_BYTES / MINI_PAGE_ALLOC_BYTES;
 
-/// The maximum number of MiniPages which can be allocated, every. Dictated by the maximum WASM heap size.
-const MAX_MINI_PAGES: u32 = MINI_PAGES_PER_WASM_PAGE * heap::MAX_PAGES;
+/// The maximum number of MiniPages which fit in the MAX_HOST_PAGES the allocator uses. Size of the MetaPage tables which have an entry for every MiniPage.
+const MAX_HOST_MINI_PAGES: usize = MAX_HOST_PAGES * (MINI_PAGES_PER_WASM_PAGE as usize);
+
+/// Number of bytes in the MAX_HOST_PAGES the allocator uses. Every AllocAddr is less than this.
+pub(crate) const MAX_HOST_HEAP_BYTES: u32 = (MAX_HOST_PAGES as u32) * heap::PAGE_BYTES;
+
+/// Number of bytes required to hold a bitmap with one bit for every MiniPage which can be allocated. See AlligatorAlloc::heap_bitmap().
+pub const HEAP_BITMAP_BYTES: usize = (MAX_HOST_MINI_PAGES + 7) / 8;
+
+/// Number of host pages the MetaPage needs, plus room to align the first MiniPage after it. The first allocation grows the heap by this many pages, so an allocator made with AlligatorAlloc::with_max_pages() needs more to allocate anything. Depends on the enabled features, ie., requested-size and zero-tracking grow every MiniPageHeader.
+pub const META_PAGE_HOST_PAGES: usize = (MetaPage::max_bytes() + (MINI_PAGE_ALLOC_BYTES as usize) + (heap::PAGE_BYTES as usize) - 1) / (heap::PAGE_BYTES as usize);
+
+cfg_if! {
+    if #[cfg(feature = "compact-meta")] {
+        /// MiniPage index as stored in the MetaPage (big allocation flags and free minipages stacks). The compact-meta feature stores a u16, which holds the index of every MiniPage in MAX_HOST_PAGES.
+        type PageIdx = u16;
+
+        // Every MiniPage index the allocator can hand out must fit in a PageIdx
+        const _: () = assert!((MAX_HOST_PAGES as u32) * MINI_PAGES_PER_WASM_PAGE <= (u16::MAX as u32) + 1);
+
+        /// Converts a MiniPage index to the form stored in the MetaPage.
+        fn compact_page_idx(page_idx: usize) -> PageIdx {
+            page_idx as PageIdx
+        }
+
+        /// Converts a MiniPage index stored in the MetaPage back to a usize.
+        fn expand_page_idx(page_idx: PageIdx) -> usize {
+            usize::from(page_idx)
+        }
+    } else {
+        /// MiniPage index as stored in the MetaPage (big allocation flags and free minipages stacks).
+        type PageIdx = usize;
+
+        /// Converts a MiniPage index to the form stored in the MetaPage.
+        fn compact_page_idx(page_idx: usize) -> PageIdx {
+            page_idx
+        }
+
+        /// Converts a MiniPage index stored in the MetaPage back to a usize.
+        fn expand_page_idx(page_idx: PageIdx) -> usize {
+            page_idx
+        }
+    }
+}
 
 cfg_if! {
     if #[cfg(feature = "metrics")] {
@@ -66,18 +191,70 @@ cfg_if! {
 
             /// Cumulative memory write operations. Unit of bytes.
             pub heap_bytes_write: usize,
+
+            /// Number of alloc_zeroed calls which had to fill memory with zeros. Without the zero-tracking feature this is every alloc_zeroed call, with it only calls for memory not known to be zero. The filled bytes are counted in heap_bytes_write.
+            pub total_zero_fills: u32,
+
+            /// Number of BigAllocHeaders created. Big allocations which reuse a free BigAllocHeader do not change this.
+            pub total_big_headers: u32,
+
+            /// Number of times two adjacent free big allocations were merged into one, when one was freed or a shrinking realloc gave back the tail of one.
+            pub big_coalesces: u32,
+
+            /// Number of times a free big allocation was split when reused, so the unused tail could be allocated separately.
+            pub big_splits: u32,
+
+            /// Number of MiniPages which had all their segments freed and were made available to every size class. MiniPages reused this way are not counted again in total_minipages.
+            pub reclaimed_minipages: u32,
+
+            /// Number of BigAllocHeaders dealloc read to find the header of a big allocation. The header is found from the big allocation flags, so this is one per big dealloc no matter how many big allocations there are.
+            pub big_dealloc_header_reads: u32,
+
+            /// Most BigAllocHeaders walked by a single alloc or dealloc looking for a big allocation. alloc walks the big allocation list looking for a free big allocation which fits, so this grows with the number of big allocations. dealloc finds the header from the big allocation flags and walks 1.
+            pub max_big_alloc_scan: u32,
+
+            /// Internal fragmentation. Bytes lost to rounding up live allocations: the sum of each live allocation's usable size minus the size of the Layout it was allocated with. Freed allocations are subtracted using the Layout passed to dealloc, so this is only exact if dealloc is passed the allocation's Layout, as GlobalAlloc requires.
+            pub internal_frag_bytes: usize,
+
+            /// External fragmentation. Free bytes in MiniPages which have at least one live segment. These can only be used by allocations of the same size class.
+            pub external_frag_bytes: usize,
+
+            /// Bytes in live allocations, the segment size of each live MiniPage allocation plus the size_bytes of each live big allocation.
+            pub current_used_bytes: u32,
+
+            /// Highest current_used_bytes has ever been. The most memory the program needed at once, which is what the heap must be sized for.
+            pub peak_used_bytes: u32,
+
+            /// Number of MiniPages with at least one live segment.
+            pub current_minipages: u32,
+
+            /// Highest current_minipages has ever been.
+            pub peak_minipages: u32,
         }
 
         impl AllocMetrics {
             /// Allocate an AllocMetrics at start_addr. Returns a pointer to the allocation and a pointer to the byte of memory after the allocation.
             unsafe fn alloc(start_addr: *mut u8) -> (*mut AllocMetrics, *mut u8) {
-                // Allocate
-                let metrics_ptr = start_addr as *mut AllocMetrics;
+                // Allocate, at the first address aligned for AllocMetrics
+                let metrics_ptr = start_addr.offset(start_addr.align_offset(align_of::<AllocMetrics>()) as isize) as *mut AllocMetrics;
                 (*metrics_ptr).total_allocs = [0; NUM_SIZE_CLASSES_USIZE+1];
                 (*metrics_ptr).total_deallocs = [0; NUM_SIZE_CLASSES_USIZE+1];
                 (*metrics_ptr).total_minipages = 0;
                 (*metrics_ptr).heap_bytes_read = 0;
                 (*metrics_ptr).heap_bytes_write = 0;
+                (*metrics_ptr).total_zero_fills = 0;
+                (*metrics_ptr).total_big_headers = 0;
+                (*metrics_ptr).big_coalesces = 0;
+                (*metrics_ptr).big_splits = 0;
+                (*metrics_ptr).reclaimed_minipages = 0;
+                (*metrics_ptr).big_dealloc_header_reads = 0;
+                (*metrics_ptr).max_big_alloc_scan = 0;
+                (*metrics_ptr).internal_frag_bytes = 0;
+                (*metrics_ptr).external_frag_bytes = 0;
+                (*metrics_ptr).current_used_bytes = 0;
+                (*metrics_ptr).peak_used_bytes = 0;
+                (*metrics_ptr).current_minipages = 0;
+                (*metrics_ptr).peak_minipages = 0;
 
                 // Determine byte of memory after the allocation
                 let next_ptr = metrics_ptr.offset(1) as *mut u8;
@@ -85,30 +262,268 @@ cfg_if! {
                 return (metrics_ptr, next_ptr);
             }
 
-            /// Print a dot graphviz representation of the allocator's state.
-            unsafe fn dot_graph<H>(alloc: *mut AllocatorImpl<H>) -> String where H: HostHeap {
-                let mut out = String::from("digraph A {\n");
-                // out += "alligator -> minipages;\n";
-                // for i in MIN_SIZE_CLASS..=MAX_SIZE_CLASS {
-                //     let size_class = SizeClass::new(i);
-                //     let mut minipage_i = 0;
-                //     let mut minipage_ptr = (*alloc).minipage_lists[size_class.exp_as_idx()];
-                //     out += format!("minipages -> size_class_{};\n", i).as_str();
-                    
-                //     while !minipage_ptr.is_null() {
-                //         out += format!("size_class_{sz} -> minipage_{sz}_{mp};\n", sz=i, mp=minipage_i).as_str();
-                        
-                //         // Iterate on next minipage
-                //         minipage_i += 1;
-                //         minipage_ptr = match (*minipage_ptr).next {
-                //             Some(ptr) => ptr,
-                //             None => null_mut(),
-                //         };
-                //     }
-                // }
-                out += "}\n";
+            /// Write a dot graphviz representation of the allocator's state to out. See AlligatorAlloc::dot_graph().
+            unsafe fn dot_graph<H, W>(alloc: *mut AllocatorImpl<H>, out: &mut W) -> core::fmt::Result where H: HostHeap, W: core::fmt::Write {
+                write!(out, "digraph A {{\n")?;
+                write!(out, "    alligator [label=\"alligator\\nused_page_units={}\"];\n", (*alloc).used_page_units())?;
+
+                // MiniPages, in the order of each size class's minipage_lists chain
+                for i in MIN_SIZE_CLASS..=MAX_SIZE_CLASS {
+                    let size_class = SizeClass::new(i);
+                    write!(out, "    size_class_{sz} [label=\"size class {sz}\\n{bytes} bytes\"];\n", sz=i, bytes=size_class.segment_bytes())?;
+                    write!(out, "    alligator -> size_class_{};\n", i)?;
+
+                    let mut minipage_i = 0;
+                    let mut minipage_ptr = (*alloc).minipage_lists[size_class.exp_as_idx()];
+                    while !minipage_ptr.is_null() {
+                        let used = (*minipage_ptr).used_segments_count();
+                        write!(out, "    minipage_{sz}_{mp} [label=\"minipage {mp}\\nused={used} free={free}\"];\n", sz=i, mp=minipage_i, used=used, free=size_class.segments_max_num() - used)?;
+
+                        match minipage_i {
+                            0 => write!(out, "    size_class_{sz} -> minipage_{sz}_0;\n", sz=i)?,
+                            _ => write!(out, "    minipage_{sz}_{prev} -> minipage_{sz}_{mp};\n", sz=i, prev=minipage_i - 1, mp=minipage_i)?,
+                        };
+
+                        // Iterate on next minipage
+                        minipage_i += 1;
+                        minipage_ptr = (*minipage_ptr).next.unwrap_or(null_mut());
+                    }
+                }
+
+                // Big allocations, in memory order
+                let mut big_i = 0;
+                let mut big_ptr = (*alloc).big_alloc_head;
+                while let Some(big_head) = big_ptr {
+                    let state = match (*big_head).free {
+                        true => "free",
+                        false => "used",
+                    };
+                    write!(out, "    big_{b} [label=\"big {b}\\nsize_bytes={size} {state}\"];\n", b=big_i, size=(*big_head).size_bytes, state=state)?;
+
+                    match big_i {
+                        0 => write!(out, "    alligator -> big_0;\n")?,
+                        _ => write!(out, "    big_{prev} -> big_{b};\n", prev=big_i - 1, b=big_i)?,
+                    };
+
+                    big_i += 1;
+                    big_ptr = (*big_head).next;
+                }
+
+                write!(out, "}}\n")
+            }
+        }
+    }
+}
+
+cfg_if! {
+    if #[cfg(feature = "chrome-trace")] {
+        /// Maximum number of events recorded for the Chrome trace. Once full the oldest events are overwritten.
+        const CHROME_TRACE_CAPACITY: usize = 1024;
+
+        /// Type of operation recorded in a ChromeTraceEvent.
+        #[derive(Copy, Clone, Debug)]
+        enum ChromeTraceEventKind {
+            /// Call to alloc.
+            Alloc,
+
+            /// Call to dealloc.
+            Dealloc,
+        }
+
+        /// An alloc or dealloc call recorded for the Chrome trace.
+        #[derive(Copy, Clone, Debug)]
+        struct ChromeTraceEvent {
+            /// Operation which was performed.
+            kind: ChromeTraceEventKind,
+
+            /// Logical timestamp, the number of operations recorded before this one. There is no portable clock available to the allocator.
+            ts: u64,
+
+            /// Pointer which was returned by alloc, or passed to dealloc.
+            ptr: usize,
+
+            /// Size of the Layout passed to the operation.
+            size: usize,
+        }
+
+        /// Fixed size ring buffer of allocation events. Stored in the AllocatorImpl so recording never allocates.
+        struct ChromeTrace {
+            /// Recorded events, oldest event is at next_idx once the buffer has filled.
+            events: [ChromeTraceEvent; CHROME_TRACE_CAPACITY],
+
+            /// Index in events where the next event will be written.
+            next_idx: usize,
+
+            /// Number of valid items in events.
+            len: usize,
+
+            /// Logical timestamp which will be given to the next event.
+            clock: u64,
+        }
+
+        impl ChromeTrace {
+            /// Empty ChromeTrace.
+            const INIT: ChromeTrace = ChromeTrace{
+                events: [ChromeTraceEvent{
+                    kind: ChromeTraceEventKind::Alloc,
+                    ts: 0,
+                    ptr: 0,
+                    size: 0,
+                }; CHROME_TRACE_CAPACITY],
+                next_idx: 0,
+                len: 0,
+                clock: 0,
+            };
+
+            /// Record an operation.
+            fn record(&mut self, kind: ChromeTraceEventKind, ptr: *mut u8, size: usize) {
+                self.events[self.next_idx] = ChromeTraceEvent{
+                    kind: kind,
+                    ts: self.clock,
+                    ptr: ptr as usize,
+                    size: size,
+                };
+
+                self.clock += 1;
+                self.next_idx = (self.next_idx + 1) % CHROME_TRACE_CAPACITY;
+                if self.len < CHROME_TRACE_CAPACITY {
+                    self.len += 1;
+                }
+            }
+
+            /// Writes the recorded events, oldest first, as a Chrome Trace Event Format JSON array. Each operation is an instant event.
+            fn dump<W>(&self, out: &mut W) -> core::fmt::Result where W: core::fmt::Write {
+                write!(out, "[")?;
+                let oldest_idx = (self.next_idx + CHROME_TRACE_CAPACITY - self.len) % CHROME_TRACE_CAPACITY;
+
+                for i in 0..self.len {
+                    let event = self.events[(oldest_idx + i) % CHROME_TRACE_CAPACITY];
+                    let name = match event.kind {
+                        ChromeTraceEventKind::Alloc => "alloc",
+                        ChromeTraceEventKind::Dealloc => "dealloc",
+                    };
+
+                    if i > 0 {
+                        write!(out, ",")?;
+                    }
+                    write!(out, "\n{{\"name\":\"{name}\",\"cat\":\"alligator\",\"ph\":\"i\",\"s\":\"g\",\"ts\":{ts},\"pid\":0,\"tid\":0,\"args\":{{\"ptr\":\"{ptr:#x}\",\"size\":{size}}}}}",
+                           name=name, ts=event.ts, ptr=event.ptr, size=event.size)?;
+                }
+
+                write!(out, "\n]\n")
+            }
+        }
+    }
+}
+
+cfg_if! {
+    if #[cfg(feature = "trace")] {
+        /// Maximum number of events kept by the trace feature. Once full the oldest events are overwritten.
+        pub const TRACE_CAPACITY: usize = 1024;
+
+        /// Type of operation recorded in a TraceEvent.
+        #[derive(Copy, Clone, Debug, PartialEq, Eq)]
+        pub enum TraceEventKind {
+            /// Call to alloc, ptr is the returned pointer (null if it failed).
+            Alloc,
+
+            /// Call to dealloc, ptr is the freed pointer.
+            Dealloc,
+        }
+
+        /// An alloc or dealloc call recorded by the trace feature, retrieved via AlligatorAlloc::drain_trace().
+        #[derive(Copy, Clone, Debug, PartialEq, Eq)]
+        pub struct TraceEvent {
+            /// Operation which was performed.
+            pub kind: TraceEventKind,
+
+            /// Pointer which was returned by alloc, or passed to dealloc.
+            pub ptr: usize,
+
+            /// Size of the Layout passed to the operation.
+            pub size: usize,
+        }
+
+        /// Fixed size ring buffer of TraceEvents. Stored in the MetaPage so recording never allocates.
+        struct TraceBuffer {
+            /// Recorded events, oldest event is len items before next_idx.
+            events: [TraceEvent; TRACE_CAPACITY],
+
+            /// Index in events where the next event will be written.
+            next_idx: usize,
+
+            /// Number of valid items in events.
+            len: usize,
+        }
+
+        impl TraceBuffer {
+            /// Allocate an empty TraceBuffer at the first address at or after `start_addr` aligned for it. Returns the buffer and a pointer to the byte of memory after it.
+            unsafe fn alloc(start_addr: *mut u8) -> (*mut TraceBuffer, *mut u8) {
+                let trace_ptr = start_addr.offset(start_addr.align_offset(align_of::<TraceBuffer>()) as isize) as *mut TraceBuffer;
+                (*trace_ptr).next_idx = 0;
+                (*trace_ptr).len = 0;
+
+                (trace_ptr, trace_ptr.offset(1) as *mut u8)
+            }
+
+            /// Record an operation.
+            fn record(&mut self, kind: TraceEventKind, ptr: *mut u8, size: usize) {
+                self.events[self.next_idx] = TraceEvent{
+                    kind: kind,
+                    ptr: ptr as usize,
+                    size: size,
+                };
+
+                self.next_idx = (self.next_idx + 1) % TRACE_CAPACITY;
+                if self.len < TRACE_CAPACITY {
+                    self.len += 1;
+                }
+            }
+
+            /// Removes and returns the oldest event.
+            fn pop_oldest(&mut self) -> Option<TraceEvent> {
+                if self.len == 0 {
+                    return None;
+                }
+
+                let oldest_idx = (self.next_idx + TRACE_CAPACITY - self.len) % TRACE_CAPACITY;
+                self.len -= 1;
+
+                Some(self.events[oldest_idx])
+            }
+        }
+
+        /// Iterator returned by AlligatorAlloc::drain_trace(). Removes each event from the trace buffer as it is returned.
+        struct TraceDrain<'a, H> where H: HostHeap {
+            /// Allocator whose trace buffer is drained.
+            alloc: &'a AlligatorAlloc<H>,
+
+            /// Number of events left to return. Only the events recorded before drain_trace() was called are returned, so allocations made while iterating do not keep the iterator going.
+            remaining: usize,
+        }
+
+        impl<'a, H> Iterator for TraceDrain<'a, H> where H: HostHeap {
+            type Item = TraceEvent;
+
+            fn next(&mut self) -> Option<TraceEvent> {
+                if self.remaining == 0 {
+                    return None;
+                }
+
+                #[cfg(feature = "sync")]
+                let _guard = self.alloc.lock();
+
+                // The MetaPage may have been torn down by release() since the last event
+                let trace = match unsafe { (*self.alloc.alloc.get()).meta_page } {
+                    Some(meta_page) => unsafe { &mut *(*meta_page).trace },
+                    None => {
+                        self.remaining = 0;
+                        return None;
+                    },
+                };
 
-                out
+                self.remaining -= 1;
+                trace.pop_oldest()
             }
         }
     }
@@ -117,8 +532,17 @@ cfg_if! {
 /// Allocates an initial number of memory pages, then
 /// maintains a free linked list.
 struct AllocatorImpl<H> where H: HostHeap {
-    /// True if the initial call to allocate all the memory we will use has been made.
+    /// True once the host heap has been grown by enough pages for the MetaPage.
     did_init_heap: bool,
+
+    /// Most host pages the allocator will grow the host heap by. At most MAX_HOST_PAGES.
+    max_pages: usize,
+
+    /// Number of host pages, starting from the host heap base, the allocator has grown the host heap by and may hand out memory from. Grows lazily, as allocations need more pages, up to max_pages. Shrinks when release_unused_pages() gives pages back to the host.
+    heap_pages: usize,
+
+    /// The host's memory_size() before the allocator first grew it, the host page the heap starts at. Every later grow must start at host_start_page + heap_pages, otherwise something else grew the host memory in between and the new pages are not after the heap.
+    host_start_page: usize,
     
     /// The HostHeap implementation for the current platform.
     /// TODO Make Option and remove did_init_heap
@@ -132,6 +556,9 @@ struct AllocatorImpl<H> where H: HostHeap {
     /// TODO Make use free stacks instead
     big_alloc_head: Option<*mut BigAllocHeader>,
 
+    /// Last BigAllocHeader of the big allocation list, the one with the highest address. New big allocations are linked after it, so the list stays ordered by address.
+    big_alloc_tail: Option<*mut BigAllocHeader>,
+
     /// The first MiniPage worth of space in the heap is reserved for this "meta page". It is used to store information which needs to be placed on the heap for the Allicator implementation. Some if allocated and None if not allocated yet.
     meta_page: Option<*mut MetaPage>,
 
@@ -151,9 +578,34 @@ struct AllocatorImpl<H> where H: HostHeap {
     /// TODO Make Option
     fresh_minipages: [*mut MiniPageHeader; NUM_SIZE_CLASSES_USIZE],
 
+    /// Ratio of fresh to reused MiniPage allocations alloc aims for in each size class. FRESH_REUSED_RATIO unless set by with_fresh_reused_ratio().
+    fresh_reused_ratio: f64,
+
+    /// How alloc picks a free big allocation to reuse. BIG_ALLOC_POLICY unless set by with_big_alloc_policy().
+    big_alloc_policy: BigAllocPolicy,
+
+    /// If true allocations larger than MAX_SIZE_CLASS fail instead of being made with the big allocation technique, so the big allocation list is never used. Set by small_only().
+    small_only: bool,
+
     /// Cause of the failure.
     #[cfg(feature = "metrics")]
     failure: Option<AllocFail>,
+
+    /// Number of times each AllocFail occurred, indexed by `AllocFail as usize`. Kept outside of the MetaPage so it is not reset by release().
+    #[cfg(feature = "metrics")]
+    failure_counts: [u32; NUM_ALLOC_FAILS],
+
+    /// Highest address ever handed out to a MiniPage or big allocation. Memory at or after this address has not been written since the host grew it, and so is still zero. null_mut() if the heap has not been initialized.
+    #[cfg(feature = "zero-tracking")]
+    zeroed_start_ptr: *mut u8,
+
+    /// True once the emergency reserve at the end of the heap may be allocated from.
+    #[cfg(feature = "emergency-reserve")]
+    reserve_released: bool,
+
+    /// Recorded alloc and dealloc calls.
+    #[cfg(feature = "chrome-trace")]
+    chrome_trace: ChromeTrace,
 }
 
 cfg_if! {
@@ -173,15 +625,84 @@ cfg_if! {
             /// Failed to add a new MiniPage because there is no room left of the heap.
             AddMiniPageNoSpace,
 
+            /// Failed to make a new big allocation because there is no room left on the heap.
+            BigAllocNoSpace,
+
             /// A MiniPage taken off a free minipages stack ended up not having free segments. This is a breach of the free minipages stack "contract", where all MiniPages on this stack should have at least one free segment.
             FreeMiniPagesContractBreach,
 
             /// A de-allocation call was made, where it was determined that the pointer was from a big allocation. The program then tried to find the corresponding BigAllocHeader for the provided pointer. However a corresponding header was not found. The de-allocation call is considered a user error.
             BigDeallocHeaderNotFound,
 
-		  /// The allocator's logic made it try and access a MiniPageHeader which does not exist.
-		  MiniPageHeaderNotFound,
+            /// The allocator's logic made it try and access a MiniPageHeader which does not exist.
+            MiniPageHeaderNotFound,
+
+            /// The address computed for an allocation was outside of the heap region managed by the allocator. Indicates the allocator's internal state is corrupted.
+            AddrOutOfRange,
+
+            /// A de-allocation call was made for a MiniPage segment or big allocation which is already free. The de-allocation call is considered a user error.
+            DoubleFree,
+
+            /// A push onto one of the MetaPage's UnsafeStacks failed because it was full. The stacks are sized so this cannot happen, so the allocator's state is corrupted. The pushed free segment or MiniPage is lost and will not be handed out again.
+            StackOverflow,
+
+            /// The allocation requested a larger alignment than its size class can provide. MiniPage segments are aligned to their size and big allocations to MINI_PAGE_ALLOC_BYTES.
+            AlignTooLarge,
+
+            /// The allocation needed a size class larger than MAX_SIZE_CLASS, but the allocator was made with AlligatorAlloc::small_only() so it never makes big allocations.
+            AllocTooLarge,
+
+            /// A de-allocation call was made for a pointer which is not in the allocator's MiniPages or big allocations, ie., one into the MetaPage before them. The de-allocation call is considered a user error and does nothing.
+            InvalidFreePointer,
+        }
+
+        /// Number of AllocFail variants. Size of the array returned by AlligatorAlloc::failure_counts(), which is indexed by `AllocFail as usize`.
+        pub const NUM_ALLOC_FAILS: usize = 14;
+
+        // InvalidFreePointer must stay the last variant, or NUM_ALLOC_FAILS must be updated
+        const _: () = assert!(AllocFail::InvalidFreePointer as usize + 1 == NUM_ALLOC_FAILS);
+    }
+}
+
+cfg_if! {
+    if #[cfg(feature = "debug-layout")] {
+        /// Value of AllocDebugHeader::magic, the bytes "ALGT" read as a little endian u32. Lets external tools check they found the header.
+        pub const ALLOC_DEBUG_MAGIC: u32 = 0x54474c41;
+
+        /// Copy of some allocator state kept at a fixed place in memory, so external debuggers (ie., a browser's WebAssembly memory inspector) can read it without calling into the allocator. It is the first field of the MetaPage, its address is returned by AlligatorAlloc::debug_header_ptr() and the exported alligator_debug_header_offset() function.
+        ///
+        /// Layout, every field is a little endian u32:
+        ///
+        /// | Offset | Field             |
+        /// | ------ | ----------------- |
+        /// | 0      | magic             |
+        /// | 4      | live_bytes        |
+        /// | 8      | total_minipages   |
+        /// | 12     | next_alloc_offset |
+        /// | 16     | last_failure_code |
+        ///
+        /// Updated after every alloc and dealloc call made through AlligatorAlloc. Debuggers must treat it as read-only.
+        #[repr(C)]
+        #[derive(Copy, Clone, Debug)]
+        pub struct AllocDebugHeader {
+            /// Always ALLOC_DEBUG_MAGIC.
+            pub magic: u32,
+
+            /// Usable bytes of all live allocations.
+            pub live_bytes: u32,
+
+            /// Same as AllocMetrics::total_minipages.
+            pub total_minipages: u32,
+
+            /// Offset of the next free byte on the heap from the host heap base, which is 0 on WebAssembly.
+            pub next_alloc_offset: u32,
+
+            /// `AllocFail as u32 + 1` of the most recent failure, 0 if there has not been one.
+            pub last_failure_code: u32,
         }
+
+        // Debuggers rely on the documented field offsets
+        const _: () = assert!(size_of::<AllocDebugHeader>() == 20);
     }
 }
 
@@ -189,31 +710,57 @@ cfg_if! {
 #[derive(Debug, Copy, Clone)]
 struct BigAllocFlag {
     /// Index to the first MiniPage of space in the heap where the big allocation header resides.
-    start_idx: usize,
+    start_idx: PageIdx,
+}
+
+impl BigAllocFlag {
+    /// Creates a flag for a big allocation whose header is in the MiniPage at start_idx.
+    fn new(start_idx: usize) -> BigAllocFlag {
+        BigAllocFlag{
+            start_idx: compact_page_idx(start_idx),
+        }
+    }
+
+    /// Returns the index of the MiniPage where the big allocation header resides.
+    fn start_idx(self) -> usize {
+        expand_page_idx(self.start_idx)
+    }
 }
 
 /// The first MiniPage of the heap will hold some metadata which we don't want / can't put in the AllocatorImpl stack object.
 #[derive(Debug)]
+#[cfg_attr(feature = "debug-layout", repr(C))]
 struct MetaPage {
-    /// Headers for all MiniPages.
-    minipage_headers: [Option<MiniPageHeader>; MAX_MINI_PAGES as usize],
+    /// State for external debuggers. Must stay the first field so it is at the start of the MetaPage.
+    #[cfg(feature = "debug-layout")]
+    debug_header: AllocDebugHeader,
+
+    /// Headers for all MiniPages. Stored on the heap after the MetaPage, MAX_HOST_MINI_PAGES long.
+    minipage_headers: &'static mut [Option<MiniPageHeader>],
 
-    /// Array of flags which indicate if a MiniPage index actually belongs to a big allocation.
-    big_alloc_flags: [Option<BigAllocFlag>; MAX_MINI_PAGES as usize],
+    /// Array of flags which indicate if a MiniPage index actually belongs to a big allocation. Stored on the heap after the MetaPage, MAX_HOST_MINI_PAGES long.
+    big_alloc_flags: &'static mut [Option<BigAllocFlag>],
     
     /// Stacks of free MiniPage indexes for each size class.
     ///
     /// The head of each stack indicates the in use MiniPage. The free_segments stack's values relate to these in use MiniPages.
     ///
     /// MiniPages are popped off stacks when there are no free segments left (ie., their free_segments stack is empty).
-    free_minipages: [*mut UnsafeStack<usize>; NUM_SIZE_CLASSES_USIZE],
+    free_minipages: [*mut UnsafeStack<PageIdx>; NUM_SIZE_CLASSES_USIZE],
 
     /// Free segment indexes for in use MiniPage (head of free_minipages stack) of each size class. Allows us to avoid searching the MiniPageHeader bitmap for the most recently used MiniPage.
     free_segments: [*mut UnsafeStack<u16>; NUM_SIZE_CLASSES_USIZE],
 
+    /// Indexes of MiniPages which had all their segments freed and no longer belong to a size class. add_minipage() takes from here before using new heap space.
+    reclaimed_minipages: *mut UnsafeStack<PageIdx>,
+
     /// Allocator metrics
     #[cfg(feature = "metrics")]
     metrics: *mut AllocMetrics,
+
+    /// Most recent alloc and dealloc calls
+    #[cfg(feature = "trace")]
+    trace: *mut TraceBuffer,
 }
 
 impl MetaPage {
@@ -230,27 +777,50 @@ impl MetaPage {
     unsafe fn alloc(alloc_ptr: *mut u8) -> (*mut MetaPage, *mut u8) {
         let page_ptr = alloc_ptr as *mut MetaPage;
 
+        // Space after this MetaPage struct in which we can place other allocations
+        let mut next_ptr = page_ptr.offset(1) as *mut u8;
+
+        // Setup the tables with an entry for every MiniPage
+        let (minipage_headers, after_ptr) = MetaPage::alloc_table(next_ptr, None);
+        next_ptr = after_ptr;
+        let (big_alloc_flags, after_ptr) = MetaPage::alloc_table(next_ptr, None);
+        next_ptr = after_ptr;
+
 	   // Zero out all values
-	   (*page_ptr).minipage_headers = [None; MAX_MINI_PAGES as usize];
- 	   (*page_ptr).big_alloc_flags = [None; MAX_MINI_PAGES as usize];
+	   (*page_ptr).minipage_headers = minipage_headers;
+ 	   (*page_ptr).big_alloc_flags = big_alloc_flags;
 	   (*page_ptr).free_minipages = [null_mut(); NUM_SIZE_CLASSES as usize];
 	   (*page_ptr).free_segments = [null_mut(); NUM_SIZE_CLASSES as usize];
+	   (*page_ptr).reclaimed_minipages = null_mut();
 	   cfg_if! {
-		  if #[cfg(features = "metrics")] {
+		  if #[cfg(feature = "debug-layout")] {
+			 (*page_ptr).debug_header = AllocDebugHeader{
+				magic: ALLOC_DEBUG_MAGIC,
+				live_bytes: 0,
+				total_minipages: 0,
+				next_alloc_offset: 0,
+				last_failure_code: 0,
+			 };
+		  }
+	   }
+	   cfg_if! {
+		  if #[cfg(feature = "metrics")] {
 			 (*page_ptr).metrics = null_mut();
 		  }
 	   }
-
-        // Space after this MetaPage struct in which we can place other allocations
-        let mut next_ptr = page_ptr.offset(1) as *mut u8;
+	   cfg_if! {
+		  if #[cfg(feature = "trace")] {
+			 (*page_ptr).trace = null_mut();
+		  }
+	   }
 
         // Setup free minipages stacks
         for i in MIN_SIZE_CLASS..=MAX_SIZE_CLASS {
             let size_class = SizeClass::new(i);
             
-            let (stack, after_ptr) = UnsafeStack::<usize>::alloc(
+            let (stack, after_ptr) = UnsafeStack::<PageIdx>::alloc(
                 next_ptr,
-                MINI_PAGE_ALLOC_BYTES / 2_u32.pow(u32::from(size_class.exp)), // TODO This is wrong, should be MAX_MINI_PAGES
+                (MAX_HOST_MINI_PAGES / size_class.minipage_intervals()) as u32, // Every MiniPage of the size class which fits in the heap, so a push never fails
             );
             (*page_ptr).free_minipages[size_class.exp_as_idx()] = stack;
             next_ptr = after_ptr;
@@ -268,6 +838,11 @@ impl MetaPage {
             next_ptr = after_ptr;
         }
 
+        // Setup reclaimed minipages stack, every MiniPage could end up on it
+        let (stack, after_ptr) = UnsafeStack::<PageIdx>::alloc(next_ptr, MAX_HOST_MINI_PAGES as u32);
+        (*page_ptr).reclaimed_minipages = stack;
+        next_ptr = after_ptr;
+
         cfg_if! {
             if #[cfg(feature = "metrics")] {
                 // Setup metrics if feature is enabled
@@ -278,13 +853,92 @@ impl MetaPage {
             }
         }
 
+        cfg_if! {
+            if #[cfg(feature = "trace")] {
+                let (trace, after_ptr) = TraceBuffer::alloc(next_ptr);
+                (*page_ptr).trace = trace;
+
+                next_ptr = after_ptr;
+            }
+        }
+
         return (page_ptr, next_ptr);
     }
+
+    /// Allocate a table of MAX_HOST_MINI_PAGES items at the first address at or after `start_ptr` aligned for T, with every item set to `value`. Returns the table and a pointer to the byte of memory after it.
+    unsafe fn alloc_table<T>(start_ptr: *mut u8, value: T) -> (&'static mut [T], *mut u8) where T: Copy {
+        let table_ptr = start_ptr.offset(start_ptr.align_offset(align_of::<T>()) as isize) as *mut T;
+        for i in 0..MAX_HOST_MINI_PAGES {
+            table_ptr.add(i).write(value);
+        }
+
+        (from_raw_parts_mut(table_ptr, MAX_HOST_MINI_PAGES), table_ptr.add(MAX_HOST_MINI_PAGES) as *mut u8)
+    }
+
+    /// Most bytes alloc() can use, counting the padding which aligns its tables wherever it starts. The host heap must have at least this many bytes before the MetaPage is allocated.
+    const fn max_bytes() -> usize {
+        let mut bytes = size_of::<MetaPage>();
+
+        // Tables
+        bytes += align_of::<Option<MiniPageHeader>>() - 1 + (MAX_HOST_MINI_PAGES * size_of::<Option<MiniPageHeader>>());
+        bytes += align_of::<Option<BigAllocFlag>>() - 1 + (MAX_HOST_MINI_PAGES * size_of::<Option<BigAllocFlag>>());
+
+        // Free minipages and free segments stacks. A while loop so this stays a const fn, see META_PAGE_HOST_PAGES
+        let mut i = MIN_SIZE_CLASS;
+        while i <= MAX_SIZE_CLASS {
+            let size_class = SizeClass::new(i);
+
+            bytes += UnsafeStack::<PageIdx>::max_bytes(MAX_HOST_MINI_PAGES / size_class.minipage_intervals());
+            bytes += UnsafeStack::<u16>::max_bytes(size_class.segments_max_num() as usize);
+            i += 1;
+        }
+
+        // Reclaimed minipages stack
+        bytes += UnsafeStack::<PageIdx>::max_bytes(MAX_HOST_MINI_PAGES);
+
+        cfg_if! {
+            if #[cfg(feature = "metrics")] {
+                bytes += align_of::<AllocMetrics>() - 1 + size_of::<AllocMetrics>();
+            }
+        }
+
+        cfg_if! {
+            if #[cfg(feature = "trace")] {
+                bytes += align_of::<TraceBuffer>() - 1 + size_of::<TraceBuffer>();
+            }
+        }
+
+        bytes
+    }
+
+    /// Returns the index of the first interval of the MiniPage which the interval at page_idx is part of. MiniPages of size classes larger than MAX_SINGLE_INTERVAL_SIZE_CLASS span several intervals and only the first has a header in minipage_headers, so this searches back for it. Returns page_idx if it is not inside a larger MiniPage.
+    fn minipage_start(&self, page_idx: usize) -> usize {
+        if self.minipage_headers[page_idx].is_some() || self.big_alloc_flags[page_idx].is_some() {
+            return page_idx;
+        }
+
+        // A MiniPage can only start up to MAX_MINI_PAGE_INTERVALS - 1 intervals before
+        for back in 1..MAX_MINI_PAGE_INTERVALS.min(page_idx + 1) {
+            let start_idx = page_idx - back;
+            if self.big_alloc_flags[start_idx].is_some() {
+                return page_idx;
+            }
+
+            if let Some(header) = &self.minipage_headers[start_idx] {
+                return match back < SizeClass::new(header.size_class_exp).minipage_intervals() {
+                    true => start_idx,
+                    false => page_idx,
+                };
+            }
+        }
+
+        page_idx
+    }
 }
 
-/// Stack stored on the heap. Implemented as a fixed size circular stack. Does not implement size growing. Can store up to 2^16 items.
+/// Stack stored on the heap. Implemented as a fixed size last in first out stack, items are stored in order from the bottom of the stack at index 0 up to the head at index size - 1. Does not implement size growing. Can store up to 2^16 items.
 #[derive(Copy, Clone, Debug)]
-struct UnsafeStack<T> where T: Copy {
+pub(crate) struct UnsafeStack<T> where T: Copy {
     /// Address of the first data index. Items in the stack will be stored in a contiguous segment following this location.
     data_ptr: *mut T,
 
@@ -293,42 +947,53 @@ struct UnsafeStack<T> where T: Copy {
 
     /// Current size of the stack.
     size: u32,
-
-    /// The index of the head within the data memory segment.
-    head_idx: u32,
 }
 
 impl <T> UnsafeStack<T> where T: Copy {
-    /// Initialize an unsafe stack in memory at the addr.
+    /// Initialize an unsafe stack in memory at the first address at or after start_addr aligned for it, see UnsafeStack::max_bytes() for how much padding that can take.
     /// Returns the tuple: (UnsafeStack, next ptr), the next ptr is a pointer to the next byte of memory after the new UnsafeStack structure and the following data. Memory between start_addr and next ptr is managed by the new UnsafeStack.
     ///
     /// # Panics
     /// If the size of T is larger than what can be represented by isize. But the overall Alligator is the only one who should be using this structure, so this should never happen.
-    unsafe fn alloc(start_addr: *mut u8, max_size: u32) -> (*mut UnsafeStack<T>, *mut u8) {
-        // Setup new UnsafeStack
-        let stack_ptr = start_addr as *mut UnsafeStack<T>;
-        
-        (*stack_ptr).data_ptr = start_addr.offset(size_of::<UnsafeStack<T>>().try_into().unwrap()) as *mut T; // TODO: Align this
+    pub(crate) unsafe fn alloc(start_addr: *mut u8, max_size: u32) -> (*mut UnsafeStack<T>, *mut u8) {
+        // Setup new UnsafeStack, the struct and its data are each placed at the first address aligned for them
+        let stack_ptr = start_addr.offset(start_addr.align_offset(align_of::<UnsafeStack<T>>()) as isize) as *mut UnsafeStack<T>;
+        let data_start = stack_ptr.offset(1) as *mut u8;
+
+        (*stack_ptr).data_ptr = data_start.offset(data_start.align_offset(align_of::<T>()) as isize) as *mut T;
         (*stack_ptr).max_size = max_size;
         (*stack_ptr).size = 0;
-        (*stack_ptr).head_idx = 0;
 
         // Calculate next ptr
-        let next_ptr = (*stack_ptr).data_ptr.offset(max_size.try_into().unwrap()) as *mut u8; // TODO: Align this
+        let next_ptr = (*stack_ptr).data_ptr.offset(max_size.try_into().unwrap()) as *mut u8;
 
         return (stack_ptr, next_ptr);
     }
 
-    /// Returns a MemAddr which points to the location in the heap for a data item of type T at index i.
+    /// Most bytes alloc() can use for a stack of max_size items, counting the padding which aligns the UnsafeStack and its data wherever it starts.
+    const fn max_bytes(max_size: usize) -> usize {
+        align_of::<UnsafeStack<T>>() - 1 + size_of::<UnsafeStack<T>>() + align_of::<T>() - 1 + (max_size * size_of::<T>())
+    }
+
+    /// Returns a pointer to the location in the heap for a data item of type T at index i. i must be less than max_size.
     ///
     /// # Panics
     /// If the size of T is larger than what can be represented by isize. But the overall Alligator is the only one who should be using this structure, so this should never happen.
     unsafe fn item_ptr(&mut self, i: u32) -> *mut T {
-        self.data_ptr.offset((i % self.max_size).try_into().unwrap())
+        debug_assert!(i < self.max_size, "UnsafeStack index {} is outside of its {} items", i, self.max_size);
+
+        cfg_if! {
+            if #[cfg(feature = "no-panic")] {
+                // A u32 always fits in a usize on the 32 and 64 bit targets the allocator supports
+                self.data_ptr.add(i as usize)
+            } else {
+                self.data_ptr.offset(i.try_into().unwrap())
+            }
+        }
     }
 
-    /// Push an item onto the stack. Returns the Some(item) on success and None if there was no more space.
-    unsafe fn push(&mut self, item: T) -> Option<T> {
+    /// Push an item onto the head of the stack. Returns the Some(item) on success and None if there was no more space.
+    pub(crate) unsafe fn push(&mut self, item: T) -> Option<T> {
         // Check there is space remaining
         if self.size == self.max_size {
             return None;
@@ -344,22 +1009,26 @@ impl <T> UnsafeStack<T> where T: Copy {
     }
 
     /// Pop an item from the head of the stack. Returns Some(item) on success and None if there are no items on the stack.
-    unsafe fn pop(&mut self) -> Option<T> {
+    pub(crate) unsafe fn pop(&mut self) -> Option<T> {
         // Get item 
         match self.peek() {
             None => None,
             Some(item) => {
                 // Remove from stack
                 self.size -= 1;
-                self.head_idx = (self.head_idx + 1) % self.max_size;
-        
+
                 Some(item)
             },
         }
     }
 
+    /// Removes all items from the stack.
+    pub(crate) fn clear(&mut self) {
+        self.size = 0;
+    }
+
     /// Return the item at the head of the stack without removing it. Returns None if the stack is empty.
-    unsafe fn peek(&mut self) -> Option<T> {
+    pub(crate) unsafe fn peek(&mut self) -> Option<T> {
         // Check if empty
         if self.size == 0 {
             return None;
@@ -372,6 +1041,36 @@ impl <T> UnsafeStack<T> where T: Copy {
         Some(item)
     }
 
+    /// Return the item n below the head of the stack without modifying the stack, peek_nth(0) is the same as peek(). Returns None if the stack has n or fewer items.
+    pub(crate) unsafe fn peek_nth(&mut self, n: u32) -> Option<T> {
+        // Check n is on the stack
+        if n >= self.size {
+            return None;
+        }
+
+        // Get item
+        let item_ptr = self.item_ptr(self.size - 1 - n);
+        let item = *item_ptr;
+
+        Some(item)
+    }
+
+    /// Number of items currently on the stack.
+    pub(crate) fn len(&self) -> u32 {
+        self.size
+    }
+
+    /// Returns true if item is anywhere on the stack. Searches every item, so only meant for checking invariants in debug builds.
+    pub(crate) unsafe fn contains(&mut self, item: T) -> bool where T: PartialEq {
+        for i in 0..self.size {
+            if *self.item_ptr(i) == item {
+                return true;
+            }
+        }
+
+        false
+    }
+
     cfg_if! {
         if #[cfg(feature = "metrics")] {
             /// Records the cost of a push operation in the meta-page for metrics. Only records the cost of accessing the self.data array as all the custodial accesses of self.size, ect are constant.
@@ -401,11 +1100,19 @@ struct MiniPageHeader {
     /// The next free node of the same size class.
     next: Option<*mut MiniPageHeader>,
 
-    /// Bit-packed free list. A 1 means that segment is free, 0 means allocated.
+    /// Bit-packed free list. A 1 means that segment is free, 0 means allocated. See bitmap_position() for the bit ordering.
     free_segments: [u8; MINI_PAGE_FREE_SEGMENTS_SIZE],
 
     /// True if this MiniPage is on the Allocator's free minipages stack. Storing this flag here allows us to not do a linear search through the entire free minipages stack every deallocation.
     on_free_minipages_stack: bool,
+
+    /// Bit-packed clean list, same layout as free_segments. A 1 means that segment is known to only contain zeros, 0 means it may have been written.
+    #[cfg(feature = "zero-tracking")]
+    clean_segments: [u8; MINI_PAGE_FREE_SEGMENTS_SIZE],
+
+    /// Number of bytes requested for each segment's allocation, indexed by segment index. Only meaningful for allocated segments. The largest segment is 2^MAX_SIZE_CLASS bytes, so a u16 holds any request a segment can satisfy.
+    #[cfg(feature = "requested-size")]
+    requested_bytes: [u16; MINI_PAGE_MAX_SEGMENTS],
 }
 
 impl MiniPageHeader {
@@ -415,7 +1122,7 @@ impl MiniPageHeader {
     /// TODO
     unsafe fn write_free_bitmap(&mut self, segment: MiniPageSegment, free: bool) {
         // Write
-        let byte = (*self).free_segments[segment.bitmap_byte_idx];
+        let byte = self.free_segments[segment.bitmap_byte_idx];
         let bit_mask: u8 = 1 << segment.bitmap_byte_bit_idx;
 
         let new_byte: u8 = match free {
@@ -430,22 +1137,61 @@ impl MiniPageHeader {
             }
         };
 
-        (*self).free_segments[segment.bitmap_byte_idx] = new_byte;
+        self.free_segments[segment.bitmap_byte_idx] = new_byte;
     }
 
-    /// Returns the segment's free status from its MiniPage header free bitmap. Returns true if free and false if not-free.
+    /// Returns the segment's free status from its MiniPage header free bitmap, the last value write_free_bitmap() wrote for it. Returns true if free and false if not-free.
     ///
-    /// Safety:
-    /// TODO
-    unsafe fn get_free_bitmap(&mut self, segment: MiniPageSegment) -> bool {
+    /// Only reads one byte through the reference. The dealloc path calls this for every freed segment, a header is over 250 bytes so it is never copied.
+    fn get_free_bitmap(&self, segment: MiniPageSegment) -> bool {
         let search_mask = 1 << segment.bitmap_byte_bit_idx;
-        let bit_free_status = ((*self).free_segments[segment.bitmap_byte_idx] & search_mask) >> segment.bitmap_byte_bit_idx;
+        let bit_free_status = (self.free_segments[segment.bitmap_byte_idx] & search_mask) >> segment.bitmap_byte_bit_idx;
 
         match bit_free_status {
             1 => true,
             _ => false,
         }
     }
+
+    /// Returns true if the segment is known to only contain zeros, and marks it as possibly written. Call when the segment is handed out.
+    #[cfg(feature = "zero-tracking")]
+    fn take_clean_bitmap(&mut self, segment: MiniPageSegment) -> bool {
+        let bit_mask: u8 = 1 << segment.bitmap_byte_bit_idx;
+        let byte = self.clean_segments[segment.bitmap_byte_idx];
+
+        self.clean_segments[segment.bitmap_byte_idx] = byte & !bit_mask;
+
+        byte & bit_mask != 0
+    }
+
+    /// Returns the index of the first segment marked as free in the free bitmap. None if all segments are allocated.
+    fn first_free_segment(&self) -> Option<u16> {
+        let size_class = SizeClass::new(self.size_class_exp);
+
+        for segment_i in 0..size_class.segments_max_num() {
+            let (byte_idx, bit_idx) = bitmap_position(usize::from(segment_i));
+            if (self.free_segments[byte_idx] >> bit_idx) & 1 == 1 {
+                return Some(segment_i);
+            }
+        }
+
+        None
+    }
+
+    /// Returns the number of segments marked as allocated in the free bitmap.
+    fn used_segments_count(&self) -> u16 {
+        let size_class = SizeClass::new(self.size_class_exp);
+        let mut used = 0;
+
+        for segment_i in 0..size_class.segments_max_num() {
+            let (byte_idx, bit_idx) = bitmap_position(usize::from(segment_i));
+            if (self.free_segments[byte_idx] >> bit_idx) & 1 == 0 {
+                used += 1;
+            }
+        }
+
+        used
+    }
 }
 
 /// Calculates useful size class values.
@@ -457,7 +1203,7 @@ pub struct SizeClass {
 
 impl SizeClass {
     /// New size class from an exponent number. Will normalize values smaller than MIN_SIZE_CLASS to be MIN_SIZE_CLASS.
-    pub fn new(exp: u8) -> SizeClass {
+    pub const fn new(exp: u8) -> SizeClass {
         let mut norm_exp = exp;
         if norm_exp < MIN_SIZE_CLASS {
             norm_exp = MIN_SIZE_CLASS;
@@ -470,14 +1216,35 @@ impl SizeClass {
     }
 
     /// Creates the size class required to fit a number of bytes. The resulting size class is normalized using SizeClass::new() to never be smaller than the smallest size class.
-    pub fn new_from_bytes(bytes: u16) -> SizeClass {
-        let fbytes = f32::from(bytes);
-        // # Panics
-        // Won't panic because fbytes is representing a unsigned 16 bit number, and the integer log2 version of this will be 8 bits.
-        let exp = fbytes.log2().ceil() as u32;
-        let exp_u8 = u8::try_from(exp).unwrap();
+    /// Sizes which fit in a MiniPage are looked up in SIZE_CLASS_TABLE, larger sizes are computed. bytes is never truncated, sizes larger than MAX_SIZE_CLASS get a size class exponent larger than MAX_SIZE_CLASS, which the allocator serves with a big allocation.
+    pub fn new_from_bytes(bytes: usize) -> SizeClass {
+        match SIZE_CLASS_TABLE.get(bytes) {
+            Some(exp) => SizeClass{
+                exp: *exp,
+            },
+            None => SizeClass::new(SizeClass::exp_from_bytes(bytes)),
+        }
+    }
+
+    /// Integer log2 of bytes rounded up: the number of bits needed to represent bytes - 1. Not normalized. At most the number of bits in a usize, so always fits in a u8.
+    const fn exp_from_bytes(bytes: usize) -> u8 {
+        match bytes {
+            0 | 1 => 0,
+            _ => ((size_of::<usize>() * 8) as u32 - (bytes - 1).leading_zeros()) as u8,
+        }
+    }
 
-        SizeClass::new(exp_u8)
+    /// Creates the size class required to fit an allocation with layout. Segments are aligned to their size, so the size class is chosen to fit the larger of the layout's size and alignment. This ensures the segment satisfies the requested alignment. MiniPages of size classes larger than MAX_SINGLE_INTERVAL_SIZE_CLASS start on a multiple of their segment size so this holds for them too. Big allocations are only aligned to MINI_PAGE_ALLOC_BYTES.
+    pub fn new_from_layout(layout: Layout) -> SizeClass {
+        SizeClass::new_from_bytes(layout.size().max(layout.align()))
+    }
+
+    /// Returns this size class, or the size class with exponent min_exp if this size class is smaller.
+    pub fn at_least(self, min_exp: u8) -> SizeClass {
+        match self.exp < min_exp {
+            true => SizeClass::new(min_exp),
+            false => self,
+        }
     }
 
     /// Returns the exponent which indicates the size class as an index number which starts counting at 0. Used to access arrays where the 0 index is the MIN_SIZE_CLASS and the largest index is the MAX_SIZE_CLASS.
@@ -486,20 +1253,84 @@ impl SizeClass {
     }
     
     /// Size of a segment in bytes.
-    /// Returns u16 because the maximum size class is 11 and 2^11 requires at least 11 bits, thus 16 are required.
-    pub fn segment_bytes(self) -> u16 {
-        2_u16.pow(u32::from(self.exp))
+    /// Returns u16 because the maximum size class is 15 and 2^15 requires at least 16 bits.
+    pub const fn segment_bytes(self) -> u16 {
+        2_u16.pow(self.exp as u32)
+    }
+
+    /// Number of bytes of heap one MiniPage of this size class takes up. MINI_PAGE_ALLOC_BYTES, except for size classes larger than MAX_SINGLE_INTERVAL_SIZE_CLASS whose MiniPages hold LARGE_MINI_PAGE_SEGMENTS segments.
+    pub const fn minipage_bytes(self) -> u32 {
+        match self.exp <= MAX_SINGLE_INTERVAL_SIZE_CLASS {
+            true => MINI_PAGE_ALLOC_BYTES,
+            false => self.segment_bytes() as u32 * LARGE_MINI_PAGE_SEGMENTS,
+        }
+    }
+
+    /// Number of contiguous MINI_PAGE_ALLOC_BYTES intervals one MiniPage of this size class spans. Only the first has a MiniPageHeader, see MetaPage::minipage_start().
+    pub const fn minipage_intervals(self) -> usize {
+        (self.minipage_bytes() / MINI_PAGE_ALLOC_BYTES) as usize
     }
 
     /// Returns the maximum number of segments which can be stored in a MiniPage for this size class.
-    pub fn segments_max_num(&self) -> u16 {
-        (MINI_PAGE_ALLOC_BYTES as u16) / self.segment_bytes()
+    pub const fn segments_max_num(&self) -> u16 {
+        (self.minipage_bytes() / self.segment_bytes() as u32) as u16
     }
 }
 
+// Every size class's segments must fit in a MiniPageHeader's free_segments bitmap, one bit each, and their indexes in the u16 items of its free segments stack, whose max_size is segments_max_num(). The smallest size class has the most segments, MINI_PAGE_ALLOC_BYTES / 2^MIN_SIZE_CLASS. Changing MINI_PAGE_ALLOC_BYTES, MIN_SIZE_CLASS, or LARGE_MINI_PAGE_SEGMENTS so either stops holding fails to compile here
+const _: () = {
+    let mut exp = MIN_SIZE_CLASS;
+    while exp <= MAX_SIZE_CLASS {
+        let size_class = SizeClass::new(exp);
+        let segments = size_class.minipage_bytes() / size_class.segment_bytes() as u32;
+
+        assert!(segments <= u16::MAX as u32, "segments_max_num() would not fit in a u16");
+        assert!(segments as usize <= MINI_PAGE_FREE_SEGMENTS_SIZE * 8, "free_segments bitmap does not have a bit for every segment");
+
+        #[cfg(feature = "requested-size")]
+        assert!(segments as usize <= MINI_PAGE_MAX_SEGMENTS, "requested_bytes does not have an item for every segment");
+
+        exp += 1;
+    }
+};
+
+/// Normalized size class exponent for every request of 0 to MINI_PAGE_ALLOC_BYTES bytes, indexed by bytes. Turns SizeClass::new_from_bytes() into one array index for requests which fit in a MiniPage.
+static SIZE_CLASS_TABLE: [u8; MINI_PAGE_ALLOC_BYTES as usize + 1] = size_class_table();
+
+/// Builds SIZE_CLASS_TABLE.
+const fn size_class_table() -> [u8; MINI_PAGE_ALLOC_BYTES as usize + 1] {
+    let mut table = [0; MINI_PAGE_ALLOC_BYTES as usize + 1];
+
+    let mut bytes = 0;
+    while bytes < table.len() {
+        table[bytes] = SizeClass::new(SizeClass::exp_from_bytes(bytes)).exp;
+        bytes += 1;
+    }
+
+    table
+}
+
+// Check the table for the sizes at and around every size class boundary
+const _: () = {
+    let table = size_class_table();
+
+    assert!(table[0] == MIN_SIZE_CLASS && table[1] == MIN_SIZE_CLASS);
+
+    let mut exp = MIN_SIZE_CLASS;
+    while exp <= MAX_SINGLE_INTERVAL_SIZE_CLASS {
+        let segment_bytes = 1_usize << exp;
+        assert!(table[segment_bytes] == exp);
+        if exp > MIN_SIZE_CLASS {
+            assert!(table[segment_bytes - 1] == exp);
+            assert!(table[(segment_bytes / 2) + 1] == exp);
+        }
+        exp += 1;
+    }
+};
+
 /// Represents an allocated address in memory within the context of the memory allocator design.
 #[derive(Copy, Clone)]
-struct AllocAddr {
+pub(crate) struct AllocAddr {
     /// Numerical representation of address. This is used to complete all pointer math against.
     /// The unsafe as_ptr() method will turn this number into a memory address which is gaurenteed to be safe.
     /// This address assumes the base memory address of the heap is 0. This simplifies calculations. The actual heap base offset is added via as_ptr().
@@ -508,7 +1339,7 @@ struct AllocAddr {
 
 impl AllocAddr {
     /// Initializes an AllocAddr from a u32.
-    fn new(addr: u32) -> AllocAddr {
+    pub(crate) fn new(addr: u32) -> AllocAddr {
         AllocAddr{
             addr: addr,
         }
@@ -528,17 +1359,24 @@ impl AllocAddr {
 
     /// Initializes an AllocAddr from a raw pointer and heap base pointer. The returned AllocAddr will represent the raw_ptr, the base_ptr will be used to determine the start of the heap. As all AllocAddrs are relative to this address.
     /// 
-    /// # Safety
-    /// Calls std::mem::transmute which is only safe if the result type is the same size as the input type. For this method this is the case because:
-    /// - program only supports 32 bit addresses => a raw pointer will be a 32 bit unsigned number
-    /// - target type of transmute is a 32 bit unsigned integer => transmute call is safe
+    /// Only the offset from base_ptr has to fit in 32 bits, so this also works with 64 bit pointers as long as the heap is at most 4 GB. Every HostHeap guarantees this.
     unsafe fn from_ptr(base_ptr: *mut u8, raw_ptr: *mut u8) -> AllocAddr {
-        let base_n = transmute::<*mut u8, u32>(base_ptr);
-        let raw_n = transmute::<*mut u8, u32>(raw_ptr);
-        assert!(base_n <= raw_n, "Address ({:?}) from which to make AllocAddr cannot be less than the base_ptr ({:?})", raw_ptr, base_ptr);
+        let base_n = base_ptr as usize;
+        let raw_n = raw_ptr as usize;
+
+        cfg_if! {
+            if #[cfg(feature = "no-panic")] {
+                // Saturate instead, the address is past the end of the heap so lookups with it find nothing
+                debug_assert!(base_n <= raw_n, "Address ({:?}) from which to make AllocAddr cannot be less than the base_ptr ({:?})", raw_ptr, base_ptr);
+                let addr = u32::try_from(raw_n.wrapping_sub(base_n)).unwrap_or(u32::MAX);
+            } else {
+                assert!(base_n <= raw_n, "Address ({:?}) from which to make AllocAddr cannot be less than the base_ptr ({:?})", raw_ptr, base_ptr);
+                let addr = u32::try_from(raw_n - base_n).unwrap();
+            }
+        }
         
         AllocAddr{
-            addr: raw_n - base_n,
+            addr: addr,
         }
     }
 
@@ -547,37 +1385,62 @@ impl AllocAddr {
         MiniPageMeta::from_addr(self)
     }
 
-    /// Returns information about the segment from which this memory address was allocated.
-    fn get_segment(self, size_class: SizeClass) -> MiniPageSegment {
-        MiniPageSegment::from_addr(size_class, self)
+    /// Returns information about the segment of page, a MiniPage of size_class, which this memory address is in. For size classes larger than MAX_SINGLE_INTERVAL_SIZE_CLASS page is not necessarily get_page_meta(), see MetaPage::minipage_start().
+    fn get_segment(self, page: MiniPageMeta, size_class: SizeClass) -> MiniPageSegment {
+        MiniPageSegment::from_addr(page, size_class, self)
     }
 
-    /// Returns addr as an unsafe pointer gaurenteed not to be null.
+    /// Returns addr as an unsafe pointer. Null if addr is outside the largest host heap.
     /// 
     /// # Safety
     /// The returned pointer will is known to be valid because:
     /// - It will be no earlier than the base pointer of the host heap => Pointer is not refering to memory too early in the heap, which is memory we may not control or does not exist.
     ///
-    /// However the .addr field is not checked for correctness => The resulting pointer will only be safe if addr is not larger than the total host heap.
-    unsafe fn as_ptr(self, base_ptr: *mut u8) -> *mut u8 {
+    /// However the .addr field is only checked against MAX_HOST_HEAP_BYTES, not the pages the host heap actually has => The resulting pointer will only be safe if addr is not larger than the total host heap.
+    ///
+    /// # Panics
+    /// With debug assertions, if addr is not less than MAX_HOST_HEAP_BYTES. Such an addr can only come from a bug or corrupted allocator state. Without debug assertions null is returned instead, callers fail the allocation or treat the address as not allocated rather than use a pointer to some other allocation.
+    pub(crate) unsafe fn as_ptr(self, base_ptr: *mut u8) -> *mut u8 {
+        debug_assert!(self.addr < MAX_HOST_HEAP_BYTES, "AllocAddr {} is outside the {} bytes of the largest host heap", self.addr, MAX_HOST_HEAP_BYTES);
+        if self.addr >= MAX_HOST_HEAP_BYTES {
+            return null_mut();
+        }
+        let addr = self.addr;
+
         // # Panics
         // Should not panic because:
         // - program only supports 32 bit memory addresses => isize will be 32 bits
-        // - .addr should always refer to a valid 32 bit address (up to user of AllocAddr to ensure) => .addr + base_ptr will always fit in 32 bits
+        // - addr is less than MAX_HOST_HEAP_BYTES => .addr + base_ptr will always fit in 32 bits
         // - isize is 32 bits and resulting memory address will always fit in 32 bits => cast to isize will not fail
-        base_ptr.offset(isize::try_from(self.addr).unwrap())
+        cfg_if! {
+            if #[cfg(feature = "no-panic")] {
+                base_ptr.add(addr as usize)
+            } else {
+                base_ptr.offset(isize::try_from(addr).unwrap())
+            }
+        }
     }
 
-    /// Returns the .addr field as a f64.
-    fn addr_f64(self) -> f64 {
-        f64::from(self.addr)
+    /// Returns addr as a pointer if it is within the first region_bytes of memory after base_ptr. Returns None if addr is out of this range. Unlike as_ptr() this does not rely on the caller to ensure addr is valid.
+    pub(crate) unsafe fn try_as_ptr(self, base_ptr: *mut u8, region_bytes: u32) -> Option<*mut u8> {
+        if self.addr >= region_bytes {
+            return None;
+        }
+
+        Some(self.as_ptr(base_ptr))
     }
 
     /// Returns the .addr field as a usize.
     /// # Panics
     /// usize::try_from should always work since this program only supports 32-bit addresses (aka usize will be 32 bits) and addr is u32 (32 bits).
     fn addr_usize(self) -> usize {
-        usize::try_from(self.addr).unwrap()
+        cfg_if! {
+            if #[cfg(feature = "no-panic")] {
+                self.addr as usize
+            } else {
+                usize::try_from(self.addr).unwrap()
+            }
+        }
     }
 }
 
@@ -603,12 +1466,8 @@ impl MiniPageMeta {
     
     /// Determines MiniPageMeta information from an AllocAddr.
     fn from_addr(addr: AllocAddr) -> MiniPageMeta {
-        // # Panics
-        // Shouldn't panic because:
-        // - program only supported with 32-bit addresses (so usize will be 32 bits) so 32 bit data will fit.
-        // - dividing two f64's which are only holding u32 values => cast back to 32 bit data shouldn't overflow
-        // - division result is floored so result should be an integer (this also forces any address within the page to map to the correct page)
-        let page_idx: usize = usize::try_from((addr.addr_f64() / f64::from(MINI_PAGE_ALLOC_BYTES)).floor() as u32).unwrap();
+        // Integer division rounds down, so any address within the page maps to the page. MINI_PAGE_ALLOC_BYTES is a power of two so this compiles to a shift
+        let page_idx: usize = (addr.addr / MINI_PAGE_ALLOC_BYTES) as usize;
 
         // Determine the segment within the page
         let page_addr: usize = (MINI_PAGE_ALLOC_BYTES as usize) * page_idx;
@@ -619,15 +1478,9 @@ impl MiniPageMeta {
         }
     }
 
-    /// Returns the page_idx as a u32.
-    fn page_idx_u32(self) -> u32 {
-        self.page_idx as u32
-    }
-
     /// Returns a MiniPageSegment refering to segment_idx of size_class.
     fn get_segment(self, size_class: SizeClass, segment_idx: usize) -> MiniPageSegment {
-        MiniPageSegment::from_addr(size_class, AllocAddr::from_usize(self.addr.addr_usize() + (usize::from(size_class.segment_bytes()) * segment_idx)
-        ))
+        MiniPageSegment::new(self, size_class, segment_idx)
     }
 }
 
@@ -643,39 +1496,27 @@ struct MiniPageSegment {
     /// The segment index within a MiniPage
     segment_idx: usize,
 
-    /// The byte within a bit-map in which the bit for this MiniPage Segment is located.
+    /// The byte within a bit-map in which the bit for this MiniPage Segment is located. segment_idx / 8, see bitmap_position().
     bitmap_byte_idx: usize,
 
-    /// The bit within the byte refered to by bitmap_byte_idx which refers to this MiniPageSegment. Range [0, 7].
+    /// The bit within the byte refered to by bitmap_byte_idx which refers to this MiniPageSegment. Range [0, 7]. segment_idx % 8, see bitmap_position().
     bitmap_byte_bit_idx: usize,
 }
 
 impl MiniPageSegment {
-    /// Creates a MiniPageSegment from an AllocAddr.
-    fn from_addr(size_class: SizeClass, addr: AllocAddr) -> MiniPageSegment {
-        // Determine the MiniPage
-        let page = MiniPageMeta::from_addr(addr);
-
+    /// Creates a MiniPageSegment from an AllocAddr inside page, a MiniPage of size_class.
+    fn from_addr(page: MiniPageMeta, size_class: SizeClass, addr: AllocAddr) -> MiniPageSegment {
         let segment_relative_addr: usize = addr.addr_usize() - page.addr.addr_usize();
-        // # Panics
-        // Shouldn't panic because:
-        // - program only supports 32 bit addresses => usize will be 32 bits
-        // - floor() called on result => result number will be integer
-        // - converted f64s represent 32 bit data => division of the two should be 32 bits if integer
-        let segment_idx_u32: u32 = ((f64::from(segment_relative_addr as u32) / f64::from(size_class.segment_bytes() as u32)).floor()) as u32;
-        // # Panics
-        // Shouldn't panic because:
-        // - program only supports 32 bit addresses => usize will be 32 bits
-        // - cast from 32 bit unsigned integer to usize should not fail => usize::try_from always = Result not Err.
-        let segment_idx: usize = usize::try_from(segment_idx_u32).unwrap();
+        // Rounds down to the segment the address is in. segment_bytes() is a power of two so this compiles to a shift
+        let segment_idx: usize = segment_relative_addr / usize::from(size_class.segment_bytes());
 
-        // Determine the bitmap byte index
-        // # Panics
-        // Shouldn't panic because:
-        // - program only supports 32 bit addresses => usize will be 32 bits
-        // - dividing a u32 => usize cast to u32 shouldn't fail
-        let bitmap_byte_idx: usize = usize::try_from((f64::from(segment_idx_u32) / 8.0).ceil() as u32).unwrap();
-        let bitmap_byte_bit_idx: usize = segment_idx % 8;
+        MiniPageSegment::new(page, size_class, segment_idx)
+    }
+
+    /// Creates the MiniPageSegment for segment_idx of page, a MiniPage of size_class.
+    fn new(page: MiniPageMeta, size_class: SizeClass, segment_idx: usize) -> MiniPageSegment {
+        // Determine the bitmap byte and bit index
+        let (bitmap_byte_idx, bitmap_byte_bit_idx) = bitmap_position(segment_idx);
         
         MiniPageSegment{
             page: page,
@@ -704,6 +1545,8 @@ impl MiniPageSegment {
 }
 
 /// Big allocations (gt MAX_SIZE_CLASS) are allocated to the nearest aligned free heap. This header is placed before allocated memory segment. Holds metadata about the allocation.
+/// With the cache-align feature the header takes up a whole cache line so the allocated memory after it is cache line aligned.
+#[cfg_attr(feature = "cache-align", repr(align(64)))]
 struct BigAllocHeader {
     /// Size class for this allocation.
     size_class_exp: u8,
@@ -716,54 +1559,129 @@ struct BigAllocHeader {
 
     /// The size of the allocated segment of memory directly after this header. In bytes.
     size_bytes: u32,
+
+    /// True if the allocated segment after this header is known to only contain zeros.
+    #[cfg(feature = "zero-tracking")]
+    known_zero: bool,
 }
 
+/// Size of a BigAllocHeader in bytes. Every big allocation's size_bytes is preceded by this many bytes of header.
+const BIG_ALLOC_HEADER_SIZE_U32: u32 = size_of::<BigAllocHeader>() as u32;
+
+// The header and allocated bytes of a big allocation must fill its MiniPage intervals exactly
+const _: () = {
+    let (size_bytes, interval) = BigAllocHeader::compute_size(1);
+    assert!(interval == 1);
+    assert!(size_bytes + BIG_ALLOC_HEADER_SIZE_U32 == interval * MINI_PAGE_TOTAL_BYTES);
+
+    let (size_bytes, interval) = BigAllocHeader::compute_size(MINI_PAGE_TOTAL_BYTES as usize);
+    assert!(interval == 2);
+    assert!(size_bytes + BIG_ALLOC_HEADER_SIZE_U32 == interval * MINI_PAGE_TOTAL_BYTES);
+};
+
 impl BigAllocHeader {
-    /// Determine the size_bytes field value which must be used in order to fullfill an allocation request for alloc_bytes. Returns (size_bytes, interval). The returned number of bytes will make sure that the big allocation's total size (header + allocated segment) is some interval of MINI_PAGE_ALLOC_BYTES. This returned bytes value should be used as the size_bytes field in a BigAllocHeader. The returned interval will indicate the total number of bytes the big allocation will take up, the units will be intervals of MINI_PAGE_ALLOC_BYTES.
-    fn compute_size(alloc_bytes: usize) -> (u32, u32) {
+    /// Determine the size_bytes field value which must be used in order to fullfill an allocation request for alloc_bytes. Returns (size_bytes, interval). The returned number of bytes will make sure that the big allocation's total size (header + allocated segment) is some interval of MINI_PAGE_TOTAL_BYTES. This returned bytes value should be used as the size_bytes field in a BigAllocHeader. The returned interval will indicate the total number of bytes the big allocation will take up, the units will be intervals of MINI_PAGE_TOTAL_BYTES.
+    const fn compute_size(alloc_bytes: usize) -> (u32, u32) {
         // Find the minimum amount of space required for the allocation. This includes the BigAllocHeader.
-        // # Panics
-        // Shouldn't panic because:
-        // - program only works with 32 bit addresses => usize is 32 bits
-        // - usize is 32 bits => cast to u32 shouldn't fail
-        let min_bytes = (size_of::<BigAllocHeader>() + alloc_bytes) as u32;
+        // Program only works with 32 bit addresses => usize is 32 bits => cast to u32 doesn't truncate
+        let min_bytes = BIG_ALLOC_HEADER_SIZE_U32 + (alloc_bytes as u32);
 
-        // Determine the closest interval of MINI_PAGE_TOTAL_BYTES to required_bytes.
-        // # Panics
-        // Shouldn't panic because:
-        // - Program only works with 32 bit addresses => usize is 32 bits
-        // - f64 from 32 bit address should not panic
-        // - division and ceiling equation only operates on 32 bit input values => output value should be 32 bits
-        let interval_mult = (f64::try_from(min_bytes).unwrap() / (MINI_PAGE_ALLOC_BYTES as f64)).ceil() as u32;
+        // Determine the closest interval of MINI_PAGE_TOTAL_BYTES to required_bytes, rounding up
+        let interval_mult = (min_bytes + MINI_PAGE_TOTAL_BYTES - 1) / MINI_PAGE_TOTAL_BYTES;
 
-        let required_bytes = interval_mult * (MINI_PAGE_ALLOC_BYTES as u32);
+        let required_bytes = interval_mult * MINI_PAGE_TOTAL_BYTES;
         
-        let size_bytes = required_bytes - (size_of::<BigAllocHeader>() as u32);
+        let size_bytes = required_bytes - BIG_ALLOC_HEADER_SIZE_U32;
 
         return (size_bytes, interval_mult);
     }
+
+    /// Returns true if a big allocation of alloc_bytes must start on a host page boundary and take up a whole number of host pages. Only with the big-page-align feature, for allocations of at least BIG_PAGE_ALIGN_MIN_BYTES.
+    fn page_aligned(alloc_bytes: usize) -> bool {
+        cfg!(feature = "big-page-align") && alloc_bytes >= BIG_PAGE_ALIGN_MIN_BYTES
+    }
+
+    /// Same as compute_size() but if page_aligned(alloc_bytes) the interval is rounded up so the big allocation takes up a whole number of host pages. The extra bytes are added to size_bytes.
+    fn compute_footprint(alloc_bytes: usize) -> (u32, u32) {
+        let (size_bytes, interval) = BigAllocHeader::compute_size(alloc_bytes);
+        if !BigAllocHeader::page_aligned(alloc_bytes) {
+            return (size_bytes, interval);
+        }
+
+        let page_interval = ((interval + MINI_PAGES_PER_WASM_PAGE - 1) / MINI_PAGES_PER_WASM_PAGE) * MINI_PAGES_PER_WASM_PAGE;
+
+        (size_bytes + ((page_interval - interval) * MINI_PAGE_TOTAL_BYTES), page_interval)
+    }
 }
 
 impl AllocatorImpl<HeapType> {
     /// Initialized allocator structure with a WASMHostHeap.
-    pub const INIT: AllocatorImpl<HeapType> = AllocatorImpl{
-        did_init_heap: false,
-        heap: UnsafeCell::new(heap::INIT),
-        
-        minipage_lists: [null_mut(); NUM_SIZE_CLASSES_USIZE],
-        big_alloc_head: None,
-        meta_page: None,
-	   
-        alloc_start_ptr: None,            
-        next_alloc_ptr: None,
-
-        total_alloc_reused: [0; NUM_SIZE_CLASSES_USIZE],
-        total_alloc_fresh: [0; NUM_SIZE_CLASSES_USIZE],
-        fresh_minipages: [null_mut(); NUM_SIZE_CLASSES_USIZE],
-
-        #[cfg(feature = "metrics")]
-        failure: None,
-    };
+    pub const INIT: AllocatorImpl<HeapType> = AllocatorImpl::new(heap::INIT, MAX_HOST_PAGES);
+}
+
+impl<H> AllocatorImpl<H> where H: HostHeap {
+    /// Allocator which uses heap, and grows it by at most max_pages. See AlligatorAlloc::with_max_pages().
+    const fn new(heap: H, max_pages: usize) -> AllocatorImpl<H> {
+        assert!(max_pages > 0 && max_pages <= MAX_HOST_PAGES, "max_pages must be between 1 and MAX_HOST_PAGES");
+
+        AllocatorImpl{
+            did_init_heap: false,
+            max_pages: max_pages,
+            heap_pages: 0,
+            host_start_page: 0,
+            heap: UnsafeCell::new(heap),
+            
+            minipage_lists: [null_mut(); NUM_SIZE_CLASSES_USIZE],
+            big_alloc_head: None,
+            big_alloc_tail: None,
+            meta_page: None,
+
+            alloc_start_ptr: None,            
+            next_alloc_ptr: None,
+
+            total_alloc_reused: [0; NUM_SIZE_CLASSES_USIZE],
+            total_alloc_fresh: [0; NUM_SIZE_CLASSES_USIZE],
+            fresh_minipages: [null_mut(); NUM_SIZE_CLASSES_USIZE],
+            fresh_reused_ratio: FRESH_REUSED_RATIO,
+            big_alloc_policy: BIG_ALLOC_POLICY,
+            small_only: false,
+
+            #[cfg(feature = "metrics")]
+            failure: None,
+
+            #[cfg(feature = "metrics")]
+            failure_counts: [0; NUM_ALLOC_FAILS],
+
+            #[cfg(feature = "zero-tracking")]
+            zeroed_start_ptr: null_mut(),
+
+            #[cfg(feature = "emergency-reserve")]
+            reserve_released: false,
+
+            #[cfg(feature = "chrome-trace")]
+            chrome_trace: ChromeTrace::INIT,
+        }
+    }
+
+    /// Allocator which aims for fresh_reused_ratio fresh MiniPage allocations per reused MiniPage allocation instead of FRESH_REUSED_RATIO. See AlligatorAlloc::with_fresh_reused_ratio().
+    const fn with_fresh_reused_ratio(mut self, fresh_reused_ratio: f64) -> AllocatorImpl<H> {
+        assert!(fresh_reused_ratio >= 0_f64, "fresh_reused_ratio must be a number which is 0 or more");
+
+        self.fresh_reused_ratio = fresh_reused_ratio;
+        self
+    }
+
+    /// Allocator which picks free big allocations to reuse with big_alloc_policy instead of BIG_ALLOC_POLICY. See AlligatorAlloc::with_big_alloc_policy().
+    const fn with_big_alloc_policy(mut self, big_alloc_policy: BigAllocPolicy) -> AllocatorImpl<H> {
+        self.big_alloc_policy = big_alloc_policy;
+        self
+    }
+
+    /// Allocator which never makes big allocations. See AlligatorAlloc::small_only().
+    const fn small_only(mut self) -> AllocatorImpl<H> {
+        self.small_only = true;
+        self
+    }
 }
 
 impl<H> AllocatorImpl<H> where H: HostHeap {
@@ -779,24 +1697,28 @@ impl<H> AllocatorImpl<H> where H: HostHeap {
 	   // Check if the allocator has grabbed its pages
         // from the host yet.
         if !self.did_init_heap {
-            // If the pages haven't been grabbed yet
-            // Determine delta pages we need to grow by
-            let current_pages = (*self.heap.get()).memory_size();
-            let delta_pages = MAX_HOST_PAGES - current_pages;
+            // Only grow the pages the MetaPage needs for now, plus room to align the first MiniPage. ensure_room() grows the rest as allocations need them, so hosts which cannot grant all max_pages still work.
+            let meta_page_pages = META_PAGE_HOST_PAGES;
+            cfg_if! {
+                if #[cfg(feature = "no-panic")] {
+                    // max_pages is set by the program, not the allocator's state, so this is an allocation failure instead of an invariant
+                    if meta_page_pages > self.max_pages {
+                        cfg_if! {
+                            if #[cfg(feature = "strict")] {
+                                panic!("{:?}: MetaPage needs {} host pages, the allocator may only use {}", AllocFail::HostGrowFail, meta_page_pages, self.max_pages);
+                            } else if #[cfg(feature = "metrics")] {
+                                self.record_failure(AllocFail::HostGrowFail);
+                            }
+                        }
 
-            assert!(delta_pages > 0, "Shouldn't be requesting to grow the memory by a negative number");
-            
-            // Request the memory is grown via the host. grow_res will be the number of pages before the grow, and thus the start of our new allocated pages, or usize::MAX if error.  
-            let grow_res = (*self.heap.get()).memory_grow(delta_pages);
-            if grow_res == usize::MAX {
-                // Failed to allocate the memory we need
-                cfg_if! {
-                    if #[cfg(feature = "metrics")] {
-                        self.failure = Some(AllocFail::HostGrowFail);
+                        return Err(());
                     }
+                } else {
+                    assert!(meta_page_pages <= self.max_pages, "MetaPage does not fit in the {} host pages the allocator may use", self.max_pages);
                 }
-                
-                // from the host
+            }
+
+            if !self.grow_heap(meta_page_pages) {
                 return Err(());
             }
 
@@ -808,14 +1730,33 @@ impl<H> AllocatorImpl<H> where H: HostHeap {
 
 	   // Allocate meta page
         match self.meta_page {
-            Some(p) => Ok((base_ptr, p, self.alloc_start_ptr.unwrap(), self.next_alloc_ptr.unwrap())),
+            Some(p) => {
+                assert_or_return!(self.alloc_start_ptr.is_some() && self.next_alloc_ptr.is_some(), Err(()), "alloc_start_ptr and next_alloc_ptr should be set along with the MetaPage");
+
+                // Both checked above
+                Ok((base_ptr, p, self.alloc_start_ptr.unwrap_or(null_mut()), self.next_alloc_ptr.unwrap_or(null_mut())))
+            },
             None => {			 
                 // Initialize meta page
                 let (meta_page_ptr, next_ptr) = MetaPage::alloc(base_ptr);
+
+                // Start MiniPages on a MINI_PAGE_ALLOC_BYTES boundary so segments are aligned to their size
+                let alloc_start_ptr = next_ptr.offset(next_ptr.align_offset(MINI_PAGE_ALLOC_BYTES as usize) as isize);
+                assert_or_return!(AllocAddr::from_ptr(base_ptr, alloc_start_ptr).addr_usize() <= self.heap_pages * (heap::PAGE_BYTES as usize), Err(()), "MetaPage does not fit in the {} host pages the allocator may use", self.heap_pages);
+
                 self.meta_page = Some(meta_page_ptr);
 
-                self.alloc_start_ptr = Some(next_ptr);
-			 self.next_alloc_ptr = Some(next_ptr);
+                self.alloc_start_ptr = Some(alloc_start_ptr);
+			 self.next_alloc_ptr = Some(alloc_start_ptr);
+
+                cfg_if! {
+                    if #[cfg(feature = "zero-tracking")] {
+                        // Memory handed out before a release() is no longer zero
+                        if self.zeroed_start_ptr < alloc_start_ptr {
+                            self.zeroed_start_ptr = alloc_start_ptr;
+                        }
+                    }
+                }
 
                 cfg_if! {
                     if #[cfg(feature = "metrics")] {
@@ -827,7 +1768,7 @@ impl<H> AllocatorImpl<H> where H: HostHeap {
                     }
                 }
                 
-                Ok((base_ptr, meta_page_ptr, next_ptr, next_ptr))
+                Ok((base_ptr, meta_page_ptr, alloc_start_ptr, alloc_start_ptr))
             },
         }
     }
@@ -842,12 +1783,11 @@ impl<H> AllocatorImpl<H> where H: HostHeap {
 		  Err(_) => return None,
 	   };
 
-        let mut search_byte_i = 0;
         let mut first_free_found: Option<u16> = None;
 
         for search_bit_i in 0..size_class.segments_max_num() {
             // Check if the bit corresponding to segment search_bit_i is marked as free
-            let within_byte_bit_i = search_bit_i % 8;
+            let (search_byte_i, within_byte_bit_i) = bitmap_position(usize::from(search_bit_i));
 
             let search_byte = (*minipage).free_segments[search_byte_i];
             let search_mask = 1 << within_byte_bit_i;
@@ -866,7 +1806,15 @@ impl<H> AllocatorImpl<H> where H: HostHeap {
                     first_free_found = Some(search_bit_i);
                 }
                 
-                (*(*meta_page).free_segments[size_class.exp_as_idx()]).push(search_bit_i);
+                if (*(*meta_page).free_segments[size_class.exp_as_idx()]).push(search_bit_i).is_none() {
+                    cfg_if! {
+                        if #[cfg(feature = "strict")] {
+                            panic!("{:?}: free segments stack of size class {} is full, could not push segment {}", AllocFail::StackOverflow, size_class.exp, search_bit_i);
+                        } else if #[cfg(feature = "metrics")] {
+                            self.record_failure(AllocFail::StackOverflow);
+                        }
+                    }
+                }
 
                 cfg_if! {
                     if #[cfg(feature = "metrics")] {
@@ -875,40 +1823,279 @@ impl<H> AllocatorImpl<H> where H: HostHeap {
                     }
                 }
             }
-            
-            // Check if last bit of the search byte, and need to retrieve next search byte from MiniPage's bitmap to look at in the next iteration
-            if within_byte_bit_i == 7 {
-                search_byte_i += 1;
-            }
         }
 
         first_free_found
     }
 
+    /// Records fail as the most recent failure cause and counts it.
+    #[cfg(feature = "metrics")]
+    fn record_failure(&mut self, fail: AllocFail) {
+        self.failure = Some(fail);
+        self.failure_counts[fail as usize] += 1;
+    }
+
+    /// Updates the fragmentation and used memory metrics for ptr, an allocation of requested_bytes. Call right after ptr is allocated with allocated = true, and right before it is freed with allocated = false. Does nothing if ptr is not a live allocation.
+    #[cfg(feature = "metrics")]
+    unsafe fn record_usage(&mut self, ptr: *mut u8, requested_bytes: usize, allocated: bool) {
+        let (meta_page, alloc_start_ptr) = match (self.meta_page, self.alloc_start_ptr) {
+            (Some(meta_page), Some(alloc_start_ptr)) => (meta_page, alloc_start_ptr),
+            _ => return,
+        };
+        let metrics = &mut *(*meta_page).metrics;
+
+        let usable_bytes = self.usable_size(ptr) as u32;
+        metrics.current_used_bytes = match allocated {
+            true => metrics.current_used_bytes + usable_bytes,
+            false => metrics.current_used_bytes - usable_bytes,
+        };
+        metrics.peak_used_bytes = metrics.peak_used_bytes.max(metrics.current_used_bytes);
+
+        let internal_bytes = match self.allocation_kind(ptr) {
+            Some(AllocKind::Small{ size_class_exp }) => {
+                // Free bytes of the MiniPage when used_segments segments are live, only counted if at least one is
+                let size_class = SizeClass::new(size_class_exp);
+                let page_free_bytes = |used_segments: u16| -> isize {
+                    match used_segments {
+                        0 => 0,
+                        _ => (size_class.segments_max_num() - used_segments) as isize * size_class.segment_bytes() as isize,
+                    }
+                };
+
+                // ptr is live so the MiniPage has at least one used segment, the difference is the change from ptr being allocated
+                let page_idx = (*meta_page).minipage_start(AllocAddr::from_ptr(alloc_start_ptr, ptr).get_page_meta().page_idx);
+                if let Some(header) = &(*meta_page).minipage_headers[page_idx] {
+                    let used_segments = header.used_segments_count();
+                    let external_delta = page_free_bytes(used_segments) - page_free_bytes(used_segments - 1);
+
+                    metrics.external_frag_bytes = match allocated {
+                        true => (metrics.external_frag_bytes as isize + external_delta) as usize,
+                        false => (metrics.external_frag_bytes as isize - external_delta) as usize,
+                    };
+
+                    // ptr is the only live segment, the MiniPage just became live or is about to stop being live
+                    if used_segments == 1 {
+                        metrics.current_minipages = match allocated {
+                            true => metrics.current_minipages + 1,
+                            false => metrics.current_minipages - 1,
+                        };
+                        metrics.peak_minipages = metrics.peak_minipages.max(metrics.current_minipages);
+                    }
+                }
+
+                cfg_if! {
+                    if #[cfg(feature = "requested-size")] {
+                        // Exact even if dealloc was not passed the Layout the allocation was made with
+                        let requested_bytes = self.requested_size(ptr).unwrap_or(requested_bytes);
+                    }
+                }
+
+                usize::from(size_class.segment_bytes()).saturating_sub(requested_bytes)
+            },
+            Some(AllocKind::Big{ size_bytes }) => (size_bytes as usize).saturating_sub(requested_bytes),
+            None => return,
+        };
+
+        metrics.internal_frag_bytes = match allocated {
+            true => metrics.internal_frag_bytes + internal_bytes,
+            false => metrics.internal_frag_bytes.saturating_sub(internal_bytes),
+        };
+    }
+
+    /// Copies allocator state into the MetaPage's AllocDebugHeader. live_bytes_delta is added to its live_bytes. Does nothing if the MetaPage has not been allocated.
+    #[cfg(feature = "debug-layout")]
+    unsafe fn sync_debug_header(&mut self, live_bytes_delta: isize) {
+        let meta_page = match self.meta_page {
+            Some(meta_page) => meta_page,
+            None => return,
+        };
+        let base_ptr = (*self.heap.get()).base_ptr();
+        let header = &mut (*meta_page).debug_header;
+
+        header.live_bytes = (header.live_bytes as isize + live_bytes_delta) as u32;
+        header.total_minipages = (*(*meta_page).metrics).total_minipages;
+        if let Some(next_alloc_ptr) = self.next_alloc_ptr {
+            header.next_alloc_offset = (next_alloc_ptr as usize - base_ptr as usize) as u32;
+        }
+        header.last_failure_code = match self.failure {
+            Some(fail) => fail as u32 + 1,
+            None => 0,
+        };
+    }
+
+    /// Records an alloc or dealloc call in the MetaPage's trace buffer. Does nothing if the MetaPage has not been allocated.
+    #[cfg(feature = "trace")]
+    unsafe fn record_trace(&mut self, kind: TraceEventKind, ptr: *mut u8, size: usize) {
+        if let Some(meta_page) = self.meta_page {
+            (*(*meta_page).trace).record(kind, ptr, size);
+        }
+    }
+
+    /// Returns the address, relative to the host heap base, at or before which every MiniPage and big allocation must end. This is the end of the host pages the allocator was granted. With the emergency-reserve feature this excludes the reserve until it is released.
+    fn max_allowed_addr(&self) -> AllocAddr {
+        let heap_end_addr = (self.heap_pages as u32) * heap::PAGE_BYTES;
+
+        cfg_if! {
+            if #[cfg(feature = "emergency-reserve")] {
+                if !self.reserve_released {
+                    return AllocAddr::new(heap_end_addr - EMERGENCY_RESERVE_BYTES);
+                }
+            }
+        }
+
+        AllocAddr::new(heap_end_addr)
+    }
+
+    /// Returns true if memory which ends at end_addr, relative to the host heap base, is at or before max_allowed_addr(). If it is not, grows the host heap to make room, as long as it stays within max_pages.
+    unsafe fn ensure_room(&mut self, end_addr: u32) -> bool {
+        let max_allowed_addr = self.max_allowed_addr();
+        if end_addr <= max_allowed_addr.addr {
+            return true;
+        }
+
+        // Bytes max_allowed_addr() keeps back from the end of the heap must still be kept back after growing
+        let held_back_bytes = (self.heap_pages as u32) * heap::PAGE_BYTES - max_allowed_addr.addr;
+        let needed_pages = ((end_addr as usize) + (held_back_bytes as usize) + (heap::PAGE_BYTES as usize) - 1) / (heap::PAGE_BYTES as usize);
+        if needed_pages > self.max_pages {
+            return false;
+        }
+
+        self.grow_heap(needed_pages)
+    }
+
+    /// Grows the host heap until the allocator may use its first min_pages pages. Asks for HEAP_GROW_PAGES more than needed, as far as max_pages allows, and only for the pages needed if the host refuses that. A host may grow by fewer pages than asked for, those pages are kept and the rest asked for again.
+    /// Returns false if the host stopped growing before min_pages.
+    unsafe fn grow_heap(&mut self, min_pages: usize) -> bool {
+        while self.heap_pages < min_pages {
+            let needed_pages = min_pages - self.heap_pages;
+            let chunk_pages = (min_pages + HEAP_GROW_PAGES).min(self.max_pages) - self.heap_pages;
+
+            let mut granted_pages = self.host_grow(chunk_pages);
+            if granted_pages == 0 && chunk_pages > needed_pages {
+                granted_pages = self.host_grow(needed_pages);
+            }
+
+            if granted_pages == 0 {
+                cfg_if! {
+                    if #[cfg(feature = "strict")] {
+                        panic!("{:?}: failed to grow host heap from {} to {} pages", AllocFail::HostGrowFail, self.heap_pages, min_pages);
+                    } else if #[cfg(feature = "metrics")] {
+                        self.record_failure(AllocFail::HostGrowFail);
+                    }
+                }
+
+                return false;
+            }
+        }
+
+        true
+    }
+
+    /// Asks the host to grow the heap by delta_pages. Returns the number of pages it actually grew by, which may be fewer than delta_pages, and adds them to heap_pages. Returns 0 if the grow failed, or if the new pages do not start at the end of the heap because something else grew the host memory since the allocator last did.
+    unsafe fn host_grow(&mut self, delta_pages: usize) -> usize {
+        let host_heap = &mut *self.heap.get();
+
+        // The number of host pages before the grow, and thus the start of the new pages, or usize::MAX if error
+        let old_host_pages = host_heap.memory_grow(delta_pages);
+        if old_host_pages == usize::MAX {
+            return 0;
+        }
+
+        // The heap starts wherever the first grow put it
+        if self.heap_pages == 0 && !self.did_init_heap {
+            self.host_start_page = old_host_pages;
+        }
+        if old_host_pages != self.host_start_page + self.heap_pages {
+            return 0;
+        }
+
+        let granted_pages = host_heap.memory_size().saturating_sub(old_host_pages).min(delta_pages);
+        self.heap_pages += granted_pages;
+
+        granted_pages
+    }
+
+    /// Gives the host pages after the last page in use back to the host. See AlligatorAlloc::release_unused_pages().
+    unsafe fn release_unused_pages(&mut self) -> usize {
+        // Nothing at or after next_alloc_ptr is in use, keep every page up to and including the one it is in. Without a MetaPage, as after a release(), no page is in use
+        let keep_pages = match (self.meta_page, self.next_alloc_ptr) {
+            (Some(_), Some(next_alloc_ptr)) => {
+                let base_ptr = (*self.heap.get()).base_ptr();
+                let used_bytes = AllocAddr::from_ptr(base_ptr, next_alloc_ptr).addr_usize();
+                (used_bytes + (heap::PAGE_BYTES as usize) - 1) / (heap::PAGE_BYTES as usize)
+            },
+            _ => 0,
+        };
+        if keep_pages >= self.heap_pages {
+            return 0;
+        }
+
+        let delta_pages = self.heap_pages - keep_pages;
+        if (*self.heap.get()).memory_shrink(delta_pages).is_err() {
+            return 0;
+        }
+
+        self.heap_pages = keep_pages;
+
+        delta_pages
+    }
+
     /// Setup a new MiniPageHead. Updates the next_alloc_ptr, the minipage_lists head, MetaPage.free_minipages, and fresh_minipages for the size class. Always adds the new MiniPageHead to the head of minipage_lists.
+    /// Takes a MiniPage from MetaPage.reclaimed_minipages if there is one, only otherwise is new space taken from the heap at next_alloc_ptr.
     /// Returns Option with the created MiniPage header if there was free space in the heap. Along with the index of the page.
     /// Returns None if there is no space in the heap. This is fatal.
     unsafe fn add_minipage(&mut self, size_class_exp: u8) -> Option<(*mut MiniPageHeader, usize)> {
         let size_class = SizeClass::new(size_class_exp);
 
         let (base_ptr, meta_page, alloc_start_ptr, next_alloc_ptr) = match self.ensure_heap() {
-		  Ok(v) => v,
-		  Err(_) => return None,
-	   };
+            Ok(v) => v,
+            Err(_) => return None,
+        };
 
-        cfg_if! {
-            if #[cfg(feature = "metrics")] {
-                (*(*meta_page).metrics).total_minipages += 1;
-            }
-        }
-        
-        // Check there is room on the heap
-        let max_allowed_addr = AllocAddr::new((MAX_HOST_PAGES as u32) * heap::PAGE_BYTES);
-	   let after_alloc_addr = AllocAddr::from_ptr(base_ptr, next_alloc_ptr.offset(MINI_PAGE_ALLOC_BYTES as isize));
-        if after_alloc_addr.addr >= max_allowed_addr.addr {
-            // Out of space on the host heap
-            return None;
-        }
+        // MiniPages which span several intervals skip ahead to start on a multiple of their segment size, so every segment is aligned to its size. The skipped intervals are left unused, like those skipped by page aligned big allocations
+        let next_alloc_ptr = match size_class.minipage_intervals() {
+            1 => next_alloc_ptr,
+            _ => next_alloc_ptr.offset(next_alloc_ptr.align_offset(usize::from(size_class.segment_bytes())) as isize),
+        };
+
+        // Reuse a reclaimed MiniPage before taking new space from the heap. Reclaimed MiniPages are one interval, too small for size classes whose MiniPages span several
+        let reclaimed_idx = match size_class.minipage_intervals() {
+            1 => (*(*meta_page).reclaimed_minipages).pop().map(expand_page_idx),
+            _ => None,
+        };
+
+        let page_meta = match reclaimed_idx {
+            Some(page_idx) => {
+                cfg_if! {
+                    if #[cfg(feature = "metrics")] {
+                        // For popping the reclaimed_minipages UnsafeStack on the heap
+                        (*(*meta_page).reclaimed_minipages).record_pop_cost(meta_page);
+                    }
+                }
+
+                // reclaim_minipage() unlinked it from its old size class, so none of the old size class's MiniPages end up in this size class's minipage_lists chain
+                debug_assert!((*meta_page).minipage_headers[page_idx].as_ref().map_or(true, |header| header.next.is_none() && !header.on_free_minipages_stack), "reclaimed MiniPage {} is still linked to its old size class", page_idx);
+
+                MiniPageMeta::new(page_idx)
+            },
+            None => {
+                // Check there is room on the heap
+                let after_alloc_addr = AllocAddr::from_ptr(base_ptr, next_alloc_ptr.offset(size_class.minipage_bytes() as isize));
+                if !self.ensure_room(after_alloc_addr.addr) {
+                    // Out of space on the host heap, a MiniPage which ends exactly at the end of the heap still fits
+                    return None;
+                }
+
+                cfg_if! {
+                    if #[cfg(feature = "metrics")] {
+                        (*(*meta_page).metrics).total_minipages += 1;
+                    }
+                }
+
+                // Page indexes are relative to alloc_start_ptr, like everywhere else the MetaPage tables are read
+                MiniPageMeta::from_addr(AllocAddr::from_ptr(alloc_start_ptr, next_alloc_ptr))
+            },
+        };
 
         // Determine what the next node will be
         let mut next: Option<*mut MiniPageHeader> = None;
@@ -916,17 +2103,26 @@ impl<H> AllocatorImpl<H> where H: HostHeap {
             next = Some(self.minipage_lists[size_class.exp_as_idx()]);
         }
           
-        // Create new node
-	   let page_addr = AllocAddr::from_ptr(base_ptr, next_alloc_ptr);
-	   let page_meta = MiniPageMeta::from_addr(page_addr);
+        // Create new node, a reclaimed MiniPage's old header is overwritten
         let node_idx_ptr = &mut (*meta_page).minipage_headers[page_meta.page_idx];
-	   *node_idx_ptr = Some(MiniPageHeader{
-		  next: next,
-		  size_class_exp: size_class_exp,
-		  free_segments: [255; MINI_PAGE_FREE_SEGMENTS_SIZE], // All 1 = all unallocated
-		  on_free_minipages_stack: true, // pushed later in this method
-	   });
-	   let node_ptr = &mut (node_idx_ptr.unwrap());
+        *node_idx_ptr = Some(MiniPageHeader{
+            next: next,
+            size_class_exp: size_class_exp,
+            free_segments: [255; MINI_PAGE_FREE_SEGMENTS_SIZE], // All 1 = all unallocated
+            on_free_minipages_stack: true, // pushed later in this method
+
+            #[cfg(feature = "zero-tracking")]
+            clean_segments: match reclaimed_idx.is_none() && next_alloc_ptr >= self.zeroed_start_ptr {
+                true => [255; MINI_PAGE_FREE_SEGMENTS_SIZE], // All 1 = all still zero
+                false => [0; MINI_PAGE_FREE_SEGMENTS_SIZE],
+            },
+
+            #[cfg(feature = "requested-size")]
+            requested_bytes: [0; MINI_PAGE_MAX_SEGMENTS],
+        });
+        // Point at the header in the MetaPage, not a copy, so writes through node_ptr are kept
+        let node_ptr: *mut MiniPageHeader = node_idx_ptr.as_mut().map_or(null_mut(), |header| header);
+        assert_or_return!(!node_ptr.is_null(), None, "MiniPageHeader for MiniPage {} should have just been stored in the MetaPage", page_meta.page_idx);
 
         cfg_if! {
             if #[cfg(feature = "metrics")] {
@@ -938,8 +2134,20 @@ impl<H> AllocatorImpl<H> where H: HostHeap {
         // Set size class's free list head to new node
         self.minipage_lists[size_class.exp_as_idx()] = node_ptr;
 
-        // Record this MiniPage as having free segments
-        (*(*meta_page).free_minipages[size_class.exp_as_idx()]).push(page_meta.page_idx);
+        // Record this MiniPage as having free segments. It must not already be on the stack, or it would be handed out twice once its segments run out
+        debug_assert!(!(*(*meta_page).free_minipages[size_class.exp_as_idx()]).contains(compact_page_idx(page_meta.page_idx)), "new MiniPage {} is already on the size class {} free minipages stack", page_meta.page_idx, size_class.exp);
+        if (*(*meta_page).free_minipages[size_class.exp_as_idx()]).push(compact_page_idx(page_meta.page_idx)).is_none() {
+            cfg_if! {
+                if #[cfg(feature = "strict")] {
+                    panic!("{:?}: free minipages stack of size class {} is full, could not push new MiniPage {}", AllocFail::StackOverflow, size_class.exp, page_meta.page_idx);
+                } else if #[cfg(feature = "metrics")] {
+                    self.record_failure(AllocFail::StackOverflow);
+                }
+            }
+        }
+
+        // It is now the head, the free segments left on the stack belong to the old head. They are still in the old head's bitmap
+        (*(*meta_page).free_segments[size_class.exp_as_idx()]).clear();
 
         cfg_if! {
             if #[cfg(feature = "metrics")] {
@@ -951,306 +2159,1596 @@ impl<H> AllocatorImpl<H> where H: HostHeap {
         // Set this as the current new fresh MiniPage
         self.fresh_minipages[size_class.exp_as_idx()] = node_ptr;
 
-        // Increment the next MiniPageHeader address
-	   self.next_alloc_ptr = Some(next_alloc_ptr.offset(MINI_PAGE_ALLOC_BYTES as isize));
+        // Increment the next MiniPageHeader address, if new space was used
+        if reclaimed_idx.is_none() {
+            self.next_alloc_ptr = Some(next_alloc_ptr.offset(size_class.minipage_bytes() as isize));
 
-        Some((node_ptr, page_meta.page_idx))
-    }
-
-    /// Allocate memory.
-    unsafe fn alloc(&mut self, layout: Layout) -> *mut u8 {        
-        // Don't allow 0 sized allocations
-        if layout.size() == 0 {
             cfg_if! {
-                if #[cfg(feature = "metrics")] {
-                    self.failure = Some(AllocFail::NoZeroAlloc);
+                if #[cfg(feature = "zero-tracking")] {
+                    self.raise_zeroed_start_ptr();
                 }
             }
-            
-            return null_mut();
         }
-       
-        // Get details about host heap
-        let (base_ptr, meta_page, alloc_start_ptr, next_alloc_ptr) = match self.ensure_heap() {
-		  Ok(v) => v,
-		  Err(_) => return null_mut(),
-	   };
 
-        // Determine size class of allocation
-        let size_class = SizeClass::new_from_bytes(layout.size() as u16);
+        Some((node_ptr, page_meta.page_idx))
+    }
 
-        // Check if size class not too small
-        if size_class.exp < MIN_SIZE_CLASS {
-            // Size class is too small for allocator. We check this because the SizeClass structure is supposed to smartly round up smaller size classes to the smallest size class. If it doesn't do this then all logic in the program will use smaller than allowed size classes.
-            cfg_if! {
-                if #[cfg(feature = "metrics")] {
-                    self.failure = Some(AllocFail::SizeClassTooSmall);
+    /// Moves the MiniPage at page_idx, which has no allocations left and is the head of its size class's free minipages stack, to MetaPage.reclaimed_minipages, and unlinks it from its size class's minipage_lists chain. add_minipage() can then give it to any size class.
+    unsafe fn reclaim_minipage(&mut self, meta_page: *mut MetaPage, page_idx: usize, header: *mut MiniPageHeader) {
+        let size_class = SizeClass::new((*header).size_class_exp);
+
+        (*(*meta_page).free_minipages[size_class.exp_as_idx()]).pop();
+        (*header).on_free_minipages_stack = false;
+
+        // The free segments stack only describes the head MiniPage. Once empty the new head rebuilds it from its bitmap
+        (*(*meta_page).free_segments[size_class.exp_as_idx()]).clear();
+
+        if self.fresh_minipages[size_class.exp_as_idx()] == header {
+            self.fresh_minipages[size_class.exp_as_idx()] = null_mut();
+        }
+
+        // Unlink from the size class's minipage_lists chain. Otherwise once the MiniPage is reused, maybe for another size class, its new next link would join this size class's chain onto the other one, or loop back on itself
+        if self.minipage_lists[size_class.exp_as_idx()] == header {
+            self.minipage_lists[size_class.exp_as_idx()] = (*header).next.unwrap_or(null_mut());
+        } else {
+            // Singly linked, search for the MiniPage before it. Only done once the MiniPage is empty, not on every allocation
+            let mut prev_ptr = self.minipage_lists[size_class.exp_as_idx()];
+            while !prev_ptr.is_null() {
+                cfg_if! {
+                    if #[cfg(feature = "metrics")] {
+                        // For reading the next field of a MiniPageHeader in the MetaPage
+                        (*(*meta_page).metrics).heap_bytes_read += size_of::<Option<*mut MiniPageHeader>>();
+                    }
                 }
+
+                if (*prev_ptr).next == Some(header) {
+                    (*prev_ptr).next = (*header).next;
+                    break;
+                }
+
+                prev_ptr = (*prev_ptr).next.unwrap_or(null_mut());
             }
-            
-            return null_mut();
         }
+        (*header).next = None;
 
-        // Check if not bigger than the largest MiniPage size class. If the case, we must use big alloc.
-        if size_class.exp > MAX_SIZE_CLASS {
-            // Record metrics
+        if (*(*meta_page).reclaimed_minipages).push(compact_page_idx(page_idx)).is_none() {
             cfg_if! {
-                if #[cfg(feature = "metrics")] {
-                    (*(*meta_page).metrics).total_allocs[NUM_SIZE_CLASSES_USIZE] += 1;
+                if #[cfg(feature = "strict")] {
+                    panic!("{:?}: reclaimed minipages stack is full, could not push MiniPage {}", AllocFail::StackOverflow, page_idx);
+                } else if #[cfg(feature = "metrics")] {
+                    self.record_failure(AllocFail::StackOverflow);
                 }
             }
-            
-            // Try and find a free big alloc segment, or allocate a new one
-            let mut search_ptr = self.big_alloc_head;
+        }
 
-            while let Some(big_head) = search_ptr {
-                // Check if free and fits
-                if (*big_head).free && (*big_head).size_class_exp >= size_class.exp {
-                    // Free and will fit
-                    // Now mark this as being used, as we will use it for this allocation
-                    cfg_if! {
-                        if #[cfg(feature = "metrics")] {
-                            (*(*meta_page).metrics).heap_bytes_write += size_of::<bool>();
-                        }
-                    }
-                    
-                    (*big_head).free = false; // false = allocated
+        cfg_if! {
+            if #[cfg(feature = "metrics")] {
+                // For popping the free_minipages UnsafeStack and pushing onto the reclaimed_minipages UnsafeStack on the heap
+                (*(*meta_page).free_minipages[size_class.exp_as_idx()]).record_pop_cost(meta_page);
+                (*(*meta_page).reclaimed_minipages).record_push_cost(meta_page);
 
-                    // Exit early so we use this pointer
-                    break;
-                }
-                
-                // Iterate
-                search_ptr = (*big_head).next;
+                (*(*meta_page).metrics).reclaimed_minipages += 1;
             }
+        }
+    }
 
-            // If no valid free big allocations are found
-            let big_ptr = match search_ptr {
-                Some(ptr) => ptr,
-                None => {
-                    // No free big alloc headers, must allocate one
-                    cfg_if! {
-                        if #[cfg(feature = "metrics")] {
-                            (*(*meta_page).metrics).heap_bytes_write += size_of::<BigAllocHeader>();
-                        }
-                    }
-
-				let page_meta = MiniPageMeta::from_addr(AllocAddr::from_ptr(alloc_start_ptr, next_alloc_ptr));
+    /// Returns the number of MiniPage sized intervals of the heap, starting at alloc_start_ptr, which have been handed out to MiniPages or big allocations. Returns 0 if the heap has not been initialized.
+    unsafe fn used_page_units(&self) -> usize {
+        match (self.alloc_start_ptr, self.next_alloc_ptr) {
+            (Some(alloc_start_ptr), Some(next_alloc_ptr)) => {
+                AllocAddr::from_ptr(alloc_start_ptr, next_alloc_ptr).get_page_meta().page_idx
+            },
+            _ => 0,
+        }
+    }
 
-				// Setup big alloc header
-                    let big_ptr = next_alloc_ptr as *mut BigAllocHeader;
-                    (*big_ptr).size_class_exp = size_class.exp;
-                    (*big_ptr).next = self.big_alloc_head;
-                    (*big_ptr).free = false; // allocated
+    /// Returns true if the MiniPage sized interval of the heap at page_idx contains at least one live allocation. Reads the big allocation flags and MiniPage free bitmaps, does not search any lists.
+    unsafe fn page_unit_is_live(&self, meta_page: *mut MetaPage, alloc_start_ptr: *mut u8, page_idx: usize) -> bool {
+        if let Some(big_alloc_flag) = (*meta_page).big_alloc_flags[page_idx] {
+            let big_ptr = MiniPageMeta::new(big_alloc_flag.start_idx()).addr.as_ptr(alloc_start_ptr) as *mut BigAllocHeader;
+            return !big_ptr.is_null() && !(*big_ptr).free;
+        }
 
-                    let (size_bytes, interval) = BigAllocHeader::compute_size(layout.size());
-                    (*big_ptr).size_bytes = size_bytes;
+        match &(*meta_page).minipage_headers[(*meta_page).minipage_start(page_idx)] {
+            Some(header) => header.used_segments_count() > 0,
+            None => false,
+        }
+    }
 
-				// Set big allocation flags
-				for page_i in page_meta.page_idx..=(page_meta.page_idx + (interval as usize)) {
-				    (*meta_page).big_alloc_flags[page_i] = Some(BigAllocFlag{
-					   start_idx: page_meta.page_idx,
-				    });
-				}
-                    
-                    self.big_alloc_head = Some(big_ptr);
+    /// Returns true if ptr is inside a MiniPage or big allocation made by this allocator. See AlligatorAlloc::owns().
+    unsafe fn owns(&self, ptr: *mut u8) -> bool {
+        let (meta_page, alloc_start_ptr, next_alloc_ptr) = match (self.meta_page, self.alloc_start_ptr, self.next_alloc_ptr) {
+            (Some(meta_page), Some(alloc_start_ptr), Some(next_alloc_ptr)) => (meta_page, alloc_start_ptr, next_alloc_ptr),
+            _ => return false,
+        };
 
-				self.next_alloc_ptr = Some(next_alloc_ptr.offset((interval * MINI_PAGE_ALLOC_BYTES) as isize));
+        // Also rules out the MetaPage, which is before alloc_start_ptr
+        if ptr < alloc_start_ptr || ptr >= next_alloc_ptr {
+            return false;
+        }
 
-                    big_ptr
-                },
-            };
+        let page_idx = (*meta_page).minipage_start(AllocAddr::from_ptr(alloc_start_ptr, ptr).get_page_meta().page_idx);
+        (*meta_page).big_alloc_flags[page_idx].is_some() || (*meta_page).minipage_headers[page_idx].is_some()
+    }
 
-            // Compute the allocated address
-            let alloc_addr = big_ptr.offset(1) as *mut u8;
+    /// Returns how the live allocation which ptr points to was allocated. Uses the same big allocation flag and MiniPage header lookups as dealloc. Returns None if ptr is not a live allocation from this allocator.
+    unsafe fn allocation_kind(&self, ptr: *mut u8) -> Option<AllocKind> {
+        let (meta_page, alloc_start_ptr, next_alloc_ptr) = match (self.meta_page, self.alloc_start_ptr, self.next_alloc_ptr) {
+            (Some(meta_page), Some(alloc_start_ptr), Some(next_alloc_ptr)) => (meta_page, alloc_start_ptr, next_alloc_ptr),
+            _ => return None,
+        };
 
-            // Big allocation complete!
-            return alloc_addr;
+        if ptr < alloc_start_ptr || ptr >= next_alloc_ptr {
+            return None;
         }
 
-        // If program reaches this line we are using MiniPage "small" allocation
-        // Record metrics
-        cfg_if! {
-            if #[cfg(feature = "metrics")] {
-                (*(*meta_page).metrics).total_allocs[size_class.exp_as_idx()] += 1;
-            }
+        let addr = AllocAddr::from_ptr(alloc_start_ptr, ptr);
+        let page_meta = addr.get_page_meta();
+
+        if let Some(big_alloc_flag) = (*meta_page).big_alloc_flags[page_meta.page_idx] {
+            let big_ptr = MiniPageMeta::new(big_alloc_flag.start_idx()).addr.as_ptr(alloc_start_ptr) as *mut BigAllocHeader;
+            return match big_ptr.is_null() || (*big_ptr).free {
+                true => None,
+                false => Some(AllocKind::Big{ size_bytes: (*big_ptr).size_bytes }),
+            };
         }
 
-        // Determine if we need to allocate from a fresh or reused MiniPage
-        let need_alloc_fresh = match self.total_alloc_reused[size_class.exp_as_idx()] > 0 {
-            true => {
-                let fresh_reused_ratio = f64::from(self.total_alloc_fresh[size_class.exp_as_idx()]) / f64::from(self.total_alloc_reused[size_class.exp_as_idx()]);
-                fresh_reused_ratio < FRESH_REUSED_RATIO
+        let page_meta = MiniPageMeta::new((*meta_page).minipage_start(page_meta.page_idx));
+        match &(*meta_page).minipage_headers[page_meta.page_idx] {
+            Some(header) => {
+                let size_class = SizeClass::new(header.size_class_exp);
+                match header.get_free_bitmap(addr.get_segment(page_meta, size_class)) {
+                    true => None,
+                    false => Some(AllocKind::Small{ size_class_exp: size_class.exp }),
+                }
             },
-            false => false,
+            None => None,
+        }
+    }
+
+    /// Returns the number of bytes which can be used in the allocation which ptr points to. This is the segment size for MiniPage allocations and BigAllocHeader.size_bytes for big allocations. Returns 0 if ptr is not a live allocation from this allocator.
+    unsafe fn usable_size(&self, ptr: *mut u8) -> usize {
+        match self.allocation_kind(ptr) {
+            Some(AllocKind::Small{ size_class_exp }) => usize::from(SizeClass::new(size_class_exp).segment_bytes()),
+            Some(AllocKind::Big{ size_bytes }) => size_bytes as usize,
+            None => 0,
+        }
+    }
+
+    /// Returns a pointer to the requested_bytes item of ptr's segment. None if ptr is not a live MiniPage allocation.
+    #[cfg(feature = "requested-size")]
+    unsafe fn requested_bytes_ptr(&self, ptr: *mut u8) -> Option<*mut u16> {
+        let size_class = match self.allocation_kind(ptr) {
+            Some(AllocKind::Small{ size_class_exp }) => SizeClass::new(size_class_exp),
+            _ => return None,
         };
 
-        let (node_ptr, page_idx) = match need_alloc_fresh {
-            true => {
-                // Need to allocate from a fresh minipage                
-                match self.add_minipage(size_class.exp) {
-                    Some((ptr, page_idx)) => {
-                        // Put free indexes of segments on the segments stack for this new MiniPage
-                        self.free_segments_update(ptr);
-                        
-                        (ptr, page_idx)
-                    },
-                    None => {
-                        // No space on host heap
-                        cfg_if! {
-                            if #[cfg(feature = "metrics")] {
-                                self.failure = Some(AllocFail::AddMiniPageNoSpace);
-                            }
-                        }
-                        
-                        return null_mut();
-                    },
-                }
-            },
-            false => {
-                // Need to try and allocate from a reused minipage
-                // Find the most recently used MiniPage header for this size class
-                match (*(*meta_page).free_minipages[size_class.exp_as_idx()]).peek() {
-                    Some(page_idx) => {
-                        // There is a MiniPage with free segments for this size class
-                        cfg_if! {
-                            if #[cfg(feature = "metrics")] {
-                                // For peeking the free_minipages UnsafeStack on the heap
-                                (*(*meta_page).free_minipages[size_class.exp_as_idx()]).record_peek_cost(meta_page);
-                            }
-                        }
-				    
-				    let ptr: *mut MiniPageHeader = match (*meta_page).minipage_headers[page_idx] {
-					   Some(mut value) => &mut value,
-					   None => {
-						  cfg_if! {
-							 if #[cfg(feature = "metrics")] {
-								self.failure = Some(AllocFail::MiniPageHeaderNotFound);
-							 }
-						  }
-
-						  return null_mut();
-					   },
-				    };
+        // allocation_kind() found a live segment, so the heap is initialized
+        let (meta_page, alloc_start_ptr) = (self.meta_page?, self.alloc_start_ptr?);
+        let addr = AllocAddr::from_ptr(alloc_start_ptr, ptr);
+        let page_meta = MiniPageMeta::new((*meta_page).minipage_start(addr.get_page_meta().page_idx));
+        let segment = addr.get_segment(page_meta, size_class);
 
-                        // If free segments stack size is 0 => the MiniPage we just peeked was just added and we haven't grabbed the free indexes from the stack yet
-                        if (*(*meta_page).free_segments[size_class.exp_as_idx()]).size == 0 {
-                            self.free_segments_update(ptr);
-                        } 
-                        
-                        (ptr, usize::from(page_idx))
-                    },
-                    None => {
-                        // If no MiniPage with free segments for the size class was found
-                        
-                        // This means we have to initialize the first MiniPage for this size class
-                        // Or that there are no free MiniPages
-                        match self.add_minipage(size_class.exp) {
-                            Some((ptr, page_idx)) => {
-						  assert!((*(*meta_page).free_segments[size_class.exp_as_idx()]).size == 0, "There should be no free segment indexes left here because we didn't find a free MiniPage");
-						  
-                                // Put free indexes of segments on the segments stack for this new MiniPage
-                                self.free_segments_update(ptr);
-                                
-                                (ptr, page_idx)
-                            },
-                            None => {
-                                // No space on host heap
-                                cfg_if! {
-                                    if #[cfg(feature = "metrics")] {
-                                        self.failure = Some(AllocFail::AddMiniPageNoSpace);
-                                    }
-                                }
-                                
-                                return null_mut();
-                            },
-                        }
-                    },
-                }
-            },
+        let header = (*meta_page).minipage_headers[page_meta.page_idx].as_mut()?;
+        Some(&mut header.requested_bytes[segment.segment_idx])
+    }
+
+    /// Returns the number of bytes requested for ptr. See AlligatorAlloc::requested_size().
+    #[cfg(feature = "requested-size")]
+    unsafe fn requested_size(&self, ptr: *mut u8) -> Option<usize> {
+        self.requested_bytes_ptr(ptr).map(|requested_bytes| usize::from(*requested_bytes))
+    }
+
+    /// Records bytes as the number of bytes requested for ptr, after realloc resized it in place. Does nothing if ptr is not a live MiniPage allocation.
+    #[cfg(feature = "requested-size")]
+    unsafe fn set_requested_size(&mut self, ptr: *mut u8, bytes: usize) {
+        if let Some(requested_bytes) = self.requested_bytes_ptr(ptr) {
+            *requested_bytes = bytes as u16;
+        }
+    }
+
+    /// Returns true if there are no live allocations.
+    unsafe fn is_empty(&self) -> bool {
+        let (meta_page, alloc_start_ptr) = match (self.meta_page, self.alloc_start_ptr) {
+            (Some(meta_page), Some(alloc_start_ptr)) => (meta_page, alloc_start_ptr),
+            _ => return true,
         };
 
-        assert!(!node_ptr.is_null(), "A MiniPageHeader should have been found at which to begin the search for a free segment to allocate");
-        assert!(!self.minipage_lists[size_class.exp_as_idx()].is_null(), "Since a MiniPageHeader to begin the search was found, the head of this size class's free free list should not be null");
+        for page_idx in 0..self.used_page_units() {
+            if self.page_unit_is_live(meta_page, alloc_start_ptr, page_idx) {
+                return false;
+            }
+        }
 
-        // Find the next free segment
-        let next_free_segment_idx: u16 = match (*(*meta_page).free_segments[size_class.exp_as_idx()]).pop() {
-            Some(idx) => {
-                 // The free segments stack for the MiniPage had segments on it
-                cfg_if! {
-                    if #[cfg(feature = "metrics")] {
-                        // For popping a segment index of an UnsafeStack in the heap
-                        (*(*meta_page).free_segments[size_class.exp_as_idx()]).record_pop_cost(meta_page);
-                    }
-                }
-                
-                idx
-            },
-            None => {
-                // Fatal error: There are no free segments in the current MiniPage. This should not occur! As the current MiniPage was taken off of free_minipages. A stack where only MiniPages with free segments are stored.
-                cfg_if! {
-                    if #[cfg(feature = "metrics")] {
-                        self.failure = Some(AllocFail::FreeMiniPagesContractBreach);
-                    }
-                }
+        true
+    }
 
-                
-                return null_mut();
-            },
+    /// Computes the number of live bytes for each size class and for big allocations. See AlligatorAlloc::live_by_class().
+    unsafe fn live_by_class(&self) -> LiveBreakdown {
+        let mut breakdown = LiveBreakdown{
+            small: [0; NUM_SIZE_CLASSES_USIZE],
+            big: 0,
         };
 
-        assert!(!node_ptr.is_null(), "node_ptr should not be null");
+        let meta_page = match self.meta_page {
+            Some(meta_page) => meta_page,
+            None => return breakdown,
+        };
 
-        // Count allocation as either using a reused MiniPage or a fresh MiniPage
-        // We must do this before the next block, where fresh_minipages is potentially reset.
-        if self.fresh_minipages[size_class.exp_as_idx()] == node_ptr {
-            self.total_alloc_fresh[size_class.exp_as_idx()] += 1;
-        } else {
-            self.total_alloc_reused[size_class.exp_as_idx()] += 1;
+        for page_idx in 0..self.used_page_units() {
+            if let Some(header) = &(*meta_page).minipage_headers[page_idx] {
+                let size_class = SizeClass::new(header.size_class_exp);
+                breakdown.small[size_class.exp_as_idx()] += u32::from(header.used_segments_count()) * u32::from(size_class.segment_bytes());
+            }
         }
 
-        // Determine if the MiniPage we just got a free segment index from still has free space after this allocation
-        if (*(*meta_page).free_segments[size_class.exp_as_idx()]).size == 0 {
-            // After this allocation this MiniPage will no longer have any free segments
-            // Remove from free_minipages
-            (*(*meta_page).free_minipages[size_class.exp_as_idx()]).pop();
-            
-            (*node_ptr).on_free_minipages_stack = false;
+        let mut big_ptr = self.big_alloc_head;
+        while let Some(big_head) = big_ptr {
+            if !(*big_head).free {
+                breakdown.big += (*big_head).size_bytes;
+            }
 
-            cfg_if! {
-                if #[cfg(feature = "metrics")] {
-                    // For popping a MiniPage of an UnsafeStack in the heap
-                    (*(*meta_page).free_minipages[size_class.exp_as_idx()]).record_pop_cost(meta_page);
+            big_ptr = (*big_head).next;
+        }
 
-                    // For setting the on_free_minipages_stack field on a MiniPageHeader in the heap
-                    (*(*meta_page).metrics).heap_bytes_write += size_of::<bool>();
+        breakdown
+    }
+
+    /// Counts the live allocations and the pages they are in. See AlligatorAlloc::live_stats().
+    unsafe fn live_stats(&self) -> LiveStats {
+        let mut stats = LiveStats{
+            bytes_in_use: 0,
+            live_minipages: 0,
+            live_big_allocs: 0,
+            live_segments: [0; NUM_SIZE_CLASSES_USIZE],
+        };
+
+        let meta_page = match self.meta_page {
+            Some(meta_page) => meta_page,
+            None => return stats,
+        };
+
+        for page_idx in 0..self.used_page_units() {
+            if let Some(header) = &(*meta_page).minipage_headers[page_idx] {
+                let size_class = SizeClass::new(header.size_class_exp);
+                let used_segments = u32::from(header.used_segments_count());
+                if used_segments > 0 {
+                    stats.live_minipages += 1;
+                    stats.live_segments[size_class.exp_as_idx()] += used_segments;
+                    stats.bytes_in_use += (used_segments as usize) * usize::from(size_class.segment_bytes());
                 }
             }
+        }
 
-            // If this MiniPage was also considered "fresh" then unmark as fresh
-            if self.fresh_minipages[size_class.exp_as_idx()] == node_ptr {
-                self.fresh_minipages[size_class.exp_as_idx()] = null_mut();
+        let mut big_ptr = self.big_alloc_head;
+        while let Some(big_head) = big_ptr {
+            if !(*big_head).free {
+                stats.live_big_allocs += 1;
+                stats.bytes_in_use += (*big_head).size_bytes as usize;
             }
+
+            big_ptr = (*big_head).next;
         }
 
-        // Determine address we will allocate
-	   let page_meta = MiniPageMeta::new(page_idx);
-        let segment = page_meta.get_segment(size_class, usize::from(next_free_segment_idx));
+        stats
+    }
 
-        // Mark segment as not free
-        (*node_ptr).write_free_bitmap(segment, false);
+    /// Counts the live segments and MiniPages of each size class. See AlligatorAlloc::occupancy_stats().
+    unsafe fn occupancy_stats(&self) -> OccupancyStats {
+        let mut stats = OccupancyStats{
+            live_segments: [0; NUM_SIZE_CLASSES_USIZE],
+            minipages_per_class: [0; NUM_SIZE_CLASSES_USIZE],
+        };
 
-        cfg_if! {
-            if #[cfg(feature = "metrics")] {
-                // For writing to a MiniPageHeader free_segments byte on the heap
-                (*(*meta_page).metrics).heap_bytes_write += size_of::<bool>();
+        let meta_page = match self.meta_page {
+            Some(meta_page) => meta_page,
+            None => return stats,
+        };
+
+        for page_idx in 0..self.used_page_units() {
+            if let Some(header) = &(*meta_page).minipage_headers[page_idx] {
+                let used_segments = header.used_segments_count();
+
+                // A MiniPage with free segments is on its size class's free minipages stack, so an empty one which is not has been reclaimed
+                if used_segments == 0 && !header.on_free_minipages_stack {
+                    continue;
+                }
+
+                let idx = SizeClass::new(header.size_class_exp).exp_as_idx();
+                stats.live_segments[idx] += u32::from(used_segments);
+                stats.minipages_per_class[idx] += 1;
             }
         }
 
-        // assert!(false,  "alloc made node_ptr={:?}", *node_ptr);
-
-        // Return address
-        segment.as_addr().as_ptr(alloc_start_ptr)
+        stats
+    }
+
+    /// Calls f with the pointer and usable size of every live allocation. See AlligatorAlloc::for_each_live().
+    unsafe fn for_each_live<F>(&self, mut f: F) where F: FnMut(*mut u8, usize) {
+        let (meta_page, alloc_start_ptr) = match (self.meta_page, self.alloc_start_ptr) {
+            (Some(meta_page), Some(alloc_start_ptr)) => (meta_page, alloc_start_ptr),
+            _ => return,
+        };
+
+        for page_idx in 0..self.used_page_units() {
+            if let Some(header) = &(*meta_page).minipage_headers[page_idx] {
+                let size_class = SizeClass::new(header.size_class_exp);
+                let page_meta = MiniPageMeta::new(page_idx);
+
+                for segment_idx in 0..usize::from(size_class.segments_max_num()) {
+                    // A 0 bit means the segment is allocated
+                    let (byte_idx, bit_idx) = bitmap_position(segment_idx);
+                    if header.free_segments[byte_idx] & (1 << bit_idx) == 0 {
+                        let ptr = page_meta.get_segment(size_class, segment_idx).as_addr().as_ptr(alloc_start_ptr);
+                        if !ptr.is_null() {
+                            f(ptr, usize::from(size_class.segment_bytes()));
+                        }
+                    }
+                }
+            }
+        }
+
+        let mut big_ptr = self.big_alloc_head;
+        while let Some(big_head) = big_ptr {
+            if !(*big_head).free {
+                f(big_head.offset(1) as *mut u8, (*big_head).size_bytes as usize);
+            }
+
+            big_ptr = (*big_head).next;
+        }
+    }
+
+    /// Takes a StatsSnapshot of the current heap. See AlligatorAlloc::snapshot_stats().
+    unsafe fn snapshot_stats(&self) -> StatsSnapshot {
+        let mut snapshot = StatsSnapshot{
+            live: self.live_by_class(),
+            minipages: [0; NUM_SIZE_CLASSES_USIZE],
+            big_pages: 0,
+            used_pages: self.used_page_units() as u32,
+        };
+
+        let meta_page = match self.meta_page {
+            Some(meta_page) => meta_page,
+            None => return snapshot,
+        };
+
+        for page_idx in 0..self.used_page_units() {
+            if (*meta_page).big_alloc_flags[page_idx].is_some() {
+                snapshot.big_pages += 1;
+            } else if let Some(header) = &(*meta_page).minipage_headers[page_idx] {
+                // Reclaimed MiniPages keep their header until reused, like occupancy_stats() they are not counted
+                if header.used_segments_count() == 0 && !header.on_free_minipages_stack {
+                    continue;
+                }
+
+                snapshot.minipages[SizeClass::new(header.size_class_exp).exp_as_idx()] += 1;
+            }
+        }
+
+        snapshot
+    }
+
+    /// Frees every allocation at once, keeping the MetaPage and the host pages. See AlligatorAlloc::reset().
+    unsafe fn reset(&mut self) {
+        let (meta_page, alloc_start_ptr) = match (self.meta_page, self.alloc_start_ptr) {
+            (Some(meta_page), Some(alloc_start_ptr)) => (meta_page, alloc_start_ptr),
+            _ => return,
+        };
+
+        // Only intervals before next_alloc_ptr were ever handed out
+        for page_idx in 0..self.used_page_units() {
+            (*meta_page).minipage_headers[page_idx] = None;
+            (*meta_page).big_alloc_flags[page_idx] = None;
+        }
+
+        for i in 0..NUM_SIZE_CLASSES_USIZE {
+            (*(*meta_page).free_minipages[i]).clear();
+            (*(*meta_page).free_segments[i]).clear();
+        }
+        (*(*meta_page).reclaimed_minipages).clear();
+
+        // zeroed_start_ptr is kept, the memory before it is not zero anymore
+        self.minipage_lists = [null_mut(); NUM_SIZE_CLASSES_USIZE];
+        self.big_alloc_head = None;
+        self.big_alloc_tail = None;
+        self.next_alloc_ptr = Some(alloc_start_ptr);
+        self.total_alloc_reused = [0; NUM_SIZE_CLASSES_USIZE];
+        self.total_alloc_fresh = [0; NUM_SIZE_CLASSES_USIZE];
+        self.fresh_minipages = [null_mut(); NUM_SIZE_CLASSES_USIZE];
+
+        cfg_if! {
+            if #[cfg(feature = "metrics")] {
+                // Nothing is live anymore, the peaks are kept
+                (*(*meta_page).metrics).internal_frag_bytes = 0;
+                (*(*meta_page).metrics).external_frag_bytes = 0;
+                (*(*meta_page).metrics).current_used_bytes = 0;
+                (*(*meta_page).metrics).current_minipages = 0;
+            }
+        }
+
+        cfg_if! {
+            if #[cfg(feature = "debug-layout")] {
+                (*meta_page).debug_header.live_bytes = 0;
+                self.sync_debug_header(0);
+            }
+        }
+    }
+
+    /// Tears down the MetaPage and all allocator state if there are no live allocations. See AlligatorAlloc::release().
+    unsafe fn release(&mut self) -> bool {
+        if !self.is_empty() {
+            return false;
+        }
+
+        self.minipage_lists = [null_mut(); NUM_SIZE_CLASSES_USIZE];
+        self.big_alloc_head = None;
+        self.big_alloc_tail = None;
+        self.meta_page = None;
+        self.alloc_start_ptr = None;
+        self.next_alloc_ptr = None;
+        self.total_alloc_reused = [0; NUM_SIZE_CLASSES_USIZE];
+        self.total_alloc_fresh = [0; NUM_SIZE_CLASSES_USIZE];
+        self.fresh_minipages = [null_mut(); NUM_SIZE_CLASSES_USIZE];
+
+        // With the MetaPage gone every page is unused, give them all back if the host can shrink. Otherwise they are kept and reused
+        self.release_unused_pages();
+        if self.heap_pages == 0 {
+            // The next allocation grows the heap from scratch, wherever the host puts it
+            self.did_init_heap = false;
+        }
+
+        true
+    }
+
+    /// Writes a bitmap of which MiniPage sized intervals of the heap contain live allocations. See AlligatorAlloc::heap_bitmap().
+    unsafe fn heap_bitmap(&self, out: &mut [u8]) {
+        for byte in out.iter_mut() {
+            *byte = 0;
+        }
+
+        let (meta_page, alloc_start_ptr) = match (self.meta_page, self.alloc_start_ptr) {
+            (Some(meta_page), Some(alloc_start_ptr)) => (meta_page, alloc_start_ptr),
+            _ => return,
+        };
+
+        for page_idx in 0..self.used_page_units() {
+            let byte_idx = page_idx / 8;
+            if byte_idx >= out.len() {
+                // Caller did not provide enough room for the rest of the heap
+                return;
+            }
+
+            if self.page_unit_is_live(meta_page, alloc_start_ptr, page_idx) {
+                out[byte_idx] |= 1 << (page_idx % 8);
+            }
+        }
+    }
+
+    /// Moves the live segments of each size class's sparsest MiniPages into the free segments of its other MiniPages, then reclaims the emptied MiniPages. See AlligatorAlloc::compact().
+    unsafe fn compact(&mut self) -> CompactReport {
+        let mut report = CompactReport{
+            relocations: [Relocation{ old_ptr: null_mut(), new_ptr: null_mut() }; COMPACT_MAX_RELOCATIONS],
+            len: 0,
+            freed_minipages: 0,
+            complete: true,
+        };
+
+        let (meta_page, alloc_start_ptr) = match (self.meta_page, self.alloc_start_ptr) {
+            (Some(meta_page), Some(alloc_start_ptr)) => (meta_page, alloc_start_ptr),
+            _ => return report,
+        };
+
+        // Larger size classes have one segment per interval or fewer, their MiniPages are either full or empty
+        for exp in MIN_SIZE_CLASS..MAX_SINGLE_INTERVAL_SIZE_CLASS {
+            let size_class = SizeClass::new(exp);
+            let segment_bytes = usize::from(size_class.segment_bytes());
+
+            // Empty the sparsest MiniPage into the others until they do not have room for it
+            while let Some((src_idx, src_used)) = self.compact_source(meta_page, size_class) {
+                if report.len + usize::from(src_used) > COMPACT_MAX_RELOCATIONS {
+                    report.complete = false;
+                    break;
+                }
+
+                let src_header: *mut MiniPageHeader = match (*meta_page).minipage_headers[src_idx].as_mut() {
+                    Some(header) => header,
+                    None => break,
+                };
+                let src_page = MiniPageMeta::new(src_idx);
+
+                for segment_idx in 0..usize::from(size_class.segments_max_num()) {
+                    let src_segment = src_page.get_segment(size_class, segment_idx);
+                    if (*src_header).get_free_bitmap(src_segment) {
+                        continue;
+                    }
+
+                    // compact_source() checked the other MiniPages have room
+                    let (dst_idx, dst_header) = match self.compact_destination(meta_page, size_class, src_idx) {
+                        Some(dst) => dst,
+                        None => break,
+                    };
+                    let dst_segment_idx = match (*dst_header).first_free_segment() {
+                        Some(idx) => idx,
+                        None => break,
+                    };
+                    let dst_segment = MiniPageMeta::new(dst_idx).get_segment(size_class, usize::from(dst_segment_idx));
+
+                    let old_ptr = src_segment.as_addr().as_ptr(alloc_start_ptr);
+                    let new_ptr = dst_segment.as_addr().as_ptr(alloc_start_ptr);
+                    if old_ptr.is_null() || new_ptr.is_null() {
+                        break;
+                    }
+
+                    (*dst_header).write_free_bitmap(dst_segment, false);
+
+                    cfg_if! {
+                        if #[cfg(feature = "zero-tracking")] {
+                            (*dst_header).take_clean_bitmap(dst_segment);
+                        }
+                    }
+
+                    cfg_if! {
+                        if #[cfg(feature = "requested-size")] {
+                            (*dst_header).requested_bytes[dst_segment.segment_idx] = (*src_header).requested_bytes[src_segment.segment_idx];
+                        }
+                    }
+
+                    cfg_if! {
+                        if #[cfg(feature = "metrics")] {
+                            // The requested size is not known, passing the segment size leaves the internal fragmentation as it was
+                            self.record_usage(new_ptr, segment_bytes, true);
+                        }
+                    }
+
+                    copy_nonoverlapping(old_ptr, new_ptr, segment_bytes);
+
+                    cfg_if! {
+                        if #[cfg(feature = "metrics")] {
+                            self.record_usage(old_ptr, segment_bytes, false);
+                        }
+                    }
+
+                    (*src_header).write_free_bitmap(src_segment, true);
+
+                    report.relocations[report.len] = Relocation{
+                        old_ptr: old_ptr,
+                        new_ptr: new_ptr,
+                    };
+                    report.len += 1;
+                }
+            }
+
+            report.freed_minipages += self.compact_rebuild_stacks(meta_page, size_class);
+        }
+
+        report
+    }
+
+    /// Returns the index and used segment count of the size class's MiniPage with the fewest live segments, if the size class's other live MiniPages have enough free segments to hold them. Ties go to the MiniPage at the highest address, so the end of the heap is emptied first.
+    unsafe fn compact_source(&self, meta_page: *mut MetaPage, size_class: SizeClass) -> Option<(usize, u16)> {
+        let mut sparsest: Option<(usize, u16)> = None;
+        let mut free_segments: usize = 0;
+
+        for page_idx in 0..self.used_page_units() {
+            if let Some(header) = &(*meta_page).minipage_headers[page_idx] {
+                let used_segments = header.used_segments_count();
+                if header.size_class_exp != size_class.exp || used_segments == 0 {
+                    continue;
+                }
+
+                free_segments += usize::from(size_class.segments_max_num() - used_segments);
+                if sparsest.map_or(true, |(_, sparsest_used)| used_segments <= sparsest_used) {
+                    sparsest = Some((page_idx, used_segments));
+                }
+            }
+        }
+
+        let (src_idx, src_used) = sparsest?;
+        let other_free_segments = free_segments - usize::from(size_class.segments_max_num() - src_used);
+        match other_free_segments >= usize::from(src_used) {
+            true => Some((src_idx, src_used)),
+            false => None,
+        }
+    }
+
+    /// Returns the size class's MiniPage, other than src_idx, with the most live segments which still has a free segment. Empty MiniPages are never returned, so compaction does not move segments into MiniPages which are about to be reclaimed or already were.
+    unsafe fn compact_destination(&self, meta_page: *mut MetaPage, size_class: SizeClass, src_idx: usize) -> Option<(usize, *mut MiniPageHeader)> {
+        let mut densest: Option<(usize, u16)> = None;
+
+        for page_idx in 0..self.used_page_units() {
+            if page_idx == src_idx {
+                continue;
+            }
+
+            if let Some(header) = &(*meta_page).minipage_headers[page_idx] {
+                let used_segments = header.used_segments_count();
+                if header.size_class_exp != size_class.exp || used_segments == 0 || used_segments == size_class.segments_max_num() {
+                    continue;
+                }
+
+                if densest.map_or(true, |(_, densest_used)| used_segments > densest_used) {
+                    densest = Some((page_idx, used_segments));
+                }
+            }
+        }
+
+        let (dst_idx, _) = densest?;
+        let header: *mut MiniPageHeader = (*meta_page).minipage_headers[dst_idx].as_mut()?;
+        Some((dst_idx, header))
+    }
+
+    /// Rebuilds the size class's free minipages stack from the MiniPage free bitmaps, after compact() moved segments between them. MiniPages with free segments are pushed, lowest address on top. Empty MiniPages are reclaimed. Returns the number reclaimed.
+    unsafe fn compact_rebuild_stacks(&mut self, meta_page: *mut MetaPage, size_class: SizeClass) -> u32 {
+        let free_minipages = (*meta_page).free_minipages[size_class.exp_as_idx()];
+        for n in 0..(*free_minipages).len() {
+            if let Some(page_idx) = (*free_minipages).peek_nth(n) {
+                if let Some(header) = (*meta_page).minipage_headers[expand_page_idx(page_idx)].as_mut() {
+                    header.on_free_minipages_stack = false;
+                }
+            }
+        }
+        (*free_minipages).clear();
+        (*(*meta_page).free_segments[size_class.exp_as_idx()]).clear();
+
+        let mut reclaimed: u32 = 0;
+        for page_idx in (0..self.used_page_units()).rev() {
+            let header: *mut MiniPageHeader = match (*meta_page).minipage_headers[page_idx].as_mut() {
+                Some(header) if header.size_class_exp == size_class.exp => header,
+                _ => continue,
+            };
+
+            let used_segments = (*header).used_segments_count();
+            if used_segments == size_class.segments_max_num() {
+                if self.fresh_minipages[size_class.exp_as_idx()] == header {
+                    // Filled up by compaction instead of by allocations
+                    self.fresh_minipages[size_class.exp_as_idx()] = null_mut();
+                }
+
+                continue;
+            }
+
+            // Reclaimed MiniPages keep their header, they are empty but must not be reclaimed twice
+            if used_segments == 0 && (*(*meta_page).reclaimed_minipages).contains(compact_page_idx(page_idx)) {
+                continue;
+            }
+
+            if (*free_minipages).push(compact_page_idx(page_idx)).is_none() {
+                cfg_if! {
+                    if #[cfg(feature = "strict")] {
+                        panic!("{:?}: free minipages stack of size class {} is full, could not push MiniPage {}", AllocFail::StackOverflow, size_class.exp, page_idx);
+                    } else if #[cfg(feature = "metrics")] {
+                        self.record_failure(AllocFail::StackOverflow);
+                    }
+                }
+
+                continue;
+            }
+            (*header).on_free_minipages_stack = true;
+
+            // Just pushed, so it is the head reclaim_minipage() expects
+            if used_segments == 0 {
+                self.reclaim_minipage(meta_page, page_idx, header);
+                reclaimed += 1;
+            }
+        }
+
+        reclaimed
+    }
+
+    /// Checks the invariants the allocator relies on, returning the first one which is broken. See AlligatorAlloc::validate_heap().
+    unsafe fn validate_heap(&self) -> Result<(), HeapInvariantViolation> {
+        let (meta_page, alloc_start_ptr, next_alloc_ptr) = match (self.meta_page, self.alloc_start_ptr, self.next_alloc_ptr) {
+            (Some(meta_page), Some(alloc_start_ptr), Some(next_alloc_ptr)) => (meta_page, alloc_start_ptr, next_alloc_ptr),
+            _ => return Ok(()),
+        };
+
+        // The other checks only look at intervals before next_alloc_ptr, so it must be checked first
+        let base_ptr = (*self.heap.get()).base_ptr();
+        let next_alloc_offset = (next_alloc_ptr as usize).wrapping_sub(base_ptr as usize);
+        if next_alloc_ptr < alloc_start_ptr || next_alloc_offset > self.heap_pages * (heap::PAGE_BYTES as usize) {
+            return Err(HeapInvariantViolation::NextAllocOutOfBounds{ next_alloc_offset: next_alloc_offset });
+        }
+
+        let used_page_units = self.used_page_units();
+
+        // MiniPages must be on their size class's free minipages stack exactly when their flag says so, and only if they have a free segment
+        for page_idx in 0..used_page_units {
+            if let Some(header) = &(*meta_page).minipage_headers[page_idx] {
+                let size_class = SizeClass::new(header.size_class_exp);
+                let on_stack = (*(*meta_page).free_minipages[size_class.exp_as_idx()]).contains(compact_page_idx(page_idx));
+
+                if header.on_free_minipages_stack != on_stack {
+                    return Err(HeapInvariantViolation::OnFreeStackMismatch{
+                        page_idx: page_idx,
+                        on_free_minipages_stack: header.on_free_minipages_stack,
+                    });
+                }
+
+                if on_stack && header.used_segments_count() == size_class.segments_max_num() {
+                    return Err(HeapInvariantViolation::FullMiniPageOnFreeStack{
+                        page_idx: page_idx,
+                        size_class_exp: size_class.exp,
+                    });
+                }
+            }
+        }
+
+        for exp in MIN_SIZE_CLASS..=MAX_SIZE_CLASS {
+            let stack = &mut *(*meta_page).free_minipages[SizeClass::new(exp).exp_as_idx()];
+            for i in 0..stack.size {
+                let page_idx = expand_page_idx(*stack.item_ptr(i));
+                let is_minipage = page_idx < used_page_units && match &(*meta_page).minipage_headers[page_idx] {
+                    Some(header) => header.size_class_exp == exp,
+                    None => false,
+                };
+
+                if !is_minipage {
+                    return Err(HeapInvariantViolation::FreeStackNotMiniPage{
+                        page_idx: page_idx,
+                        size_class_exp: exp,
+                    });
+                }
+            }
+        }
+
+        // Big allocations must be in address order without overlapping
+        let mut prev: Option<(usize, usize)> = None;
+        let mut big_ptr = self.big_alloc_head;
+        while let Some(big_head) = big_ptr {
+            let start_ptr = big_head as *mut u8;
+            let end_addr = (start_ptr as usize) + (BIG_ALLOC_HEADER_SIZE_U32 as usize) + ((*big_head).size_bytes as usize);
+            if start_ptr < alloc_start_ptr || end_addr > next_alloc_ptr as usize {
+                return Err(HeapInvariantViolation::BigAllocOutOfBounds{ ptr: start_ptr as usize });
+            }
+
+            let page_idx = AllocAddr::from_ptr(alloc_start_ptr, start_ptr).get_page_meta().page_idx;
+            if let Some((prev_page_idx, prev_end_addr)) = prev {
+                if page_idx <= prev_page_idx {
+                    return Err(HeapInvariantViolation::BigAllocOutOfOrder{ page_idx: page_idx, prev_page_idx: prev_page_idx });
+                }
+                if (start_ptr as usize) < prev_end_addr {
+                    return Err(HeapInvariantViolation::BigAllocOverlap{ page_idx: page_idx, prev_page_idx: prev_page_idx });
+                }
+            }
+
+            prev = Some((page_idx, end_addr));
+            big_ptr = (*big_head).next;
+        }
+
+        // Big allocations must flag exactly the intervals they cover, intervals between them must not be flagged
+        let mut unflagged_start_idx = 0;
+        let mut big_ptr = self.big_alloc_head;
+        while let Some(big_head) = big_ptr {
+            let page_idx = AllocAddr::from_ptr(alloc_start_ptr, big_head as *mut u8).get_page_meta().page_idx;
+            for flag_idx in unflagged_start_idx..page_idx {
+                if (*meta_page).big_alloc_flags[flag_idx].is_some() {
+                    return Err(HeapInvariantViolation::BigAllocFlagMismatch{ page_idx: flag_idx });
+                }
+            }
+
+            let footprint_bytes = BIG_ALLOC_HEADER_SIZE_U32 + (*big_head).size_bytes;
+            let intervals = ((footprint_bytes + MINI_PAGE_TOTAL_BYTES - 1) / MINI_PAGE_TOTAL_BYTES) as usize;
+            for flag_idx in page_idx..(page_idx + intervals) {
+                match (*meta_page).big_alloc_flags[flag_idx] {
+                    Some(flag) if flag.start_idx() == page_idx => (),
+                    _ => return Err(HeapInvariantViolation::BigAllocFlagMismatch{ page_idx: flag_idx }),
+                }
+            }
+
+            unflagged_start_idx = page_idx + intervals;
+            big_ptr = (*big_head).next;
+        }
+
+        for flag_idx in unflagged_start_idx..used_page_units {
+            if (*meta_page).big_alloc_flags[flag_idx].is_some() {
+                return Err(HeapInvariantViolation::BigAllocFlagMismatch{ page_idx: flag_idx });
+            }
+        }
+
+        Ok(())
+    }
+
+    cfg_if! {
+        if #[cfg(feature = "mock")] {
+            /// Breaks one of the invariants validate_heap() checks. See AlligatorAlloc::corrupt().
+            unsafe fn corrupt(&mut self, corruption: HeapCorruption) {
+                let meta_page = match self.meta_page {
+                    Some(meta_page) => meta_page,
+                    None => return,
+                };
+
+                let free_minipage_header = |size_class_exp: u8| -> Option<&mut MiniPageHeader> {
+                    let page_idx = (*(*meta_page).free_minipages[SizeClass::new(size_class_exp).exp_as_idx()]).peek()?;
+                    (*meta_page).minipage_headers[expand_page_idx(page_idx)].as_mut()
+                };
+
+                match corruption {
+                    HeapCorruption::FillFreeMiniPage{ size_class_exp } => {
+                        if let Some(header) = free_minipage_header(size_class_exp) {
+                            header.free_segments = [0; MINI_PAGE_FREE_SEGMENTS_SIZE];
+                        }
+                    },
+                    HeapCorruption::ClearOnFreeStackFlag{ size_class_exp } => {
+                        if let Some(header) = free_minipage_header(size_class_exp) {
+                            header.on_free_minipages_stack = false;
+                        }
+                    },
+                    HeapCorruption::GrowFirstBigAlloc => {
+                        if let Some(big_head) = self.big_alloc_head {
+                            (*big_head).size_bytes += MINI_PAGE_TOTAL_BYTES;
+                        }
+                    },
+                    HeapCorruption::NextAllocPastHeap => {
+                        let heap_end_ptr = (*self.heap.get()).base_ptr().add(self.heap_pages * (heap::PAGE_BYTES as usize));
+                        self.next_alloc_ptr = Some(heap_end_ptr.add(MINI_PAGE_ALLOC_BYTES as usize));
+                    },
+                }
+            }
+        }
+    }
+
+    cfg_if! {
+        if #[cfg(feature = "zero-tracking")] {
+            /// Moves zeroed_start_ptr up to next_alloc_ptr, call after next_alloc_ptr is incremented.
+            unsafe fn raise_zeroed_start_ptr(&mut self) {
+                if let Some(next_alloc_ptr) = self.next_alloc_ptr {
+                    if self.zeroed_start_ptr < next_alloc_ptr {
+                        self.zeroed_start_ptr = next_alloc_ptr;
+                    }
+                }
+            }
+
+            /// Returns true if the allocation which ptr points to is known to only contain zeros, and marks it as possibly written. Must be called once for every allocation handed out. Returns false if ptr is not a live allocation from this allocator.
+            unsafe fn take_known_zero(&mut self, ptr: *mut u8) -> bool {
+                let (meta_page, alloc_start_ptr, next_alloc_ptr) = match (self.meta_page, self.alloc_start_ptr, self.next_alloc_ptr) {
+                    (Some(meta_page), Some(alloc_start_ptr), Some(next_alloc_ptr)) => (meta_page, alloc_start_ptr, next_alloc_ptr),
+                    _ => return false,
+                };
+
+                if ptr < alloc_start_ptr || ptr >= next_alloc_ptr {
+                    return false;
+                }
+
+                let addr = AllocAddr::from_ptr(alloc_start_ptr, ptr);
+                let page_meta = addr.get_page_meta();
+
+                if let Some(big_alloc_flag) = (*meta_page).big_alloc_flags[page_meta.page_idx] {
+                    let big_ptr = MiniPageMeta::new(big_alloc_flag.start_idx()).addr.as_ptr(alloc_start_ptr) as *mut BigAllocHeader;
+                    if big_ptr.is_null() {
+                        return false;
+                    }
+
+                    let known_zero = (*big_ptr).known_zero;
+                    (*big_ptr).known_zero = false;
+
+                    return known_zero;
+                }
+
+                let page_meta = MiniPageMeta::new((*meta_page).minipage_start(page_meta.page_idx));
+                match (*meta_page).minipage_headers[page_meta.page_idx].as_mut() {
+                    Some(header) => {
+                        let size_class = SizeClass::new(header.size_class_exp);
+                        header.take_clean_bitmap(addr.get_segment(page_meta, size_class))
+                    },
+                    None => false,
+                }
+            }
+        }
+    }
+
+    /// Allocate memory which only contains zeros. With the zero-tracking feature memory is only filled with zeros if it is not known to still be zero, otherwise it is always filled.
+    unsafe fn alloc_zeroed(&mut self, layout: Layout) -> *mut u8 {
+        let ptr = self.alloc(layout);
+        if ptr.is_null() {
+            return ptr;
+        }
+
+        cfg_if! {
+            if #[cfg(feature = "zero-tracking")] {
+                if self.take_known_zero(ptr) {
+                    return ptr;
+                }
+            }
+        }
+
+        core::ptr::write_bytes(ptr, 0, layout.size());
+
+        cfg_if! {
+            if #[cfg(feature = "metrics")] {
+                if let Some(meta_page) = self.meta_page {
+                    (*(*meta_page).metrics).total_zero_fills += 1;
+                    (*(*meta_page).metrics).heap_bytes_write += layout.size();
+                }
+            }
+        }
+
+        ptr
+    }
+
+    /// Shrinks big_ptr, a big allocation which is being reused for alloc_bytes, to the MiniPage intervals alloc_bytes needs. The intervals left over become a new free big allocation, linked after big_ptr. Does nothing if no whole interval is left over.
+    unsafe fn split_big_alloc(&mut self, meta_page: *mut MetaPage, alloc_start_ptr: *mut u8, big_ptr: *mut BigAllocHeader, alloc_bytes: usize) {
+        let (size_bytes, interval) = BigAllocHeader::compute_footprint(alloc_bytes);
+        let total_interval = ((*big_ptr).size_bytes + BIG_ALLOC_HEADER_SIZE_U32) / MINI_PAGE_TOTAL_BYTES;
+        if total_interval <= interval {
+            return;
+        }
+
+        // Setup the tail's header at the start of the first interval big_ptr no longer needs
+        let tail_interval = total_interval - interval;
+        let tail_size_bytes = (tail_interval * MINI_PAGE_TOTAL_BYTES) - BIG_ALLOC_HEADER_SIZE_U32;
+        let tail_ptr = (big_ptr as *mut u8).offset((interval * MINI_PAGE_TOTAL_BYTES) as isize) as *mut BigAllocHeader;
+        (*tail_ptr).size_class_exp = (u32::BITS - (tail_size_bytes - 1).leading_zeros()) as u8;
+        (*tail_ptr).next = (*big_ptr).next;
+        (*tail_ptr).free = true;
+        (*tail_ptr).size_bytes = tail_size_bytes;
+
+        cfg_if! {
+            if #[cfg(feature = "zero-tracking")] {
+                (*tail_ptr).known_zero = (*big_ptr).known_zero;
+            }
+        }
+
+        (*big_ptr).next = Some(tail_ptr);
+        (*big_ptr).size_bytes = size_bytes;
+        if self.big_alloc_tail == Some(big_ptr) {
+            self.big_alloc_tail = Some(tail_ptr);
+        }
+
+        // Point the tail's big allocation flags at its own header
+        let tail_page_idx = AllocAddr::from_ptr(alloc_start_ptr, tail_ptr as *mut u8).get_page_meta().page_idx;
+        for page_i in tail_page_idx..(tail_page_idx + (tail_interval as usize)) {
+            (*meta_page).big_alloc_flags[page_i] = Some(BigAllocFlag::new(tail_page_idx));
+        }
+
+        cfg_if! {
+            if #[cfg(feature = "metrics")] {
+                (*(*meta_page).metrics).heap_bytes_write += size_of::<BigAllocHeader>();
+                (*(*meta_page).metrics).big_splits += 1;
+            }
+        }
+    }
+
+    /// Merges big_ptr, a free big allocation, with the free big allocations directly before and after it in memory, so a later big allocation can reuse their intervals together. Big allocations with unused intervals between them, ie., skipped for page alignment, are not merged.
+    unsafe fn coalesce_big_alloc(&mut self, meta_page: *mut MetaPage, alloc_start_ptr: *mut u8, big_ptr: *mut BigAllocHeader) {
+        // The next big allocation in the list is the next one in memory, it is adjacent if it starts where big_ptr ends
+        if let Some(next_ptr) = (*big_ptr).next {
+            if (*next_ptr).free && (big_ptr.offset(1) as *mut u8).offset((*big_ptr).size_bytes as isize) == next_ptr as *mut u8 {
+                self.merge_big_allocs(meta_page, alloc_start_ptr, big_ptr, next_ptr);
+            }
+        }
+
+        // An adjacent big allocation before big_ptr flags the interval before big_ptr's header
+        let page_idx = AllocAddr::from_ptr(alloc_start_ptr, big_ptr as *mut u8).get_page_meta().page_idx;
+        if page_idx == 0 {
+            return;
+        }
+
+        if let Some(prev_flag) = (*meta_page).big_alloc_flags[page_idx - 1] {
+            let prev_ptr = MiniPageMeta::new(prev_flag.start_idx()).addr.as_ptr(alloc_start_ptr) as *mut BigAllocHeader;
+            if !prev_ptr.is_null() && (*prev_ptr).free && (*prev_ptr).next == Some(big_ptr) {
+                self.merge_big_allocs(meta_page, alloc_start_ptr, prev_ptr, big_ptr);
+            }
+        }
+    }
+
+    /// Grows big_ptr over next_ptr, the free big allocation directly after it in memory, and unlinks next_ptr. See coalesce_big_alloc().
+    unsafe fn merge_big_allocs(&mut self, meta_page: *mut MetaPage, alloc_start_ptr: *mut u8, big_ptr: *mut BigAllocHeader, next_ptr: *mut BigAllocHeader) {
+        let size_bytes = (*big_ptr).size_bytes + BIG_ALLOC_HEADER_SIZE_U32 + (*next_ptr).size_bytes;
+        (*big_ptr).size_class_exp = (u32::BITS - (size_bytes - 1).leading_zeros()) as u8;
+        (*big_ptr).next = (*next_ptr).next;
+        (*big_ptr).size_bytes = size_bytes;
+
+        cfg_if! {
+            if #[cfg(feature = "zero-tracking")] {
+                // next_ptr's header is now inside big_ptr's memory
+                (*big_ptr).known_zero = false;
+            }
+        }
+
+        if self.big_alloc_tail == Some(next_ptr) {
+            self.big_alloc_tail = Some(big_ptr);
+        }
+
+        // Point next_ptr's big allocation flags at big_ptr's header
+        let big_page_idx = AllocAddr::from_ptr(alloc_start_ptr, big_ptr as *mut u8).get_page_meta().page_idx;
+        let next_page_idx = AllocAddr::from_ptr(alloc_start_ptr, next_ptr as *mut u8).get_page_meta().page_idx;
+        let next_interval = (((*next_ptr).size_bytes + BIG_ALLOC_HEADER_SIZE_U32) / MINI_PAGE_TOTAL_BYTES) as usize;
+        for page_i in next_page_idx..(next_page_idx + next_interval) {
+            (*meta_page).big_alloc_flags[page_i] = Some(BigAllocFlag::new(big_page_idx));
+        }
+
+        cfg_if! {
+            if #[cfg(feature = "metrics")] {
+                (*(*meta_page).metrics).heap_bytes_write += size_of::<BigAllocHeader>();
+                (*(*meta_page).metrics).big_coalesces += 1;
+            }
+        }
+    }
+
+    /// Gives back the MiniPage intervals at the end of ptr, a live big allocation, which new_size bytes do not need. They become a free big allocation linked after ptr's, see split_big_alloc(). Does nothing if ptr is not a big allocation.
+    unsafe fn shrink_big_alloc(&mut self, ptr: *mut u8, new_size: usize) {
+        let (meta_page, alloc_start_ptr) = match (self.meta_page, self.alloc_start_ptr) {
+            (Some(meta_page), Some(alloc_start_ptr)) => (meta_page, alloc_start_ptr),
+            _ => return,
+        };
+
+        let page_meta = AllocAddr::from_ptr(alloc_start_ptr, ptr).get_page_meta();
+        if let Some(big_alloc_flag) = (*meta_page).big_alloc_flags[page_meta.page_idx] {
+            let big_ptr = MiniPageMeta::new(big_alloc_flag.start_idx()).addr.as_ptr(alloc_start_ptr) as *mut BigAllocHeader;
+            if !big_ptr.is_null() {
+                self.split_big_alloc(meta_page, alloc_start_ptr, big_ptr, new_size);
+
+                // The tail may be right before a free big allocation
+                if let Some(tail_ptr) = (*big_ptr).next {
+                    if (*tail_ptr).free {
+                        self.coalesce_big_alloc(meta_page, alloc_start_ptr, tail_ptr);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Creates a new BigAllocHeader at next_alloc_ptr, with enough space after it for alloc_bytes, and links it to the head of the big allocation list. The header's free field is set to free. Updates next_alloc_ptr and the big allocation flags.
+    /// Returns None if there is no space left on the heap.
+    unsafe fn add_big_alloc(&mut self, size_class_exp: u8, alloc_bytes: usize, free: bool) -> Option<*mut BigAllocHeader> {
+        let (base_ptr, meta_page, alloc_start_ptr, next_alloc_ptr) = match self.ensure_heap() {
+		  Ok(v) => v,
+		  Err(_) => return None,
+	   };
+
+        // A request larger than the whole heap can never fit. Checked before computing the footprint, which uses 32 bit sizes, so a huge alloc_bytes cannot wrap around into a small allocation
+        if alloc_bytes > self.max_pages * (heap::PAGE_BYTES as usize) {
+            cfg_if! {
+                if #[cfg(feature = "strict")] {
+                    panic!("{:?}: big allocation of {} bytes is larger than the heap", AllocFail::BigAllocNoSpace, alloc_bytes);
+                } else if #[cfg(feature = "metrics")] {
+                    self.record_failure(AllocFail::BigAllocNoSpace);
+                }
+            }
+
+            return None;
+        }
+
+        let page_aligned = BigAllocHeader::page_aligned(alloc_bytes);
+        let (size_bytes, interval) = BigAllocHeader::compute_footprint(alloc_bytes);
+
+        // Page aligned allocations skip ahead to the next host page, the skipped MiniPage intervals are left unused
+        let next_alloc_ptr = match page_aligned {
+            true => {
+                let page_offset = AllocAddr::from_ptr(base_ptr, next_alloc_ptr).addr % heap::PAGE_BYTES;
+                next_alloc_ptr.offset(((heap::PAGE_BYTES - page_offset) % heap::PAGE_BYTES) as isize)
+            },
+            false => next_alloc_ptr,
+        };
+
+        // Check there is room on the heap, same as add_minipage an allocation which ends exactly at the end of the heap fits
+        let after_alloc_addr = AllocAddr::from_ptr(base_ptr, next_alloc_ptr).addr.checked_add(interval * MINI_PAGE_TOTAL_BYTES);
+        if after_alloc_addr.map_or(true, |addr| !self.ensure_room(addr)) {
+            cfg_if! {
+                if #[cfg(feature = "strict")] {
+                    panic!("{:?}: no room on the heap for a big allocation of {} MiniPages", AllocFail::BigAllocNoSpace, interval);
+                } else if #[cfg(feature = "metrics")] {
+                    self.record_failure(AllocFail::BigAllocNoSpace);
+                }
+            }
+
+            return None;
+        }
+
+        cfg_if! {
+            if #[cfg(feature = "metrics")] {
+                (*(*meta_page).metrics).heap_bytes_write += size_of::<BigAllocHeader>();
+                (*(*meta_page).metrics).total_big_headers += 1;
+            }
+        }
+
+	   let page_meta = MiniPageMeta::from_addr(AllocAddr::from_ptr(alloc_start_ptr, next_alloc_ptr));
+
+	   // Setup big alloc header
+        let big_ptr = next_alloc_ptr as *mut BigAllocHeader;
+        (*big_ptr).size_class_exp = size_class_exp;
+        (*big_ptr).next = None;
+        (*big_ptr).free = free;
+        (*big_ptr).size_bytes = size_bytes;
+
+        cfg_if! {
+            if #[cfg(feature = "zero-tracking")] {
+                (*big_ptr).known_zero = next_alloc_ptr >= self.zeroed_start_ptr;
+            }
+        }
+
+	   // Set big allocation flags, one for each of the interval MiniPage intervals the allocation covers. The interval after the allocation is not part of it
+	   for page_i in page_meta.page_idx..(page_meta.page_idx + (interval as usize)) {
+		  (*meta_page).big_alloc_flags[page_i] = Some(BigAllocFlag::new(page_meta.page_idx));
+	   }
+        
+        // New space is always after every other big allocation, link it at the end of the list
+        match self.big_alloc_tail {
+            Some(tail_ptr) => {
+                (*tail_ptr).next = Some(big_ptr);
+
+                cfg_if! {
+                    if #[cfg(feature = "metrics")] {
+                        (*(*meta_page).metrics).heap_bytes_write += size_of::<Option<*mut BigAllocHeader>>();
+                    }
+                }
+            },
+            None => self.big_alloc_head = Some(big_ptr),
+        }
+        self.big_alloc_tail = Some(big_ptr);
+
+	   self.next_alloc_ptr = Some(next_alloc_ptr.offset((interval * MINI_PAGE_TOTAL_BYTES) as isize));
+
+        cfg_if! {
+            if #[cfg(feature = "zero-tracking")] {
+                self.raise_zeroed_start_ptr();
+            }
+        }
+
+        Some(big_ptr)
+    }
+
+    /// Creates count free big allocations which can each hold bytes_each. See AlligatorAlloc::reserve_big().
+    unsafe fn reserve_big(&mut self, count: usize, bytes_each: usize) -> usize {
+        let size_class = SizeClass::new_from_layout(Layout::from_size_align_unchecked(bytes_each, 1)).at_least(MIN_ALLOC_SIZE_CLASS);
+        if size_class.exp <= MAX_SIZE_CLASS || self.small_only {
+            // Would be allocated from a MiniPage, or could never be allocated
+            return 0;
+        }
+
+        for reserved in 0..count {
+            if self.add_big_alloc(size_class.exp, bytes_each, true).is_none() {
+                return reserved;
+            }
+        }
+
+        count
+    }
+
+    /// Allocate memory, from the same MiniPage as hint_ptr if possible. See AlligatorAlloc::alloc_near().
+    unsafe fn alloc_near(&mut self, hint_ptr: *mut u8, layout: Layout) -> *mut u8 {
+        let size_class = SizeClass::new_from_layout(layout).at_least(MIN_ALLOC_SIZE_CLASS);
+        let (meta_page, alloc_start_ptr, next_alloc_ptr) = match (self.meta_page, self.alloc_start_ptr, self.next_alloc_ptr) {
+            (Some(meta_page), Some(alloc_start_ptr), Some(next_alloc_ptr)) => (meta_page, alloc_start_ptr, next_alloc_ptr),
+            _ => return self.alloc(layout),
+        };
+
+        if layout.size() == 0 || size_class.exp > MAX_SIZE_CLASS || hint_ptr < alloc_start_ptr || hint_ptr >= next_alloc_ptr {
+            return self.alloc(layout);
+        }
+
+        let page_idx = (*meta_page).minipage_start(AllocAddr::from_ptr(alloc_start_ptr, hint_ptr).get_page_meta().page_idx);
+        // A MiniPage off the free minipages stack is either full or has been reclaimed. A reclaimed one keeps its size class until add_minipage() hands it to another, allocating from it would overlap that MiniPage's allocations
+        let header: *mut MiniPageHeader = match (*meta_page).minipage_headers[page_idx].as_mut() {
+            Some(header) if header.size_class_exp == size_class.exp && header.on_free_minipages_stack => header,
+            _ => return self.alloc(layout),
+        };
+
+        // The head of the free minipages stack is where the normal path allocates from, its free segments are on the free segments stack
+        if (*(*meta_page).free_minipages[size_class.exp_as_idx()]).peek() == Some(compact_page_idx(page_idx)) {
+            return self.alloc(layout);
+        }
+
+        // Any other MiniPage on the free minipages stack must keep at least one free segment, so it still has one when it becomes the head
+        let free_segments = size_class.segments_max_num() - (*header).used_segments_count();
+        if free_segments < 2 {
+            return self.alloc(layout);
+        }
+
+        let segment_idx = match (*header).first_free_segment() {
+            Some(idx) => idx,
+            None => return self.alloc(layout),
+        };
+
+        let segment = MiniPageMeta::new(page_idx).get_segment(size_class, usize::from(segment_idx));
+        (*header).write_free_bitmap(segment, false);
+
+        cfg_if! {
+            if #[cfg(feature = "metrics")] {
+                (*(*meta_page).metrics).total_allocs[size_class.exp_as_idx()] += 1;
+
+                // For reading and writing a MiniPageHeader free_segments bitmap on the heap
+                (*(*meta_page).metrics).heap_bytes_read += MINI_PAGE_FREE_SEGMENTS_SIZE;
+                (*(*meta_page).metrics).heap_bytes_write += size_of::<bool>();
+            }
+        }
+
+        cfg_if! {
+            if #[cfg(feature = "requested-size")] {
+                // The size class fits the request, so it is at most 2^MAX_SIZE_CLASS
+                (*header).requested_bytes[segment.segment_idx] = layout.size() as u16;
+
+                cfg_if! {
+                    if #[cfg(feature = "metrics")] {
+                        // For writing to a MiniPageHeader requested_bytes item on the heap
+                        (*(*meta_page).metrics).heap_bytes_write += size_of::<u16>();
+                    }
+                }
+            }
+        }
+
+        // Count the allocation as either using a reused MiniPage or a fresh MiniPage, like alloc()
+        if self.fresh_minipages[size_class.exp_as_idx()] == header {
+            self.total_alloc_fresh[size_class.exp_as_idx()] += 1;
+        } else {
+            self.total_alloc_reused[size_class.exp_as_idx()] += 1;
+        }
+
+        segment.as_addr().as_ptr(alloc_start_ptr)
+    }
+
+    /// Allocate memory.
+    unsafe fn alloc(&mut self, layout: Layout) -> *mut u8 {        
+        // Don't allow 0 sized allocations
+        if layout.size() == 0 {
+            cfg_if! {
+                if #[cfg(feature = "strict")] {
+                    panic!("{:?}: alloc called with a 0 byte layout {:?}", AllocFail::NoZeroAlloc, layout);
+                } else if #[cfg(feature = "metrics")] {
+                    self.record_failure(AllocFail::NoZeroAlloc);
+                }
+            }
+            
+            return null_mut();
+        }
+       
+        // Get details about host heap
+        let (base_ptr, meta_page, alloc_start_ptr, _next_alloc_ptr) = match self.ensure_heap() {
+            Ok(v) => v,
+            Err(_) => return null_mut(),
+        };
+
+        // Determine size class of allocation
+        let size_class = SizeClass::new_from_layout(layout).at_least(MIN_ALLOC_SIZE_CLASS);
+
+        // Check if size class not too small
+        if size_class.exp < MIN_SIZE_CLASS {
+            // Size class is too small for allocator. We check this because the SizeClass structure is supposed to smartly round up smaller size classes to the smallest size class. If it doesn't do this then all logic in the program will use smaller than allowed size classes.
+            cfg_if! {
+                if #[cfg(feature = "strict")] {
+                    panic!("{:?}: size class exponent {} chosen for {} bytes is smaller than {}", AllocFail::SizeClassTooSmall, size_class.exp, layout.size(), MIN_SIZE_CLASS);
+                } else if #[cfg(feature = "metrics")] {
+                    self.record_failure(AllocFail::SizeClassTooSmall);
+                }
+            }
+            
+            return null_mut();
+        }
+
+        // Check a big allocation is allowed
+        if size_class.exp > MAX_SIZE_CLASS && self.small_only {
+            cfg_if! {
+                if #[cfg(feature = "strict")] {
+                    panic!("{:?}: {:?} needs size class exponent {}, larger than MAX_SIZE_CLASS, and big allocations are disabled", AllocFail::AllocTooLarge, layout, size_class.exp);
+                } else if #[cfg(feature = "metrics")] {
+                    self.record_failure(AllocFail::AllocTooLarge);
+                }
+            }
+
+            return null_mut();
+        }
+
+        // Check the alignment can be honored, segments are aligned to their size and big allocations to MINI_PAGE_ALLOC_BYTES
+        let max_align = match size_class.exp > MAX_SIZE_CLASS {
+            true => MINI_PAGE_ALLOC_BYTES as usize,
+            false => usize::from(size_class.segment_bytes()),
+        };
+        if layout.align() > max_align {
+            cfg_if! {
+                if #[cfg(feature = "strict")] {
+                    panic!("{:?}: alignment {} of {:?} is larger than the {} bytes size class exponent {} is aligned to", AllocFail::AlignTooLarge, layout.align(), layout, max_align, size_class.exp);
+                } else if #[cfg(feature = "metrics")] {
+                    self.record_failure(AllocFail::AlignTooLarge);
+                }
+            }
+
+            return null_mut();
+        }
+
+        // Check if not bigger than the largest MiniPage size class. If the case, we must use big alloc.
+        if size_class.exp > MAX_SIZE_CLASS {
+            // Record metrics
+            cfg_if! {
+                if #[cfg(feature = "metrics")] {
+                    (*(*meta_page).metrics).total_allocs[NUM_SIZE_CLASSES_USIZE] += 1;
+                }
+            }
+            
+            // Try and find a free big alloc segment, or allocate a new one
+            let page_aligned = BigAllocHeader::page_aligned(layout.size());
+            let (needed_bytes, _) = BigAllocHeader::compute_footprint(layout.size());
+            let mut search_ptr = self.big_alloc_head;
+            let mut found_ptr: Option<*mut BigAllocHeader> = None;
+            #[cfg(feature = "metrics")]
+            let mut scanned: u32 = 0;
+
+            while let Some(big_head) = search_ptr {
+                cfg_if! {
+                    if #[cfg(feature = "metrics")] {
+                        // For reading the BigAllocHeader
+                        scanned += 1;
+                    }
+                }
+
+                // Check if free and fits, page aligned allocations can only reuse page aligned segments
+                let fits_alignment = !page_aligned || AllocAddr::from_ptr(base_ptr, big_head as *mut u8).addr % heap::PAGE_BYTES == 0;
+                if (*big_head).free && (*big_head).size_bytes as usize >= layout.size() && fits_alignment {
+                    // Free and will fit, keep it if it is the smallest so far
+                    if found_ptr.map_or(true, |found| (*big_head).size_bytes < (*found).size_bytes) {
+                        found_ptr = Some(big_head);
+                    }
+
+                    // First fit uses the first one found. Best fit keeps searching, unless this one has no intervals to spare
+                    if self.big_alloc_policy == BigAllocPolicy::FirstFit || (*big_head).size_bytes == needed_bytes {
+                        break;
+                    }
+                }
+                
+                // Iterate
+                search_ptr = (*big_head).next;
+            }
+
+            if let Some(big_head) = found_ptr {
+                // Now mark this as being used, as we will use it for this allocation
+                cfg_if! {
+                    if #[cfg(feature = "metrics")] {
+                        (*(*meta_page).metrics).heap_bytes_write += size_of::<bool>();
+                    }
+                }
+
+                (*big_head).free = false; // false = allocated
+                (*big_head).size_class_exp = size_class.exp;
+
+                // Give back any whole intervals this allocation does not need
+                self.split_big_alloc(meta_page, alloc_start_ptr, big_head, layout.size());
+            }
+
+            cfg_if! {
+                if #[cfg(feature = "metrics")] {
+                    let metrics = &mut *(*meta_page).metrics;
+                    metrics.max_big_alloc_scan = metrics.max_big_alloc_scan.max(scanned);
+                }
+            }
+
+            // If no valid free big allocations are found
+            let big_ptr = match found_ptr {
+                Some(ptr) => ptr,
+                None => {
+                    // No free big alloc headers, must allocate one
+                    match self.add_big_alloc(size_class.exp, layout.size(), false) {
+                        Some(ptr) => ptr,
+                        None => return null_mut(),
+                    }
+                },
+            };
+
+            // Compute the allocated address
+            let alloc_addr = big_ptr.offset(1) as *mut u8;
+
+            // Big allocation complete!
+            return alloc_addr;
+        }
+
+        // If program reaches this line we are using MiniPage "small" allocation
+        // Record metrics
+        cfg_if! {
+            if #[cfg(feature = "metrics")] {
+                (*(*meta_page).metrics).total_allocs[size_class.exp_as_idx()] += 1;
+            }
+        }
+
+        // Determine if we need to allocate from a fresh or reused MiniPage
+        let need_alloc_fresh = match self.total_alloc_reused[size_class.exp_as_idx()] > 0 {
+            true => {
+                let fresh_reused_ratio = f64::from(self.total_alloc_fresh[size_class.exp_as_idx()]) / f64::from(self.total_alloc_reused[size_class.exp_as_idx()]);
+                fresh_reused_ratio < self.fresh_reused_ratio
+            },
+            false => false,
+        };
+
+        let (node_ptr, page_idx) = match need_alloc_fresh {
+            true => {
+                // Need to allocate from a fresh minipage                
+                match self.add_minipage(size_class.exp) {
+                    Some((ptr, page_idx)) => {
+                        // Put free indexes of segments on the segments stack for this new MiniPage
+                        self.free_segments_update(ptr);
+                        
+                        (ptr, page_idx)
+                    },
+                    None => {
+                        // No space on host heap
+                        cfg_if! {
+                            if #[cfg(feature = "strict")] {
+                                panic!("{:?}: no room on the heap for a new MiniPage of size class exponent {}", AllocFail::AddMiniPageNoSpace, size_class.exp);
+                            } else if #[cfg(feature = "metrics")] {
+                                self.record_failure(AllocFail::AddMiniPageNoSpace);
+                            }
+                        }
+                        
+                        return null_mut();
+                    },
+                }
+            },
+            false => {
+                // Need to try and allocate from a reused minipage
+                // Find the most recently used MiniPage header for this size class
+                match (*(*meta_page).free_minipages[size_class.exp_as_idx()]).peek().map(expand_page_idx) {
+                    Some(page_idx) => {
+                        // There is a MiniPage with free segments for this size class
+                        cfg_if! {
+                            if #[cfg(feature = "metrics")] {
+                                // For peeking the free_minipages UnsafeStack on the heap
+                                (*(*meta_page).free_minipages[size_class.exp_as_idx()]).record_peek_cost(meta_page);
+                            }
+                        }
+
+                        // Point at the header in the MetaPage, not a copy, so free bitmap updates are kept
+                        let ptr: *mut MiniPageHeader = match (*meta_page).minipage_headers[page_idx].as_mut() {
+                            Some(header) => header,
+                            None => {
+                                cfg_if! {
+                                    if #[cfg(feature = "strict")] {
+                                        panic!("{:?}: no MiniPageHeader for page {} while allocating", AllocFail::MiniPageHeaderNotFound, page_idx);
+                                    } else if #[cfg(feature = "metrics")] {
+                                        self.record_failure(AllocFail::MiniPageHeaderNotFound);
+                                    }
+                                }
+
+                                return null_mut();
+                            },
+                        };
+
+                        // If free segments stack size is 0 => the MiniPage we just peeked was just added and we haven't grabbed the free indexes from the stack yet
+                        if (*(*meta_page).free_segments[size_class.exp_as_idx()]).size == 0 {
+                            self.free_segments_update(ptr);
+                        } 
+
+                        (ptr, usize::from(page_idx))
+                    },
+                    None => {
+                        // If no MiniPage with free segments for the size class was found
+
+                        // This means we have to initialize the first MiniPage for this size class
+                        // Or that there are no free MiniPages
+                        match self.add_minipage(size_class.exp) {
+                            Some((ptr, page_idx)) => {
+                                assert_or_return!((*(*meta_page).free_segments[size_class.exp_as_idx()]).size == 0, null_mut(), "There should be no free segment indexes left here because we didn't find a free MiniPage");
+
+                                // Put free indexes of segments on the segments stack for this new MiniPage
+                                self.free_segments_update(ptr);
+                                
+                                (ptr, page_idx)
+                            },
+                            None => {
+                                // No space on host heap
+                                cfg_if! {
+                                    if #[cfg(feature = "strict")] {
+                                        panic!("{:?}: no room on the heap for a new MiniPage of size class exponent {}", AllocFail::AddMiniPageNoSpace, size_class.exp);
+                                    } else if #[cfg(feature = "metrics")] {
+                                        self.record_failure(AllocFail::AddMiniPageNoSpace);
+                                    }
+                                }
+                                
+                                return null_mut();
+                            },
+                        }
+                    },
+                }
+            },
+        };
+
+        assert_or_return!(!node_ptr.is_null(), null_mut(), "A MiniPageHeader should have been found at which to begin the search for a free segment to allocate");
+        assert_or_return!(!self.minipage_lists[size_class.exp_as_idx()].is_null(), null_mut(), "Since a MiniPageHeader to begin the search was found, the head of this size class's free free list should not be null");
+
+        // Find the next free segment
+        let next_free_segment_idx: u16 = match (*(*meta_page).free_segments[size_class.exp_as_idx()]).pop() {
+            Some(idx) => {
+                 // The free segments stack for the MiniPage had segments on it
+                cfg_if! {
+                    if #[cfg(feature = "metrics")] {
+                        // For popping a segment index of an UnsafeStack in the heap
+                        (*(*meta_page).free_segments[size_class.exp_as_idx()]).record_pop_cost(meta_page);
+                    }
+                }
+                
+                idx
+            },
+            None => {
+                // Fatal error: There are no free segments in the current MiniPage. This should not occur! As the current MiniPage was taken off of free_minipages. A stack where only MiniPages with free segments are stored.
+                cfg_if! {
+                    if #[cfg(feature = "strict")] {
+                        panic!("{:?}: MiniPage {:?} of size class exponent {} was on the free minipages stack with no free segments", AllocFail::FreeMiniPagesContractBreach, node_ptr, size_class.exp);
+                    } else if #[cfg(feature = "metrics")] {
+                        self.record_failure(AllocFail::FreeMiniPagesContractBreach);
+                    }
+                }
+
+                
+                return null_mut();
+            },
+        };
+
+        assert_or_return!(!node_ptr.is_null(), null_mut(), "node_ptr should not be null");
+
+        // Count allocation as either using a reused MiniPage or a fresh MiniPage
+        // We must do this before the next block, where fresh_minipages is potentially reset.
+        if self.fresh_minipages[size_class.exp_as_idx()] == node_ptr {
+            self.total_alloc_fresh[size_class.exp_as_idx()] += 1;
+        } else {
+            self.total_alloc_reused[size_class.exp_as_idx()] += 1;
+        }
+
+        // Determine if the MiniPage we just got a free segment index from still has free space after this allocation
+        if (*(*meta_page).free_segments[size_class.exp_as_idx()]).size == 0 {
+            // After this allocation this MiniPage will no longer have any free segments
+            // Remove from free_minipages
+            (*(*meta_page).free_minipages[size_class.exp_as_idx()]).pop();
+            
+            (*node_ptr).on_free_minipages_stack = false;
+
+            cfg_if! {
+                if #[cfg(feature = "metrics")] {
+                    // For popping a MiniPage of an UnsafeStack in the heap
+                    (*(*meta_page).free_minipages[size_class.exp_as_idx()]).record_pop_cost(meta_page);
+
+                    // For setting the on_free_minipages_stack field on a MiniPageHeader in the heap
+                    (*(*meta_page).metrics).heap_bytes_write += size_of::<bool>();
+                }
+            }
+
+            // If this MiniPage was also considered "fresh" then unmark as fresh
+            if self.fresh_minipages[size_class.exp_as_idx()] == node_ptr {
+                self.fresh_minipages[size_class.exp_as_idx()] = null_mut();
+            }
+        }
+
+        // Determine address we will allocate
+        let page_meta = MiniPageMeta::new(page_idx);
+        let segment = page_meta.get_segment(size_class, usize::from(next_free_segment_idx));
+
+        // Mark segment as not free
+        (*node_ptr).write_free_bitmap(segment, false);
+
+        cfg_if! {
+            if #[cfg(feature = "metrics")] {
+                // For writing to a MiniPageHeader free_segments byte on the heap
+                (*(*meta_page).metrics).heap_bytes_write += size_of::<bool>();
+            }
+        }
+
+        cfg_if! {
+            if #[cfg(feature = "requested-size")] {
+                // The size class fits the request, so it is at most 2^MAX_SIZE_CLASS
+                (*node_ptr).requested_bytes[segment.segment_idx] = layout.size() as u16;
+
+                cfg_if! {
+                    if #[cfg(feature = "metrics")] {
+                        // For writing to a MiniPageHeader requested_bytes item on the heap
+                        (*(*meta_page).metrics).heap_bytes_write += size_of::<u16>();
+                    }
+                }
+            }
+        }
+
+        // assert!(false,  "alloc made node_ptr={:?}", *node_ptr);
+
+        // Return address, if it is actually within the region of the heap we manage
+        let region_bytes = ((self.heap_pages as u32) * heap::PAGE_BYTES) - AllocAddr::from_ptr(base_ptr, alloc_start_ptr).addr;
+        match segment.as_addr().try_as_ptr(alloc_start_ptr, region_bytes) {
+            Some(ptr) => ptr,
+            None => {
+                cfg_if! {
+                    if #[cfg(feature = "strict")] {
+                        panic!("{:?}: segment address {:#x} is outside the {} byte heap region", AllocFail::AddrOutOfRange, segment.as_addr().addr, region_bytes);
+                    } else if #[cfg(feature = "metrics")] {
+                        self.record_failure(AllocFail::AddrOutOfRange);
+                    }
+                }
+
+                null_mut()
+            },
+        }
     }
 
     unsafe fn dealloc(&mut self, ptr: *mut u8, _layout: Layout) {
@@ -1260,189 +3758,1059 @@ impl<H> AllocatorImpl<H> where H: HostHeap {
 		  Err(_) => return,
 	   };
 
-	   let addr = AllocAddr::from_ptr(alloc_start_ptr, ptr);
-        let page_meta = MiniPageMeta::from_addr(addr);
+        // A pointer before the MiniPages, ie., into the MetaPage, would make AllocAddr::from_ptr() panic, and one far enough past them indexes outside the MetaPage's tables
+        if ptr < alloc_start_ptr || ptr >= next_alloc_ptr {
+            cfg_if! {
+                if #[cfg(feature = "strict")] {
+                    panic!("{:?}: dealloc of {:?}, which is not in the allocator's MiniPages or big allocations", AllocFail::InvalidFreePointer, ptr);
+                } else if #[cfg(feature = "metrics")] {
+                    self.record_failure(AllocFail::InvalidFreePointer);
+                }
+            }
+
+            return;
+        }
+
+	   let addr = AllocAddr::from_ptr(alloc_start_ptr, ptr);
+        let page_meta = MiniPageMeta::from_addr(addr);
+
+	   // Determine if big alloc
+	   match (*meta_page).big_alloc_flags[page_meta.page_idx] {
+		  Some(big_alloc_flag) => {
+			 // Is big alloc
+			 
+			 // Memory was allocated using the big allocation technique
+			 // Record metrics
+			 cfg_if! {
+				if #[cfg(feature = "metrics")] {
+                        (*(*meta_page).metrics).total_deallocs[NUM_SIZE_CLASSES as usize] += 1;
+				}
+			 }
+
+			 // The flag points at the interval the allocation's header is in, no need to search the big allocation list
+			 let big_head = MiniPageMeta::new(big_alloc_flag.start_idx()).addr.as_ptr(alloc_start_ptr) as *mut BigAllocHeader;
+
+			 cfg_if! {
+				if #[cfg(feature = "metrics")] {
+                        (*(*meta_page).metrics).heap_bytes_read += size_of::<BigAllocHeader>();
+                        (*(*meta_page).metrics).big_dealloc_header_reads += 1;
+                        (*(*meta_page).metrics).max_big_alloc_scan = (*(*meta_page).metrics).max_big_alloc_scan.max(1);
+				}
+			 }
+
+			 // Check in big allocation header's range. A null big_head means the flag points outside the heap, nothing is in its range
+			 let in_range = !big_head.is_null() && {
+				let start_addr = AllocAddr::from_ptr(alloc_start_ptr, big_head.offset(1) as *mut u8);
+				let end_addr = AllocAddr::new(u32::from(start_addr.addr) + (*big_head).size_bytes);
+
+				addr.addr >= start_addr.addr && addr.addr <= end_addr.addr
+			 };
+
+			 if in_range {
+				// In range, big_head is the header this allocation came from
+				// Ensure it was not already freed
+				if (*big_head).free {
+				    cfg_if! {
+					   if #[cfg(feature = "strict")] {
+						  panic!("{:?}: big allocation {:?} for {:?} is already free", AllocFail::DoubleFree, big_head, ptr);
+					   } else if #[cfg(feature = "metrics")] {
+						  self.record_failure(AllocFail::DoubleFree);
+					   }
+				    }
+
+				    return;
+				}
+
+				// Now free!
+				cfg_if! {
+				    if #[cfg(feature = "metrics")] {
+					   (*(*meta_page).metrics).heap_bytes_write += size_of::<bool>();
+				    }
+				}
+
+				(*big_head).free = true; // true = unallocated
+				self.coalesce_big_alloc(meta_page, alloc_start_ptr, big_head);
+
+				return;
+			 }
+
+			 // ptr is not inside the allocation its big allocation flag points at. Which means the deallocation call is invalid.
+
+			 cfg_if! {
+				if #[cfg(feature = "strict")] {
+                        panic!("{:?}: no BigAllocHeader found for dealloc of {:?}", AllocFail::BigDeallocHeaderNotFound, ptr);
+				} else if #[cfg(feature = "metrics")] {
+                        self.record_failure(AllocFail::BigDeallocHeaderNotFound);
+				}
+			 }
+
+			 return;
+		  },
+		  None => {
+			 // Normal alloc, or no allocation at this address at all
+			 
+			 // Memory was allocated using MiniPages
+			 // MiniPages of large size classes span several intervals, use the first which has the header
+			 let page_meta = MiniPageMeta::new((*meta_page).minipage_start(page_meta.page_idx));
+
+			 // Read the size class
+			 // Point at the header in the MetaPage, not a copy, so the free bitmap update below is kept
+			 let minipage_header: *mut MiniPageHeader = match (*meta_page).minipage_headers[page_meta.page_idx].as_mut() {
+				Some(header) => header,
+				None => {
+				    cfg_if! {
+					   if #[cfg(feature = "strict")] {
+						  panic!("{:?}: no MiniPageHeader for page {} while deallocating {:?}", AllocFail::MiniPageHeaderNotFound, page_meta.page_idx, ptr);
+					   } else if #[cfg(feature = "metrics")] {
+						  self.record_failure(AllocFail::MiniPageHeaderNotFound);
+					   }
+				    }
+
+				    return;
+				},
+			 };
+			 let size_class = SizeClass::new((*minipage_header).size_class_exp);
+
+			 // Record metrics
+			 cfg_if! {
+				if #[cfg(feature = "metrics")] {
+                        (*(*meta_page).metrics).total_deallocs[size_class.exp_as_idx()] += 1;
+				}
+			 }
+
+			 // Determine segment
+			 let segment = addr.get_segment(page_meta, size_class);
+
+			 // Ensure segment was previously allocated
+			 if (*minipage_header).get_free_bitmap(segment) {
+				// Segment not allocated
+				cfg_if! {
+                        if #[cfg(feature = "strict")] {
+					   panic!("{:?}: segment {} of page {} for {:?} is already free", AllocFail::DoubleFree, segment.segment_idx, page_meta.page_idx, ptr);
+                        } else if #[cfg(feature = "metrics")] {
+					   // For reading from a MiniPageHeader free_segments byte on the heap
+					   (*(*meta_page).metrics).heap_bytes_read += size_of::<bool>();
+
+					   self.record_failure(AllocFail::DoubleFree);
+                        }
+				}
+				
+				return;
+			 }
+
+			 // Update segment bitmap
+			 (*minipage_header).write_free_bitmap(segment, true); // true = free
+
+			 cfg_if! {
+				if #[cfg(feature = "metrics")] {
+                        // For writing to a MiniPageHeader free_segments byte on the heap
+                        (*(*meta_page).metrics).heap_bytes_write += size_of::<bool>();
+				}
+			 }
+
+			 // Push onto free segments stack if minipage is the current MiniPage.
+			 // If the MiniPage is on the free minipages stack but is not the head the freed segment is only recorded in the bitmap. This is enough: once the MiniPage becomes the head the free segments stack is empty, so alloc rebuilds it from the bitmap with free_segments_update().
+			 // The same goes for a head whose free segments stack is empty, it has not been rebuilt yet. Pushing onto it would stop alloc from rebuilding it, so the head's other free segments would be left only in the bitmap.
+			 if (*(*meta_page).free_minipages[size_class.exp_as_idx()]).peek() == Some(compact_page_idx(page_meta.page_idx)) {
+				if (*(*meta_page).free_segments[size_class.exp_as_idx()]).size > 0 && (*(*meta_page).free_segments[size_class.exp_as_idx()]).push(segment.segment_idx_u16()).is_none() {
+				    cfg_if! {
+					   if #[cfg(feature = "strict")] {
+						  panic!("{:?}: free segments stack of size class {} is full, could not push freed segment {} of page {}", AllocFail::StackOverflow, size_class.exp, segment.segment_idx, page_meta.page_idx);
+					   } else if #[cfg(feature = "metrics")] {
+						  self.record_failure(AllocFail::StackOverflow);
+					   }
+				    }
+				}
+
+				cfg_if! {
+                        if #[cfg(feature = "metrics")] {
+					   // For peeking the free_minipages UnsafeStack on the heap
+					   (*(*meta_page).free_minipages[size_class.exp_as_idx()]).record_peek_cost(meta_page);
+					   
+					   // For pushing a free segment onto the free_segments UnsafeStack on the heap
+					   (*(*meta_page).free_segments[size_class.exp_as_idx()]).record_push_cost(meta_page);
+                        }
+				}
+			 } else if !(*minipage_header).on_free_minipages_stack {
+				// Not pushed on minipages stack
+				// First time we have deallocated from this MiniPage since it was full
+				
+				debug_assert!(!(*(*meta_page).free_minipages[size_class.exp_as_idx()]).contains(compact_page_idx(page_meta.page_idx)), "MiniPage {} is on the size class {} free minipages stack but its on_free_minipages_stack flag is false", page_meta.page_idx, size_class.exp);
+				if (*(*meta_page).free_minipages[size_class.exp_as_idx()]).push(compact_page_idx(page_meta.page_idx)).is_none() {
+				    cfg_if! {
+					   if #[cfg(feature = "strict")] {
+						  panic!("{:?}: free minipages stack of size class {} is full, could not push MiniPage {}", AllocFail::StackOverflow, size_class.exp, page_meta.page_idx);
+					   } else if #[cfg(feature = "metrics")] {
+						  self.record_failure(AllocFail::StackOverflow);
+					   }
+				    }
+				}
+				(*minipage_header).on_free_minipages_stack = true;
+
+				// The free segments stack holds the indexes of the old head, which are for a different MiniPage. They are still in the old head's bitmap, so drop them and let alloc rebuild the stack for this MiniPage with free_segments_update()
+				(*(*meta_page).free_segments[size_class.exp_as_idx()]).clear();
+				
+				cfg_if! {
+                        if #[cfg(feature = "metrics")] {
+					   // For pushing a MiniPageHeader pointer onto the free_minipages UnsafeStack on the heap
+					   (*(*meta_page).free_minipages[size_class.exp_as_idx()]).record_push_cost(meta_page);
+
+					   // For setting the on_free_minipages_stack field on a MiniPageHeader in the heap
+					   (*(*meta_page).metrics).heap_bytes_write += size_of::<bool>();
+                        }
+				}
+			 }
+
+			 cfg_if! {
+				if #[cfg(feature = "metrics")] {
+                        // For reading the (*minipage_header).on_free_minipages_stack bool from the heap
+                        (*(*meta_page).metrics).heap_bytes_read += size_of::<bool>();
+				}
+			 }
+
+			 // A MiniPage with no allocations left can be used by any size class. Only the head of the free minipages stack can be taken off the stack, other empty MiniPages stay with their size class. MiniPages which span several intervals also stay with their size class, reclaimed MiniPages are one interval
+			 if (*minipage_header).used_segments_count() == 0 && size_class.minipage_intervals() == 1 && (*(*meta_page).free_minipages[size_class.exp_as_idx()]).peek() == Some(compact_page_idx(page_meta.page_idx)) {
+				self.reclaim_minipage(meta_page, page_meta.page_idx, minipage_header);
+			 }
+		  }
+	   }
+    }
+
+    /// Returns the index of the first interval of the MiniPage ptr was allocated from, None if ptr is from a big allocation.
+    unsafe fn minipage_of(meta_page: *mut MetaPage, alloc_start_ptr: *mut u8, ptr: *mut u8) -> Option<usize> {
+        let page_idx = MiniPageMeta::from_addr(AllocAddr::from_ptr(alloc_start_ptr, ptr)).page_idx;
+
+        match (*meta_page).big_alloc_flags[page_idx] {
+            Some(_) => None,
+            None => Some((*meta_page).minipage_start(page_idx)),
+        }
+    }
+
+    /// Free every allocation in ptrs. Consecutive pointers from the same MiniPage are freed together by dealloc_minipage_segments(), which updates the MiniPage's free stacks once instead of once per pointer. Pointers from big allocations are freed like dealloc(). Does not allocate, so pointers are only grouped if they are next to each other in ptrs, sort them by address to group them all. Unlike dealloc() this records the usage metrics itself, each pointer's must be recorded right before it is freed.
+    unsafe fn dealloc_many(&mut self, ptrs: &[(*mut u8, Layout)]) {
+        let (_base_ptr, meta_page, alloc_start_ptr, _next_alloc_ptr) = match self.ensure_heap() {
+		  Ok(v) => v,
+		  Err(_) => return,
+	   };
+
+        let mut run_start = 0;
+        while run_start < ptrs.len() {
+            let page_idx = match AllocatorImpl::<H>::minipage_of(meta_page, alloc_start_ptr, ptrs[run_start].0) {
+                Some(page_idx) => page_idx,
+                None => {
+                    cfg_if! {
+                        if #[cfg(feature = "metrics")] {
+                            self.record_usage(ptrs[run_start].0, ptrs[run_start].1.size(), false);
+                        }
+                    }
+
+                    self.dealloc(ptrs[run_start].0, ptrs[run_start].1);
+                    run_start += 1;
+                    continue;
+                },
+            };
+
+            let mut run_end = run_start + 1;
+            while run_end < ptrs.len() && AllocatorImpl::<H>::minipage_of(meta_page, alloc_start_ptr, ptrs[run_end].0) == Some(page_idx) {
+                run_end += 1;
+            }
+
+            self.dealloc_minipage_segments(meta_page, alloc_start_ptr, page_idx, &ptrs[run_start..run_end]);
+            run_start = run_end;
+        }
+    }
+
+    /// Free ptrs, which are all segments of the MiniPage whose first interval is page_idx. Same as calling dealloc() for each, except the free segments and free minipages stacks are updated once for the whole MiniPage. Bitmap bytes holding several of the freed segments are only counted as written once.
+    unsafe fn dealloc_minipage_segments(&mut self, meta_page: *mut MetaPage, alloc_start_ptr: *mut u8, page_idx: usize, ptrs: &[(*mut u8, Layout)]) {
+        let page_meta = MiniPageMeta::new(page_idx);
+
+        // Point at the header in the MetaPage, not a copy, so the free bitmap updates below are kept
+        let minipage_header: *mut MiniPageHeader = match (*meta_page).minipage_headers[page_meta.page_idx].as_mut() {
+            Some(header) => header,
+            None => {
+                cfg_if! {
+                    if #[cfg(feature = "strict")] {
+                        panic!("{:?}: no MiniPageHeader for page {} while deallocating {} pointers", AllocFail::MiniPageHeaderNotFound, page_meta.page_idx, ptrs.len());
+                    } else if #[cfg(feature = "metrics")] {
+                        self.record_failure(AllocFail::MiniPageHeaderNotFound);
+                    }
+                }
+
+                return;
+            },
+        };
+        let size_class = SizeClass::new((*minipage_header).size_class_exp);
+
+        // Update the bitmap for every pointer
+        let mut freed_any = false;
+        let mut last_written_byte_idx: Option<usize> = None;
+        for item in ptrs.iter() {
+            let ptr = item.0;
+
+            cfg_if! {
+                if #[cfg(feature = "metrics")] {
+                    (*(*meta_page).metrics).total_deallocs[size_class.exp_as_idx()] += 1;
+                    self.record_usage(ptr, item.1.size(), false);
+                }
+            }
+
+            let segment = AllocAddr::from_ptr(alloc_start_ptr, ptr).get_segment(page_meta, size_class);
+
+            // Ensure segment was previously allocated
+            if (*minipage_header).get_free_bitmap(segment) {
+                cfg_if! {
+                    if #[cfg(feature = "strict")] {
+                        panic!("{:?}: segment {} of page {} for {:?} is already free", AllocFail::DoubleFree, segment.segment_idx, page_meta.page_idx, ptr);
+                    } else if #[cfg(feature = "metrics")] {
+                        // For reading from a MiniPageHeader free_segments byte on the heap
+                        (*(*meta_page).metrics).heap_bytes_read += size_of::<bool>();
+
+                        self.record_failure(AllocFail::DoubleFree);
+                    }
+                }
+
+                continue;
+            }
+
+            (*minipage_header).write_free_bitmap(segment, true); // true = free
+            freed_any = true;
+
+            if last_written_byte_idx != Some(segment.bitmap_byte_idx) {
+                cfg_if! {
+                    if #[cfg(feature = "metrics")] {
+                        // For writing to a MiniPageHeader free_segments byte on the heap
+                        (*(*meta_page).metrics).heap_bytes_write += size_of::<u8>();
+                    }
+                }
+
+                last_written_byte_idx = Some(segment.bitmap_byte_idx);
+            }
+        }
+
+        if !freed_any {
+            return;
+        }
+
+        if (*(*meta_page).free_minipages[size_class.exp_as_idx()]).peek() == Some(compact_page_idx(page_meta.page_idx)) {
+            // The MiniPage is the head. Instead of pushing every freed segment onto the free segments stack empty it, alloc rebuilds it from the bitmap with free_segments_update()
+            (*(*meta_page).free_segments[size_class.exp_as_idx()]).clear();
+
+            cfg_if! {
+                if #[cfg(feature = "metrics")] {
+                    // For peeking the free_minipages UnsafeStack on the heap
+                    (*(*meta_page).free_minipages[size_class.exp_as_idx()]).record_peek_cost(meta_page);
+                }
+            }
+        } else if !(*minipage_header).on_free_minipages_stack {
+            // First time we have deallocated from this MiniPage since it was full
+            debug_assert!(!(*(*meta_page).free_minipages[size_class.exp_as_idx()]).contains(compact_page_idx(page_meta.page_idx)), "MiniPage {} is on the size class {} free minipages stack but its on_free_minipages_stack flag is false", page_meta.page_idx, size_class.exp);
+            if (*(*meta_page).free_minipages[size_class.exp_as_idx()]).push(compact_page_idx(page_meta.page_idx)).is_none() {
+                cfg_if! {
+                    if #[cfg(feature = "strict")] {
+                        panic!("{:?}: free minipages stack of size class {} is full, could not push MiniPage {}", AllocFail::StackOverflow, size_class.exp, page_meta.page_idx);
+                    } else if #[cfg(feature = "metrics")] {
+                        self.record_failure(AllocFail::StackOverflow);
+                    }
+                }
+            }
+            (*minipage_header).on_free_minipages_stack = true;
+
+            // The free segments stack holds the indexes of the old head, see dealloc()
+            (*(*meta_page).free_segments[size_class.exp_as_idx()]).clear();
+
+            cfg_if! {
+                if #[cfg(feature = "metrics")] {
+                    // For pushing a MiniPageHeader pointer onto the free_minipages UnsafeStack on the heap
+                    (*(*meta_page).free_minipages[size_class.exp_as_idx()]).record_push_cost(meta_page);
+
+                    // For setting the on_free_minipages_stack field on a MiniPageHeader in the heap
+                    (*(*meta_page).metrics).heap_bytes_write += size_of::<bool>();
+                }
+            }
+        }
+
+        cfg_if! {
+            if #[cfg(feature = "metrics")] {
+                // For reading the (*minipage_header).on_free_minipages_stack bool from the heap
+                (*(*meta_page).metrics).heap_bytes_read += size_of::<bool>();
+            }
+        }
+
+        // Same as dealloc(), an empty MiniPage at the head of the free minipages stack can be used by any size class
+        if (*minipage_header).used_segments_count() == 0 && size_class.minipage_intervals() == 1 && (*(*meta_page).free_minipages[size_class.exp_as_idx()]).peek() == Some(compact_page_idx(page_meta.page_idx)) {
+            self.reclaim_minipage(meta_page, page_meta.page_idx, minipage_header);
+        }
+    }
+}
+
+/// The custom global allocator. Wraps the AllocatorImpl
+/// which performs all memory operations. See for more details.
+pub struct AlligatorAlloc<H> where H: HostHeap {
+    /// Data structure which keeps state of all memory
+    /// wrapped inside an UnsafeCell for
+    /// memory symantics.
+    alloc: UnsafeCell<AllocatorImpl<H>>,
 
-	   // Determine if big alloc
-	   match (*meta_page).big_alloc_flags[page_meta.page_idx] {
-		  Some(big_alloc_flag) => {
-			 // Is big alloc
-			 
-			 // Memory was allocated using the big allocation technique
-			 // Record metrics
-			 cfg_if! {
-				if #[cfg(feature = "metrics")] {
-                        (*(*meta_page).metrics).total_deallocs[NUM_SIZE_CLASSES as usize] += 1;
-				}
-			 }
+    /// True while a GlobalAlloc method is using alloc. See AlligatorAlloc::lock().
+    #[cfg(feature = "sync")]
+    locked: AtomicBool,
+}
+
+/// WASM is single threaded right now so this should be okay. On multithreaded hosts enable the sync feature, which makes the GlobalAlloc methods take a lock.
+unsafe impl<H> Sync for AlligatorAlloc<H> where H: HostHeap {}
+
+cfg_if! {
+    if #[cfg(feature = "sync")] {
+        /// Held by a GlobalAlloc method while it uses an AlligatorAlloc's AllocatorImpl. Unlocks when dropped.
+        struct SpinLockGuard<'a> {
+            /// AlligatorAlloc.locked of the allocator which is locked.
+            locked: &'a AtomicBool,
+        }
+
+        impl Drop for SpinLockGuard<'_> {
+            fn drop(&mut self) {
+                self.locked.store(false, Ordering::Release);
+            }
+        }
+    }
+}
+
+/// Includes statistics on which allocations were made from MiniPages which were fresh (never been fully filled up) or reused (been fully filled up, then freed into action again).
+#[derive(Copy, Clone, Debug)]
+pub struct FreshReusedStats {
+    /// Total number of allocations for each size class which were performed from a reused MiniPage header.
+    pub total_alloc_reused: [u32; NUM_SIZE_CLASSES_USIZE],
+
+    /// Total number of allocations for each size class which were performed from a newly allocated MiniPage header.
+    pub total_alloc_fresh: [u32; NUM_SIZE_CLASSES_USIZE],
+}
+
+/// How full each size class's MiniPages are. Compare with FreshReusedStats when tuning the fresh to reused ratio, a larger ratio spreads the same live segments over more MiniPages. See AlligatorAlloc::occupancy_stats().
+#[derive(Copy, Clone, Debug)]
+pub struct OccupancyStats {
+    /// Number of allocated segments for each size class, counted from the MiniPage free bitmaps. Index 0 is the MIN_SIZE_CLASS and the last index is the MAX_SIZE_CLASS.
+    pub live_segments: [u32; NUM_SIZE_CLASSES_USIZE],
+
+    /// Number of MiniPages each size class has, live or empty. Reclaimed MiniPages are not counted, they belong to no size class until they are reused.
+    pub minipages_per_class: [u32; NUM_SIZE_CLASSES_USIZE],
+}
+
+impl OccupancyStats {
+    /// Fraction of size_class's segments which are allocated, between 0 and 1. 0 if the size class has no MiniPages.
+    pub fn occupancy(&self, size_class: SizeClass) -> f64 {
+        let capacity = self.minipages_per_class[size_class.exp_as_idx()] * u32::from(size_class.segments_max_num());
+        match capacity {
+            0 => 0.0,
+            _ => f64::from(self.live_segments[size_class.exp_as_idx()]) / f64::from(capacity),
+        }
+    }
+}
+
+/// Number of live bytes, broken down by how they were allocated.
+#[derive(Copy, Clone, Debug)]
+pub struct LiveBreakdown {
+    /// Live bytes for each size class, the number of allocated segments multiplied by the segment size. Index 0 is the MIN_SIZE_CLASS and the last index is the MAX_SIZE_CLASS.
+    pub small: [u32; NUM_SIZE_CLASSES_USIZE],
+
+    /// Live bytes in big allocations, the sum of size_bytes for every allocated BigAllocHeader.
+    pub big: u32,
+}
+
+/// Summary of the live allocations. See AlligatorAlloc::live_stats().
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct LiveStats {
+    /// Bytes handed out to live allocations. The segment size for MiniPage allocations and size_bytes for big allocations, so this includes rounding up to the size class.
+    pub bytes_in_use: usize,
+
+    /// Number of MiniPages with at least one live segment.
+    pub live_minipages: u32,
+
+    /// Number of big allocations which have not been freed.
+    pub live_big_allocs: u32,
+
+    /// Number of live segments for each size class. Index 0 is the MIN_SIZE_CLASS and the last index is the MAX_SIZE_CLASS.
+    pub live_segments: [u32; NUM_SIZE_CLASSES_USIZE],
+}
+
+/// Most pointers one AlligatorAlloc::compact() call will move. Once this many have been moved compaction stops early and CompactReport::complete is false, call compact() again to continue.
+pub const COMPACT_MAX_RELOCATIONS: usize = 256;
+
+/// One allocation moved by AlligatorAlloc::compact().
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Relocation {
+    /// Where the allocation used to be. This memory is free now.
+    pub old_ptr: *mut u8,
+
+    /// Where the allocation's bytes were copied to.
+    pub new_ptr: *mut u8,
+}
+
+/// Result of AlligatorAlloc::compact(). Lists every allocation which was moved, callers must update their pointers with new_ptr().
+#[derive(Copy, Clone, Debug)]
+pub struct CompactReport {
+    /// Moved allocations, only the first len are set.
+    relocations: [Relocation; COMPACT_MAX_RELOCATIONS],
+
+    /// Number of relocations set.
+    len: usize,
+
+    /// Number of MiniPages given back to the allocator's reclaimed MiniPages, so they can be used by any size class.
+    pub freed_minipages: u32,
+
+    /// False if compaction stopped because COMPACT_MAX_RELOCATIONS allocations were moved, a MiniPage which could have been emptied was left as is.
+    pub complete: bool,
+}
+
+impl CompactReport {
+    /// Every allocation moved by the compact() call, in the order they were moved.
+    pub fn relocations(&self) -> &[Relocation] {
+        &self.relocations[..self.len]
+    }
+
+    /// Returns where the allocation at old_ptr was moved to, or old_ptr if it was not moved.
+    pub fn new_ptr(&self, old_ptr: *mut u8) -> *mut u8 {
+        match self.relocations().iter().find(|relocation| relocation.old_ptr == old_ptr) {
+            Some(relocation) => relocation.new_ptr,
+            None => old_ptr,
+        }
+    }
+}
+
+/// Point in time copy of the allocator's live bytes and page usage. Compare two with StatsSnapshot::diff() to find which size class grew.
+#[derive(Copy, Clone, Debug)]
+pub struct StatsSnapshot {
+    /// Live bytes for each size class and for big allocations.
+    pub live: LiveBreakdown,
+
+    /// Number of MiniPages for each size class. Index 0 is the MIN_SIZE_CLASS and the last index is the MAX_SIZE_CLASS.
+    pub minipages: [u32; NUM_SIZE_CLASSES_USIZE],
+
+    /// Number of MiniPage sized intervals of the heap taken up by big allocations, free or not.
+    pub big_pages: u32,
+
+    /// Number of MiniPage sized intervals of the heap handed out to MiniPages or big allocations.
+    pub used_pages: u32,
+}
+
+/// Change between two StatsSnapshots, later minus earlier. Same fields as StatsSnapshot.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct StatsDiff {
+    /// Change in live bytes for each size class.
+    pub live_small: [i64; NUM_SIZE_CLASSES_USIZE],
+
+    /// Change in live bytes in big allocations.
+    pub live_big: i64,
+
+    /// Change in the number of MiniPages for each size class.
+    pub minipages: [i64; NUM_SIZE_CLASSES_USIZE],
+
+    /// Change in the number of MiniPage sized intervals taken up by big allocations.
+    pub big_pages: i64,
+
+    /// Change in the number of MiniPage sized intervals handed out.
+    pub used_pages: i64,
+}
+
+impl StatsSnapshot {
+    /// Returns the change from earlier to this snapshot.
+    pub fn diff(&self, earlier: &StatsSnapshot) -> StatsDiff {
+        let mut diff = StatsDiff{
+            live_small: [0; NUM_SIZE_CLASSES_USIZE],
+            live_big: i64::from(self.live.big) - i64::from(earlier.live.big),
+            minipages: [0; NUM_SIZE_CLASSES_USIZE],
+            big_pages: i64::from(self.big_pages) - i64::from(earlier.big_pages),
+            used_pages: i64::from(self.used_pages) - i64::from(earlier.used_pages),
+        };
+
+        for i in 0..NUM_SIZE_CLASSES_USIZE {
+            diff.live_small[i] = i64::from(self.live.small[i]) - i64::from(earlier.live.small[i]);
+            diff.minipages[i] = i64::from(self.minipages[i]) - i64::from(earlier.minipages[i]);
+        }
+
+        diff
+    }
+}
+
+/// How a live allocation was made. See AlligatorAlloc::allocation_kind().
+/// How a big allocation picks which free big allocation to reuse. Set with AlligatorAlloc::with_big_alloc_policy().
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum BigAllocPolicy {
+    /// Reuse the first free big allocation in the big allocation list which fits. Stops searching sooner, but a small request can take, and split, a large free big allocation which a later large request needed.
+    FirstFit,
+
+    /// Reuse the smallest free big allocation which fits, searching the whole big allocation list unless one fits exactly. Any whole intervals left over are split off, see AllocMetrics::big_splits. The default.
+    BestFit,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum AllocKind {
+    /// A segment of a MiniPage. size_class_exp is the exponent of the segment's size class.
+    Small { size_class_exp: u8 },
+
+    /// A big allocation. size_bytes is the number of bytes after its BigAllocHeader.
+    Big { size_bytes: u32 },
+}
+
+/// An allocator invariant which AlligatorAlloc::validate_heap() found broken. MiniPages and big allocations are identified by the index of their first MiniPage sized interval, counting from the first MiniPage.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum HeapInvariantViolation {
+    /// next_alloc_ptr is before the first MiniPage or past the host pages the allocator may use. next_alloc_offset is its offset from the host heap base.
+    NextAllocOutOfBounds { next_alloc_offset: usize },
+
+    /// A MiniPage's on_free_minipages_stack flag does not match whether it is on its size class's free minipages stack.
+    OnFreeStackMismatch { page_idx: usize, on_free_minipages_stack: bool },
+
+    /// A MiniPage with no free segments is on its size class's free minipages stack.
+    FullMiniPageOnFreeStack { page_idx: usize, size_class_exp: u8 },
+
+    /// A free minipages stack holds an index which is not a MiniPage of its size class.
+    FreeStackNotMiniPage { page_idx: usize, size_class_exp: u8 },
+
+    /// A big allocation in the big allocation list is not inside the MiniPage intervals handed out so far. ptr is the address of its BigAllocHeader.
+    BigAllocOutOfBounds { ptr: usize },
+
+    /// A big allocation does not start after the previous big allocation in the list, the list must be ordered by address.
+    BigAllocOutOfOrder { page_idx: usize, prev_page_idx: usize },
+
+    /// A big allocation starts before the previous big allocation in the list ends.
+    BigAllocOverlap { page_idx: usize, prev_page_idx: usize },
+
+    /// The big allocation flag of the interval at page_idx does not match the big allocation list. It is missing or points at the wrong BigAllocHeader for an interval a big allocation covers, or is set for an interval no big allocation covers.
+    BigAllocFlagMismatch { page_idx: usize },
+}
+
+cfg_if! {
+    if #[cfg(feature = "mock")] {
+        /// Ways AlligatorAlloc::corrupt() can break the allocator's invariants.
+        #[derive(Copy, Clone, Debug)]
+        pub enum HeapCorruption {
+            /// Mark every segment of the MiniPage at the head of the size class's free minipages stack allocated, without taking it off the stack.
+            FillFreeMiniPage { size_class_exp: u8 },
+
+            /// Clear the on_free_minipages_stack flag of the MiniPage at the head of the size class's free minipages stack.
+            ClearOnFreeStackFlag { size_class_exp: u8 },
+
+            /// Grow the first big allocation in the big allocation list by one MiniPage interval, over whatever is after it.
+            GrowFirstBigAlloc,
+
+            /// Move next_alloc_ptr past the end of the host pages the allocator may use.
+            NextAllocPastHeap,
+        }
+    }
+}
+
+/// The technique the allocator uses to satisfy an allocation request.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum AllocStrategy {
+    /// Allocated from a segment of a MiniPage. Constant time. Used for requests which fit in MAX_SIZE_CLASS.
+    MiniPage,
+
+    /// Allocated using the big allocation technique. Linear time. Used for requests larger than MAX_SIZE_CLASS.
+    Big,
+}
+
+/// Describes how the allocator would satisfy a request for a number of bytes. See AlligatorAlloc::explain().
+#[derive(Copy, Clone, Debug)]
+pub struct AllocPlan {
+    /// Number of bytes which were requested.
+    pub requested_bytes: usize,
+
+    /// Size class exponent the request maps to.
+    pub size_class_exp: u8,
+
+    /// Technique used to allocate the request.
+    pub strategy: AllocStrategy,
+
+    /// Number of bytes the caller can actually use. For MiniPage allocations this is the segment size, for big allocations this is the BigAllocHeader.size_bytes value.
+    pub usable_bytes: usize,
+
+    /// Total number of heap bytes taken up by the allocation, including any header stored in the heap. MiniPageHeaders are stored in the MetaPage and are not counted.
+    pub footprint_bytes: usize,
+
+    /// Bytes of the footprint which were not requested: footprint_bytes - requested_bytes.
+    pub internal_frag_bytes: usize,
+}
+
+impl AlligatorAlloc<HeapType> {
+    pub const INIT: AlligatorAlloc<HeapType> = AlligatorAlloc{
+        alloc: UnsafeCell::new(AllocatorImpl::INIT),
+
+        #[cfg(feature = "sync")]
+        locked: AtomicBool::new(false),
+    };
+
+    /// Allocator which uses at most max_pages host pages, instead of MAX_HOST_PAGES like INIT. Allocations which do not fit fail like they would once INIT's pages are full.
+    ///
+    /// # Panics
+    /// If max_pages is 0 or more than MAX_HOST_PAGES, the MetaPage tables only have room for MAX_HOST_PAGES. max_pages must also leave room for the MetaPage, which takes the first few pages, otherwise the first allocation panics.
+    pub const fn with_max_pages(max_pages: usize) -> AlligatorAlloc<HeapType> {
+        AlligatorAlloc::with_heap(heap::INIT, max_pages)
+    }
+
+    /// Allocator which aims for fresh_reused_ratio allocations from a fresh MiniPage per allocation from a reused MiniPage, instead of INIT's FRESH_REUSED_RATIO of 1. A ratio of 0 never takes a new MiniPage while a reused one has free segments, packing allocations into as few MiniPages as possible. A very large ratio, ie., f64::INFINITY, takes a new MiniPage for every allocation once the size class has reused a MiniPage, trading memory for keeping new allocations away from old ones. fresh_reused_stats() shows how allocations were split.
+    ///
+    /// # Panics
+    /// If fresh_reused_ratio is negative or NaN.
+    pub const fn with_fresh_reused_ratio(fresh_reused_ratio: f64) -> AlligatorAlloc<HeapType> {
+        AlligatorAlloc{
+            alloc: UnsafeCell::new(AllocatorImpl::new(heap::INIT, MAX_HOST_PAGES).with_fresh_reused_ratio(fresh_reused_ratio)),
+
+            #[cfg(feature = "sync")]
+            locked: AtomicBool::new(false),
+        }
+    }
+
+    /// Allocator which only makes MiniPage allocations, of at most MAX_SIZE_CLASS. Larger allocations return null, with the metrics feature AllocFail::AllocTooLarge is recorded, instead of using the big allocation list. Lets systems with little memory rely on every allocation being MiniPage sized and O(1), the big allocation list's O(n) search never runs.
+    pub const fn small_only() -> AlligatorAlloc<HeapType> {
+        AlligatorAlloc{
+            alloc: UnsafeCell::new(AllocatorImpl::new(heap::INIT, MAX_HOST_PAGES).small_only()),
+
+            #[cfg(feature = "sync")]
+            locked: AtomicBool::new(false),
+        }
+    }
+
+    /// Allocator which picks the free big allocation to reuse with big_alloc_policy, instead of INIT's BigAllocPolicy::BestFit.
+    pub const fn with_big_alloc_policy(big_alloc_policy: BigAllocPolicy) -> AlligatorAlloc<HeapType> {
+        AlligatorAlloc{
+            alloc: UnsafeCell::new(AllocatorImpl::new(heap::INIT, MAX_HOST_PAGES).with_big_alloc_policy(big_alloc_policy)),
+
+            #[cfg(feature = "sync")]
+            locked: AtomicBool::new(false),
+        }
+    }
+
+    /// Explains how an allocation of a number of bytes would be satisfied. Useful for understanding why some sizes are much more expensive than others, ie., 2049 bytes is too large for a MiniPage and must use a big allocation.
+    ///
+    /// Returns None for 0 bytes, which the allocator never allocates.
+    pub fn explain(bytes: usize) -> Option<AllocPlan> {
+        if bytes == 0 {
+            return None;
+        }
+
+        let size_class = SizeClass::new_from_bytes(bytes).at_least(MIN_ALLOC_SIZE_CLASS);
+
+        if size_class.exp > MAX_SIZE_CLASS {
+            let (size_bytes, interval) = BigAllocHeader::compute_footprint(bytes);
+            let footprint_bytes = (interval * MINI_PAGE_TOTAL_BYTES) as usize;
+
+            return Some(AllocPlan{
+                requested_bytes: bytes,
+                size_class_exp: size_class.exp,
+                strategy: AllocStrategy::Big,
+                usable_bytes: size_bytes as usize,
+                footprint_bytes: footprint_bytes,
+                internal_frag_bytes: footprint_bytes - bytes,
+            });
+        }
+
+        let segment_bytes = usize::from(size_class.segment_bytes());
+        
+        Some(AllocPlan{
+            requested_bytes: bytes,
+            size_class_exp: size_class.exp,
+            strategy: AllocStrategy::MiniPage,
+            usable_bytes: segment_bytes,
+            footprint_bytes: segment_bytes,
+            internal_frag_bytes: segment_bytes - bytes,
+        })
+    }
+}
+
+impl<H> AlligatorAlloc<H> where H: HostHeap {
+    /// Allocator which uses heap instead of the platform's HeapType, for example a heap::MockHostHeap. Like with_max_pages() it uses at most max_pages host pages.
+    ///
+    /// # Panics
+    /// If max_pages is 0 or more than MAX_HOST_PAGES.
+    pub const fn with_heap(heap: H, max_pages: usize) -> AlligatorAlloc<H> {
+        AlligatorAlloc{
+            alloc: UnsafeCell::new(AllocatorImpl::new(heap, max_pages)),
+
+            #[cfg(feature = "sync")]
+            locked: AtomicBool::new(false),
+        }
+    }
+
+    /// Spins until no other thread is in a GlobalAlloc method of this allocator. The lock is held until the returned guard is dropped. Not re-entrant, a thread which already holds the lock must not call it again.
+    #[cfg(feature = "sync")]
+    fn lock(&self) -> SpinLockGuard<'_> {
+        while self.locked.compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed).is_err() {
+            // Wait for the lock to look free before trying to take it again, so waiting threads only read
+            while self.locked.load(Ordering::Relaxed) {
+                core::hint::spin_loop();
+            }
+        }
+
+        SpinLockGuard{
+            locked: &self.locked,
+        }
+    }
+
+    pub unsafe fn fresh_reused_stats(&self) -> FreshReusedStats {
+        FreshReusedStats{
+            total_alloc_reused: (*self.alloc.get()).total_alloc_reused,
+            total_alloc_fresh: (*self.alloc.get()).total_alloc_fresh,
+        }
+    }
+
+    /// Returns the live segments and number of MiniPages of each size class, see OccupancyStats::occupancy(). Computed from the MiniPage free bitmaps, does not require the metrics feature. Takes time linear in the heap size, unlike fresh_reused_stats().
+    pub unsafe fn occupancy_stats(&self) -> OccupancyStats {
+        (*self.alloc.get()).occupancy_stats()
+    }
+
+    /// Returns the number of bytes which can be used in the allocation ptr points to. Allocations are rounded up to their size class, so this is the segment size (2^n for size class n) of a MiniPage allocation, and size_bytes of a big allocation. Callers like the C API or Vec-like containers can use the extra capacity without a realloc.
+    ///
+    /// Returns 0 if ptr is not a live allocation from this allocator: null, not owned (see owns()), or already freed.
+    pub unsafe fn usable_size(&self, ptr: *mut u8) -> usize {
+        (*self.alloc.get()).usable_size(ptr)
+    }
+
+    /// Returns the number of bytes ptr was allocated, or last reallocated in place, with. usable_size() only knows the size class, this is the exact request. None if ptr is not a live MiniPage allocation. Big allocations do not record their requested size, their usable_size() is already within an interval of it.
+    #[cfg(feature = "requested-size")]
+    pub unsafe fn requested_size(&self, ptr: *mut u8) -> Option<usize> {
+        (*self.alloc.get()).requested_size(ptr)
+    }
+
+    /// Returns true if ptr points into a MiniPage or big allocation which this allocator handed out, so it is safe to pass to dealloc() if it is still live. Lets programs which use several allocators find the one a pointer came from. Does not check if the allocation is still live, see allocation_kind(). Returns false before the heap is initialized, and for pointers into the MetaPage.
+    pub unsafe fn owns(&self, ptr: *mut u8) -> bool {
+        (*self.alloc.get()).owns(ptr)
+    }
+
+    /// Returns whether ptr is a MiniPage segment or a big allocation. None if ptr is null, not from this allocator, or already freed.
+    pub unsafe fn allocation_kind(&self, ptr: *mut u8) -> Option<AllocKind> {
+        (*self.alloc.get()).allocation_kind(ptr)
+    }
+
+    /// Returns the number of live bytes for each size class and for big allocations. Computed from the MiniPage free bitmaps and the big allocation list, does not require the metrics feature.
+    pub unsafe fn live_by_class(&self) -> LiveBreakdown {
+        (*self.alloc.get()).live_by_class()
+    }
+
+    cfg_if! {
+        if #[cfg(feature = "emergency-reserve")] {
+            /// Makes the emergency reserve, EMERGENCY_RESERVE_BYTES at the end of the heap, available for allocations. Call once an allocation has failed so failure handling code (logging, unwinding) has some memory to use. Returns false if the reserve was already released.
+            pub unsafe fn release_reserve(&self) -> bool {
+                #[cfg(feature = "sync")]
+                let _guard = self.lock();
+
+                let alloc = &mut *self.alloc.get();
+                if alloc.reserve_released {
+                    return false;
+                }
+
+                alloc.reserve_released = true;
+                true
+            }
+        }
+    }
+
+    /// Allocates layout from the same MiniPage as hint_ptr, a live allocation, if hint_ptr is from a MiniPage of the same size class with free segments. Otherwise allocates like GlobalAlloc::alloc(). Keeps related objects close together in memory.
+    pub unsafe fn alloc_near(&self, hint_ptr: *mut u8, layout: Layout) -> *mut u8 {
+        #[cfg(feature = "sync")]
+        let _guard = self.lock();
+
+        let ptr = (*self.alloc.get()).alloc_near(hint_ptr, layout);
+
+        cfg_if! {
+            if #[cfg(feature = "zero-tracking")] {
+                // Caller may write to the memory
+                (*self.alloc.get()).take_known_zero(ptr);
+            }
+        }
+
+        cfg_if! {
+            if #[cfg(feature = "metrics")] {
+                (*self.alloc.get()).record_usage(ptr, layout.size(), true);
+            }
+        }
+
+        cfg_if! {
+            if #[cfg(feature = "debug-layout")] {
+                let alloc = &mut *self.alloc.get();
+                let live_bytes = alloc.usable_size(ptr) as isize;
+                alloc.sync_debug_header(live_bytes);
+            }
+        }
+
+        cfg_if! {
+            if #[cfg(feature = "chrome-trace")] {
+                (*self.alloc.get()).chrome_trace.record(ChromeTraceEventKind::Alloc, ptr, layout.size());
+            }
+        }
+
+        cfg_if! {
+            if #[cfg(feature = "trace")] {
+                (*self.alloc.get()).record_trace(TraceEventKind::Alloc, ptr, layout.size());
+            }
+        }
+
+        ptr
+    }
+
+    /// Frees every (pointer, Layout) in ptrs, like calling GlobalAlloc::dealloc() for each. Consecutive pointers from the same MiniPage are freed together, updating the MiniPage's free stacks once instead of once per pointer. Meant for containers which drop many objects at once, ie., an arena. Pointers are only grouped if they are next to each other in ptrs, sort them by address to group them all.
+    pub unsafe fn dealloc_many(&self, ptrs: &[(*mut u8, Layout)]) {
+        #[cfg(feature = "sync")]
+        let _guard = self.lock();
+
+        cfg_if! {
+            if #[cfg(feature = "debug-layout")] {
+                let mut freed_bytes: isize = 0;
+                for (ptr, _layout) in ptrs.iter() {
+                    freed_bytes += (*self.alloc.get()).usable_size(*ptr) as isize;
+                }
+            }
+        }
+
+        (*self.alloc.get()).dealloc_many(ptrs);
+
+        cfg_if! {
+            if #[cfg(feature = "debug-layout")] {
+                (*self.alloc.get()).sync_debug_header(-freed_bytes);
+            }
+        }
 
-			 // Search for a big allocation header corresponding to ptr
-			 let mut big_ptr = self.big_alloc_head;
+        cfg_if! {
+            if #[cfg(feature = "chrome-trace")] {
+                for (ptr, layout) in ptrs.iter() {
+                    (*self.alloc.get()).chrome_trace.record(ChromeTraceEventKind::Dealloc, *ptr, layout.size());
+                }
+            }
+        }
+
+        cfg_if! {
+            if #[cfg(feature = "trace")] {
+                for (ptr, layout) in ptrs.iter() {
+                    (*self.alloc.get()).record_trace(TraceEventKind::Dealloc, *ptr, layout.size());
+                }
+            }
+        }
+    }
+
+    /// Creates count free big allocations which can each hold bytes_each bytes, without allocating them. Later big allocations of up to bytes_each bytes reuse these instead of taking new space from the heap. Returns the number created, which is less than count if the heap runs out of space, and 0 if bytes_each would be allocated from a MiniPage or the allocator is small_only().
+    pub unsafe fn reserve_big(&self, count: usize, bytes_each: usize) -> usize {
+        #[cfg(feature = "sync")]
+        let _guard = self.lock();
+
+        let created = (*self.alloc.get()).reserve_big(count, bytes_each);
+
+        cfg_if! {
+            if #[cfg(feature = "debug-layout")] {
+                (*self.alloc.get()).sync_debug_header(0);
+            }
+        }
 
-			 while let Some(big_head) = big_ptr {
-				cfg_if! {
-                        if #[cfg(feature = "metrics")] {
-					   (*(*meta_page).metrics).heap_bytes_read += size_of::<BigAllocHeader>();
-                        }
-				}
-				
-				// Check allocated
-				if !(*big_head).free {
-                        // Check in big allocation header's range
-                        let start_addr = AllocAddr::from_ptr(alloc_start_ptr, big_head.offset(1) as *mut u8);
-                        let end_addr = AllocAddr::new(u32::from(start_addr.addr) + (*big_head).size_bytes);
-
-                        if addr.addr >= start_addr.addr && addr.addr <= end_addr.addr {
-					   // In range, big_head is the header this allocation came from
-					   // Now free!
-					   cfg_if! {
-						  if #[cfg(feature = "metrics")] {
-							 (*(*meta_page).metrics).heap_bytes_write += size_of::<bool>();
-						  }
-					   }
-					   
-					   (*big_head).free = true; // true = unallocated
+        created
+    }
 
-					   // Exit early, as we have found the allocation's header and freed it
-					   return;
-                        }
-				}
-				
-				// Iterate
-				big_ptr = (*big_head).next;
-			 }
+    cfg_if! {
+        if #[cfg(feature = "debug-layout")] {
+            /// Returns the address of the AllocDebugHeader, at the start of the MetaPage. On WebAssembly this is also its offset in the linear memory. Null if the allocator has not allocated anything yet.
+            pub unsafe fn debug_header_ptr(&self) -> *const AllocDebugHeader {
+                match (*self.alloc.get()).meta_page {
+                    Some(meta_page) => &(*meta_page).debug_header,
+                    None => core::ptr::null(),
+                }
+            }
+        }
+    }
 
-			 // If the while loop finishes without returning from the method then no big allocation header was found for this pointer. Which means the deallocation call is invalid.
+    /// Returns the bytes in use, live MiniPages, live big allocations, and live segments for each size class. Computed from the MiniPage headers and the big allocation list, does not require the metrics feature or change any state. Takes time linear in the heap size, so call it for reporting, not on every allocation.
+    pub unsafe fn live_stats(&self) -> LiveStats {
+        (*self.alloc.get()).live_stats()
+    }
 
-			 cfg_if! {
-				if #[cfg(feature = "metrics")] {
-                        self.failure = Some(AllocFail::BigDeallocHeaderNotFound);
-				}
-			 }
+    /// Calls f with the pointer and usable size (see usable_size()) of every live allocation: each allocated segment in the MiniPage free bitmaps, then each big allocation which is not free. For leak detection, ie., check only the allocations a test expects to still be live are reported. Does not require the metrics feature or change any state.
+    ///
+    /// f must not allocate or free with this allocator, the allocator's state is being walked while f runs. If this allocator is the global allocator f must not allocate at all, ie., collect into a Vec with enough capacity reserved beforehand.
+    pub unsafe fn for_each_live<F>(&self, f: F) where F: FnMut(*mut u8, usize) {
+        (*self.alloc.get()).for_each_live(f)
+    }
 
-			 return;
-		  },
-		  None => {
-			 // Normal alloc, or no allocation at this address at all
-			 
-			 // Memory was allocated using MiniPages
-			 // Read the size class
-			 let minipage_header: *mut MiniPageHeader = match (*meta_page).minipage_headers[page_meta.page_idx] {
-				Some(mut value) => &mut value,
-				None => {
-				    cfg_if! {
-					   if #[cfg(feature = "metrics")] {
-						  self.failure = Some(AllocFail::MiniPageHeaderNotFound);
-					   }
-				    }
+    /// Returns a copy of the live bytes and page counts for each size class. Cheap enough to take before and after a suspect operation, then print the StatsSnapshot::diff() to see which size class grew. Does not require the metrics feature.
+    pub unsafe fn snapshot_stats(&self) -> StatsSnapshot {
+        (*self.alloc.get()).snapshot_stats()
+    }
 
-				    return;
-				},
-			 };
-			 let size_class = SizeClass::new((*minipage_header).size_class_exp);
+    /// Tears down the MetaPage and all allocator state, if every allocation has been freed. The next allocation will rebuild the MetaPage from scratch. Returns true if released, false if there are still live allocations, in which case nothing changes.
+    ///
+    /// Metrics are stored in the MetaPage and are reset by a release.
+    ///
+    /// Every page the allocator grew the host heap by is given back to the host, if the HostHeap can shrink. Otherwise the pages remain owned by the allocator and are reused after the release.
+    pub unsafe fn release(&self) -> bool {
+        #[cfg(feature = "sync")]
+        let _guard = self.lock();
 
-			 // Record metrics
-			 cfg_if! {
-				if #[cfg(feature = "metrics")] {
-                        (*(*meta_page).metrics).total_deallocs[size_class.exp_as_idx()] += 1;
-				}
-			 }
+        (*self.alloc.get()).release()
+    }
 
-			 // Determine segment
-			 let segment = addr.get_segment(size_class);
+    /// Frees every allocation at once, for arena style use where everything made while handling something (ie., parsing a document) is dropped together. Allocation starts from the beginning of the heap again, as it did after the first allocation set up the MetaPage.
+    ///
+    /// **Every pointer this allocator has returned is dangling after a reset.** Reading, writing, or freeing one is undefined behavior, and can corrupt new allocations made at the same address. Unlike release() nothing checks that there are no live allocations.
+    ///
+    /// The host pages are kept, call release_unused_pages() afterwards to give them back. Metrics and failure causes are kept too.
+    pub unsafe fn reset(&self) {
+        #[cfg(feature = "sync")]
+        let _guard = self.lock();
 
-			 // Ensure segment was previously allocated
-			 if (*minipage_header).get_free_bitmap(segment) {
-				// Segment not allocated
-				cfg_if! {
-                        if #[cfg(feature = "metrics")] {
-					   // For reading from a MiniPageHeader free_segments byte on the heap
-					   (*(*meta_page).metrics).heap_bytes_read += size_of::<bool>();
-                        }
-				}
-				
-				return;
-			 }
+        (*self.alloc.get()).reset()
+    }
 
-			 // Update segment bitmap
-			 (*minipage_header).write_free_bitmap(segment, true); // true = free
+    /// Shrinks the host heap down to the pages which have been used, giving every page after the last MiniPage or big allocation back to the host. Returns the number of pages released, 0 if none were or the HostHeap cannot shrink (WASM memory can't). The released pages are grown back when a later allocation needs them.
+    ///
+    /// Freed MiniPages and big allocations before the end of the used pages are kept for reuse, so release_unused_pages() helps most after the newest allocations were freed.
+    pub unsafe fn release_unused_pages(&self) -> usize {
+        #[cfg(feature = "sync")]
+        let _guard = self.lock();
 
-			 cfg_if! {
-				if #[cfg(feature = "metrics")] {
-                        // For writing to a MiniPageHeader free_segments byte on the heap
-                        (*(*meta_page).metrics).heap_bytes_write += size_of::<bool>();
-				}
-			 }
+        (*self.alloc.get()).release_unused_pages()
+    }
 
-			 // Push onto free segments stack if minipage is the current MiniPage
-			 if (*(*meta_page).free_minipages[size_class.exp_as_idx()]).peek() == Some(page_meta.page_idx) {
-				(*(*meta_page).free_segments[size_class.exp_as_idx()]).push(segment.segment_idx_u16());
+    /// Returns the number of host pages the allocator has grown the heap by and not given back.
+    pub unsafe fn heap_pages(&self) -> usize {
+        (*self.alloc.get()).heap_pages
+    }
 
-				cfg_if! {
-                        if #[cfg(feature = "metrics")] {
-					   // For peeking the free_minipages UnsafeStack on the heap
-					   (*(*meta_page).free_minipages[size_class.exp_as_idx()]).record_peek_cost(meta_page);
-					   
-					   // For pushing a free segment onto the free_segments UnsafeStack on the heap
-					   (*(*meta_page).free_segments[size_class.exp_as_idx()]).record_push_cost(meta_page);
-                        }
-				}
-			 } else if !(*minipage_header).on_free_minipages_stack {
-				// Not pushed on minipages stack
-				// First time we have deallocated from this MiniPage since it was full
-				
-				(*(*meta_page).free_minipages[size_class.exp_as_idx()]).push(page_meta.page_idx);
-				
-				cfg_if! {
-                        if #[cfg(feature = "metrics")] {
-					   // For pushing a MiniPageHeader pointer onto the free_minipages UnsafeStack on the heap
-					   (*(*meta_page).free_minipages[size_class.exp_as_idx()]).record_push_cost(meta_page);
-                        }
-				}
-			 }
+    /// Writes a snapshot of which parts of the heap are in use. Each bit represents one MiniPage sized interval of the heap, in order starting from the first MiniPage. Bit i is stored in byte i / 8 at position i % 8 (1 << (i % 8)). A 1 means the interval contains at least one live allocation, 0 means it is entirely free or has not been used yet.
+    ///
+    /// Computed from the MiniPage free bitmaps and big allocation flags. Does not allocate. out should be HEAP_BITMAP_BYTES long, if it is shorter intervals which do not fit are not reported.
+    pub unsafe fn heap_bitmap(&self, out: &mut [u8]) {
+        (*self.alloc.get()).heap_bitmap(out);
+    }
 
-			 cfg_if! {
-				if #[cfg(feature = "metrics")] {
-                        // For reading the (*minipage_header).on_free_minipages_stack bool from the heap
-                        (*(*meta_page).metrics).heap_bytes_read += size_of::<bool>();
-				}
-			 }
-		  }
-	   }
+    /// Walks the MiniPage headers, free minipages stacks, big allocation list and big allocation flags, and checks they agree with each other. Returns the first broken invariant found. Meant for debugging heap corruption, ie., call it after every operation to find the one which broke the heap. Takes time linear in the size of the heap and does not allocate.
+    pub unsafe fn validate_heap(&self) -> Result<(), HeapInvariantViolation> {
+        (*self.alloc.get()).validate_heap()
     }
-}
 
-/// The custom global allocator. Wraps the AllocatorImpl
-/// which performs all memory operations. See for more details.
-pub struct AlligatorAlloc<H> where H: HostHeap {
-    /// Data structure which keeps state of all memory
-    /// wrapped inside an UnsafeCell for
-    /// memory symantics.
-    alloc: UnsafeCell<AllocatorImpl<H>>,
-}
+    /// Defragments the MiniPages. For each size class the live allocations in the MiniPages with the fewest of them are copied into free segments of the size class's other MiniPages, then the emptied MiniPages are reclaimed so any size class can use them. MiniPages are emptied until the rest of the size class has no room for the next one. Big allocations and size classes whose segments take up a whole MiniPage are not moved.
+    ///
+    /// **Allocations move.** The caller must not hold any pointer from this allocator while compact() runs, and must look every pointer it keeps up in the returned CompactReport afterwards, ie., with CompactReport::new_ptr(). Reading or writing through an old pointer is undefined behavior, its memory may be handed to a new allocation. Only the allocation's bytes are copied, so values which point into themselves or which others point to must be fixed up by the caller too.
+    ///
+    /// At most COMPACT_MAX_RELOCATIONS allocations are moved, if more could be CompactReport::complete is false. Takes time proportional to the number of MiniPages times the allocations moved, so call it when idle, not on every allocation.
+    pub unsafe fn compact(&self) -> CompactReport {
+        #[cfg(feature = "sync")]
+        let _guard = self.lock();
 
-/// WASM is single threaded right now so this should be okay.
-unsafe impl<H> Sync for AlligatorAlloc<H> where H: HostHeap {}
+        let report = (*self.alloc.get()).compact();
 
-/// Includes statistics on which allocations were made from MiniPages which were fresh (never been fully filled up) or reused (been fully filled up, then freed into action again).
-#[derive(Copy, Clone, Debug)]
-pub struct FreshReusedStats {
-    /// Total number of allocations for each size class which were performed from a reused MiniPage header.
-    pub total_alloc_reused: [u32; NUM_SIZE_CLASSES_USIZE],
+        cfg_if! {
+            if #[cfg(feature = "debug-layout")] {
+                (*self.alloc.get()).sync_debug_header(0);
+            }
+        }
 
-    /// Total number of allocations for each size class which were performed from a newly allocated MiniPage header.
-    pub total_alloc_fresh: [u32; NUM_SIZE_CLASSES_USIZE],
-}
+        report
+    }
 
-impl AlligatorAlloc<HeapType> {
-    pub const INIT: AlligatorAlloc<HeapType> = AlligatorAlloc{
-        alloc: UnsafeCell::new(AllocatorImpl::INIT),
-    };
+    cfg_if! {
+        if #[cfg(feature = "mock")] {
+            /// For testing validate_heap(). Breaks the invariant described by corruption, the allocator must not be used afterwards. Does nothing if what corruption changes does not exist yet, ie., there is no big allocation to grow.
+            pub unsafe fn corrupt(&self, corruption: HeapCorruption) {
+                #[cfg(feature = "sync")]
+                let _guard = self.lock();
 
-    pub unsafe fn fresh_reused_stats(&self) -> FreshReusedStats {
-        FreshReusedStats{
-            total_alloc_reused: (*self.alloc.get()).total_alloc_reused,
-            total_alloc_fresh: (*self.alloc.get()).total_alloc_fresh,
+                (*self.alloc.get()).corrupt(corruption)
+            }
         }
     }
 
@@ -1461,20 +4829,238 @@ impl AlligatorAlloc<HeapType> {
                 (*self.alloc.get()).failure
             }
 
-            /// Returns a dot graphviz representation of the allocator state.
-            pub unsafe fn dot_graph(&self) -> String {
-                AllocMetrics::dot_graph(self.alloc.get())
+            /// Returns the number of times each failure has occurred since the allocator was created, indexed by `AllocFail as usize`. Unlike alloc_failure_cause() this keeps the history of every failure.
+            pub unsafe fn failure_counts(&self) -> [u32; NUM_ALLOC_FAILS] {
+                (*self.alloc.get()).failure_counts
+            }
+
+            /// Writes a dot graphviz representation of the allocator state to out. There is a node for the allocator, one for each size class, one for each MiniPage in a size class's list labeled with its used and free segment counts, and one for each big allocation labeled with its size_bytes and if it is free. Node ids are minipage_<size class>_<position in list> and big_<position in list>.
+            ///
+            /// Does not allocate itself. If Alligator is the global allocator, out should not allocate as it is written to either (ie., a String with enough capacity reserved beforehand, or a fixed size buffer), otherwise the allocator would be re-entered while its state is being inspected.
+            pub unsafe fn dot_graph<W>(&self, out: &mut W) -> core::fmt::Result where W: core::fmt::Write {
+                AllocMetrics::dot_graph(self.alloc.get(), out)
+            }
+        }
+    }
+
+    cfg_if! {
+        if #[cfg(feature = "chrome-trace")] {
+            /// Writes the most recent alloc and dealloc calls to out in the Chrome Trace Event Format, which can be loaded by chrome://tracing or Perfetto. The ts field of events is a logical timestamp which counts operations, not microseconds.
+            ///
+            /// Does not allocate itself. If Alligator is the global allocator, out should not allocate as it is written to, see dot_graph().
+            pub unsafe fn dump_chrome_trace<W>(&self, out: &mut W) -> core::fmt::Result where W: core::fmt::Write {
+                (*self.alloc.get()).chrome_trace.dump(out)
+            }
+        }
+    }
+
+    cfg_if! {
+        if #[cfg(feature = "trace")] {
+            /// Returns the alloc and dealloc calls recorded since the last drain, oldest first, removing them from the trace buffer. Only the most recent TRACE_CAPACITY calls are kept. Calls made while the iterator is in use are kept for the next drain. The trace buffer is in the MetaPage, so release() discards it.
+            ///
+            /// Does not allocate itself, so it can be used while Alligator is the global allocator.
+            pub unsafe fn drain_trace(&self) -> impl Iterator<Item = TraceEvent> + '_ {
+                #[cfg(feature = "sync")]
+                let _guard = self.lock();
+
+                let remaining = match (*self.alloc.get()).meta_page {
+                    Some(meta_page) => (*(*meta_page).trace).len,
+                    None => 0,
+                };
+
+                TraceDrain{
+                    alloc: self,
+                    remaining: remaining,
+                }
             }
         }
     }
 }
 
+/// With the sync feature each method holds AlligatorAlloc::lock() while it uses the AllocatorImpl. The other pub methods of AlligatorAlloc do not lock.
 unsafe impl<H> GlobalAlloc for AlligatorAlloc<H> where H: HostHeap {
     unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
-        return (*self.alloc.get()).alloc(layout);
+        #[cfg(feature = "sync")]
+        let _guard = self.lock();
+
+        let ptr = (*self.alloc.get()).alloc(layout);
+
+        cfg_if! {
+            if #[cfg(feature = "zero-tracking")] {
+                // Caller may write to the memory
+                (*self.alloc.get()).take_known_zero(ptr);
+            }
+        }
+
+        cfg_if! {
+            if #[cfg(feature = "metrics")] {
+                (*self.alloc.get()).record_usage(ptr, layout.size(), true);
+            }
+        }
+
+        cfg_if! {
+            if #[cfg(feature = "debug-layout")] {
+                let alloc = &mut *self.alloc.get();
+                let live_bytes = alloc.usable_size(ptr) as isize;
+                alloc.sync_debug_header(live_bytes);
+            }
+        }
+
+        cfg_if! {
+            if #[cfg(feature = "chrome-trace")] {
+                (*self.alloc.get()).chrome_trace.record(ChromeTraceEventKind::Alloc, ptr, layout.size());
+            }
+        }
+
+        cfg_if! {
+            if #[cfg(feature = "trace")] {
+                (*self.alloc.get()).record_trace(TraceEventKind::Alloc, ptr, layout.size());
+            }
+        }
+
+        return ptr;
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        #[cfg(feature = "sync")]
+        let _guard = self.lock();
+
+        let ptr = (*self.alloc.get()).alloc_zeroed(layout);
+
+        cfg_if! {
+            if #[cfg(feature = "metrics")] {
+                (*self.alloc.get()).record_usage(ptr, layout.size(), true);
+            }
+        }
+
+        cfg_if! {
+            if #[cfg(feature = "debug-layout")] {
+                let alloc = &mut *self.alloc.get();
+                let live_bytes = alloc.usable_size(ptr) as isize;
+                alloc.sync_debug_header(live_bytes);
+            }
+        }
+
+        cfg_if! {
+            if #[cfg(feature = "chrome-trace")] {
+                (*self.alloc.get()).chrome_trace.record(ChromeTraceEventKind::Alloc, ptr, layout.size());
+            }
+        }
+
+        cfg_if! {
+            if #[cfg(feature = "trace")] {
+                (*self.alloc.get()).record_trace(TraceEventKind::Alloc, ptr, layout.size());
+            }
+        }
+
+        return ptr;
+    }
+
+    /// Shrinking a MiniPage allocation never moves it, the same pointer is returned. A segment belongs to exactly one size class, so the space after new_size stays reserved until the allocation is freed. Growing within the segment's usable size also does not move. A big allocation does not move if new_size still fits in its size_bytes, when shrinking the whole MiniPage intervals it no longer needs become a free big allocation right after it. Otherwise a new allocation is made, the contents are copied, and the old allocation is freed.
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        // Only lock while resizing in place, self.alloc() and self.dealloc() below take the lock themselves
+        {
+            #[cfg(feature = "sync")]
+            let _guard = self.lock();
+
+            let alloc = &mut *self.alloc.get();
+            let fits_in_place = match alloc.allocation_kind(ptr) {
+                Some(AllocKind::Small{ size_class_exp }) => new_size <= usize::from(SizeClass::new(size_class_exp).segment_bytes()),
+                Some(AllocKind::Big{ size_bytes }) => new_size <= size_bytes as usize,
+                None => false,
+            };
+            if new_size > 0 && fits_in_place {
+                // Same allocation, only the requested size, and for a big allocation its usable size, changed
+                cfg_if! {
+                    if #[cfg(feature = "metrics")] {
+                        alloc.record_usage(ptr, layout.size(), false);
+                    }
+                }
+
+                cfg_if! {
+                    if #[cfg(feature = "debug-layout")] {
+                        let old_usable_bytes = alloc.usable_size(ptr) as isize;
+                    }
+                }
+
+                alloc.shrink_big_alloc(ptr, new_size);
+
+                cfg_if! {
+                    if #[cfg(feature = "requested-size")] {
+                        alloc.set_requested_size(ptr, new_size);
+                    }
+                }
+
+                cfg_if! {
+                    if #[cfg(feature = "metrics")] {
+                        alloc.record_usage(ptr, new_size, true);
+                    }
+                }
+
+                cfg_if! {
+                    if #[cfg(feature = "debug-layout")] {
+                        let new_usable_bytes = alloc.usable_size(ptr) as isize;
+                        alloc.sync_debug_header(new_usable_bytes - old_usable_bytes);
+                    }
+                }
+
+                return ptr;
+            }
+        }
+
+        cfg_if! {
+            if #[cfg(feature = "requested-size")] {
+                // Only the bytes which were requested need to be copied, layout may be larger, ie., the C API passes the usable size
+                let old_bytes = (*self.alloc.get()).requested_size(ptr).unwrap_or(layout.size()).min(layout.size());
+            } else {
+                let old_bytes = layout.size();
+            }
+        }
+
+        let new_layout = Layout::from_size_align_unchecked(new_size, layout.align());
+        let new_ptr = self.alloc(new_layout);
+        if !new_ptr.is_null() {
+            copy_nonoverlapping(ptr, new_ptr, old_bytes.min(new_size));
+            self.dealloc(ptr, layout);
+        }
+
+        new_ptr
     }
 
     unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        #[cfg(feature = "sync")]
+        let _guard = self.lock();
+
+        cfg_if! {
+            if #[cfg(feature = "metrics")] {
+                (*self.alloc.get()).record_usage(ptr, layout.size(), false);
+            }
+        }
+
+        cfg_if! {
+            if #[cfg(feature = "debug-layout")] {
+                let freed_bytes = (*self.alloc.get()).usable_size(ptr) as isize;
+            }
+        }
+
         (*self.alloc.get()).dealloc(ptr, layout);
+
+        cfg_if! {
+            if #[cfg(feature = "debug-layout")] {
+                (*self.alloc.get()).sync_debug_header(-freed_bytes);
+            }
+        }
+
+        cfg_if! {
+            if #[cfg(feature = "chrome-trace")] {
+                (*self.alloc.get()).chrome_trace.record(ChromeTraceEventKind::Dealloc, ptr, layout.size());
+            }
+        }
+
+        cfg_if! {
+            if #[cfg(feature = "trace")] {
+                (*self.alloc.get()).record_trace(TraceEventKind::Dealloc, ptr, layout.size());
+            }
+        }
     }
 }
@@ -0,0 +1,501 @@
+use core::fmt;
+use std::vec::Vec;
+
+use super::{AlligatorAlloc,AllocatorImpl,AllocAddr,SizeClass,expand_page_idx,bitmap_position};
+use super::{MINI_PAGE_ALLOC_BYTES,MIN_SIZE_CLASS,NUM_SIZE_CLASSES_USIZE};
+use super::heap::HostHeap;
+
+/// Number of bytes in a MiniPageSnapshot's free bitmap, one bit for every segment of the size class with the most segments.
+pub const SNAPSHOT_BITMAP_BYTES: usize = ((MINI_PAGE_ALLOC_BYTES >> MIN_SIZE_CLASS) as usize + 7) / 8;
+
+/// First 4 bytes of HeapSnapshot::to_bytes(), "ALGS".
+const SNAPSHOT_MAGIC: [u8; 4] = *b"ALGS";
+
+/// Extra items reserved in every HeapSnapshot list, so the lists still fit if reserving them allocated from the allocator being snapshotted.
+const SNAPSHOT_SLACK: usize = 8;
+
+/// One MiniPage, see HeapSnapshot::minipages.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MiniPageSnapshot {
+    /// Index of the MiniPage interval in the heap, counted from the first MiniPage.
+    pub page_idx: u32,
+
+    /// Size class exponent of the MiniPage's segments.
+    pub size_class_exp: u8,
+
+    /// Free bitmap, one bit per segment in the same order as the MiniPage header. A 1 means that segment is free. Bits past the size class's segments are always 1.
+    pub free_segments: [u8; SNAPSHOT_BITMAP_BYTES],
+
+    /// True if the MiniPage is on its size class's free minipages stack. An empty MiniPage which is not has been reclaimed.
+    pub on_free_minipages_stack: bool,
+}
+
+/// One big allocation, see HeapSnapshot::big_allocs.
+#[derive(Clone, Debug, PartialEq)]
+pub struct BigAllocSnapshot {
+    /// Offset of the BigAllocHeader from the first MiniPage, in bytes.
+    pub offset: u32,
+
+    /// Usable bytes after the header.
+    pub size_bytes: u32,
+
+    /// True if the big allocation has been freed and can be reused.
+    pub free: bool,
+}
+
+/// One size class's free stacks, see HeapSnapshot::free_stacks.
+#[derive(Clone, Debug, PartialEq)]
+pub struct FreeStackSnapshot {
+    /// Size class exponent of the stacks.
+    pub size_class_exp: u8,
+
+    /// Page indexes on the free minipages stack, top first.
+    pub free_minipages: Vec<u32>,
+
+    /// Segment indexes on the free segments stack, top first.
+    pub free_segments: Vec<u16>,
+}
+
+/// Owned copy of an allocator's bookkeeping, taken by AlligatorAlloc::snapshot(). Holds the state needed to see where memory went after a crash, not the contents of allocations. to_bytes() and from_bytes() convert it to and from a binary format, so it can be written out and read by another program.
+#[derive(Clone, Debug, PartialEq)]
+pub struct HeapSnapshot {
+    /// Host pages the allocator may use.
+    pub heap_pages: u32,
+
+    /// MiniPage intervals handed out to MiniPages or big allocations.
+    pub used_page_units: u32,
+
+    /// Every MiniPage, including reclaimed ones, ordered by page_idx.
+    pub minipages: Vec<MiniPageSnapshot>,
+
+    /// Every big allocation, ordered by offset.
+    pub big_allocs: Vec<BigAllocSnapshot>,
+
+    /// Free stacks for every size class, from MIN_SIZE_CLASS up.
+    pub free_stacks: Vec<FreeStackSnapshot>,
+
+    /// Page indexes on the reclaimed minipages stack, top first.
+    pub reclaimed_minipages: Vec<u32>,
+}
+
+/// Reasons HeapSnapshot::from_bytes() can fail.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SnapshotDecodeError {
+    /// The bytes do not start with the snapshot magic, they were not written by to_bytes().
+    BadMagic,
+
+    /// The bytes ended in the middle of a value.
+    UnexpectedEnd,
+
+    /// A bool was not 0 or 1.
+    InvalidBool,
+
+    /// A MiniPage's bitmap length was not SNAPSHOT_BITMAP_BYTES.
+    InvalidBitmapLength,
+
+    /// Bytes were left after the snapshot.
+    TrailingBytes,
+}
+
+impl fmt::Display for SnapshotDecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SnapshotDecodeError::BadMagic => write!(f, "bytes are not a heap snapshot"),
+            SnapshotDecodeError::UnexpectedEnd => write!(f, "heap snapshot ended early"),
+            SnapshotDecodeError::InvalidBool => write!(f, "heap snapshot has a bool which is not 0 or 1"),
+            SnapshotDecodeError::InvalidBitmapLength => write!(f, "heap snapshot has a MiniPage bitmap which is not {} bytes", SNAPSHOT_BITMAP_BYTES),
+            SnapshotDecodeError::TrailingBytes => write!(f, "heap snapshot has bytes after its end"),
+        }
+    }
+}
+
+impl std::error::Error for SnapshotDecodeError {}
+
+/// Number of items in each HeapSnapshot list. Counted before the lists are reserved, so the walk which fills them does not allocate.
+#[derive(Copy, Clone, PartialEq)]
+struct SnapshotCounts {
+    minipages: usize,
+    big_allocs: usize,
+    free_minipages: [usize; NUM_SIZE_CLASSES_USIZE],
+    free_segments: [usize; NUM_SIZE_CLASSES_USIZE],
+    reclaimed_minipages: usize,
+}
+
+impl SnapshotCounts {
+    /// True if lists reserved for reserved hold every item counted by self.
+    fn fits_in(&self, reserved: &SnapshotCounts) -> bool {
+        self.minipages <= reserved.minipages &&
+            self.big_allocs <= reserved.big_allocs &&
+            self.reclaimed_minipages <= reserved.reclaimed_minipages &&
+            (0..NUM_SIZE_CLASSES_USIZE).all(|i| {
+                self.free_minipages[i] <= reserved.free_minipages[i] && self.free_segments[i] <= reserved.free_segments[i]
+            })
+    }
+
+    /// Counts with SNAPSHOT_SLACK added to every list.
+    fn with_slack(&self) -> SnapshotCounts {
+        let mut counts = *self;
+        counts.minipages += SNAPSHOT_SLACK;
+        counts.big_allocs += SNAPSHOT_SLACK;
+        counts.reclaimed_minipages += SNAPSHOT_SLACK;
+        for i in 0..NUM_SIZE_CLASSES_USIZE {
+            counts.free_minipages[i] += SNAPSHOT_SLACK;
+            counts.free_segments[i] += SNAPSHOT_SLACK;
+        }
+
+        counts
+    }
+}
+
+impl HeapSnapshot {
+    /// Empty snapshot with room for counts items in every list.
+    fn with_capacity(counts: &SnapshotCounts) -> HeapSnapshot {
+        let mut free_stacks = Vec::with_capacity(NUM_SIZE_CLASSES_USIZE);
+        for i in 0..NUM_SIZE_CLASSES_USIZE {
+            free_stacks.push(FreeStackSnapshot{
+                size_class_exp: MIN_SIZE_CLASS + i as u8,
+                free_minipages: Vec::with_capacity(counts.free_minipages[i]),
+                free_segments: Vec::with_capacity(counts.free_segments[i]),
+            });
+        }
+
+        HeapSnapshot{
+            heap_pages: 0,
+            used_page_units: 0,
+            minipages: Vec::with_capacity(counts.minipages),
+            big_allocs: Vec::with_capacity(counts.big_allocs),
+            free_stacks: free_stacks,
+            reclaimed_minipages: Vec::with_capacity(counts.reclaimed_minipages),
+        }
+    }
+
+    /// Serializes the snapshot. Every integer is little endian and every list is prefixed by its u32 length:
+    ///
+    /// ```text
+    /// "ALGS" heap_pages:u32 used_page_units:u32
+    /// minipages:    len:u32 { page_idx:u32 size_class_exp:u8 on_free_minipages_stack:u8 bitmap_len:u32 bitmap:[u8] }
+    /// big_allocs:   len:u32 { offset:u32 size_bytes:u32 free:u8 }
+    /// free_stacks:  len:u32 { size_class_exp:u8 len:u32 [u32] len:u32 [u16] }
+    /// reclaimed_minipages: len:u32 [u32]
+    /// ```
+    ///
+    /// Allocates the returned Vec from the global allocator.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        out.extend_from_slice(&SNAPSHOT_MAGIC);
+        write_u32(&mut out, self.heap_pages);
+        write_u32(&mut out, self.used_page_units);
+
+        write_len(&mut out, self.minipages.len());
+        for minipage in &self.minipages {
+            write_u32(&mut out, minipage.page_idx);
+            out.push(minipage.size_class_exp);
+            out.push(u8::from(minipage.on_free_minipages_stack));
+            write_len(&mut out, minipage.free_segments.len());
+            out.extend_from_slice(&minipage.free_segments);
+        }
+
+        write_len(&mut out, self.big_allocs.len());
+        for big_alloc in &self.big_allocs {
+            write_u32(&mut out, big_alloc.offset);
+            write_u32(&mut out, big_alloc.size_bytes);
+            out.push(u8::from(big_alloc.free));
+        }
+
+        write_len(&mut out, self.free_stacks.len());
+        for free_stack in &self.free_stacks {
+            out.push(free_stack.size_class_exp);
+            write_len(&mut out, free_stack.free_minipages.len());
+            for page_idx in &free_stack.free_minipages {
+                write_u32(&mut out, *page_idx);
+            }
+            write_len(&mut out, free_stack.free_segments.len());
+            for segment_idx in &free_stack.free_segments {
+                out.extend_from_slice(&segment_idx.to_le_bytes());
+            }
+        }
+
+        write_len(&mut out, self.reclaimed_minipages.len());
+        for page_idx in &self.reclaimed_minipages {
+            write_u32(&mut out, *page_idx);
+        }
+
+        out
+    }
+
+    /// Parses bytes written by to_bytes().
+    pub fn from_bytes(bytes: &[u8]) -> Result<HeapSnapshot, SnapshotDecodeError> {
+        let mut reader = SnapshotReader{
+            bytes: bytes,
+        };
+
+        if reader.take(SNAPSHOT_MAGIC.len())? != SNAPSHOT_MAGIC {
+            return Err(SnapshotDecodeError::BadMagic);
+        }
+
+        let heap_pages = reader.u32()?;
+        let used_page_units = reader.u32()?;
+
+        // Lengths come from the bytes, so they are not trusted with a reservation
+        let mut minipages = Vec::new();
+        for _ in 0..reader.u32()? {
+            let page_idx = reader.u32()?;
+            let size_class_exp = reader.u8()?;
+            let on_free_minipages_stack = reader.bool()?;
+
+            if reader.u32()? as usize != SNAPSHOT_BITMAP_BYTES {
+                return Err(SnapshotDecodeError::InvalidBitmapLength);
+            }
+            let mut free_segments = [0; SNAPSHOT_BITMAP_BYTES];
+            free_segments.copy_from_slice(reader.take(SNAPSHOT_BITMAP_BYTES)?);
+
+            minipages.push(MiniPageSnapshot{
+                page_idx: page_idx,
+                size_class_exp: size_class_exp,
+                free_segments: free_segments,
+                on_free_minipages_stack: on_free_minipages_stack,
+            });
+        }
+
+        let mut big_allocs = Vec::new();
+        for _ in 0..reader.u32()? {
+            let offset = reader.u32()?;
+            let size_bytes = reader.u32()?;
+            let free = reader.bool()?;
+
+            big_allocs.push(BigAllocSnapshot{
+                offset: offset,
+                size_bytes: size_bytes,
+                free: free,
+            });
+        }
+
+        let mut free_stacks = Vec::new();
+        for _ in 0..reader.u32()? {
+            let size_class_exp = reader.u8()?;
+
+            let mut free_minipages = Vec::new();
+            for _ in 0..reader.u32()? {
+                free_minipages.push(reader.u32()?);
+            }
+
+            let mut free_segments = Vec::new();
+            for _ in 0..reader.u32()? {
+                free_segments.push(reader.u16()?);
+            }
+
+            free_stacks.push(FreeStackSnapshot{
+                size_class_exp: size_class_exp,
+                free_minipages: free_minipages,
+                free_segments: free_segments,
+            });
+        }
+
+        let mut reclaimed_minipages = Vec::new();
+        for _ in 0..reader.u32()? {
+            reclaimed_minipages.push(reader.u32()?);
+        }
+
+        if !reader.bytes.is_empty() {
+            return Err(SnapshotDecodeError::TrailingBytes);
+        }
+
+        Ok(HeapSnapshot{
+            heap_pages: heap_pages,
+            used_page_units: used_page_units,
+            minipages: minipages,
+            big_allocs: big_allocs,
+            free_stacks: free_stacks,
+            reclaimed_minipages: reclaimed_minipages,
+        })
+    }
+}
+
+/// Appends n as a little endian u32.
+fn write_u32(out: &mut Vec<u8>, n: u32) {
+    out.extend_from_slice(&n.to_le_bytes());
+}
+
+/// Appends a list length. The allocator's lists are bounded by MAX_HOST_PAGES, so they always fit in a u32.
+fn write_len(out: &mut Vec<u8>, len: usize) {
+    write_u32(out, len as u32);
+}
+
+/// Reads values from the front of a byte slice for HeapSnapshot::from_bytes().
+struct SnapshotReader<'a> {
+    /// Bytes not read yet.
+    bytes: &'a [u8],
+}
+
+impl<'a> SnapshotReader<'a> {
+    /// Removes and returns the next n bytes.
+    fn take(&mut self, n: usize) -> Result<&'a [u8], SnapshotDecodeError> {
+        if self.bytes.len() < n {
+            return Err(SnapshotDecodeError::UnexpectedEnd);
+        }
+
+        let (taken, rest) = self.bytes.split_at(n);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    fn u8(&mut self) -> Result<u8, SnapshotDecodeError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn bool(&mut self) -> Result<bool, SnapshotDecodeError> {
+        match self.u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(SnapshotDecodeError::InvalidBool),
+        }
+    }
+
+    fn u16(&mut self) -> Result<u16, SnapshotDecodeError> {
+        let mut buf = [0; 2];
+        buf.copy_from_slice(self.take(2)?);
+        Ok(u16::from_le_bytes(buf))
+    }
+
+    fn u32(&mut self) -> Result<u32, SnapshotDecodeError> {
+        let mut buf = [0; 4];
+        buf.copy_from_slice(self.take(4)?);
+        Ok(u32::from_le_bytes(buf))
+    }
+}
+
+impl<H> AllocatorImpl<H> where H: HostHeap {
+    /// Counts the items snapshot_fill() will push. All 0 if the heap has not been initialized. Does not allocate.
+    unsafe fn snapshot_counts(&self) -> SnapshotCounts {
+        let mut counts = SnapshotCounts{
+            minipages: 0,
+            big_allocs: 0,
+            free_minipages: [0; NUM_SIZE_CLASSES_USIZE],
+            free_segments: [0; NUM_SIZE_CLASSES_USIZE],
+            reclaimed_minipages: 0,
+        };
+
+        let meta_page = match self.meta_page {
+            Some(meta_page) => meta_page,
+            None => return counts,
+        };
+
+        for page_idx in 0..self.used_page_units() {
+            if (*meta_page).minipage_headers[page_idx].is_some() {
+                counts.minipages += 1;
+            }
+        }
+
+        let mut big_ptr = self.big_alloc_head;
+        while let Some(big_head) = big_ptr {
+            counts.big_allocs += 1;
+            big_ptr = (*big_head).next;
+        }
+
+        for i in 0..NUM_SIZE_CLASSES_USIZE {
+            counts.free_minipages[i] = (*(*meta_page).free_minipages[i]).len() as usize;
+            counts.free_segments[i] = (*(*meta_page).free_segments[i]).len() as usize;
+        }
+        counts.reclaimed_minipages = (*(*meta_page).reclaimed_minipages).len() as usize;
+
+        counts
+    }
+
+    /// Pushes the allocator's state onto snapshot's lists, which were reserved with room for reserved items. Returns false without pushing anything if the state no longer fits in them, pushing past the reservation would allocate.
+    unsafe fn snapshot_fill(&self, reserved: &SnapshotCounts, snapshot: &mut HeapSnapshot) -> bool {
+        if !self.snapshot_counts().fits_in(reserved) {
+            return false;
+        }
+
+        snapshot.heap_pages = self.heap_pages as u32;
+        snapshot.used_page_units = self.used_page_units() as u32;
+
+        let (meta_page, alloc_start_ptr) = match (self.meta_page, self.alloc_start_ptr) {
+            (Some(meta_page), Some(alloc_start_ptr)) => (meta_page, alloc_start_ptr),
+            _ => return true,
+        };
+
+        for page_idx in 0..self.used_page_units() {
+            if let Some(header) = &(*meta_page).minipage_headers[page_idx] {
+                let size_class = SizeClass::new(header.size_class_exp);
+
+                // Copy only the bits segments use, so the bitmap does not depend on the rest of the header's array
+                let mut free_segments = [255; SNAPSHOT_BITMAP_BYTES];
+                for segment_idx in 0..usize::from(size_class.segments_max_num()) {
+                    let (byte_idx, bit_idx) = bitmap_position(segment_idx);
+                    if header.free_segments[byte_idx] & (1 << bit_idx) == 0 {
+                        free_segments[byte_idx] &= !(1 << bit_idx);
+                    }
+                }
+
+                snapshot.minipages.push(MiniPageSnapshot{
+                    page_idx: page_idx as u32,
+                    size_class_exp: header.size_class_exp,
+                    free_segments: free_segments,
+                    on_free_minipages_stack: header.on_free_minipages_stack,
+                });
+            }
+        }
+
+        let mut big_ptr = self.big_alloc_head;
+        while let Some(big_head) = big_ptr {
+            snapshot.big_allocs.push(BigAllocSnapshot{
+                offset: AllocAddr::from_ptr(alloc_start_ptr, big_head as *mut u8).addr_usize() as u32,
+                size_bytes: (*big_head).size_bytes,
+                free: (*big_head).free,
+            });
+
+            big_ptr = (*big_head).next;
+        }
+
+        for (i, free_stack) in snapshot.free_stacks.iter_mut().enumerate() {
+            let free_minipages = &mut *(*meta_page).free_minipages[i];
+            for n in 0..free_minipages.len() {
+                if let Some(page_idx) = free_minipages.peek_nth(n) {
+                    free_stack.free_minipages.push(expand_page_idx(page_idx) as u32);
+                }
+            }
+
+            let free_segments = &mut *(*meta_page).free_segments[i];
+            for n in 0..free_segments.len() {
+                if let Some(segment_idx) = free_segments.peek_nth(n) {
+                    free_stack.free_segments.push(segment_idx);
+                }
+            }
+        }
+
+        let reclaimed_minipages = &mut *(*meta_page).reclaimed_minipages;
+        for n in 0..reclaimed_minipages.len() {
+            if let Some(page_idx) = reclaimed_minipages.peek_nth(n) {
+                snapshot.reclaimed_minipages.push(expand_page_idx(page_idx) as u32);
+            }
+        }
+
+        true
+    }
+}
+
+impl<H> AlligatorAlloc<H> where H: HostHeap {
+    /// Copies the allocator's bookkeeping into an owned HeapSnapshot: every MiniPage's size class and free bitmap, every big allocation, and the free and reclaimed minipages stacks. Meant for post-mortem analysis, ie., take a snapshot when something goes wrong and write out HeapSnapshot::to_bytes(). Returns an empty snapshot if the heap has not been initialized.
+    ///
+    /// The snapshot's lists come from the global allocator. They are all reserved before the heap is walked, and without holding the sync lock, so the walk never allocates, even if this allocator is the global allocator. In that case the snapshot includes its own lists.
+    pub unsafe fn snapshot(&self) -> HeapSnapshot {
+        loop {
+            let reserved = {
+                #[cfg(feature = "sync")]
+                let _guard = self.lock();
+
+                (*self.alloc.get()).snapshot_counts().with_slack()
+            };
+            let mut snapshot = HeapSnapshot::with_capacity(&reserved);
+
+            #[cfg(feature = "sync")]
+            let _guard = self.lock();
+
+            // Reserving may have allocated from this allocator, if so the state might have outgrown the reservation
+            if (*self.alloc.get()).snapshot_fill(&reserved, &mut snapshot) {
+                return snapshot;
+            }
+        }
+    }
+}
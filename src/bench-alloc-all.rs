@@ -1,6 +1,6 @@
 mod alloc;
-use alloc::{AlligatorAlloc,MIN_SIZE_CLASS,MAX_SIZE_CLASS};
-use alloc::heap::HeapType;
+use alloc::{AlligatorAlloc,AllocAddr,AllocKind,BigAllocPolicy,HeapInvariantViolation,SizeClass,UnsafeStack,MIN_SIZE_CLASS,MAX_SIZE_CLASS,MAX_HOST_HEAP_BYTES,META_PAGE_HOST_PAGES,HEAP_BITMAP_BYTES};
+use alloc::heap::{self,HeapType,HostHeap};
 use core::alloc::Layout;
 use std::alloc::GlobalAlloc;
 use cfg_if::cfg_if;
@@ -14,12 +14,25 @@ use cfg_if::cfg_if;
 // #[global_allocator]
 static ALLOC: AlligatorAlloc<HeapType> = AlligatorAlloc::INIT;
 
+/// Host pages TINY_ALLOC and RESET_ALLOC may use, a few more than the MetaPage needs with the enabled features.
+const TINY_MAX_PAGES: usize = META_PAGE_HOST_PAGES + 10;
+
+/// Bytes in the smallest size class whether or not cache-align is enabled, which raises the smallest size class to 64 bytes.
+const SMALLEST_CLASS_BYTES: usize = 64;
+
+/// Allocator only used by reset(), reused_minipage() and release(), small so it can be filled quickly.
+static RESET_ALLOC: AlligatorAlloc<HeapType> = AlligatorAlloc::with_max_pages(TINY_MAX_PAGES);
+
+/// Allocator with a small max number of pages, used to check allocations fail cleanly once it is full.
+#[cfg(all(feature = "metrics", not(feature = "strict")))]
+static TINY_ALLOC: AlligatorAlloc<HeapType> = AlligatorAlloc::with_max_pages(TINY_MAX_PAGES);
+
 /// Allocate then free a number of bytes for each size class which will require more than one MiniPage.
 unsafe fn alloc_all() {
     for n in MIN_SIZE_CLASS..=MAX_SIZE_CLASS {
         let mut free_later: Vec<*mut u8> = vec!();
 
-        let segments_per_page = 2048 / 2_u32.pow(u32::from(n));
+        let segments_per_page = u32::from(SizeClass::new(n).segments_max_num());
 
         for i in 0..segments_per_page * 10 {
             // Create layout which requests the maximum number of bytes possible for this size class
@@ -31,15 +44,18 @@ unsafe fn alloc_all() {
             // Call allocate
             let ptr = ALLOC.alloc(layout);
 
-            // Ensure the allocation succeeded
-            cfg_if! {
-                if #[cfg(feature = "metrics")] {
-                    if ptr.is_null() {
-                        eprintln!("alloc failure cause={:?}", ALLOC.alloc_failure_cause());
+            // If the heap is full stop this size class and report how far we got, instead of aborting the whole benchmark
+            if ptr.is_null() {
+                println!("alloc() failed (returned null): size class={}, {} of {} allocations succeeded", n, i, segments_per_page * 10);
+
+                cfg_if! {
+                    if #[cfg(feature = "metrics")] {
+                        println!("alloc failure cause={:?}", ALLOC.alloc_failure_cause());
                     }
                 }
+
+                break;
             }
-            assert!(!ptr.is_null(), "alloc() failed (returned null): size class={}, i={}", n, i);
 
             // For 1/6th of allocations don't free them immediately, free them later
             if i % 6 == 0 {
@@ -74,12 +90,2177 @@ unsafe fn alloc_all() {
     println!("done");
 }
 
-/// Allocate and free a lot of times.
-fn main() {
-    for i in 0..1 {
-        println!("Benchmark iteration {}", i);
-        unsafe {
-            alloc_all();
+/// Fill then free one MiniPage of every size class, twice. Fully freed MiniPages are reclaimed for any size class, so the second pass must not need any new MiniPages.
+#[cfg(feature = "metrics")]
+unsafe fn alternate_size_classes() {
+    let mut first_pass_minipages = 0;
+
+    for pass in 0..2 {
+        for n in MIN_SIZE_CLASS..=MAX_SIZE_CLASS {
+            let layout = match Layout::from_size_align(2_usize.pow(u32::from(n)), 1) {
+                Ok(l) => l,
+                Err(e) => panic!("error making Layout: {}", e),
+            };
+
+            let segments_per_page = SizeClass::new(n).segments_max_num();
+            let mut ptrs: Vec<*mut u8> = vec!();
+            for _i in 0..segments_per_page {
+                let ptr = ALLOC.alloc(layout);
+                assert!(!ptr.is_null(), "alloc() failed (returned null): size class={}, cause={:?}", n, ALLOC.alloc_failure_cause());
+                ptrs.push(ptr);
+            }
+
+            for ptr in ptrs.iter() {
+                ALLOC.dealloc(*ptr, layout);
+            }
+        }
+
+        let metrics = ALLOC.metrics().unwrap();
+        println!("alternating size classes pass {}: total_minipages={}, reclaimed_minipages={}", pass, metrics.total_minipages, metrics.reclaimed_minipages);
+
+        match pass {
+            0 => first_pass_minipages = metrics.total_minipages,
+            _ => assert!(metrics.total_minipages <= first_pass_minipages, "total_minipages grew from {} to {} even though every MiniPage was freed", first_pass_minipages, metrics.total_minipages),
+        }
+    }
+}
+
+/// Free a big allocation, then reuse it for a smaller one. The unused tail must be split off.
+/// Anything up to 32 KB is a MiniPage allocation, so the tail is too small to hold another big allocation. Uses its own allocator so no other free big allocation could be reused instead.
+#[cfg(feature = "metrics")]
+unsafe fn split_big_alloc() {
+    let alloc: AlligatorAlloc<HeapType> = AlligatorAlloc::INIT;
+    let big_layout = Layout::from_size_align(usize::from(u16::MAX), 1).unwrap();
+    let small_layout = Layout::from_size_align(33 * 1024, 1).unwrap();
+
+    let big_ptr = alloc.alloc(big_layout);
+    assert!(!big_ptr.is_null(), "alloc({}) failed: {:?}", big_layout.size(), alloc.alloc_failure_cause());
+    alloc.dealloc(big_ptr, big_layout);
+
+    let splits_before = alloc.metrics().unwrap().big_splits;
+
+    let front_ptr = alloc.alloc(small_layout);
+    assert!(!front_ptr.is_null(), "alloc({}) failed: {:?}", small_layout.size(), alloc.alloc_failure_cause());
+
+    let splits = alloc.metrics().unwrap().big_splits - splits_before;
+    let big_range = (big_ptr as usize)..(big_ptr as usize + big_layout.size());
+    println!("split big allocation: {:?} reused for {:?}, {} splits", big_ptr, front_ptr, splits);
+
+    assert!(splits >= 1, "reusing a {} byte big allocation for {} bytes did not split it", big_layout.size(), small_layout.size());
+    assert!(big_range.contains(&(front_ptr as usize)), "the {} byte allocation should come from the freed {} byte big allocation", small_layout.size(), big_layout.size());
+
+    alloc.dealloc(front_ptr, small_layout);
+}
+
+/// Free three adjacent big allocations, the middle one last, then shrink a big allocation which is right before a free one with realloc. Each must be merged with the free big allocations next to it, and the three merged allocations reused by one larger allocation. Uses its own allocator so the big allocations are adjacent.
+#[cfg(feature = "metrics")]
+unsafe fn coalesce_big_allocs() {
+    let alloc: AlligatorAlloc<HeapType> = AlligatorAlloc::INIT;
+    // Whole host pages, so with big-page-align every one starts on a page boundary and the merged allocations can be reused for another page aligned allocation
+    let layout = Layout::from_size_align(heap::PAGE_BYTES as usize, 1).unwrap();
+    let merged_layout = Layout::from_size_align(3 * (heap::PAGE_BYTES as usize), 1).unwrap();
+
+    let ptrs: Vec<*mut u8> = (0..4).map(|_| alloc.alloc(layout)).collect();
+    assert!(ptrs.iter().all(|ptr| !ptr.is_null()), "alloc({}) failed: {:?}", layout.size(), alloc.alloc_failure_cause());
+
+    // ptrs[3] stays live, so the merged allocation can only come from the three freed ones
+    alloc.dealloc(ptrs[0], layout);
+    alloc.dealloc(ptrs[2], layout);
+    assert_eq!(alloc.metrics().unwrap().big_coalesces, 0, "free big allocations with a live one between them should not be merged");
+    alloc.dealloc(ptrs[1], layout);
+    assert_eq!(alloc.metrics().unwrap().big_coalesces, 2, "freeing the middle big allocation should merge it with the free ones on both sides");
+
+    let merged_ptr = alloc.alloc(merged_layout);
+    println!("coalesce big allocations: {:?} reused for {} bytes", merged_ptr, merged_layout.size());
+    assert_eq!(merged_ptr, ptrs[0], "a {} byte allocation should reuse the three merged {} byte big allocations", merged_layout.size(), layout.size());
+    assert_eq!(alloc.validate_heap(), Ok(()), "heap broken after reusing merged big allocations");
+
+    // Whatever is after merged_ptr is free once ptrs[3] is, so the tail realloc gives back is merged with it
+    alloc.dealloc(ptrs[3], layout);
+    let coalesces_before = alloc.metrics().unwrap().big_coalesces;
+    let shrunk_ptr = alloc.realloc(merged_ptr, merged_layout, 4 * 1024);
+    assert_eq!(shrunk_ptr, merged_ptr, "shrinking a big allocation should not move it");
+    assert_eq!(alloc.metrics().unwrap().big_coalesces, coalesces_before + 1, "the tail a shrinking realloc gives back should be merged with the free big allocation after it");
+    assert_eq!(alloc.validate_heap(), Ok(()), "heap broken after merging a realloc tail");
+
+    alloc.dealloc(shrunk_ptr, Layout::from_size_align(4 * 1024, 1).unwrap());
+}
+
+/// Shrink a 60 KB big allocation to 4 KB with realloc. It must not move, and the intervals it no longer needs must be handed out to the next big allocation. Uses its own allocator so no other free big allocation could be picked instead.
+unsafe fn realloc_shrink_big() {
+    let alloc: AlligatorAlloc<HeapType> = AlligatorAlloc::INIT;
+    let big_layout = Layout::from_size_align(60 * 1024, 1).unwrap();
+    let shrunk_bytes = 4 * 1024;
+    let tail_layout = Layout::from_size_align(40 * 1024, 1).unwrap();
+
+    let big_ptr = alloc.alloc(big_layout);
+    assert!(!big_ptr.is_null(), "alloc({}) failed", big_layout.size());
+
+    let shrunk_ptr = alloc.realloc(big_ptr, big_layout, shrunk_bytes);
+    let shrunk_usable = alloc.usable_size(shrunk_ptr);
+    assert_eq!(shrunk_ptr, big_ptr, "shrinking a big allocation should not move it");
+    assert!(shrunk_usable >= shrunk_bytes && shrunk_usable < tail_layout.size(), "a big allocation shrunk to {} bytes should have given back its tail, it has {} usable bytes", shrunk_bytes, shrunk_usable);
+
+    let tail_ptr = alloc.alloc(tail_layout);
+    println!("realloc shrink big: {:?} shrunk from {} to {} bytes, {} byte allocation from its tail at {:?}", big_ptr, big_layout.size(), shrunk_bytes, tail_layout.size(), tail_ptr);
+    let big_range = (big_ptr as usize)..(big_ptr as usize + big_layout.size());
+    assert!(big_range.contains(&(tail_ptr as usize)) && tail_ptr > big_ptr, "the {} byte allocation should reuse the tail of the shrunk allocation", tail_layout.size());
+
+    alloc.dealloc(tail_ptr, tail_layout);
+    alloc.dealloc(shrunk_ptr, Layout::from_size_align(shrunk_bytes, 1).unwrap());
+}
+
+/// Allocate sizes at and past u16::MAX. Their size classes must come from the full size, not one truncated to 16 bits, so each is a big allocation with room for every requested byte.
+unsafe fn large_request_routing() {
+    for (bytes, exp) in [(65535, 16), (65536, 16), (70000, 17), (131072, 17)].iter() {
+        let layout = Layout::from_size_align(*bytes, 1).unwrap();
+        assert_eq!(SizeClass::new_from_layout(layout).exp, *exp, "size class for {} bytes should be {}", bytes, exp);
+
+        let ptr = ALLOC.alloc(layout);
+        assert!(!ptr.is_null(), "alloc({}) failed", bytes);
+        match ALLOC.allocation_kind(ptr) {
+            Some(AllocKind::Big{ size_bytes }) => assert!(size_bytes as usize >= *bytes, "alloc({}) is a big allocation of only {} bytes", bytes, size_bytes),
+            kind => panic!("alloc({}) should be a big allocation, got {:?}", bytes, kind),
+        }
+
+        // Write every byte, a too small allocation would overwrite the allocator's data
+        std::ptr::write_bytes(ptr, 0xAB, *bytes);
+        ALLOC.dealloc(ptr, layout);
+    }
+}
+
+/// Make and free a mix of MiniPage and big allocations, validate_heap() must not find a broken invariant after any of them. Then write past the end of a big allocation over the BigAllocHeader of the one after it, validate_heap() must report that the big allocation flags no longer match the big allocation list.
+unsafe fn validate_heap() {
+    let validate_alloc: AlligatorAlloc<HeapType> = AlligatorAlloc::INIT;
+    let overflow_alloc: AlligatorAlloc<HeapType> = AlligatorAlloc::INIT;
+    assert_eq!(validate_alloc.validate_heap(), Ok(()), "heap should be valid before the first alloc");
+
+    let layouts: Vec<Layout> = [8, 32, 100, 2048, 5000, 40 * 1024, 70000].iter().map(|bytes| Layout::from_size_align(*bytes, 1).unwrap()).collect();
+    let mut ptrs: Vec<(*mut u8, Layout)> = vec!();
+    for round in 0..100 {
+        let layout = layouts[round % layouts.len()];
+        let ptr = validate_alloc.alloc(layout);
+        assert!(!ptr.is_null(), "round {}: alloc({}) failed", round, layout.size());
+        ptrs.push((ptr, layout));
+
+        // Free some allocations so MiniPages and big allocations are reused
+        if round % 3 == 2 {
+            let (ptr, layout) = ptrs.remove(round % ptrs.len());
+            validate_alloc.dealloc(ptr, layout);
+        }
+
+        assert_eq!(validate_alloc.validate_heap(), Ok(()), "round {}: heap should be valid", round);
+    }
+
+    for (ptr, layout) in ptrs.drain(..) {
+        validate_alloc.dealloc(ptr, layout);
+        assert_eq!(validate_alloc.validate_heap(), Ok(()), "heap should be valid after dealloc({:?})", ptr);
+    }
+
+    let big_layout = Layout::from_size_align(40 * 1024, 1).unwrap();
+    let first_ptr = overflow_alloc.alloc(big_layout);
+    let second_ptr = overflow_alloc.alloc(big_layout);
+    assert!(!first_ptr.is_null() && !second_ptr.is_null(), "alloc({}) failed", big_layout.size());
+    assert_eq!(overflow_alloc.validate_heap(), Ok(()), "heap should be valid before the overflow");
+
+    // A big allocation's BigAllocHeader is at the start of the 2 KB MiniPage interval its memory starts in
+    let first_end = first_ptr as usize + overflow_alloc.usable_size(first_ptr);
+    let second_header = (second_ptr as usize) & !(2048 - 1);
+    assert_eq!(first_end, second_header, "the two big allocations should be next to each other");
+    std::ptr::write_bytes(first_end as *mut u8, 0, second_ptr as usize - first_end);
+
+    let result = overflow_alloc.validate_heap();
+    println!("validate heap: after overflowing a big allocation {:?}", result);
+    assert!(matches!(result, Err(HeapInvariantViolation::BigAllocFlagMismatch{ .. })), "overwriting a BigAllocHeader should break the big allocation flags, got {:?}", result);
+}
+
+/// Break each invariant validate_heap() checks with AlligatorAlloc::corrupt(). Each uses its own MockHostHeap, so the corrupted allocator's memory is freed after. validate_heap() must report the matching HeapInvariantViolation.
+#[cfg(feature = "mock")]
+unsafe fn validate_heap_corruption() {
+    use alloc::HeapCorruption;
+    use alloc::heap::MockHostHeap;
+
+    let small_layout = Layout::from_size_align(SMALLEST_CLASS_BYTES, 1).unwrap();
+    let big_layout = Layout::from_size_align(40 * 1024, 1).unwrap();
+
+    let cases: [(HeapCorruption, fn(&HeapInvariantViolation) -> bool); 4] = [
+        (HeapCorruption::FillFreeMiniPage{ size_class_exp: 6 }, |v| matches!(v, HeapInvariantViolation::FullMiniPageOnFreeStack{ size_class_exp: 6, .. })),
+        (HeapCorruption::ClearOnFreeStackFlag{ size_class_exp: 6 }, |v| matches!(v, HeapInvariantViolation::OnFreeStackMismatch{ on_free_minipages_stack: false, .. })),
+        (HeapCorruption::GrowFirstBigAlloc, |v| matches!(v, HeapInvariantViolation::BigAllocOverlap{ .. })),
+        (HeapCorruption::NextAllocPastHeap, |v| matches!(v, HeapInvariantViolation::NextAllocOutOfBounds{ .. })),
+    ];
+
+    for (corruption, expected) in cases.iter() {
+        let corrupt_alloc = AlligatorAlloc::with_heap(MockHostHeap::new(TINY_MAX_PAGES, 1), TINY_MAX_PAGES);
+        for layout in [small_layout, big_layout, big_layout].iter() {
+            assert!(!corrupt_alloc.alloc(*layout).is_null(), "{:?}: alloc({}) failed", corruption, layout.size());
+        }
+        assert_eq!(corrupt_alloc.validate_heap(), Ok(()), "heap should be valid before {:?}", corruption);
+
+        corrupt_alloc.corrupt(*corruption);
+        let result = corrupt_alloc.validate_heap();
+        println!("validate heap: {:?} reported as {:?}", corruption, result);
+        assert!(result.as_ref().err().map_or(false, expected), "{:?} was reported as {:?}", corruption, result);
+    }
+}
+
+/// Put values of several types, including zero sized, over-aligned, big, and ones which own std memory, in AllocBoxes. Each must read back what was moved in and be freed when dropped, so the same memory is handed out again.
+#[cfg(feature = "std")]
+fn alloc_box() {
+    let alloc: AlligatorAlloc<HeapType> = AlligatorAlloc::INIT;
+    use alloc::boxed::alloc_value;
+    use std::cell::Cell;
+
+    /// Counts how many times it is dropped.
+    struct DropCounter<'a> {
+        drops: &'a Cell<u32>,
+    }
+
+    impl Drop for DropCounter<'_> {
+        fn drop(&mut self) {
+            self.drops.set(self.drops.get() + 1);
+        }
+    }
+
+    #[derive(Debug, PartialEq)]
+    #[repr(align(64))]
+    struct CacheLine([u8; 8]);
+
+    let byte = alloc_value(&alloc, 7_u8);
+    let mut word = alloc_value(&alloc, 0xdead_beef_u64);
+    let array = alloc_value(&alloc, [3_u32; 64]);
+    let aligned = alloc_value(&alloc, CacheLine([1; 8]));
+    let big = alloc_value(&alloc, [9_u8; 40 * 1024]);
+    let string = alloc_value(&alloc, String::from("alligator"));
+    let unit = alloc_value(&alloc, ());
+
+    *word += 1;
+    assert_eq!(*byte, 7);
+    assert_eq!(*word, 0xdead_bef0);
+    assert!(array.iter().all(|x| *x == 3), "array was not moved into its AllocBox");
+    assert_eq!(*aligned, CacheLine([1; 8]));
+    assert_eq!(aligned.as_ptr() as usize % 64, 0, "AllocBox<CacheLine> is not aligned to 64 bytes");
+    assert!(big.iter().all(|x| *x == 9), "big array was not moved into its AllocBox");
+    assert_eq!(string.as_str(), "alligator");
+    assert_eq!(*unit, ());
+    unsafe {
+        assert!(alloc.owns(word.as_ptr() as *mut u8), "AllocBox memory should come from its allocator");
+        assert!(alloc.owns(big.as_ptr() as *mut u8), "big AllocBox memory should come from its allocator");
+    }
+
+    // Dropping frees with the same layout, so the next AllocBox of each type gets the same memory
+    let word_ptr = word.as_ptr();
+    let big_ptr = big.as_ptr();
+    drop(word);
+    drop(big);
+    let word = alloc_value(&alloc, 1_u64);
+    let big = alloc_value(&alloc, [0_u8; 40 * 1024]);
+    assert_eq!(word.as_ptr(), word_ptr, "dropped AllocBox<u64> was not freed");
+    assert_eq!(big.as_ptr() as usize, big_ptr as usize, "dropped big AllocBox was not freed");
+
+    // The value is dropped along with its AllocBox
+    let drops = Cell::new(0);
+    {
+        let _counters = [alloc_value(&alloc, DropCounter{ drops: &drops }), alloc_value(&alloc, DropCounter{ drops: &drops })];
+        assert_eq!(drops.get(), 0, "value dropped while its AllocBox is alive");
+    }
+    assert_eq!(drops.get(), 2, "dropping an AllocBox should drop its value");
+
+    drop((byte, word, array, aligned, big, string, unit));
+    unsafe {
+        assert_eq!(alloc.validate_heap(), Ok(()), "heap broken after dropping every AllocBox");
+    }
+}
+
+/// Snapshots a heap with live and freed MiniPage and big allocations, serializes it, and checks parsing the bytes gives back the same snapshot. The global allocator is the system allocator here, so the snapshot's lists must not come from the allocator being snapshotted.
+#[cfg(feature = "std")]
+unsafe fn snapshot_round_trip() {
+    let alloc: AlligatorAlloc<HeapType> = AlligatorAlloc::INIT;
+    use alloc::snapshot::{HeapSnapshot,SnapshotDecodeError};
+
+    let empty = alloc.snapshot();
+    assert!(empty.minipages.is_empty() && empty.big_allocs.is_empty(), "snapshot before the heap is initialized should be empty");
+
+    // 48 bytes is in the 64 byte size class, which cache-align does not change
+    let small = Layout::from_size_align(48, 8).unwrap();
+    let big = Layout::from_size_align(40 * 1024, 8).unwrap();
+
+    let mut ptrs: Vec<*mut u8> = vec!();
+    for i in 0..40 {
+        let ptr = alloc.alloc(small);
+        assert!(!ptr.is_null(), "small alloc {} failed", i);
+        ptrs.push(ptr);
+    }
+    // The freed big allocation is not the last one, so it stays in the big allocation list
+    let big_freed = alloc.alloc(big);
+    let big_live = alloc.alloc(big);
+    assert!(!big_live.is_null() && !big_freed.is_null(), "big alloc failed");
+
+    // Free every other small allocation, so the free segments stack and bitmaps are not trivial
+    for ptr in ptrs.iter().step_by(2) {
+        alloc.dealloc(*ptr, small);
+    }
+    alloc.dealloc(big_freed, big);
+
+    let snapshot = alloc.snapshot();
+    assert!(!snapshot.minipages.is_empty(), "snapshot has no MiniPages");
+    assert_eq!(snapshot.big_allocs.iter().filter(|b| b.free).count(), 1, "snapshot should have the freed big allocation");
+    assert_eq!(snapshot.big_allocs.iter().filter(|b| !b.free).count(), 1, "snapshot should have the live big allocation");
+    let size_class = SizeClass::new_from_layout(small);
+    let live_segments: u32 = snapshot.minipages.iter()
+        .filter(|minipage| minipage.size_class_exp == size_class.exp)
+        .map(|minipage| minipage.free_segments.iter().map(|byte| byte.count_zeros()).sum::<u32>())
+        .sum();
+    assert_eq!(live_segments, 20, "snapshot bitmaps should have the 20 live small segments");
+    assert!(!alloc.owns(snapshot.minipages.as_ptr() as *mut u8), "snapshot lists should come from the global allocator");
+
+    let bytes = snapshot.to_bytes();
+    assert_eq!(HeapSnapshot::from_bytes(&bytes), Ok(snapshot.clone()), "snapshot changed in a to_bytes() from_bytes() round trip");
+    assert_eq!(HeapSnapshot::from_bytes(&bytes[..bytes.len() - 1]), Err(SnapshotDecodeError::UnexpectedEnd));
+    assert_eq!(HeapSnapshot::from_bytes(&bytes[1..]), Err(SnapshotDecodeError::BadMagic));
+    println!("heap snapshot: {} MiniPages, {} big allocations, {} bytes serialized", snapshot.minipages.len(), snapshot.big_allocs.len(), bytes.len());
+
+    for ptr in ptrs.iter().skip(1).step_by(2) {
+        alloc.dealloc(*ptr, small);
+    }
+    alloc.dealloc(big_live, big);
+}
+
+/// Move MiniPages back and forth between the 64 and 128 byte size classes, which cache-align does not change. Each round fills one MiniPage and starts a second, then frees both. The filled MiniPage is reclaimed while the second is the head of the size class's minipage_lists chain, so it must be unlinked from the middle of the chain. The next round reuses both for the other size class. The dot graph walks every chain, it must only ever show the MiniPages with live allocations.
+#[cfg(feature = "metrics")]
+unsafe fn reclaimed_minipage_links() {
+    let alloc: AlligatorAlloc<HeapType> = AlligatorAlloc::INIT;
+    let dot_minipages = |prefix: &str| -> usize {
+        let mut dot = String::new();
+        alloc.dot_graph(&mut dot).unwrap();
+        dot.lines().filter(|line| line.trim_start().starts_with(prefix) && line.contains("[label=")).count()
+    };
+
+    for round in 0..20 {
+        let size_class = SizeClass::new(6 + (round % 2));
+        let layout = Layout::from_size_align(usize::from(size_class.segment_bytes()), 1).unwrap();
+
+        let ptrs: Vec<*mut u8> = (0..=size_class.segments_max_num()).map(|_i| alloc.alloc(layout)).collect();
+        assert!(ptrs.iter().all(|ptr| !ptr.is_null()), "round {}: alloc({}) failed: {:?}", round, layout.size(), alloc.alloc_failure_cause());
+        assert_eq!(dot_minipages("minipage_"), 2, "round {}: only the 2 MiniPages of size class {} should be in a chain", round, size_class.exp);
+        assert_eq!(dot_minipages(&format!("minipage_{}_", size_class.exp)), 2, "round {}: size class {} should have 2 MiniPages", round, size_class.exp);
+
+        for ptr in ptrs.iter() {
+            alloc.dealloc(*ptr, layout);
+        }
+        assert_eq!(dot_minipages("minipage_"), 0, "round {}: every MiniPage was reclaimed, none should be left in a chain", round);
+    }
+
+    let metrics = alloc.metrics().unwrap();
+    println!("reclaimed MiniPage links: {} MiniPages reclaimed {} times", metrics.total_minipages, metrics.reclaimed_minipages);
+    assert_eq!(metrics.total_minipages, 2, "every round after the first should reuse the 2 reclaimed MiniPages");
+}
+
+/// Fill a whole 64 KB page with 4 KB allocations. 4 KB is a large size class, so these must be MiniPage allocations and not big allocations.
+#[cfg(feature = "metrics")]
+unsafe fn large_size_class_minipages() {
+    let layout = Layout::from_size_align(4 * 1024, 1).unwrap();
+    let size_class = SizeClass::new(12);
+
+    let before = ALLOC.metrics().unwrap();
+    let mut ptrs: Vec<*mut u8> = vec!();
+    for _i in 0..16 {
+        let ptr = ALLOC.alloc(layout);
+        assert!(!ptr.is_null(), "alloc({}) failed: {:?}", layout.size(), ALLOC.alloc_failure_cause());
+        ptrs.push(ptr);
+    }
+    let after = ALLOC.metrics().unwrap();
+
+    let minipage_allocs = after.total_allocs[size_class.exp_as_idx()] - before.total_allocs[size_class.exp_as_idx()];
+    println!("large size class: {} allocations of {} bytes, {} new MiniPages, {} new big allocation headers", minipage_allocs, layout.size(), after.total_minipages - before.total_minipages, after.total_big_headers - before.total_big_headers);
+
+    assert_eq!(minipage_allocs, 16, "{} byte allocations were not made from size class {} MiniPages", layout.size(), size_class.exp);
+    assert_eq!(after.total_big_headers, before.total_big_headers, "{} byte allocations should not create big allocations", layout.size());
+
+    for ptr in ptrs.iter() {
+        ALLOC.dealloc(*ptr, layout);
+    }
+}
+
+/// Free the oldest of many live big allocations. It is at the end of the big allocation list, but dealloc must find its header directly from the big allocation flags, reading only one header.
+#[cfg(feature = "metrics")]
+unsafe fn big_dealloc_lookup() {
+    let layout = Layout::from_size_align(40 * 1024, 1).unwrap();
+    let mut ptrs: Vec<*mut u8> = vec!();
+    for _i in 0..64 {
+        let ptr = ALLOC.alloc(layout);
+        assert!(!ptr.is_null(), "alloc({}) failed: {:?}", layout.size(), ALLOC.alloc_failure_cause());
+        ptrs.push(ptr);
+    }
+
+    let reads_before = ALLOC.metrics().unwrap().big_dealloc_header_reads;
+    ALLOC.dealloc(ptrs[0], layout);
+    let reads = ALLOC.metrics().unwrap().big_dealloc_header_reads - reads_before;
+
+    println!("big dealloc lookup: {} BigAllocHeaders read to free the oldest of {} big allocations", reads, ptrs.len());
+    assert_eq!(reads, 1, "dealloc searched the big allocation list instead of using the big allocation flags");
+    assert!(ALLOC.allocation_kind(ptrs[0]).is_none(), "dealloc did not free the oldest big allocation");
+
+    for ptr in ptrs[1..].iter() {
+        ALLOC.dealloc(*ptr, layout);
+    }
+}
+
+/// Check MetaPage::alloc() sets up the metrics. There are none before the first allocation, and right after it only that allocation is counted.
+#[cfg(feature = "metrics")]
+unsafe fn meta_page_metrics() {
+    let alloc: AlligatorAlloc<HeapType> = AlligatorAlloc::INIT;
+    assert!(alloc.metrics().is_none(), "there should be no metrics before the MetaPage is allocated");
+
+    let layout = Layout::from_size_align(8, 1).unwrap();
+    let ptr = alloc.alloc(layout);
+    assert!(!ptr.is_null(), "alloc({}) failed: {:?}", layout.size(), alloc.alloc_failure_cause());
+
+    let metrics = match alloc.metrics() {
+        Some(m) => m,
+        None => panic!("there should be metrics once the MetaPage is allocated"),
+    };
+    println!("MetaPage metrics after one allocation: {:?}", metrics);
+    assert_eq!(metrics.total_allocs.iter().sum::<u32>(), 1, "only the first allocation should be counted");
+    assert_eq!(metrics.total_deallocs.iter().sum::<u32>(), 0, "nothing has been freed");
+    assert_eq!(metrics.total_minipages, 1, "only the first allocation's MiniPage should be counted");
+    assert_eq!(metrics.total_big_headers, 0, "no big allocations have been made");
+
+    alloc.dealloc(ptr, layout);
+}
+
+/// Check dot_graph() has a node for each MiniPage and big allocation. One more 64 byte allocation than fits in a MiniPage needs 2 MiniPages, with one 128 byte allocation and one 40 KB big allocation there should be 3 MiniPage nodes and 1 big allocation node. The size classes are at least 64 bytes so cache-align does not change them.
+#[cfg(feature = "metrics")]
+unsafe fn dot_graph() {
+    let alloc: AlligatorAlloc<HeapType> = AlligatorAlloc::INIT;
+    let mut sizes: Vec<usize> = vec![64; usize::from(SizeClass::new(6).segments_max_num()) + 1];
+    sizes.push(128);
+    sizes.push(40 * 1024);
+
+    let mut ptrs: Vec<(*mut u8, Layout)> = vec!();
+    for bytes in sizes.iter() {
+        let layout = Layout::from_size_align(*bytes, 1).unwrap();
+        let ptr = alloc.alloc(layout);
+        assert!(!ptr.is_null(), "alloc({}) failed: {:?}", bytes, alloc.alloc_failure_cause());
+        ptrs.push((ptr, layout));
+    }
+
+    let mut dot = String::new();
+    alloc.dot_graph(&mut dot).unwrap();
+    println!("dot graph:\n{}", dot);
+
+    let nodes = |prefix: &str| dot.lines().filter(|line| line.trim_start().starts_with(prefix) && line.contains("[label=")).count();
+    assert_eq!(nodes("size_class_"), usize::from(MAX_SIZE_CLASS - MIN_SIZE_CLASS + 1), "there should be a node for every size class");
+    assert_eq!(nodes("minipage_"), 3, "there should be 2 MiniPage nodes for the 64 byte size class and 1 for the 128 byte size class");
+    assert_eq!(nodes("minipage_6_"), 2, "the 64 byte size class should have 2 MiniPage nodes");
+    assert_eq!(nodes("big_"), 1, "there should be a node for the one big allocation");
+    assert!(dot.contains(&format!("used=1 free={}", SizeClass::new(7).segments_max_num() - 1)), "the 128 byte size class's MiniPage should have 1 used segment");
+    assert!(dot.starts_with("digraph A {") && dot.ends_with("}\n"), "dot graph is not wrapped in a digraph");
+
+    for (ptr, layout) in ptrs.iter() {
+        alloc.dealloc(*ptr, *layout);
+    }
+}
+
+/// Check a 100 byte allocation in the 128 byte size class counts 28 bytes of internal fragmentation, and the rest of its MiniPage counts as external fragmentation. The size class is larger than 64 bytes so cache-align does not change it.
+#[cfg(feature = "metrics")]
+unsafe fn fragmentation() {
+    let alloc: AlligatorAlloc<HeapType> = AlligatorAlloc::INIT;
+    let layout = Layout::from_size_align(100, 1).unwrap();
+    let ptr = alloc.alloc(layout);
+    assert!(!ptr.is_null(), "alloc({}) failed: {:?}", layout.size(), alloc.alloc_failure_cause());
+    assert_eq!(alloc.usable_size(ptr), 128, "a {} byte allocation should be in the 128 byte size class", layout.size());
+
+    let segments = usize::from(SizeClass::new(7).segments_max_num());
+    let metrics = alloc.metrics().unwrap();
+    assert_eq!(metrics.internal_frag_bytes, 28, "a {} byte allocation in a 128 byte segment should waste 28 bytes", layout.size());
+    assert_eq!(metrics.external_frag_bytes, (segments - 1) * 128, "every other segment of the MiniPage should be free");
+
+    // A second allocation in the same MiniPage uses one of its free segments
+    let other_ptr = alloc.alloc(Layout::from_size_align(128, 1).unwrap());
+    assert!(!other_ptr.is_null(), "alloc(128) failed: {:?}", alloc.alloc_failure_cause());
+    let metrics = alloc.metrics().unwrap();
+    assert_eq!(metrics.internal_frag_bytes, 28, "a 128 byte allocation in a 128 byte segment should not waste any bytes");
+    assert_eq!(metrics.external_frag_bytes, (segments - 2) * 128, "the second allocation should use one of the free segments");
+
+    // Growing in place only changes how much of the segment is requested
+    let grown_ptr = alloc.realloc(ptr, layout, 120);
+    assert_eq!(grown_ptr, ptr, "realloc within the segment should not move");
+    assert_eq!(alloc.metrics().unwrap().internal_frag_bytes, 8, "a 120 byte allocation in a 128 byte segment should waste 8 bytes");
+
+    alloc.dealloc(other_ptr, Layout::from_size_align(128, 1).unwrap());
+    alloc.dealloc(grown_ptr, Layout::from_size_align(120, 1).unwrap());
+    let metrics = alloc.metrics().unwrap();
+    assert_eq!((metrics.internal_frag_bytes, metrics.external_frag_bytes), (0, 0), "nothing is fragmented once everything is freed");
+}
+
+/// Allocate, free everything, then allocate less. The peaks must stay at the most memory used at once, not drop to what is used at the end.
+#[cfg(feature = "metrics")]
+unsafe fn peak_usage() {
+    let alloc: AlligatorAlloc<HeapType> = AlligatorAlloc::INIT;
+    let sizes: [usize; 4] = [100, 100, 8, 40 * 1024];
+    let mut ptrs: Vec<(*mut u8, Layout)> = vec!();
+    for bytes in sizes.iter() {
+        let layout = Layout::from_size_align(*bytes, 1).unwrap();
+        let ptr = alloc.alloc(layout);
+        assert!(!ptr.is_null(), "alloc({}) failed: {:?}", bytes, alloc.alloc_failure_cause());
+        ptrs.push((ptr, layout));
+    }
+
+    // cache-align puts the 8 byte allocation in a 64 byte segment, so its size is taken from the segment
+    let peak_bytes = ((2 * 128) + alloc.usable_size(ptrs[2].0) + alloc.usable_size(ptrs[3].0)) as u32;
+    let metrics = alloc.metrics().unwrap();
+    assert_eq!((metrics.current_used_bytes, metrics.peak_used_bytes), (peak_bytes, peak_bytes), "used bytes should be the segment sizes plus the big allocation's size");
+    assert_eq!((metrics.current_minipages, metrics.peak_minipages), (2, 2), "the 8 and 128 byte size classes should each have one live MiniPage");
+
+    for (ptr, layout) in ptrs.iter() {
+        alloc.dealloc(*ptr, *layout);
+    }
+
+    let metrics = alloc.metrics().unwrap();
+    assert_eq!((metrics.current_used_bytes, metrics.current_minipages), (0, 0), "nothing should be used after freeing everything");
+    assert_eq!((metrics.peak_used_bytes, metrics.peak_minipages), (peak_bytes, 2), "freeing should not lower the peaks");
+
+    let layout = Layout::from_size_align(100, 1).unwrap();
+    let ptr = alloc.alloc(layout);
+    assert!(!ptr.is_null(), "alloc({}) failed: {:?}", layout.size(), alloc.alloc_failure_cause());
+
+    let metrics = alloc.metrics().unwrap();
+    assert_eq!((metrics.current_used_bytes, metrics.current_minipages), (128, 1), "only the new allocation should be used");
+    assert_eq!((metrics.peak_used_bytes, metrics.peak_minipages), (peak_bytes, 2), "a smaller allocation after freeing should not change the peaks");
+
+    alloc.dealloc(ptr, layout);
+}
+
+/// Free a MiniPage allocation and a big allocation twice. The second free must be ignored and record the DoubleFree cause. With the strict feature double frees panic instead, so this is skipped.
+#[cfg(all(feature = "metrics", not(feature = "strict")))]
+unsafe fn double_free() {
+    use alloc::AllocFail;
+
+    for (bytes, big) in [(100, false), (40 * 1024, true)].iter() {
+        let layout = Layout::from_size_align(*bytes, 1).unwrap();
+        let ptr = ALLOC.alloc(layout);
+        assert!(!ptr.is_null(), "alloc({}) failed: {:?}", bytes, ALLOC.alloc_failure_cause());
+        let kind = ALLOC.allocation_kind(ptr);
+        assert_eq!(matches!(kind, Some(AllocKind::Big{ .. })), *big, "alloc({}) made a {:?} allocation", bytes, kind);
+
+        ALLOC.dealloc(ptr, layout);
+        let counts_before = ALLOC.failure_counts()[AllocFail::DoubleFree as usize];
+        ALLOC.dealloc(ptr, layout);
+
+        println!("double free: {:?} allocation of {} bytes, cause={:?}", kind, bytes, ALLOC.alloc_failure_cause());
+        assert!(matches!(ALLOC.alloc_failure_cause(), Some(AllocFail::DoubleFree)), "freeing a {:?} allocation twice should fail with DoubleFree, failed with {:?}", kind, ALLOC.alloc_failure_cause());
+        assert_eq!(ALLOC.failure_counts()[AllocFail::DoubleFree as usize], counts_before + 1, "freeing a {:?} allocation twice should count one DoubleFree", kind);
+    }
+}
+
+/// Fill TINY_ALLOC with MiniPages until it runs out of pages. The failure must be a clean null with the AddMiniPageNoSpace cause, and every allocation must be inside the max pages. With the strict feature the failure panics instead, so this is skipped.
+#[cfg(all(feature = "metrics", not(feature = "strict")))]
+unsafe fn max_pages_boundary() {
+    use alloc::AllocFail;
+
+    let layout = Layout::from_size_align(2048, 1).unwrap();
+    let mut ptrs: Vec<*mut u8> = vec!();
+    loop {
+        let ptr = TINY_ALLOC.alloc(layout);
+        if ptr.is_null() {
+            break;
+        }
+
+        assert!(ptrs.len() < TINY_MAX_PAGES * 32, "more MiniPages were allocated than fit in {} pages", TINY_MAX_PAGES);
+        ptrs.push(ptr);
+    }
+
+    println!("max pages boundary: {} MiniPages fit in {} pages", ptrs.len(), TINY_MAX_PAGES);
+    assert!(!ptrs.is_empty(), "no allocations fit in {} pages: {:?}", TINY_MAX_PAGES, TINY_ALLOC.alloc_failure_cause());
+    assert!(matches!(TINY_ALLOC.alloc_failure_cause(), Some(AllocFail::AddMiniPageNoSpace)), "alloc failed with {:?} instead of AddMiniPageNoSpace", TINY_ALLOC.alloc_failure_cause());
+
+    // Allocations must all be within the first TINY_MAX_PAGES pages of the first allocation's heap
+    let lowest = *ptrs.iter().min().unwrap() as usize;
+    let highest = *ptrs.iter().max().unwrap() as usize;
+    assert!(highest + layout.size() - lowest <= TINY_MAX_PAGES * (heap::PAGE_BYTES as usize), "allocations span more than {} pages", TINY_MAX_PAGES);
+
+    for ptr in ptrs.iter() {
+        TINY_ALLOC.dealloc(*ptr, layout);
+    }
+}
+
+/// Make the host refuse to grow the heap, with a MockHostHeap. Allocations must fail cleanly and record HostGrowFail, both when the heap is first set up and when pages given back by release_unused_pages() are grown again. With the strict feature the failed allocations panic instead, so this is skipped.
+#[cfg(all(feature = "metrics", feature = "mock", not(feature = "strict")))]
+unsafe fn mock_grow_failures() {
+    use alloc::AllocFail;
+    use alloc::heap::MockHostHeap;
+
+    let layout = Layout::from_size_align(SMALLEST_CLASS_BYTES, 1).unwrap();
+
+    // The first grow fails
+    let no_grow_alloc = AlligatorAlloc::with_heap(MockHostHeap::new(TINY_MAX_PAGES, 0), TINY_MAX_PAGES);
+    assert!(no_grow_alloc.alloc(layout).is_null(), "alloc() should fail when the host heap cannot grow");
+    assert!(matches!(no_grow_alloc.alloc_failure_cause(), Some(AllocFail::HostGrowFail)), "alloc failed with {:?} instead of HostGrowFail", no_grow_alloc.alloc_failure_cause());
+
+    // Only the first grow succeeds, so released pages cannot be grown again
+    let one_grow_alloc = AlligatorAlloc::with_heap(MockHostHeap::new(TINY_MAX_PAGES, 1), TINY_MAX_PAGES);
+    let ptr = one_grow_alloc.alloc(layout);
+    assert!(!ptr.is_null(), "alloc() failed even though the first grow succeeds: {:?}", one_grow_alloc.alloc_failure_cause());
+    one_grow_alloc.dealloc(ptr, layout);
+    assert!(one_grow_alloc.release_unused_pages() > 0, "no pages were released from the mock heap");
+
+    let big_layout = Layout::from_size_align(32 * 1024, 1).unwrap();
+    let mut ptrs: Vec<*mut u8> = vec!();
+    loop {
+        let ptr = one_grow_alloc.alloc(big_layout);
+        if ptr.is_null() {
+            break;
+        }
+
+        assert!(ptrs.len() < TINY_MAX_PAGES * 2, "more big allocations were made than fit in {} pages", TINY_MAX_PAGES);
+        ptrs.push(ptr);
+    }
+
+    let grow_fails = one_grow_alloc.failure_counts()[AllocFail::HostGrowFail as usize];
+    println!("mock grow failures: {} big allocations before growing back failed {} times", ptrs.len(), grow_fails);
+    assert!(grow_fails > 0, "growing released pages back should have failed, last failure was {:?}", one_grow_alloc.alloc_failure_cause());
+
+    for ptr in ptrs.iter() {
+        one_grow_alloc.dealloc(*ptr, big_layout);
+    }
+}
+
+/// The heap is grown as allocations need it, so an allocator works on a host which cannot grant all of its max pages, or only grants a few pages per grow. Each must hand out as many big allocations as an allocator whose host grants everything, then fail once the host's pages are full. With the strict feature the failed allocations panic instead, so this is skipped.
+#[cfg(all(feature = "mock", not(feature = "strict")))]
+unsafe fn lazy_grow() {
+    use alloc::heap::MockHostHeap;
+
+    let big_layout = Layout::from_size_align(40 * 1024, 1).unwrap();
+    let small_layout = Layout::from_size_align(SMALLEST_CLASS_BYTES, 1).unwrap();
+    let all_max_pages = (MAX_HOST_HEAP_BYTES as usize) / (heap::PAGE_BYTES as usize);
+
+    let fill = |alloc: &AlligatorAlloc<MockHostHeap>, name: &str| -> Vec<*mut u8> {
+        let mut ptrs: Vec<*mut u8> = vec!();
+        assert!(!alloc.alloc(small_layout).is_null(), "{}: first alloc() failed", name);
+        loop {
+            let ptr = alloc.alloc(big_layout);
+            if ptr.is_null() {
+                break;
+            }
+
+            assert!(ptrs.len() < TINY_MAX_PAGES * 2, "{}: more big allocations were made than fit in {} pages", name, TINY_MAX_PAGES);
+            ptrs.push(ptr);
+        }
+        assert_eq!(alloc.validate_heap(), Ok(()), "{}: heap broken once full", name);
+
+        ptrs
+    };
+
+    // The host grants every grow, and the allocator may use exactly its pages
+    let full_grant_alloc = AlligatorAlloc::with_heap(MockHostHeap::new(TINY_MAX_PAGES, usize::MAX), TINY_MAX_PAGES);
+    let expected = fill(&full_grant_alloc, "full grants").len();
+    assert!(expected > 0, "no big allocations fit in {} pages", TINY_MAX_PAGES);
+
+    // The allocator may use far more pages than the host has
+    let small_host_alloc = AlligatorAlloc::with_heap(MockHostHeap::new(TINY_MAX_PAGES, usize::MAX), all_max_pages);
+    let small_host_ptrs = fill(&small_host_alloc, "small host");
+    assert_eq!(small_host_ptrs.len(), expected, "an allocator with {} max pages should fit as much in a {} page host as one with {} max pages", all_max_pages, TINY_MAX_PAGES, TINY_MAX_PAGES);
+
+    // Each grow only grants one page, however many are asked for
+    let one_page_alloc = AlligatorAlloc::with_heap(MockHostHeap::new(TINY_MAX_PAGES, usize::MAX).with_max_grow_pages(1), all_max_pages);
+    let one_page_ptrs = fill(&one_page_alloc, "one page grants");
+    assert_eq!(one_page_ptrs.len(), expected, "a host which grants one page per grow should fit as much as one which grants everything");
+
+    cfg_if! {
+        if #[cfg(feature = "metrics")] {
+            use alloc::AllocFail;
+
+            for (alloc, name) in [(&small_host_alloc, "small host"), (&one_page_alloc, "one page grants")].iter() {
+                assert!(alloc.failure_counts()[AllocFail::HostGrowFail as usize] > 0, "{}: the last allocation should fail because the host could not grow, failed with {:?}", name, alloc.alloc_failure_cause());
+            }
+        }
+    }
+
+    // Freed memory is handed out again without growing
+    one_page_alloc.dealloc(one_page_ptrs[0], big_layout);
+    assert_eq!(one_page_alloc.alloc(big_layout), one_page_ptrs[0], "freed big allocation should be reused once the host is full");
+    println!("lazy grow: {} big allocations fit with full grants, a small host, and one page grants", expected);
+}
+
+/// Shrink a HostHeap, then give the allocator's unused pages back to the host. Allocations after must still succeed, growing the heap again.
+///
+/// Earlier tests may leave the heap ending anywhere inside its last host page, so a big allocation larger than the pages a grow adds beyond what it needs is made and freed first. Its grow always leaves whole pages after it to release.
+unsafe fn release_unused_pages() {
+    let mut host_heap: HeapType = heap::INIT;
+    assert_ne!(host_heap.memory_grow(4), usize::MAX, "memory_grow(4) failed");
+    assert!(host_heap.memory_shrink(3).is_ok(), "memory_shrink(3) failed");
+    assert_eq!(host_heap.memory_size(), 1, "memory_size should drop from 4 to 1 after memory_shrink(3)");
+
+    // Free big allocations earlier tests left are reused before the heap grows, keep allocating until one grows it
+    let grow_layout = Layout::from_size_align(8 * (heap::PAGE_BYTES as usize), 1).unwrap();
+    let start_pages = ALLOC.heap_pages();
+    let mut grow_ptrs: Vec<*mut u8> = vec!();
+    while ALLOC.heap_pages() == start_pages {
+        let grow_ptr = ALLOC.alloc(grow_layout);
+        assert!(!grow_ptr.is_null(), "alloc({}) failed before the heap grew", grow_layout.size());
+        grow_ptrs.push(grow_ptr);
+    }
+    for grow_ptr in grow_ptrs.iter() {
+        ALLOC.dealloc(*grow_ptr, grow_layout);
+    }
+
+    let released_pages = ALLOC.release_unused_pages();
+    println!("release unused pages: {} pages released", released_pages);
+    assert!(released_pages > 0, "no pages were released after every allocation was freed");
+
+    // Use more memory than was released so the heap has to grow back, earlier tests leave ALLOC too full for much more
+    let layout = Layout::from_size_align(32 * 1024, 1).unwrap();
+    let mut ptrs: Vec<*mut u8> = vec!();
+    for _i in 0..((released_pages + 1) * (heap::PAGE_BYTES as usize) / layout.size()) {
+        let ptr = ALLOC.alloc(layout);
+        assert!(!ptr.is_null(), "alloc({}) failed after release_unused_pages()", layout.size());
+        ptrs.push(ptr);
+    }
+
+    for ptr in ptrs.iter() {
+        ALLOC.dealloc(*ptr, layout);
+    }
+}
+
+/// Allocate a known mix of sizes from a new allocator and check live_stats() counts them, then free them and check the counts drop back to zero.
+unsafe fn live_stats() {
+    let alloc: AlligatorAlloc<HeapType> = AlligatorAlloc::INIT;
+    let sizes: [usize; 7] = [8, 8, 8, 100, 100, 4 * 1024, 40 * 1024];
+    let mut ptrs: Vec<(*mut u8, Layout)> = vec!();
+    for bytes in sizes.iter() {
+        let layout = Layout::from_size_align(*bytes, 1).unwrap();
+        let ptr = alloc.alloc(layout);
+        assert!(!ptr.is_null(), "alloc({}) failed", bytes);
+        ptrs.push((ptr, layout));
+    }
+
+    let stats = alloc.live_stats();
+    println!("live stats: {:?}", stats);
+
+    // Features like cache-align move small allocations to larger size classes, so expect the size class each MiniPage allocation's usable size says it got
+    let mut expected_segments = stats.live_segments;
+    expected_segments.iter_mut().for_each(|count| *count = 0);
+    for (ptr, _layout) in ptrs.iter().take(6) {
+        let exp = alloc.usable_size(*ptr).trailing_zeros() as u8;
+        expected_segments[SizeClass::new(exp).exp_as_idx()] += 1;
+    }
+
+    let usable_bytes: usize = ptrs.iter().map(|(ptr, _layout)| alloc.usable_size(*ptr)).sum();
+    assert_eq!(stats.bytes_in_use, usable_bytes, "bytes in use should be the usable size of every allocation");
+    assert_eq!(stats.live_segments, expected_segments, "live segments should be one for each MiniPage allocation in its size class");
+    assert_eq!(stats.live_minipages, expected_segments.iter().filter(|count| **count > 0).count() as u32, "every size class with allocations should have one live MiniPage");
+    assert_eq!(stats.live_big_allocs, 1, "only the {} byte allocation should be a big allocation", sizes[6]);
+
+    for (ptr, layout) in ptrs.iter() {
+        alloc.dealloc(*ptr, *layout);
+    }
+
+    let stats = alloc.live_stats();
+    assert_eq!(stats.bytes_in_use, 0, "bytes in use should be 0 after freeing everything: {:?}", stats);
+    assert_eq!(stats.live_minipages, 0, "no MiniPages should be live after freeing everything: {:?}", stats);
+    assert_eq!(stats.live_big_allocs, 0, "no big allocations should be live after freeing everything: {:?}", stats);
+    assert!(stats.live_segments.iter().all(|count| *count == 0), "no segments should be live after freeing everything: {:?}", stats);
+}
+
+/// Snapshot a new allocator, allocate a known mix of size classes and a big allocation, then check the diff from a second snapshot reports exactly those per class. Once they are freed the reclaimed MiniPages must not be counted. The size classes are at least 64 bytes so cache-align does not change them.
+unsafe fn snapshot_stats() {
+    let alloc: AlligatorAlloc<HeapType> = AlligatorAlloc::INIT;
+    let before = alloc.snapshot_stats();
+
+    let sizes: [usize; 6] = [64, 64, 64, 128, 128, 40 * 1024];
+    let mut ptrs: Vec<(*mut u8, Layout)> = vec!();
+    for bytes in sizes.iter() {
+        let layout = Layout::from_size_align(*bytes, 1).unwrap();
+        let ptr = alloc.alloc(layout);
+        assert!(!ptr.is_null(), "alloc({}) failed", bytes);
+        ptrs.push((ptr, layout));
+    }
+
+    let after = alloc.snapshot_stats();
+    let diff = after.diff(&before);
+    println!("snapshot stats: {:?}", diff);
+
+    let mut expected_live = [0_i64; (MAX_SIZE_CLASS - MIN_SIZE_CLASS + 1) as usize];
+    let mut expected_minipages = [0_i64; (MAX_SIZE_CLASS - MIN_SIZE_CLASS + 1) as usize];
+    expected_live[SizeClass::new(6).exp_as_idx()] = 3 * 64;
+    expected_live[SizeClass::new(7).exp_as_idx()] = 2 * 128;
+    expected_minipages[SizeClass::new(6).exp_as_idx()] = 1;
+    expected_minipages[SizeClass::new(7).exp_as_idx()] = 1;
+
+    let big_bytes = alloc.usable_size(ptrs[5].0);
+    assert_eq!(diff.live_small, expected_live, "the diff should have the live bytes of each size class allocated from");
+    assert_eq!(diff.live_big, big_bytes as i64, "the diff should have the big allocation's bytes");
+    assert_eq!(diff.minipages, expected_minipages, "the diff should have one new MiniPage for each size class allocated from");
+    assert_eq!(diff.big_pages, ((big_bytes + 2047) / 2048) as i64, "the diff should have the intervals the big allocation and its header take up");
+    assert!(diff.used_pages >= 2 + diff.big_pages, "the MiniPages and big allocation should all be in the used intervals: {:?}", diff);
+
+    for (ptr, layout) in ptrs.iter() {
+        alloc.dealloc(*ptr, *layout);
+    }
+
+    // The emptied MiniPages are reclaimed, they no longer belong to a size class
+    let freed_diff = alloc.snapshot_stats().diff(&before);
+    assert!(freed_diff.live_small.iter().all(|bytes| *bytes == 0) && freed_diff.live_big == 0, "nothing should be live after freeing everything: {:?}", freed_diff);
+    assert!(freed_diff.minipages.iter().all(|count| *count == 0), "reclaimed MiniPages should not be counted in their old size class: {:?}", freed_diff);
+}
+
+/// Free a 64KB big allocation and a 40KB one after it, then check a 36KB allocation reuses the smaller one under BigAllocPolicy::BestFit and the first one under FirstFit. Only allocations larger than MAX_SIZE_CLASS are big allocations, so the sizes are all more than 32KB. A big allocation between the two stays live so they are not coalesced.
+unsafe fn big_alloc_policy() {
+    let best_fit_alloc: AlligatorAlloc<HeapType> = AlligatorAlloc::with_big_alloc_policy(BigAllocPolicy::BestFit);
+    let first_fit_alloc: AlligatorAlloc<HeapType> = AlligatorAlloc::with_big_alloc_policy(BigAllocPolicy::FirstFit);
+    let large_layout = Layout::from_size_align(64 * 1024, 1).unwrap();
+    let small_layout = Layout::from_size_align(40 * 1024, 1).unwrap();
+    let request_layout = Layout::from_size_align(36 * 1024, 1).unwrap();
+
+    for (alloc, policy) in [(&best_fit_alloc, BigAllocPolicy::BestFit), (&first_fit_alloc, BigAllocPolicy::FirstFit)].iter() {
+        let large_ptr = alloc.alloc(large_layout);
+        let between_ptr = alloc.alloc(request_layout);
+        let small_ptr = alloc.alloc(small_layout);
+        assert!(!large_ptr.is_null() && !between_ptr.is_null() && !small_ptr.is_null(), "{:?}: allocating the big allocations to free failed", policy);
+        alloc.dealloc(large_ptr, large_layout);
+        alloc.dealloc(small_ptr, small_layout);
+
+        let ptr = alloc.alloc(request_layout);
+        let expected_ptr = match policy {
+            BigAllocPolicy::BestFit => small_ptr,
+            BigAllocPolicy::FirstFit => large_ptr,
+        };
+        println!("big alloc policy: {:?} reused {:?} for {} bytes", policy, ptr, request_layout.size());
+        assert_eq!(ptr, expected_ptr, "{:?}: a {} byte allocation should reuse the free {} byte big allocation", policy, request_layout.size(), match policy {
+            BigAllocPolicy::BestFit => small_layout.size(),
+            BigAllocPolicy::FirstFit => large_layout.size(),
+        });
+        assert_eq!(alloc.validate_heap(), Ok(()), "{:?}: heap should be valid after reusing a big allocation", policy);
+
+        alloc.dealloc(ptr, request_layout);
+        alloc.dealloc(between_ptr, request_layout);
+    }
+}
+
+/// Allocate MiniPage and big allocations, free some, then check for_each_live() reports exactly the ones still live, like a leak check would.
+unsafe fn for_each_live() {
+    let alloc: AlligatorAlloc<HeapType> = AlligatorAlloc::INIT;
+    let sizes: [usize; 8] = [8, 8, 8, 100, 100, 4 * 1024, 40 * 1024, 40 * 1024];
+    let mut ptrs: Vec<(*mut u8, Layout)> = vec!();
+    for bytes in sizes.iter() {
+        let layout = Layout::from_size_align(*bytes, 1).unwrap();
+        let ptr = alloc.alloc(layout);
+        assert!(!ptr.is_null(), "alloc({}) failed", bytes);
+        ptrs.push((ptr, layout));
+    }
+
+    // Free every other allocation, the rest are "leaked"
+    let mut expected: Vec<(usize, usize)> = vec!();
+    for (i, (ptr, layout)) in ptrs.iter().enumerate() {
+        if i % 2 == 0 {
+            alloc.dealloc(*ptr, *layout);
+        } else {
+            expected.push((*ptr as usize, alloc.usable_size(*ptr)));
         }
     }
+
+    let mut live: Vec<(usize, usize)> = vec!();
+    alloc.for_each_live(|ptr, bytes| live.push((ptr as usize, bytes)));
+
+    expected.sort();
+    live.sort();
+    println!("for each live: {} live allocations reported", live.len());
+    assert_eq!(live, expected, "for_each_live should report exactly the allocations which were not freed, as (address, usable size)");
+
+    for (i, (ptr, layout)) in ptrs.iter().enumerate() {
+        if i % 2 == 1 {
+            alloc.dealloc(*ptr, *layout);
+        }
+    }
+
+    let mut leaks = 0;
+    alloc.for_each_live(|_ptr, _bytes| leaks += 1);
+    assert_eq!(leaks, 0, "for_each_live should report nothing once everything is freed");
+}
+
+/// Without the no-panic feature the first allocation of an allocator whose max pages can't hold the MetaPage panics inside the allocator. Freeing pointers before and far past the MiniPages never panics without strict. Check they fail by returning instead, and leave the allocator usable.
+#[cfg(feature = "no-panic")]
+unsafe fn no_panic() {
+    let no_panic_alloc: AlligatorAlloc<HeapType> = AlligatorAlloc::INIT;
+    let no_room_alloc: AlligatorAlloc<HeapType> = AlligatorAlloc::with_max_pages(1);
+    let layout = Layout::from_size_align(8, 1).unwrap();
+
+    let ptr = no_room_alloc.alloc(layout);
+    assert!(ptr.is_null(), "allocating when the MetaPage does not fit in 1 host page should return null");
+
+    cfg_if! {
+        if #[cfg(feature = "metrics")] {
+            use alloc::AllocFail;
+            assert!(matches!(no_room_alloc.alloc_failure_cause(), Some(AllocFail::HostGrowFail)), "the MetaPage not fitting should fail with HostGrowFail, failed with {:?}", no_room_alloc.alloc_failure_cause());
+        }
+    }
+
+    let ptr = no_panic_alloc.alloc(layout);
+    assert!(!ptr.is_null(), "alloc failed");
+
+    // The heap is at most MAX_HOST_HEAP_BYTES, so both are outside it
+    for bad_ptr in [ptr.wrapping_sub(MAX_HOST_HEAP_BYTES as usize), ptr.wrapping_add(MAX_HOST_HEAP_BYTES as usize)].iter() {
+        no_panic_alloc.dealloc(*bad_ptr, layout);
+
+        cfg_if! {
+            if #[cfg(feature = "metrics")] {
+                assert!(matches!(no_panic_alloc.alloc_failure_cause(), Some(AllocFail::InvalidFreePointer)), "freeing {:?}, which is outside the MiniPages, should fail with InvalidFreePointer, failed with {:?}", bad_ptr, no_panic_alloc.alloc_failure_cause());
+            }
+        }
+    }
+
+    assert_eq!(no_panic_alloc.live_stats().live_minipages, 1, "freeing pointers outside the MiniPages should not free the live allocation");
+    assert_eq!(no_panic_alloc.validate_heap(), Ok(()), "heap should be valid after freeing pointers outside the MiniPages");
+
+    no_panic_alloc.dealloc(ptr, layout);
+}
+
+/// Free a pointer into the MetaPage, which is before the first MiniPage. It must be rejected with InvalidFreePointer and change nothing, instead of panicking in AllocAddr::from_ptr(). With the strict feature this panics instead, so this is skipped.
+#[cfg(not(feature = "strict"))]
+unsafe fn invalid_free_pointer() {
+    let alloc: AlligatorAlloc<HeapType> = AlligatorAlloc::INIT;
+    let layout = Layout::from_size_align(8, 1).unwrap();
+    let ptr = alloc.alloc(layout);
+    assert!(!ptr.is_null(), "alloc failed");
+
+    // The first allocation is in the first MiniPage, the MetaPage takes up more than the 2 KB before it
+    let bad_ptr = ptr.wrapping_sub(2048);
+    assert!(!alloc.owns(bad_ptr), "{:?} should be in the MetaPage, not a MiniPage", bad_ptr);
+    alloc.dealloc(bad_ptr, layout);
+
+    cfg_if! {
+        if #[cfg(feature = "metrics")] {
+            use alloc::AllocFail;
+            assert!(matches!(alloc.alloc_failure_cause(), Some(AllocFail::InvalidFreePointer)), "freeing {:?}, which is in the MetaPage, should fail with InvalidFreePointer, failed with {:?}", bad_ptr, alloc.alloc_failure_cause());
+        }
+    }
+
+    assert_eq!(alloc.live_stats().live_minipages, 1, "freeing a pointer into the MetaPage should not free the live allocation");
+    assert_eq!(alloc.validate_heap(), Ok(()), "heap should be valid after freeing a pointer into the MetaPage");
+
+    alloc.dealloc(ptr, layout);
+}
+
+/// Allocate 9 bytes, which get a 16 byte segment (64 bytes with cache-align), and check requested_size() is 9 while usable_size() is the segment size. Then check a realloc within the segment records the new size, and a realloc which moves copies the requested bytes, also for an allocation made by alloc_near().
+#[cfg(feature = "requested-size")]
+unsafe fn requested_size() {
+    let alloc: AlligatorAlloc<HeapType> = AlligatorAlloc::INIT;
+    let layout = Layout::from_size_align(9, 1).unwrap();
+    let ptr = alloc.alloc(layout);
+    assert!(!ptr.is_null(), "alloc({}) failed", layout.size());
+
+    println!("requested size: {:?} of {} usable bytes", alloc.requested_size(ptr), alloc.usable_size(ptr));
+    assert_eq!(alloc.requested_size(ptr), Some(9), "the requested size of a {} byte allocation should be recorded", layout.size());
+    let segment_bytes = if cfg!(feature = "cache-align") { 64 } else { 16 };
+    assert_eq!(alloc.usable_size(ptr), segment_bytes, "a {} byte allocation should use a {} byte segment", layout.size(), segment_bytes);
+
+    // Fits in the segment, resized in place
+    let ptr = alloc.realloc(ptr, layout, 14);
+    assert_eq!(alloc.requested_size(ptr), Some(14), "realloc in place should record the new requested size");
+
+    for i in 0..14 {
+        *ptr.add(i) = i as u8;
+    }
+    let layout = Layout::from_size_align(14, 1).unwrap();
+    let moved_ptr = alloc.realloc(ptr, layout, 100);
+    assert!(!moved_ptr.is_null(), "realloc to 100 bytes failed");
+    assert_eq!(alloc.requested_size(moved_ptr), Some(100), "the requested size of the moved allocation should be recorded");
+    assert_eq!(alloc.requested_size(ptr), None, "the allocation realloc moved from is freed, it has no requested size");
+    for i in 0..14 {
+        assert_eq!(*moved_ptr.add(i), i as u8, "byte {} was not copied when realloc moved the allocation", i);
+    }
+
+    let big_layout = Layout::from_size_align(40 * 1024, 1).unwrap();
+    let big_ptr = alloc.alloc(big_layout);
+    assert!(!big_ptr.is_null(), "alloc({}) failed", big_layout.size());
+    assert_eq!(alloc.requested_size(big_ptr), None, "big allocations do not record their requested size");
+
+    alloc.dealloc(big_ptr, big_layout);
+    alloc.dealloc(moved_ptr, Layout::from_size_align(100, 1).unwrap());
+
+    // alloc_near() only uses the hint's MiniPage when it is on the free minipages stack below the head. Fill one MiniPage, start a second, then free from the first so it is the head
+    let layout = Layout::from_size_align(30, 1).unwrap();
+    let mut first: Vec<*mut u8> = vec!(alloc.alloc(layout));
+    // The size class is taken from the segment used, cache-align makes it larger than 30 bytes need
+    for _ in 1..SizeClass::new_from_bytes(alloc.usable_size(first[0])).segments_max_num() {
+        first.push(alloc.alloc(layout));
+    }
+    let second = alloc.alloc(layout);
+    alloc.dealloc(first[0], layout);
+
+    let near = alloc.alloc_near(second, layout);
+    assert_eq!(near as usize / 2048, second as usize / 2048, "alloc_near() should allocate from the hint's MiniPage");
+    assert_eq!(alloc.requested_size(near), Some(30), "alloc_near() should record the requested size");
+
+    for i in 0..30 {
+        *near.add(i) = i as u8;
+    }
+    let moved_ptr = alloc.realloc(near, layout, 100);
+    assert!(!moved_ptr.is_null(), "realloc to 100 bytes failed");
+    for i in 0..30 {
+        assert_eq!(*moved_ptr.add(i), i as u8, "byte {} of an alloc_near() allocation was not copied when realloc moved it", i);
+    }
+
+    alloc.dealloc(moved_ptr, Layout::from_size_align(100, 1).unwrap());
+    alloc.dealloc(second, layout);
+    for ptr in first.iter().skip(1) {
+        alloc.dealloc(*ptr, layout);
+    }
+}
+
+/// Allocate a known number of segments in one size class, more than fit in one MiniPage, and check occupancy_stats() counts them and the MiniPages they need.
+unsafe fn occupancy_stats() {
+    let alloc: AlligatorAlloc<HeapType> = AlligatorAlloc::INIT;
+    let layout = Layout::from_size_align(SMALLEST_CLASS_BYTES, 1).unwrap();
+    let size_class = SizeClass::new_from_layout(layout);
+    let segments = usize::from(size_class.segments_max_num()) + 5;
+
+    let mut ptrs: Vec<*mut u8> = vec!();
+    for i in 0..segments {
+        let ptr = alloc.alloc(layout);
+        assert!(!ptr.is_null(), "alloc {} failed", i);
+        ptrs.push(ptr);
+    }
+
+    let stats = alloc.occupancy_stats();
+    println!("occupancy stats: {:?}", stats);
+    assert_eq!(stats.live_segments[size_class.exp_as_idx()] as usize, segments, "live segments of size class {} should be the number allocated", size_class.exp);
+    assert_eq!(stats.minipages_per_class[size_class.exp_as_idx()], 2, "{} segments of size class {} should take up 2 MiniPages", segments, size_class.exp);
+    assert_eq!(stats.live_segments.iter().sum::<u32>() as usize, segments, "only size class {} should have live segments", size_class.exp);
+    let expected_occupancy = segments as f64 / (2.0 * f64::from(size_class.segments_max_num()));
+    assert!((stats.occupancy(size_class) - expected_occupancy).abs() < 1e-9, "occupancy of size class {} should be {}, was {}", size_class.exp, expected_occupancy, stats.occupancy(size_class));
+
+    for ptr in ptrs.iter() {
+        alloc.dealloc(*ptr, layout);
+    }
+
+    let stats = alloc.occupancy_stats();
+    assert!(stats.live_segments.iter().all(|count| *count == 0), "no segments should be live after freeing everything: {:?}", stats);
+}
+
+/// Fill one MiniPage of 64 byte allocations and start a second. alloc_near() with a hint into the second must allocate from it once it is under the first on the free minipages stack. Then free the first until it is reclaimed, alloc_near() with a stale hint into it must not allocate from it, the next size class to need a MiniPage is handed that memory.
+unsafe fn alloc_near() {
+    let alloc: AlligatorAlloc<HeapType> = AlligatorAlloc::INIT;
+    let layout = Layout::from_size_align(SMALLEST_CLASS_BYTES, 1).unwrap();
+    let other_layout = Layout::from_size_align(128, 1).unwrap();
+    let size_class = SizeClass::new_from_layout(layout);
+    let page_of = |ptr: *mut u8| ptr as usize / 2048;
+
+    let mut first: Vec<*mut u8> = vec!();
+    for i in 0..usize::from(size_class.segments_max_num()) {
+        let ptr = alloc.alloc(layout);
+        assert!(!ptr.is_null(), "alloc {} failed", i);
+        first.push(ptr);
+    }
+    let second = alloc.alloc(layout);
+    assert!(!second.is_null(), "alloc failed");
+    assert_ne!(page_of(second), page_of(first[0]), "the first MiniPage is full, the next allocation should start a second");
+
+    // Freeing puts the first MiniPage on top of the free minipages stack, above the second
+    alloc.dealloc(first[0], layout);
+    alloc.dealloc(first[1], layout);
+    let near = alloc.alloc_near(second, layout);
+    assert_eq!(page_of(near), page_of(second), "alloc_near() should allocate from the hint's MiniPage, which has free segments");
+
+    // Emptying the first MiniPage while it is the head reclaims it
+    for ptr in first.iter().skip(2) {
+        alloc.dealloc(*ptr, layout);
+    }
+    assert_eq!(alloc.occupancy_stats().minipages_per_class[size_class.exp_as_idx()], 1, "the emptied MiniPage should have been reclaimed");
+
+    let stale = alloc.alloc_near(first[0], layout);
+    assert!(!stale.is_null(), "alloc_near failed");
+    assert_ne!(page_of(stale), page_of(first[0]), "alloc_near() allocated from a reclaimed MiniPage");
+
+    let other = alloc.alloc(other_layout);
+    assert_eq!(page_of(other), page_of(first[0]), "the reclaimed MiniPage should be reused by the next size class which needs one");
+    assert_eq!(alloc.validate_heap(), Ok(()), "heap broken after alloc_near() with a hint into a reclaimed MiniPage");
+
+    for ptr in [second, near, stale].iter() {
+        alloc.dealloc(*ptr, layout);
+    }
+    alloc.dealloc(other, other_layout);
+}
+
+/// Free a MiniPage allocation and a big allocation whose bytes all read 0xFF, which would point at some other MiniPage if dealloc() used the byte ptr points to instead of ptr's address. Only those two may be freed, and allocating again must hand the same addresses back.
+unsafe fn dealloc_by_address() {
+    let alloc: AlligatorAlloc<HeapType> = AlligatorAlloc::INIT;
+    let small_layout = Layout::from_size_align(SMALLEST_CLASS_BYTES, 1).unwrap();
+    let big_layout = Layout::from_size_align(40 * 1024, 1).unwrap();
+
+    let small_freed = alloc.alloc(small_layout);
+    let small_live = alloc.alloc(small_layout);
+    let big_freed = alloc.alloc(big_layout);
+    let big_live = alloc.alloc(big_layout);
+    for ptr in [small_freed, small_live, big_freed, big_live].iter() {
+        assert!(!ptr.is_null(), "alloc failed");
+    }
+
+    for (ptr, layout) in [(small_freed, small_layout), (big_freed, big_layout)].iter() {
+        ptr.write_bytes(0xFF, layout.size());
+        alloc.dealloc(*ptr, *layout);
+    }
+
+    assert_eq!(alloc.allocation_kind(small_freed), None, "the freed MiniPage allocation is still live");
+    assert_eq!(alloc.allocation_kind(big_freed), None, "the freed big allocation is still live");
+    assert!(alloc.allocation_kind(small_live).is_some(), "freeing an allocation freed a different MiniPage allocation");
+    assert!(alloc.allocation_kind(big_live).is_some(), "freeing an allocation freed a different big allocation");
+    assert_eq!(alloc.validate_heap(), Ok(()), "heap broken after freeing allocations filled with 0xFF");
+
+    assert_eq!(alloc.alloc(small_layout), small_freed, "the freed MiniPage segment should be handed out again");
+    assert_eq!(alloc.alloc(big_layout), big_freed, "the freed big allocation should be handed out again");
+
+    for (ptr, layout) in [(small_freed, small_layout), (small_live, small_layout), (big_freed, big_layout), (big_live, big_layout)].iter() {
+        alloc.dealloc(*ptr, *layout);
+    }
+}
+
+/// Fill several MiniPages of one size class, free most of each, then check compact() moves the rest into as few MiniPages as they fit in, frees the others, and keeps each moved allocation's bytes.
+unsafe fn compact() {
+    let alloc: AlligatorAlloc<HeapType> = AlligatorAlloc::INIT;
+    let layout = Layout::from_size_align(SMALLEST_CLASS_BYTES, 1).unwrap();
+    let segments_per_page = usize::from(SizeClass::new_from_layout(layout).segments_max_num());
+    let pages = 4;
+
+    let mut ptrs: Vec<*mut u8> = vec!();
+    for i in 0..(pages * segments_per_page) {
+        let ptr = alloc.alloc(layout);
+        assert!(!ptr.is_null(), "alloc {} failed", i);
+        std::ptr::write_bytes(ptr, i as u8, layout.size());
+        ptrs.push(ptr);
+    }
+
+    // Keep every 8th allocation, so each MiniPage is mostly empty
+    let mut live: Vec<(*mut u8, u8)> = vec!();
+    for (i, ptr) in ptrs.iter().enumerate() {
+        if i % 8 == 0 {
+            live.push((*ptr, i as u8));
+        } else {
+            alloc.dealloc(*ptr, layout);
+        }
+    }
+
+    let before = alloc.live_stats();
+    assert_eq!(before.live_minipages, pages as u32, "each MiniPage should still have live allocations before compacting: {:?}", before);
+
+    let report = alloc.compact();
+    let after = alloc.live_stats();
+    let expected_pages = (live.len() + segments_per_page - 1) / segments_per_page;
+    println!("compact: moved {} allocations, freed {} MiniPages", report.relocations().len(), report.freed_minipages);
+    assert!(report.complete, "compacting {} allocations should not hit the relocation limit", live.len());
+    assert_eq!(report.freed_minipages as usize, pages - expected_pages, "every MiniPage but the {} the live allocations fit in should be freed", expected_pages);
+    assert_eq!(after.live_minipages as usize, expected_pages, "live allocations should be packed into {} MiniPages: {:?}", expected_pages, after);
+    assert_eq!(after.live_segments, before.live_segments, "compacting should not change the number of live segments");
+    assert_eq!(alloc.validate_heap(), Ok(()), "heap should be valid after compacting");
+
+    for (old_ptr, tag) in live.iter_mut() {
+        let new_ptr = report.new_ptr(*old_ptr);
+        for offset in 0..layout.size() {
+            assert_eq!(*new_ptr.add(offset), *tag, "byte {} of allocation {} moved from {:?} to {:?} was not copied", offset, tag, old_ptr, new_ptr);
+        }
+        *old_ptr = new_ptr;
+    }
+
+    let again = alloc.compact();
+    assert_eq!((again.relocations().len(), again.freed_minipages), (0, 0), "compacting an already compact heap should not move or free anything");
+
+    for (ptr, _tag) in live.iter() {
+        alloc.dealloc(*ptr, layout);
+    }
+    assert_eq!(alloc.live_stats().live_minipages, 0, "no MiniPages should be live after freeing the moved allocations");
+    assert_eq!(alloc.validate_heap(), Ok(()), "heap should be valid after freeing the moved allocations");
+}
+
+/// Check usable_size() returns the segment size for a MiniPage allocation, size_bytes for a big allocation, and 0 for pointers which are not live allocations.
+unsafe fn usable_size() {
+    let small_layout = Layout::from_size_align(100, 1).unwrap();
+    let small_ptr = ALLOC.alloc(small_layout);
+    assert!(!small_ptr.is_null(), "alloc({}) failed", small_layout.size());
+    assert_eq!(ALLOC.usable_size(small_ptr), 128, "a {} byte allocation should have the 2^7 byte size class's usable size", small_layout.size());
+
+    let big_layout = Layout::from_size_align(40 * 1024, 1).unwrap();
+    let big_ptr = ALLOC.alloc(big_layout);
+    assert!(!big_ptr.is_null(), "alloc({}) failed", big_layout.size());
+    let big_size_bytes = match ALLOC.allocation_kind(big_ptr) {
+        Some(AllocKind::Big{ size_bytes }) => size_bytes as usize,
+        kind => panic!("a {} byte allocation should be a big allocation, was {:?}", big_layout.size(), kind),
+    };
+    assert_eq!(ALLOC.usable_size(big_ptr), big_size_bytes, "a big allocation's usable size should be its size_bytes");
+    assert!(big_size_bytes >= big_layout.size(), "a {} byte big allocation only has {} usable bytes", big_layout.size(), big_size_bytes);
+
+    let mut not_from_alloc: u8 = 0;
+    assert_eq!(ALLOC.usable_size(&mut not_from_alloc), 0, "a pointer the allocator does not own should have no usable size");
+    assert_eq!(ALLOC.usable_size(core::ptr::null_mut()), 0, "null should have no usable size");
+
+    ALLOC.dealloc(small_ptr, small_layout);
+    ALLOC.dealloc(big_ptr, big_layout);
+    assert_eq!(ALLOC.usable_size(small_ptr), 0, "a freed MiniPage allocation should have no usable size");
+    assert_eq!(ALLOC.usable_size(big_ptr), 0, "a freed big allocation should have no usable size");
+}
+
+/// Fill RESET_ALLOC until an allocation fails, reset it, and check allocation starts from the same address and fills the same number of allocations again. With the strict feature the failed allocation panics instead, so this is skipped.
+#[cfg(not(feature = "strict"))]
+unsafe fn reset() {
+    let layout = Layout::from_size_align(2048, 1).unwrap();
+
+    let fill = || -> (*mut u8, usize) {
+        let first_ptr = RESET_ALLOC.alloc(layout);
+        assert!(!first_ptr.is_null(), "alloc({}) failed on an empty allocator", layout.size());
+
+        let mut count = 1;
+        while !RESET_ALLOC.alloc(layout).is_null() {
+            count += 1;
+        }
+
+        (first_ptr, count)
+    };
+
+    let (first_ptr, count) = fill();
+    RESET_ALLOC.reset();
+
+    let stats = RESET_ALLOC.live_stats();
+    assert_eq!(stats.bytes_in_use, 0, "bytes in use should be 0 after reset(): {:?}", stats);
+    assert!(RESET_ALLOC.allocation_kind(first_ptr).is_none(), "the first allocation should not be live after reset()");
+
+    let (reset_first_ptr, reset_count) = fill();
+    println!("reset: {} allocations before reset(), {} after", count, reset_count);
+    assert_eq!(reset_first_ptr, first_ptr, "allocation should start from the beginning of the heap after reset()");
+    assert_eq!(reset_count, count, "the heap should fit as many allocations after reset() as before");
+
+    RESET_ALLOC.reset();
+}
+
+/// Allocate from a MiniPage which already has allocations, both while it is still partly free and after it was filled then had a segment freed. Both times the freed segment must be handed out again, from the same MiniPage instead of a new one. Resets RESET_ALLOC first so the size class has no other MiniPages.
+unsafe fn reused_minipage() {
+    let layout = Layout::from_size_align(SMALLEST_CLASS_BYTES, 1).unwrap();
+    let segments = usize::from(SizeClass::new_from_bytes(SMALLEST_CLASS_BYTES).segments_max_num());
+    RESET_ALLOC.reset();
+
+    // Partly free MiniPage
+    let first_ptr = RESET_ALLOC.alloc(layout);
+    let second_ptr = RESET_ALLOC.alloc(layout);
+    assert!(!first_ptr.is_null() && !second_ptr.is_null(), "alloc({}) failed", layout.size());
+    RESET_ALLOC.dealloc(first_ptr, layout);
+
+    let reused_ptr = RESET_ALLOC.alloc(layout);
+    assert_eq!(reused_ptr, first_ptr, "a partly free MiniPage should hand out its freed segment again");
+
+    // Fill the rest of the MiniPage, then free one segment
+    let mut ptrs: Vec<*mut u8> = vec!(reused_ptr, second_ptr);
+    for _i in 2..segments {
+        let ptr = RESET_ALLOC.alloc(layout);
+        assert!(!ptr.is_null(), "alloc({}) failed", layout.size());
+        ptrs.push(ptr);
+    }
+    let page_start = *ptrs.iter().min().unwrap() as usize;
+    let page_end = page_start + (segments * layout.size());
+    assert!(ptrs.iter().all(|ptr| (page_start..page_end).contains(&(*ptr as usize))), "the first {} allocations of {} bytes should fill one MiniPage", segments, layout.size());
+
+    let freed_ptr = ptrs.swap_remove(segments / 2);
+    RESET_ALLOC.dealloc(freed_ptr, layout);
+
+    let refilled_ptr = RESET_ALLOC.alloc(layout);
+    println!("reused MiniPage: freed segment {:?} of a full MiniPage, got {:?}", freed_ptr, refilled_ptr);
+    assert_eq!(refilled_ptr, freed_ptr, "a full MiniPage which had a segment freed should hand out that segment again");
+
+    RESET_ALLOC.reset();
+}
+
+/// Allocate a MiniPage and a big allocation from RESET_ALLOC, free them, and release() it. Every host page must be given back, and the next allocation must grow the heap and set up the MetaPage again. Resets RESET_ALLOC first so nothing else is live.
+unsafe fn release() {
+    let small_layout = Layout::from_size_align(SMALLEST_CLASS_BYTES, 1).unwrap();
+    let big_layout = Layout::from_size_align(8 * 1024, 1).unwrap();
+    RESET_ALLOC.reset();
+
+    let small_ptr = RESET_ALLOC.alloc(small_layout);
+    let big_ptr = RESET_ALLOC.alloc(big_layout);
+    assert!(!small_ptr.is_null() && !big_ptr.is_null(), "alloc() failed before release()");
+    assert!(!RESET_ALLOC.release(), "release() should refuse while allocations are live");
+
+    RESET_ALLOC.dealloc(small_ptr, small_layout);
+    RESET_ALLOC.dealloc(big_ptr, big_layout);
+    let pages_before = RESET_ALLOC.heap_pages();
+    assert!(RESET_ALLOC.release(), "release() should succeed once every allocation was freed");
+    println!("release: {} host pages before release(), {} after", pages_before, RESET_ALLOC.heap_pages());
+    assert!(pages_before > 0, "the heap should have grown before release()");
+    assert_eq!(RESET_ALLOC.heap_pages(), 0, "release() should give every host page back");
+
+    let ptr = RESET_ALLOC.alloc(small_layout);
+    assert!(!ptr.is_null(), "alloc() failed after release()");
+    assert!(RESET_ALLOC.heap_pages() > 0, "alloc() after release() should grow the heap again");
+    assert_eq!(RESET_ALLOC.validate_heap(), Ok(()), "heap broken after release()");
+    RESET_ALLOC.dealloc(ptr, small_layout);
+}
+
+/// Fill a MiniPage, free and reallocate one of its segments so the size class has reused a MiniPage, then free two more segments. With a ratio of 0 the next allocations must use the reused MiniPage's free segments, with an infinite ratio they must come from new MiniPages.
+unsafe fn fresh_reused_ratio() {
+    let never_fresh_alloc: AlligatorAlloc<HeapType> = AlligatorAlloc::with_fresh_reused_ratio(0_f64);
+    let always_fresh_alloc: AlligatorAlloc<HeapType> = AlligatorAlloc::with_fresh_reused_ratio(f64::INFINITY);
+    let layout = Layout::from_size_align(SMALLEST_CLASS_BYTES, 1).unwrap();
+    let segments = usize::from(SizeClass::new_from_bytes(SMALLEST_CLASS_BYTES).segments_max_num());
+    let class_idx = SizeClass::new_from_bytes(SMALLEST_CLASS_BYTES).exp_as_idx();
+
+    for (alloc, prefer_fresh) in [(&never_fresh_alloc, false), (&always_fresh_alloc, true)].iter() {
+        let mut ptrs: Vec<*mut u8> = vec!();
+        for _i in 0..segments {
+            let ptr = alloc.alloc(layout);
+            assert!(!ptr.is_null(), "alloc({}) failed", layout.size());
+            ptrs.push(ptr);
+        }
+        let page_start = *ptrs.iter().min().unwrap() as usize;
+        let page_range = page_start..(page_start + (segments * layout.size()));
+
+        // Reuse the full MiniPage once
+        alloc.dealloc(ptrs[0], layout);
+        ptrs[0] = alloc.alloc(layout);
+        assert!(page_range.contains(&(ptrs[0] as usize)), "the first allocation after freeing a segment should reuse its MiniPage");
+
+        let before = alloc.fresh_reused_stats();
+        for ptr in ptrs.drain(1..3) {
+            alloc.dealloc(ptr, layout);
+        }
+
+        let new_ptrs: Vec<*mut u8> = (0..2).map(|_i| alloc.alloc(layout)).collect();
+        let after = alloc.fresh_reused_stats();
+        let fresh = after.total_alloc_fresh[class_idx] - before.total_alloc_fresh[class_idx];
+        let reused = after.total_alloc_reused[class_idx] - before.total_alloc_reused[class_idx];
+        println!("fresh reused ratio: prefer fresh={}, {} fresh and {} reused allocations with a reused MiniPage available", prefer_fresh, fresh, reused);
+
+        for ptr in new_ptrs.iter() {
+            assert!(!ptr.is_null(), "alloc({}) failed", layout.size());
+            assert_eq!(page_range.contains(&(*ptr as usize)), !prefer_fresh, "prefer fresh={}, allocation {:?} should be in the reused MiniPage {:?} only if fresh is not preferred", prefer_fresh, ptr, page_range);
+        }
+        match prefer_fresh {
+            true => assert_eq!((fresh, reused), (2, 0), "an infinite ratio should always allocate from a fresh MiniPage"),
+            false => assert_eq!((fresh, reused), (0, 2), "a ratio of 0 should never allocate from a fresh MiniPage while a reused one has free segments"),
+        }
+
+        for ptr in ptrs.into_iter().chain(new_ptrs.into_iter()) {
+            alloc.dealloc(ptr, layout);
+        }
+    }
+}
+
+/// Fill a MiniPage, free every third segment, then allocate them again. allocation_kind() reads each segment's free bitmap bit, which must match what the last alloc or dealloc wrote.
+unsafe fn free_bitmap_round_trip() {
+    let layout = Layout::from_size_align(SMALLEST_CLASS_BYTES, 1).unwrap();
+    let segments = usize::from(SizeClass::new_from_bytes(SMALLEST_CLASS_BYTES).segments_max_num());
+    let check = |ptrs: &[*mut u8], freed: &[bool], when: &str| {
+        for (ptr, freed) in ptrs.iter().zip(freed.iter()) {
+            assert_eq!(ALLOC.allocation_kind(*ptr).is_none(), *freed, "{}: the free bitmap bit of {:?} should be {}", when, ptr, freed);
+        }
+    };
+
+    let mut ptrs: Vec<*mut u8> = (0..segments).map(|_i| ALLOC.alloc(layout)).collect();
+    assert!(ptrs.iter().all(|ptr| !ptr.is_null()), "alloc({}) failed", layout.size());
+    let mut freed = vec![false; segments];
+    check(&ptrs, &freed, "after alloc");
+
+    for i in (0..segments).step_by(3) {
+        ALLOC.dealloc(ptrs[i], layout);
+        freed[i] = true;
+    }
+    check(&ptrs, &freed, "after dealloc");
+
+    for i in (0..segments).step_by(3) {
+        ptrs[i] = ALLOC.alloc(layout);
+        freed[i] = false;
+    }
+    check(&ptrs, &freed, "after alloc again");
+
+    for ptr in ptrs.iter() {
+        ALLOC.dealloc(*ptr, layout);
+    }
+}
+
+/// Allocate layouts whose alignment is larger than their size. Segments are aligned to their size, including the segments of large size class MiniPages which span several intervals.
+unsafe fn alignment() {
+    for (bytes, align) in [(8, 16), (16, 64), (100, 4096), (4096, 4096), (8192, 32 * 1024)].iter() {
+        let layout = Layout::from_size_align(*bytes, *align).unwrap();
+        let ptr = ALLOC.alloc(layout);
+        assert!(!ptr.is_null(), "alloc({:?}) failed", layout);
+        assert_eq!(ptr as usize % align, 0, "alloc({:?}) returned {:?}, which is not aligned to {} bytes", layout, ptr, align);
+
+        ALLOC.dealloc(ptr, layout);
+    }
+
+    // A small request with a larger alignment is promoted to the size class of its alignment
+    let layout = Layout::from_size_align(8, 64).unwrap();
+    let ptr = ALLOC.alloc(layout);
+    assert_eq!(ALLOC.allocation_kind(ptr), Some(AllocKind::Small{ size_class_exp: 6 }), "alloc({:?}) should use the 64 byte size class", layout);
+    assert_eq!(ALLOC.usable_size(ptr), 64, "alloc({:?}) should get a whole 64 byte segment", layout);
+    assert_eq!(ptr as usize % 64, 0, "alloc({:?}) returned {:?}, which is not aligned to 64 bytes", layout, ptr);
+    ALLOC.dealloc(ptr, layout);
+}
+
+/// Allocate every segment of one MiniPage of each size class, then free them all. Every freed segment index is pushed onto the size class's free segments stack, which must be large enough to hold them all without a StackOverflow.
+#[cfg(feature = "metrics")]
+unsafe fn full_minipage_stacks() {
+    let alloc: AlligatorAlloc<HeapType> = AlligatorAlloc::INIT;
+    use alloc::AllocFail;
+
+    for i in MIN_SIZE_CLASS..=MAX_SIZE_CLASS {
+        let size_class = SizeClass::new(i);
+        let layout = Layout::from_size_align(usize::from(size_class.segment_bytes()), 1).unwrap();
+
+        let mut ptrs: Vec<*mut u8> = vec!();
+        for _j in 0..size_class.segments_max_num() {
+            let ptr = alloc.alloc(layout);
+            assert!(!ptr.is_null(), "alloc({}) failed: {:?}", layout.size(), alloc.alloc_failure_cause());
+            ptrs.push(ptr);
+        }
+
+        for ptr in ptrs.iter() {
+            alloc.dealloc(*ptr, layout);
+        }
+    }
+
+    assert_eq!(alloc.failure_counts()[AllocFail::StackOverflow as usize], 0, "freeing a full MiniPage overflowed a free segments stack: {:?}", alloc.alloc_failure_cause());
+}
+
+/// Fill a MiniPage and make a big allocation in two allocators, then free everything with individual dealloc calls in one and a single dealloc_many call in the other. dealloc_many must free the same memory while writing fewer heap bytes.
+#[cfg(feature = "metrics")]
+unsafe fn dealloc_many() {
+    let one_by_one_alloc: AlligatorAlloc<HeapType> = AlligatorAlloc::INIT;
+    let many_alloc: AlligatorAlloc<HeapType> = AlligatorAlloc::INIT;
+    let small_layout = Layout::from_size_align(32, 1).unwrap();
+    let big_layout = Layout::from_size_align(40 * 1024, 1).unwrap();
+    let small_count = SizeClass::new(5).segments_max_num();
+
+    let alloc_ptrs = |alloc: &AlligatorAlloc<HeapType>| -> Vec<(*mut u8, Layout)> {
+        let mut ptrs: Vec<(*mut u8, Layout)> = vec!();
+        for _i in 0..small_count {
+            ptrs.push((alloc.alloc(small_layout), small_layout));
+        }
+        ptrs.push((alloc.alloc(big_layout), big_layout));
+
+        assert!(ptrs.iter().all(|(ptr, _layout)| !ptr.is_null()), "alloc failed: {:?}", alloc.alloc_failure_cause());
+        ptrs
+    };
+
+    let one_by_one_ptrs = alloc_ptrs(&one_by_one_alloc);
+    let many_ptrs = alloc_ptrs(&many_alloc);
+
+    let one_by_one_before = one_by_one_alloc.metrics().unwrap().heap_bytes_write;
+    for (ptr, layout) in one_by_one_ptrs.iter() {
+        one_by_one_alloc.dealloc(*ptr, *layout);
+    }
+    let one_by_one_writes = one_by_one_alloc.metrics().unwrap().heap_bytes_write - one_by_one_before;
+
+    let many_before = many_alloc.metrics().unwrap().heap_bytes_write;
+    many_alloc.dealloc_many(&many_ptrs);
+    let many_writes = many_alloc.metrics().unwrap().heap_bytes_write - many_before;
+
+    println!("dealloc many: {} heap bytes written by {} dealloc calls, {} by one dealloc_many call", one_by_one_writes, one_by_one_ptrs.len(), many_writes);
+    assert!(many_writes < one_by_one_writes, "dealloc_many wrote {} heap bytes, no fewer than the {} written by individual dealloc calls", many_writes, one_by_one_writes);
+
+    let stats = many_alloc.live_stats();
+    assert_eq!(stats, one_by_one_alloc.live_stats(), "dealloc_many left different live allocations than individual dealloc calls");
+    assert_eq!(stats.bytes_in_use, 0, "dealloc_many did not free everything: {:?}", stats);
+    assert_eq!(many_alloc.metrics().unwrap().current_used_bytes, 0, "dealloc_many did not update the usage metrics");
+
+    // The freed segments must be handed out again
+    let again_ptrs = alloc_ptrs(&many_alloc);
+    many_alloc.dealloc_many(&again_ptrs);
+}
+
+/// Make many live big allocations. Each one walks the whole big allocation list without finding a free big allocation before creating a new one, so the longest walk is the length of the list before the last allocation.
+#[cfg(feature = "metrics")]
+unsafe fn big_alloc_scan() {
+    let alloc: AlligatorAlloc<HeapType> = AlligatorAlloc::INIT;
+    let layout = Layout::from_size_align(40 * 1024, 1).unwrap();
+    let count = 32;
+
+    let mut ptrs: Vec<*mut u8> = vec!();
+    for _i in 0..count {
+        let ptr = alloc.alloc(layout);
+        assert!(!ptr.is_null(), "alloc({}) failed: {:?}", layout.size(), alloc.alloc_failure_cause());
+        ptrs.push(ptr);
+    }
+
+    let metrics = alloc.metrics().unwrap();
+    println!("big alloc scan: {} BigAllocHeaders walked at most for {} big allocations", metrics.max_big_alloc_scan, metrics.total_big_headers);
+    assert_eq!(metrics.total_big_headers, count, "every big allocation should have its own header");
+    assert_eq!(metrics.max_big_alloc_scan, count - 1, "the last big allocation should have walked every other big allocation");
+
+    for ptr in ptrs.iter() {
+        alloc.dealloc(*ptr, layout);
+    }
+    assert_eq!(alloc.metrics().unwrap().max_big_alloc_scan, count - 1, "dealloc finds headers from the big allocation flags, it should not walk more");
+}
+
+/// Request a larger alignment than a big allocation can provide. It must fail with AlignTooLarge instead of returning an unaligned pointer. With the strict feature this panics instead, so this is skipped.
+#[cfg(all(feature = "metrics", not(feature = "strict")))]
+unsafe fn align_too_large() {
+    use alloc::AllocFail;
+
+    let layout = Layout::from_size_align(40 * 1024, 4096).unwrap();
+    let ptr = ALLOC.alloc(layout);
+
+    assert!(ptr.is_null(), "alloc({:?}) should fail, big allocations are not aligned to {} bytes, returned {:?}", layout, layout.align(), ptr);
+    assert!(matches!(ALLOC.alloc_failure_cause(), Some(AllocFail::AlignTooLarge)), "alloc failed with {:?} instead of AlignTooLarge", ALLOC.alloc_failure_cause());
+}
+
+/// Request 40 KB, larger than MAX_SIZE_CLASS, and 4 KB, a MiniPage size class. With big allocations disabled the 40 KB request must fail with AllocTooLarge, while ALLOC makes it a big allocation. 4 KB must succeed either way. With the strict feature the failure panics instead, so this is skipped.
+#[cfg(all(feature = "metrics", not(feature = "strict")))]
+unsafe fn small_only() {
+    let alloc: AlligatorAlloc<HeapType> = AlligatorAlloc::small_only();
+    use alloc::AllocFail;
+
+    let big_layout = Layout::from_size_align(40 * 1024, 1).unwrap();
+    let small_layout = Layout::from_size_align(4 * 1024, 1).unwrap();
+
+    let ptr = alloc.alloc(big_layout);
+    assert!(ptr.is_null(), "alloc({}) should fail when big allocations are disabled, returned {:?}", big_layout.size(), ptr);
+    assert!(matches!(alloc.alloc_failure_cause(), Some(AllocFail::AllocTooLarge)), "alloc failed with {:?} instead of AllocTooLarge", alloc.alloc_failure_cause());
+    assert_eq!(alloc.reserve_big(1, big_layout.size()), 0, "reserve_big should not make big allocations when they are disabled");
+
+    let small_ptr = alloc.alloc(small_layout);
+    assert!(!small_ptr.is_null(), "alloc({}) of a MiniPage size class failed: {:?}", small_layout.size(), alloc.alloc_failure_cause());
+    alloc.dealloc(small_ptr, small_layout);
+
+    let big_ptr = ALLOC.alloc(big_layout);
+    assert!(matches!(ALLOC.allocation_kind(big_ptr), Some(AllocKind::Big{ .. })), "alloc({}) should be a big allocation when they are enabled, got {:?}", big_layout.size(), ALLOC.allocation_kind(big_ptr));
+    ALLOC.dealloc(big_ptr, big_layout);
+}
+
+/// Make a known sequence of allocs and deallocs, some MiniPage and some big, and check drain_trace() returns exactly those calls in order. Then overflow the trace buffer and check only the most recent TRACE_CAPACITY calls are kept.
+#[cfg(feature = "trace")]
+unsafe fn trace() {
+    let alloc: AlligatorAlloc<HeapType> = AlligatorAlloc::INIT;
+    use alloc::{TraceEvent,TraceEventKind,TRACE_CAPACITY};
+
+    assert_eq!(alloc.drain_trace().count(), 0, "trace should be empty before the first alloc");
+
+    let small_layout = Layout::from_size_align(8, 1).unwrap();
+    let medium_layout = Layout::from_size_align(100, 1).unwrap();
+    let big_layout = Layout::from_size_align(40 * 1024, 1).unwrap();
+
+    let small_ptr = alloc.alloc(small_layout);
+    let medium_ptr = alloc.alloc_zeroed(medium_layout);
+    alloc.dealloc(small_ptr, small_layout);
+    let big_ptr = alloc.alloc(big_layout);
+    alloc.dealloc(medium_ptr, medium_layout);
+    alloc.dealloc(big_ptr, big_layout);
+
+    let event = |kind: TraceEventKind, ptr: *mut u8, layout: Layout| TraceEvent{
+        kind: kind,
+        ptr: ptr as usize,
+        size: layout.size(),
+    };
+    let expected = [
+        event(TraceEventKind::Alloc, small_ptr, small_layout),
+        event(TraceEventKind::Alloc, medium_ptr, medium_layout),
+        event(TraceEventKind::Dealloc, small_ptr, small_layout),
+        event(TraceEventKind::Alloc, big_ptr, big_layout),
+        event(TraceEventKind::Dealloc, medium_ptr, medium_layout),
+        event(TraceEventKind::Dealloc, big_ptr, big_layout),
+    ];
+
+    let drained: Vec<TraceEvent> = alloc.drain_trace().collect();
+    assert_eq!(drained, expected, "drained trace should match the calls made");
+    assert_eq!(alloc.drain_trace().count(), 0, "drain should empty the trace");
+
+    // Each round records an alloc and a dealloc, the first rounds are overwritten
+    let rounds = TRACE_CAPACITY / 2 + 10;
+    let mut last_ptr = small_ptr;
+    for _i in 0..rounds {
+        last_ptr = alloc.alloc(small_layout);
+        alloc.dealloc(last_ptr, small_layout);
+    }
+
+    let drained: Vec<TraceEvent> = alloc.drain_trace().collect();
+    assert_eq!(drained.len(), TRACE_CAPACITY, "a full trace should keep TRACE_CAPACITY events");
+    assert!(drained.chunks(2).all(|pair| pair[0].kind == TraceEventKind::Alloc && pair[1].kind == TraceEventKind::Dealloc), "overflowed trace should still be oldest first");
+    assert_eq!(drained[TRACE_CAPACITY - 1], event(TraceEventKind::Dealloc, last_ptr, small_layout), "newest event should be the last dealloc");
+    println!("trace: {} events kept of {} recorded", drained.len(), rounds * 2);
+}
+
+/// Interleave UnsafeStack pushes, pops and peeks, including filling it to its max size, emptying it, and filling it again. It must always hand back the last item pushed.
+fn unsafe_stack_order() {
+    const MAX_SIZE: u32 = 4;
+
+    // UnsafeStack keeps its fields at the start of the memory it is given, u64s keep them aligned
+    let mut memory = [0_u64; 8];
+
+    unsafe {
+        let (stack, next_ptr) = UnsafeStack::<u16>::alloc(memory.as_mut_ptr() as *mut u8, MAX_SIZE);
+        assert!(next_ptr as usize <= memory.as_ptr().add(memory.len()) as usize, "UnsafeStack used more memory than it was given");
+        let stack = &mut *stack;
+
+        assert_eq!(stack.peek(), None, "new stack should be empty");
+        assert_eq!(stack.pop(), None, "new stack should be empty");
+
+        // Pop then push, the push must not overwrite the item below the head
+        stack.push(1);
+        stack.push(2);
+        assert_eq!(stack.pop(), Some(2));
+        stack.push(3);
+        assert_eq!(stack.peek(), Some(3));
+        assert_eq!(stack.pop(), Some(3));
+        assert_eq!(stack.peek(), Some(1));
+        assert_eq!(stack.pop(), Some(1));
+        assert_eq!(stack.pop(), None);
+
+        // Fill, overflow, empty, then fill again after every slot was used
+        for round in 0..2_u16 {
+            for i in 0..MAX_SIZE as u16 {
+                assert_eq!(stack.push(round * 10 + i), Some(round * 10 + i), "round {}: push {} should fit", round, i);
+            }
+            assert_eq!(stack.push(99), None, "round {}: push onto a full stack should fail", round);
+            assert_eq!(stack.peek(), Some(round * 10 + MAX_SIZE as u16 - 1), "round {}: failed push changed the head", round);
+
+            for i in (0..MAX_SIZE as u16).rev() {
+                assert_eq!(stack.pop(), Some(round * 10 + i), "round {}: pops should come out in reverse push order", round);
+            }
+            assert_eq!(stack.pop(), None, "round {}: stack should be empty", round);
+        }
+
+        // Clear then reuse
+        stack.push(7);
+        stack.push(8);
+        stack.clear();
+        assert_eq!(stack.peek(), None, "cleared stack should be empty");
+        stack.push(9);
+        assert_eq!(stack.pop(), Some(9));
+    }
+}
+
+/// Push a duplicate item onto an UnsafeStack, UnsafeStack::contains() must find both the duplicate and the items below it. The allocator uses it to check no MiniPage is pushed onto a free minipages stack twice.
+fn unsafe_stack_contains() {
+    let mut memory = [0_u64; 8];
+
+    unsafe {
+        let (stack, _next_ptr) = UnsafeStack::<u16>::alloc(memory.as_mut_ptr() as *mut u8, 4);
+        let stack = &mut *stack;
+
+        assert!(!stack.contains(5), "empty stack should not contain anything");
+
+        stack.push(5);
+        stack.push(6);
+        assert!(stack.contains(5), "stack should contain the item below the head");
+        assert!(stack.contains(6), "stack should contain the head");
+        assert!(!stack.contains(7), "stack should not contain an item which was never pushed");
+
+        // A duplicate push is detected before it happens
+        assert!(stack.contains(5), "pushing 5 again would be a duplicate");
+        stack.push(5);
+        stack.pop();
+        assert!(stack.contains(5), "popping the duplicate should leave the first 5");
+
+        stack.pop();
+        stack.pop();
+        assert!(!stack.contains(5), "popped items should not be found");
+    }
+}
+
+/// UnsafeStack::peek_nth() must find items below the head without popping them, so a reuse policy can look deeper than the most recently freed MiniPage.
+fn unsafe_stack_peek_nth() {
+    let mut memory = [0_u64; 8];
+
+    unsafe {
+        let (stack, _next_ptr) = UnsafeStack::<u16>::alloc(memory.as_mut_ptr() as *mut u8, 4);
+        let stack = &mut *stack;
+
+        assert_eq!(stack.len(), 0, "new stack should be empty");
+        assert_eq!(stack.peek_nth(0), None, "empty stack has no head");
+
+        stack.push(1);
+        stack.push(2);
+        stack.push(3);
+        assert_eq!(stack.len(), 3);
+
+        // Head, middle, bottom, then past the bottom
+        assert_eq!(stack.peek_nth(0), stack.peek(), "peek_nth(0) should be the head");
+        assert_eq!(stack.peek_nth(0), Some(3));
+        assert_eq!(stack.peek_nth(1), Some(2), "peek_nth(1) should be the item below the head");
+        assert_eq!(stack.peek_nth(2), Some(1), "peek_nth(len - 1) should be the bottom");
+        assert_eq!(stack.peek_nth(3), None, "peek_nth(len) is past the bottom");
+        assert_eq!(stack.peek_nth(u32::MAX), None, "peek_nth far past the bottom");
+
+        // Peeking does not change the stack
+        assert_eq!(stack.len(), 3, "peek_nth should not remove items");
+        assert_eq!(stack.pop(), Some(3));
+        assert_eq!(stack.peek_nth(0), Some(2), "peek_nth should follow the head after a pop");
+        assert_eq!(stack.peek_nth(2), None, "popped slot should not be returned");
+        assert_eq!(stack.len(), 2);
+    }
+}
+
+/// Turn AllocAddrs at the edge of the largest host heap into pointers. The last byte is fine, one past it is a bug: with debug assertions as_ptr() must panic, without them it must return null so the caller fails instead of using a pointer outside the heap.
+fn alloc_addr_bounds() {
+    let heap = vec![0_u8; MAX_HOST_HEAP_BYTES as usize];
+    let base_ptr = heap.as_ptr() as *mut u8;
+    let last_ptr = unsafe { AllocAddr::new(MAX_HOST_HEAP_BYTES - 1).as_ptr(base_ptr) };
+    assert_eq!(last_ptr as usize - base_ptr as usize, (MAX_HOST_HEAP_BYTES - 1) as usize, "the last byte of the heap should not be moved");
+
+    let out_of_range = std::panic::catch_unwind(|| unsafe { AllocAddr::new(MAX_HOST_HEAP_BYTES).as_ptr(base_ptr) as usize });
+    match (cfg!(debug_assertions), out_of_range) {
+        (true, Ok(ptr)) => panic!("AllocAddr {} should fail a debug assertion, got pointer {:#x}", MAX_HOST_HEAP_BYTES, ptr),
+        (true, Err(_)) => println!("alloc addr bounds: AllocAddr {} failed the debug assertion", MAX_HOST_HEAP_BYTES),
+        (false, Ok(ptr)) => assert_eq!(ptr, 0, "AllocAddr {} should be a null pointer", MAX_HOST_HEAP_BYTES),
+        (false, Err(_)) => panic!("AllocAddr {} should only panic with debug assertions", MAX_HOST_HEAP_BYTES),
+    }
+}
+
+/// Turn AllocAddrs at the edge of a region into pointers with try_as_ptr(). The last byte of the region is fine, one past it and anything outside the largest host heap must be None, without the debug assertion as_ptr() has.
+fn alloc_addr_try_bounds() {
+    let region = vec![0_u8; heap::PAGE_BYTES as usize];
+    let base_ptr = region.as_ptr() as *mut u8;
+    let region_bytes = heap::PAGE_BYTES;
+
+    let last_ptr = unsafe { AllocAddr::new(region_bytes - 1).try_as_ptr(base_ptr, region_bytes) };
+    assert_eq!(last_ptr.map(|ptr| ptr as usize - base_ptr as usize), Some((region_bytes - 1) as usize), "the last byte of the region should not be moved");
+
+    for addr in [region_bytes, region_bytes + 1, MAX_HOST_HEAP_BYTES].iter() {
+        let ptr = unsafe { AllocAddr::new(*addr).try_as_ptr(base_ptr, region_bytes) };
+        assert!(ptr.is_none(), "AllocAddr {} is outside the {} byte region, got pointer {:?}", addr, region_bytes, ptr);
+    }
+    println!("alloc addr try bounds: AllocAddr {} and later are outside a {} byte region", region_bytes, region_bytes);
+}
+
+/// Check SizeClass::new_from_bytes(), which uses a lookup table for sizes up to 2048 bytes and integer math for larger sizes, picks the same size class as the floating point log2 it replaced, for every u16.
+fn size_class_integer_math() {
+    for bytes in 0..=u16::MAX {
+        let float_exp = f32::from(bytes).log2().ceil() as u8;
+        assert_eq!(SizeClass::new_from_bytes(usize::from(bytes)).exp, SizeClass::new(float_exp).exp, "size class for {} bytes differs from the float log2 result", bytes);
+    }
+}
+
+/// Check SizeClass::new_from_layout() picks the size class of the larger of a layout's size and alignment, since segments are only aligned to their own size.
+fn size_class_from_layout() {
+    for (bytes, align, exp) in [(8, 1, 3), (8, 64, 6), (64, 8, 6), (100, 8, 7), (100, 4096, 12), (4096, 16, 12)].iter() {
+        let layout = Layout::from_size_align(*bytes, *align).unwrap();
+        let size_class = SizeClass::new_from_layout(layout);
+        assert_eq!(size_class.exp, *exp, "size class for {:?} should be {}", layout, exp);
+        assert!(usize::from(size_class.segment_bytes()) >= layout.align(), "segments of size class {} are not aligned to {} bytes", size_class.exp, layout.align());
+    }
+}
+
+/// Check explain() plans 2048 bytes as a MiniPage allocation without fragmentation, and 2049 bytes as one from the next size class, which loses almost half its segment. The first size past MAX_SIZE_CLASS must be planned as a big allocation, whose footprint is the whole MiniPage intervals its header and bytes round up to.
+fn explain() {
+    use alloc::AllocStrategy;
+
+    let max_class_bytes = 1_usize << MAX_SIZE_CLASS;
+    assert!(AlligatorAlloc::explain(0).is_none(), "explain(0) should have no plan");
+
+    let exact = AlligatorAlloc::explain(2048).unwrap();
+    assert!(matches!(exact.strategy, AllocStrategy::MiniPage), "2048 bytes should be a MiniPage allocation: {:?}", exact);
+    assert_eq!((exact.size_class_exp, exact.usable_bytes, exact.internal_frag_bytes), (11, 2048, 0), "2048 bytes should fill a size class 11 segment exactly: {:?}", exact);
+
+    let over = AlligatorAlloc::explain(2049).unwrap();
+    assert!(matches!(over.strategy, AllocStrategy::MiniPage), "2049 bytes should be a MiniPage allocation: {:?}", over);
+    assert_eq!((over.size_class_exp, over.usable_bytes, over.internal_frag_bytes), (12, 4096, 4096 - 2049), "2049 bytes should round up to a size class 12 segment: {:?}", over);
+
+    let largest = AlligatorAlloc::explain(max_class_bytes).unwrap();
+    assert!(matches!(largest.strategy, AllocStrategy::MiniPage), "{} bytes should still be a MiniPage allocation: {:?}", max_class_bytes, largest);
+
+    let big = AlligatorAlloc::explain(max_class_bytes + 1).unwrap();
+    let intervals = big.footprint_bytes / 2048;
+    println!("explain: {} bytes is a big allocation of {} intervals, {} bytes lost", big.requested_bytes, intervals, big.internal_frag_bytes);
+    assert!(matches!(big.strategy, AllocStrategy::Big), "{} bytes should be a big allocation: {:?}", max_class_bytes + 1, big);
+    assert_eq!(big.footprint_bytes % 2048, 0, "a big allocation's footprint should be whole MiniPage intervals: {:?}", big);
+    assert_eq!(intervals, (max_class_bytes / 2048) + 1, "the header and the byte past {} should take one more interval: {:?}", max_class_bytes, big);
+    assert!(big.usable_bytes > big.requested_bytes && big.usable_bytes < big.footprint_bytes, "the header should take part of the last interval: {:?}", big);
+    assert_eq!(big.internal_frag_bytes, big.footprint_bytes - big.requested_bytes, "the header and interval rounding should count as fragmentation: {:?}", big);
+}
+
+/// Make two MiniPages and a big allocation in a new allocator, so they take up the first intervals of its heap in order, and check heap_bitmap() sets exactly their bits. Each one's bits must clear once it is freed.
+unsafe fn heap_bitmap() {
+    let alloc: AlligatorAlloc<HeapType> = AlligatorAlloc::INIT;
+    let small_layout = Layout::from_size_align(SMALLEST_CLASS_BYTES, 1).unwrap();
+    let medium_layout = Layout::from_size_align(1024, 1).unwrap();
+    let big_layout = Layout::from_size_align(33 * 1024, 1).unwrap();
+    let mut bitmap = vec![0_u8; HEAP_BITMAP_BYTES];
+
+    let small_ptr = alloc.alloc(small_layout);
+    let medium_ptr = alloc.alloc(medium_layout);
+    let big_ptr = alloc.alloc(big_layout);
+    assert!(!small_ptr.is_null() && !medium_ptr.is_null() && !big_ptr.is_null(), "alloc() failed");
+
+    // Interval 0 is the 64 byte MiniPage, 1 the 1024 byte MiniPage, and the big allocation's header and 33 KB take up the 17 intervals after
+    let check = |bitmap: &mut [u8], expected: [u8; 3], when: &str| {
+        alloc.heap_bitmap(bitmap);
+        println!("heap bitmap: {:08b} {:08b} {:08b} {}", bitmap[0], bitmap[1], bitmap[2], when);
+        assert_eq!(bitmap[..3], expected, "wrong intervals marked live {}", when);
+        assert!(bitmap[3..].iter().all(|byte| *byte == 0), "intervals after the big allocation should not be live {}", when);
+    };
+    check(&mut bitmap, [0b1111_1111, 0b1111_1111, 0b0000_0111], "with everything live");
+
+    alloc.dealloc(small_ptr, small_layout);
+    check(&mut bitmap, [0b1111_1110, 0b1111_1111, 0b0000_0111], "after freeing the 64 byte allocation");
+
+    alloc.dealloc(big_ptr, big_layout);
+    check(&mut bitmap, [0b0000_0010, 0, 0], "after freeing the big allocation");
+
+    alloc.dealloc(medium_ptr, medium_layout);
+    check(&mut bitmap, [0, 0, 0], "after freeing everything");
+}
+
+/// Allocate a known mix of size classes and one big allocation from a new allocator, and check live_by_class() reports each size class's live segment bytes and the big allocation's usable bytes. Freeing everything must bring every total back to zero.
+unsafe fn live_by_class() {
+    let alloc: AlligatorAlloc<HeapType> = AlligatorAlloc::INIT;
+    let sizes: [usize; 6] = [100, 100, 100, 1000, 4096, 40 * 1024];
+    let mut ptrs: Vec<(*mut u8, Layout)> = vec!();
+    for bytes in sizes.iter() {
+        let layout = Layout::from_size_align(*bytes, 1).unwrap();
+        let ptr = alloc.alloc(layout);
+        assert!(!ptr.is_null(), "alloc({}) failed", bytes);
+        ptrs.push((ptr, layout));
+    }
+
+    // Three 128 byte segments, one 1024 byte segment, one 4096 byte segment, and the big allocation's size_bytes
+    let mut expected_small = [0_u32; (MAX_SIZE_CLASS - MIN_SIZE_CLASS + 1) as usize];
+    expected_small[SizeClass::new(7).exp_as_idx()] = 3 * 128;
+    expected_small[SizeClass::new(10).exp_as_idx()] = 1024;
+    expected_small[SizeClass::new(12).exp_as_idx()] = 4096;
+    let expected_big = AlligatorAlloc::explain(40 * 1024).unwrap().usable_bytes as u32;
+
+    let breakdown = alloc.live_by_class();
+    println!("live by class: {:?}", breakdown);
+    assert_eq!(breakdown.small, expected_small, "wrong live bytes for the size classes");
+    assert_eq!(breakdown.big, expected_big, "live big bytes should be the big allocation's usable bytes");
+
+    for (ptr, layout) in ptrs.iter() {
+        alloc.dealloc(*ptr, *layout);
+    }
+
+    let freed = alloc.live_by_class();
+    assert!(freed.small.iter().all(|bytes| *bytes == 0) && freed.big == 0, "nothing should be live after freeing everything: {:?}", freed);
+}
+
+/// Allocate and free a MiniPage allocation and a big allocation from a new allocator, and check dump_chrome_trace() writes exactly one instant event for each call, oldest first, as a Chrome Trace Event Format JSON array.
+#[cfg(feature = "chrome-trace")]
+unsafe fn chrome_trace() {
+    let alloc: AlligatorAlloc<HeapType> = AlligatorAlloc::INIT;
+    let small_layout = Layout::from_size_align(100, 1).unwrap();
+    let big_layout = Layout::from_size_align(40 * 1024, 1).unwrap();
+
+    let small_ptr = alloc.alloc(small_layout);
+    let big_ptr = alloc.alloc(big_layout);
+    assert!(!small_ptr.is_null() && !big_ptr.is_null(), "alloc() failed");
+    alloc.dealloc(small_ptr, small_layout);
+    alloc.dealloc(big_ptr, big_layout);
+
+    let event = |name: &str, ts: u64, ptr: *mut u8, layout: Layout| -> String {
+        format!("\n{{\"name\":\"{}\",\"cat\":\"alligator\",\"ph\":\"i\",\"s\":\"g\",\"ts\":{},\"pid\":0,\"tid\":0,\"args\":{{\"ptr\":\"{:#x}\",\"size\":{}}}}}", name, ts, ptr as usize, layout.size())
+    };
+    let expected = format!("[{},{},{},{}\n]\n",
+                           event("alloc", 0, small_ptr, small_layout),
+                           event("alloc", 1, big_ptr, big_layout),
+                           event("dealloc", 2, small_ptr, small_layout),
+                           event("dealloc", 3, big_ptr, big_layout));
+
+    let mut out = String::new();
+    assert!(alloc.dump_chrome_trace(&mut out).is_ok(), "dump_chrome_trace() failed");
+    println!("chrome trace: {} bytes of JSON for 4 events", out.len());
+    assert_eq!(out, expected, "trace JSON should have an instant event for each call");
+}
+
+/// Get a MiniPage segment and a big allocation with alloc_zeroed() from a new allocator, write to them, free them, and get them again. With the zero-tracking feature the fresh memory is known to be zero and must not be filled, while the reused memory must still be filled. Without it every alloc_zeroed() fills. Either way the memory must read as zero.
+#[cfg(feature = "metrics")]
+unsafe fn alloc_zeroed_fills() {
+    let alloc: AlligatorAlloc<HeapType> = AlligatorAlloc::INIT;
+    let fresh_fills = if cfg!(feature = "zero-tracking") { 0 } else { 1 };
+
+    for layout in [Layout::from_size_align(100, 1).unwrap(), Layout::from_size_align(40 * 1024, 1).unwrap()].iter() {
+        let fills_before = alloc.metrics().map_or(0, |metrics| metrics.total_zero_fills);
+        let fresh_ptr = alloc.alloc_zeroed(*layout);
+        assert!(!fresh_ptr.is_null(), "alloc_zeroed({}) failed", layout.size());
+        let fresh_bytes = core::slice::from_raw_parts_mut(fresh_ptr, layout.size());
+        assert!(fresh_bytes.iter().all(|byte| *byte == 0), "fresh alloc_zeroed({}) memory should be zero", layout.size());
+        assert_eq!(alloc.metrics().unwrap().total_zero_fills - fills_before, fresh_fills, "wrong number of fills for fresh alloc_zeroed({}) memory", layout.size());
+
+        fresh_bytes.fill(0xAB);
+        alloc.dealloc(fresh_ptr, *layout);
+
+        let reused_ptr = alloc.alloc_zeroed(*layout);
+        assert_eq!(reused_ptr, fresh_ptr, "alloc_zeroed({}) should reuse the memory just freed", layout.size());
+        let reused_bytes = core::slice::from_raw_parts(reused_ptr, layout.size());
+        println!("alloc zeroed fills: {} bytes filled {} times", layout.size(), alloc.metrics().unwrap().total_zero_fills - fills_before);
+        assert!(reused_bytes.iter().all(|byte| *byte == 0), "reused alloc_zeroed({}) memory should be zero", layout.size());
+        assert_eq!(alloc.metrics().unwrap().total_zero_fills - fills_before, fresh_fills + 1, "reused alloc_zeroed({}) memory should be filled", layout.size());
+
+        alloc.dealloc(reused_ptr, *layout);
+    }
+}
+
+/// Fill a small allocator until an allocation fails at the emergency reserve, then check release_reserve() lets the next allocation succeed. Releasing the reserve again must return false. With the strict feature the failed allocation panics instead, so this is skipped.
+#[cfg(all(feature = "emergency-reserve", not(feature = "strict")))]
+unsafe fn release_reserve() {
+    let alloc: AlligatorAlloc<HeapType> = AlligatorAlloc::with_max_pages(TINY_MAX_PAGES);
+    let layout = Layout::from_size_align(2048, 1).unwrap();
+
+    let mut ptrs: Vec<*mut u8> = vec!();
+    loop {
+        let ptr = alloc.alloc(layout);
+        if ptr.is_null() {
+            break;
+        }
+
+        assert!(ptrs.len() < TINY_MAX_PAGES * 32, "more allocations were made than fit in {} pages", TINY_MAX_PAGES);
+        ptrs.push(ptr);
+    }
+
+    assert!(alloc.release_reserve(), "the first release_reserve() should release the reserve");
+    let reserve_ptr = alloc.alloc(layout);
+    println!("release reserve: {} allocations before the reserve, then {:?} from it", ptrs.len(), reserve_ptr);
+    assert!(!reserve_ptr.is_null(), "alloc({}) should succeed from the released reserve", layout.size());
+    assert!(reserve_ptr > *ptrs.iter().max().unwrap(), "the reserve should be after every allocation made before it was released");
+    assert!(!alloc.release_reserve(), "releasing the reserve again should return false");
+
+    ptrs.push(reserve_ptr);
+    for ptr in ptrs.iter() {
+        alloc.dealloc(*ptr, layout);
+    }
+}
+
+/// Make a big allocation too small to be page aligned, so the heap ends inside a host page, then one of BIG_PAGE_ALIGN_MIN_BYTES. The second's header must start on a host page boundary of the heap, and its header and usable bytes must fill whole host pages. The host heap is mapped before the allocator gets it, so the test knows its base.
+#[cfg(feature = "big-page-align")]
+unsafe fn big_page_align() {
+    let page_bytes = heap::PAGE_BYTES as usize;
+    let mut host_heap: HeapType = heap::INIT;
+    let base_ptr = host_heap.base_ptr();
+    let alloc = AlligatorAlloc::with_heap(host_heap, TINY_MAX_PAGES);
+
+    let unaligned_layout = Layout::from_size_align(33 * 1024, 1).unwrap();
+    let aligned_layout = Layout::from_size_align(page_bytes, 1).unwrap();
+    let unaligned_ptr = alloc.alloc(unaligned_layout);
+    let aligned_ptr = alloc.alloc(aligned_layout);
+    assert!(!unaligned_ptr.is_null() && !aligned_ptr.is_null(), "alloc() failed");
+
+    let plan = AlligatorAlloc::explain(aligned_layout.size()).unwrap();
+    let header_bytes = plan.footprint_bytes - plan.usable_bytes;
+    let start_offset = aligned_ptr as usize - header_bytes - base_ptr as usize;
+    println!("big page align: {} byte allocation starts {} bytes into the heap and takes up {} pages", aligned_layout.size(), start_offset, plan.footprint_bytes / page_bytes);
+    assert_eq!(start_offset % page_bytes, 0, "a {} byte big allocation should start on a host page boundary", aligned_layout.size());
+    assert_eq!(plan.footprint_bytes % page_bytes, 0, "a {} byte big allocation should take up whole host pages: {:?}", aligned_layout.size(), plan);
+    assert_eq!(alloc.usable_size(aligned_ptr) + header_bytes, plan.footprint_bytes, "the usable bytes and header should fill the allocation's host pages");
+    assert_eq!(alloc.validate_heap(), Ok(()), "heap broken after a page aligned big allocation");
+
+    alloc.dealloc(unaligned_ptr, unaligned_layout);
+    alloc.dealloc(aligned_ptr, aligned_layout);
+}
+
+/// Read the AllocDebugHeader the way an external debugger would, as little endian u32s at the start of the MetaPage, which is the start of the heap. After each alloc, realloc and dealloc its live bytes must be the usable bytes of the live allocations, and its MiniPage count and next allocation offset must follow the heap. The host heap is mapped before the allocator gets it, so the test knows its base.
+#[cfg(feature = "debug-layout")]
+unsafe fn debug_header() {
+    use alloc::ALLOC_DEBUG_MAGIC;
+
+    let mut host_heap: HeapType = heap::INIT;
+    let base_ptr = host_heap.base_ptr();
+    let alloc = AlligatorAlloc::with_heap(host_heap, TINY_MAX_PAGES);
+
+    // (magic, live_bytes, total_minipages, next_alloc_offset, last_failure_code)
+    let read = || -> (u32, u32, u32, u32, u32) {
+        let field = |offset: usize| u32::from_le_bytes(core::ptr::read_unaligned(base_ptr.add(offset) as *const [u8; 4]));
+        (field(0), field(4), field(8), field(12), field(16))
+    };
+
+    let small_layout = Layout::from_size_align(100, 1).unwrap();
+    let big_layout = Layout::from_size_align(40 * 1024, 1).unwrap();
+    let small_ptr = alloc.alloc(small_layout);
+    let big_ptr = alloc.alloc(big_layout);
+    assert!(!small_ptr.is_null() && !big_ptr.is_null(), "alloc() failed");
+    assert_eq!(alloc.debug_header_ptr() as *mut u8, base_ptr, "the debug header should be at the start of the heap");
+
+    let (magic, live_bytes, minipages, next_alloc_offset, failure_code) = read();
+    assert_eq!(magic, ALLOC_DEBUG_MAGIC, "wrong magic at the start of the heap");
+    assert_eq!(live_bytes as usize, alloc.usable_size(small_ptr) + alloc.usable_size(big_ptr), "live bytes after alloc");
+    assert_eq!(minipages, 1, "one MiniPage should have been made");
+    assert_eq!(next_alloc_offset as usize, big_ptr as usize - base_ptr as usize + alloc.usable_size(big_ptr), "the next allocation should be right after the big allocation");
+    assert_eq!(failure_code, 0, "nothing has failed");
+
+    // The MiniPage allocation moves to a larger size class, the big allocation shrinks in place
+    let moved_ptr = alloc.realloc(small_ptr, small_layout, 1000);
+    let shrunk_ptr = alloc.realloc(big_ptr, big_layout, 4 * 1024);
+    assert!(!moved_ptr.is_null() && shrunk_ptr == big_ptr, "realloc() failed");
+    let (_, live_bytes, minipages, realloc_next_offset, _) = read();
+    println!("debug header: {} live bytes in {} MiniPages after realloc", live_bytes, minipages);
+    assert_eq!(live_bytes as usize, alloc.usable_size(moved_ptr) + alloc.usable_size(shrunk_ptr), "live bytes after realloc");
+    assert_eq!(minipages, 2, "the moved allocation should be in a new MiniPage");
+    assert!(realloc_next_offset > next_alloc_offset, "the new MiniPage should be after the big allocation");
+
+    alloc.dealloc(moved_ptr, Layout::from_size_align(1000, 1).unwrap());
+    alloc.dealloc(shrunk_ptr, Layout::from_size_align(4 * 1024, 1).unwrap());
+    let (_, live_bytes, minipages, _, failure_code) = read();
+    assert_eq!((live_bytes, minipages, failure_code), (0, 2, 0), "nothing should be live after dealloc, and the MiniPage count should not drop");
+}
+
+/// Allocate and free a lot of times.
+fn main() {
+    size_class_integer_math();
+    size_class_from_layout();
+    unsafe_stack_order();
+    unsafe_stack_contains();
+    unsafe_stack_peek_nth();
+    alloc_addr_bounds();
+    alloc_addr_try_bounds();
+    explain();
+
+    unsafe {
+        live_stats();
+        snapshot_stats();
+        for_each_live();
+        occupancy_stats();
+        alloc_near();
+        dealloc_by_address();
+        compact();
+        usable_size();
+        #[cfg(not(feature = "strict"))]
+        reset();
+        reused_minipage();
+        release();
+        fresh_reused_ratio();
+        free_bitmap_round_trip();
+        realloc_shrink_big();
+        big_alloc_policy();
+        large_request_routing();
+        validate_heap();
+        alignment();
+        heap_bitmap();
+        live_by_class();
+    }
+
+    for i in 0..1 {
+        println!("Benchmark iteration {}", i);
+        unsafe {
+            alloc_all();
+        }
+    }
+
+    cfg_if! {
+        if #[cfg(feature = "metrics")] {
+            unsafe {
+                meta_page_metrics();
+                dot_graph();
+                fragmentation();
+                peak_usage();
+                alternate_size_classes();
+                reclaimed_minipage_links();
+                split_big_alloc();
+                coalesce_big_allocs();
+                large_size_class_minipages();
+                big_dealloc_lookup();
+                full_minipage_stacks();
+                dealloc_many();
+                big_alloc_scan();
+                alloc_zeroed_fills();
+            }
+        }
+    }
+
+    cfg_if! {
+        if #[cfg(all(feature = "metrics", not(feature = "strict")))] {
+            unsafe {
+                double_free();
+                align_too_large();
+                small_only();
+                max_pages_boundary();
+            }
+        }
+    }
+
+    cfg_if! {
+        if #[cfg(feature = "trace")] {
+            unsafe {
+                trace();
+            }
+        }
+    }
+
+    cfg_if! {
+        if #[cfg(all(feature = "emergency-reserve", not(feature = "strict")))] {
+            unsafe {
+                release_reserve();
+            }
+        }
+    }
+
+    cfg_if! {
+        if #[cfg(feature = "big-page-align")] {
+            unsafe {
+                big_page_align();
+            }
+        }
+    }
+
+    cfg_if! {
+        if #[cfg(feature = "debug-layout")] {
+            unsafe {
+                debug_header();
+            }
+        }
+    }
+
+    cfg_if! {
+        if #[cfg(feature = "chrome-trace")] {
+            unsafe {
+                chrome_trace();
+            }
+        }
+    }
+
+    cfg_if! {
+        if #[cfg(feature = "mock")] {
+            unsafe {
+                validate_heap_corruption();
+            }
+        }
+    }
+
+    cfg_if! {
+        if #[cfg(all(feature = "mock", not(feature = "strict")))] {
+            unsafe {
+                lazy_grow();
+            }
+        }
+    }
+
+    cfg_if! {
+        if #[cfg(feature = "std")] {
+            alloc_box();
+
+            unsafe {
+                snapshot_round_trip();
+            }
+        }
+    }
+
+    cfg_if! {
+        if #[cfg(not(feature = "strict"))] {
+            unsafe {
+                invalid_free_pointer();
+            }
+        }
+    }
+
+    cfg_if! {
+        if #[cfg(feature = "requested-size")] {
+            unsafe {
+                requested_size();
+            }
+        }
+    }
+
+    cfg_if! {
+        if #[cfg(feature = "no-panic")] {
+            unsafe {
+                no_panic();
+            }
+        }
+    }
+
+    cfg_if! {
+        if #[cfg(all(feature = "metrics", feature = "mock", not(feature = "strict")))] {
+            unsafe {
+                mock_grow_failures();
+            }
+        }
+    }
+
+    unsafe {
+        release_unused_pages();
+    }
 }
@@ -0,0 +1,216 @@
+mod alloc;
+use alloc::{AlligatorAlloc,MAX_SIZE_CLASS};
+use alloc::heap::HeapType;
+use core::alloc::Layout;
+use std::alloc::GlobalAlloc;
+use std::collections::BTreeMap;
+use std::env;
+use rand::prelude::*;
+use rand::rngs::StdRng;
+use cfg_if::cfg_if;
+
+/*
+ * What follows is the benchmark program. It checks the
+ * most basic allocator property: live allocations never
+ * overlap. ALLOC is not the global allocator so the
+ * bookkeeping below does not use Alligator.
+ *
+ * Half of the frees pass a deliberately wrong Layout.
+ * bench-random-report frees with a dummy Layout, which
+ * is only safe while dealloc ignores the Layout. If that
+ * changes this program fails.
+ *
+ * Some live allocations are resized with realloc, their
+ * contents must survive the move.
+ */
+static ALLOC: AlligatorAlloc<HeapType> = AlligatorAlloc::INIT;
+
+/// Seed used if one is not provided, so runs are reproducible.
+const DEFAULT_SEED: u64 = 1201;
+
+/// Number of alloc / dealloc operations performed if not provided.
+const DEFAULT_OPERATIONS: u64 = 50000;
+
+/// Maximum number of allocations which will be live at once.
+const MAX_LIVE: usize = 512;
+
+/// Layout which does not match any allocation, used to check dealloc does not rely on the Layout.
+const WRONG_LAYOUT: Layout = unsafe { Layout::from_size_align_unchecked(1, 1) };
+
+/// Tracks the [start, end) intervals of all live allocations.
+struct LiveModel {
+    /// Live intervals, keys are start addresses and values are end addresses.
+    intervals: BTreeMap<usize, usize>,
+
+    /// Layouts used to allocate each live pointer, keyed by start address.
+    layouts: BTreeMap<usize, Layout>,
+}
+
+impl LiveModel {
+    /// Record a new live allocation. Panics if it overlaps with any existing live allocation.
+    fn insert(&mut self, ptr: *mut u8, usable_bytes: usize, layout: Layout, op: u64) {
+        let start = ptr as usize;
+        let end = start + usable_bytes;
+
+        // Allocation directly before must end before this one starts
+        if let Some((prev_start, prev_end)) = self.intervals.range(..=start).next_back() {
+            assert!(*prev_end <= start, "op {}: allocation [{:#x}, {:#x}) overlaps live allocation [{:#x}, {:#x})", op, start, end, prev_start, prev_end);
+        }
+
+        // Allocation directly after must start after this one ends
+        if let Some((next_start, next_end)) = self.intervals.range(start..).next() {
+            assert!(end <= *next_start, "op {}: allocation [{:#x}, {:#x}) overlaps live allocation [{:#x}, {:#x})", op, start, end, next_start, next_end);
+        }
+
+        self.intervals.insert(start, end);
+        self.layouts.insert(start, layout);
+    }
+
+    /// Remove a live allocation, returns the layout it was allocated with.
+    fn remove(&mut self, start: usize) -> Layout {
+        self.intervals.remove(&start);
+        self.layouts.remove(&start).unwrap()
+    }
+}
+
+/// Random number of bytes between 1 and 2^max_exp, at most u16::MAX. Sizes are picked uniformly within a random power of two, so small and large size classes are both used often.
+fn random_bytes(rng: &mut StdRng, max_exp: u32) -> usize {
+    let exp = rng.gen_range(0..=max_exp);
+    let min_bytes = match exp {
+        0 => 1,
+        _ => 2_usize.pow(exp - 1) + 1,
+    };
+    let max_bytes = 2_usize.pow(exp).min(usize::from(u16::MAX));
+
+    rng.gen_range(min_bytes..=max_bytes)
+}
+
+/// Randomly allocate and free, checking no live allocations overlap.
+unsafe fn check_overlap(seed: u64, operations: u64) {
+    let mut rng = StdRng::seed_from_u64(seed);
+    let mut model = LiveModel{
+        intervals: BTreeMap::new(),
+        layouts: BTreeMap::new(),
+    };
+
+    // Nothing is owned before the heap is initialized
+    let mut not_from_alloc: u8 = 0;
+    assert!(!ALLOC.owns(&mut not_from_alloc), "owns() should be false before the first allocation");
+
+    // A request which could overflow the allocator's size math must fail, not return a wild pointer
+    let huge_layout = Layout::from_size_align(isize::MAX as usize, 1).unwrap();
+    assert!(ALLOC.alloc(huge_layout).is_null(), "alloc({}) should fail", huge_layout.size());
+
+    // Exponents of the random sizes, the largest only fits in a big allocation
+    let max_exp = u32::from(MAX_SIZE_CLASS) + 1;
+
+    for op in 0..operations {
+        let live = model.intervals.len();
+        let should_alloc = live == 0 || (live < MAX_LIVE && rng.gen_range(0..10) < 6);
+
+        if should_alloc {
+            let bytes = random_bytes(&mut rng, max_exp);
+            let layout = match Layout::from_size_align(bytes, 1) {
+                Ok(l) => l,
+                Err(e) => panic!("error making Layout::from_size_align({}, 1): {}", bytes, e),
+            };
+
+            let ptr = ALLOC.alloc(layout);
+
+            cfg_if! {
+                if #[cfg(feature = "metrics")] {
+                    if ptr.is_null() {
+                        eprintln!("alloc failure cause={:?}", ALLOC.alloc_failure_cause());
+                    }
+                }
+            }
+            assert!(!ptr.is_null(), "op {}: alloc({}) failed (returned null)", op, bytes);
+
+            assert!(ALLOC.owns(ptr), "op {}: alloc({}) returned {:?} which the allocator does not own", op, bytes, ptr);
+            assert!(!ALLOC.owns(&mut not_from_alloc), "op {}: the allocator should not own a stack variable", op);
+
+            let usable_bytes = ALLOC.usable_size(ptr);
+            assert!(usable_bytes >= bytes, "op {}: alloc({}) returned {:?} with only {} usable bytes", op, bytes, ptr, usable_bytes);
+
+            model.insert(ptr, usable_bytes, layout, op);
+        } else if rng.gen_range(0..3) == 0 {
+            // Resize a random live allocation
+            let realloc_i = rng.gen_range(0..live);
+            let start = *model.intervals.keys().nth(realloc_i).unwrap();
+            let layout = model.remove(start);
+            let old_ptr = start as *mut u8;
+            let old_kind = ALLOC.allocation_kind(old_ptr);
+            let old_usable_bytes = ALLOC.usable_size(old_ptr);
+
+            let new_bytes = random_bytes(&mut rng, max_exp);
+            let keep_bytes = layout.size().min(new_bytes);
+            for i in 0..keep_bytes {
+                *old_ptr.add(i) = (i as u64 ^ op) as u8;
+            }
+
+            let ptr = ALLOC.realloc(old_ptr, layout, new_bytes);
+            assert!(!ptr.is_null(), "op {}: realloc({:?}, {}) failed (returned null)", op, old_ptr, new_bytes);
+
+            // Small and big allocations are both resized in place when new_bytes fits, a shrunk big allocation gives its tail back
+            if old_kind.is_some() && new_bytes <= old_usable_bytes {
+                assert_eq!(ptr, old_ptr, "op {}: realloc({:?}, {}) of {:?} within its {} usable bytes moved", op, old_ptr, new_bytes, old_kind, old_usable_bytes);
+            }
+
+            for i in 0..keep_bytes {
+                assert_eq!(*ptr.add(i), (i as u64 ^ op) as u8, "op {}: realloc({:?}, {}) to {:?} lost byte {}", op, old_ptr, new_bytes, ptr, i);
+            }
+
+            let usable_bytes = ALLOC.usable_size(ptr);
+            assert!(usable_bytes >= new_bytes, "op {}: realloc({:?}, {}) returned {:?} with only {} usable bytes", op, old_ptr, new_bytes, ptr, usable_bytes);
+
+            model.insert(ptr, usable_bytes, Layout::from_size_align(new_bytes, 1).unwrap(), op);
+        } else {
+            // Free a random live allocation
+            let free_i = rng.gen_range(0..live);
+            let start = *model.intervals.keys().nth(free_i).unwrap();
+            let layout = model.remove(start);
+
+            let free_layout = match op % 2 {
+                0 => layout,
+                _ => WRONG_LAYOUT,
+            };
+            ALLOC.dealloc(start as *mut u8, free_layout);
+
+            assert!(ALLOC.allocation_kind(start as *mut u8).is_none(), "op {}: dealloc({:#x}, {:?}) of an allocation made with {:?} did not free it", op, start, free_layout, layout);
+        }
+
+        if let Err(violation) = ALLOC.validate_heap() {
+            panic!("op {}: heap invariant broken: {:?}", op, violation);
+        }
+    }
+
+    // Free everything left over
+    let remaining: Vec<usize> = model.intervals.keys().cloned().collect();
+    for start in remaining {
+        let layout = model.remove(start);
+        ALLOC.dealloc(start as *mut u8, layout);
+    }
+
+    println!("seed={} operations={}: no overlapping allocations", seed, operations);
+}
+
+/// Check allocations never overlap, and realloc keeps their contents. Usage: bench-overlap [-s,--seed <u64>] [-i,--operations <num>]
+fn main() {
+    let mut seed = DEFAULT_SEED;
+    let mut operations = DEFAULT_OPERATIONS;
+
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == "-s" || arg == "--seed" {
+            seed = args.next().unwrap().parse().unwrap();
+        } else if arg == "-i" || arg == "--operations" {
+            operations = args.next().unwrap().parse().unwrap();
+        } else {
+            panic!("unknown argument: {}", arg);
+        }
+    }
+
+    unsafe {
+        check_overlap(seed, operations);
+    }
+}
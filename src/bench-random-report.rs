@@ -1,12 +1,17 @@
 mod alloc;
+mod clock;
+use clock::{Clock,BenchClock};
 use alloc::{AlligatorAlloc,SizeClass,MIN_SIZE_CLASS,MAX_SIZE_CLASS};
 use alloc::heap::HeapType;
 use core::alloc::Layout;
 use std::alloc::GlobalAlloc;
 use rand::prelude::*;
+use rand::rngs::StdRng;
+use rand::distributions::WeightedIndex;
 use std::env;
 use std::process::exit;
 use std::convert::TryFrom;
+use std::fmt::Display;
 
 /*
  * What follows is the benchmark program. Right now it
@@ -19,11 +24,14 @@ static ALLOC: AlligatorAlloc<HeapType> = AlligatorAlloc::INIT;
 
 /// Implements a pattern of randomly allocation and freeing.
 struct RandomReport {
-    /// Thread random number generator.
-    rng: ThreadRng,
+    /// Allocator being benchmarked, ALLOC unless --fresh-reused-ratio was given.
+    alloc: &'static AlligatorAlloc<HeapType>,
 
-    /// Pointers which should be freed later.
-    free_later: Vec<*mut u8>,
+    /// Random number generator, seeded so a run can be reproduced.
+    rng: StdRng,
+
+    /// Pointers which should be freed later, and the Layouts they were allocated with.
+    free_later: Vec<(*mut u8, Layout)>,
     
     /// The number of times the benchmark has performed the main loop.
     iteration: u64,
@@ -33,18 +41,131 @@ struct RandomReport {
 
     /// Range of size classes which are allowed to be allocated.
     alloc_range: InclusiveRange<u8>,
+
+    /// If Some sizes are drawn from this weighted distribution of size classes instead of alloc_range.
+    size_dist: Option<SizeDist>,
+
+    /// If true allocate with alloc_zeroed instead of alloc.
+    zeroed: bool,
+
+    /// Times alloc and dealloc calls.
+    clock: BenchClock,
+
+    /// Total nanoseconds spent in alloc calls.
+    alloc_nanos: u64,
+
+    /// Total nanoseconds spent in dealloc calls.
+    dealloc_nanos: u64,
+
+    /// Seed rng was created with, included in JSON reports.
+    seed: u64,
+
+    /// How reports are printed.
+    format: ReportFormat,
+}
+
+/// Weighted distribution of size classes to allocate.
+struct SizeDist {
+    /// Size classes which can be allocated.
+    classes: Vec<u8>,
+
+    /// Picks an index into classes, using the weight given for each class.
+    index: WeightedIndex<u32>,
+
+    /// Number of allocations made for each of classes.
+    counts: Vec<u64>,
+}
+
+impl SizeDist {
+    /// Creates a SizeDist from (size class, weight) pairs. Panics if the weights are invalid, ie., all 0.
+    fn new(spec: &[(u8, u32)]) -> SizeDist {
+        let index = match WeightedIndex::new(spec.iter().map(|(_class, weight)| *weight)) {
+            Ok(index) => index,
+            Err(e) => panic!("invalid size class distribution weights: {}", e),
+        };
+
+        SizeDist{
+            classes: spec.iter().map(|(class, _weight)| *class).collect(),
+            index: index,
+            counts: vec![0; spec.len()],
+        }
+    }
+
+    /// Formats the number of allocations made for each size class like the --dist argument, ie., 3:120,10:14.
+    fn counts_spec(&self) -> String {
+        let pairs: Vec<String> = self.classes.iter().zip(self.counts.iter()).map(|(class, count)| format!("{}:{}", class, count)).collect();
+        pairs.join(",")
+    }
+}
+
+/// Formats a slice as a JSON array.
+fn json_array<T: Display>(items: &[T]) -> String {
+    let items: Vec<String> = items.iter().map(|i| i.to_string()).collect();
+    format!("[{}]", items.join(","))
 }
 
 impl RandomReport {
-    /// Prints a CSV data row based on the current allocator metrics.
+    /// Prints a report of the current allocator metrics in the chosen format.
     unsafe fn print_metrics(&mut self) {
+        match self.format {
+            ReportFormat::CSV => self.print_csv_metrics(),
+            ReportFormat::JSON => self.print_json_metrics(),
+        }
+    }
+
+    /// Prints a JSON object on one line with every allocator metric, including the per size class arrays. Index 0 of the size class arrays is min_size_class, the last index of total_allocs and total_deallocs counts big allocations.
+    unsafe fn print_json_metrics(&mut self) {
+        let metrics = match self.alloc.metrics() {
+            Some(m) => m,
+            None => panic!("no metrics found after allocations and deallocations were performed"),
+        };
+
+        let ratio = self.alloc.fresh_reused_stats();
+
+        println!("{{\"seed\":{seed},\"iteration\":{iteration},\"total_alloc_bytes\":{total_alloc_bytes},\"alloc_nanos\":{alloc_nanos},\"dealloc_nanos\":{dealloc_nanos},\"min_size_class\":{min_size_class},\"max_size_class\":{max_size_class},\"metrics\":{{\"total_allocs\":{total_allocs},\"total_deallocs\":{total_deallocs},\"total_minipages\":{total_minipages},\"heap_bytes_read\":{heap_bytes_read},\"heap_bytes_write\":{heap_bytes_write},\"total_zero_fills\":{total_zero_fills},\"total_big_headers\":{total_big_headers},\"big_coalesces\":{big_coalesces},\"big_splits\":{big_splits},\"reclaimed_minipages\":{reclaimed_minipages},\"big_dealloc_header_reads\":{big_dealloc_header_reads},\"internal_frag_bytes\":{internal_frag_bytes},\"external_frag_bytes\":{external_frag_bytes},\"current_used_bytes\":{current_used_bytes},\"peak_used_bytes\":{peak_used_bytes},\"current_minipages\":{current_minipages},\"peak_minipages\":{peak_minipages},\"max_big_alloc_scan\":{max_big_alloc_scan}}},\"dist_counts\":{dist_counts},\"fresh_reused\":{{\"total_alloc_fresh\":{total_alloc_fresh},\"total_alloc_reused\":{total_alloc_reused}}}}}",
+                 seed=self.seed,
+                 iteration=self.iteration,
+                 total_alloc_bytes=self.total_alloc_bytes,
+                 alloc_nanos=self.alloc_nanos,
+                 dealloc_nanos=self.dealloc_nanos,
+                 min_size_class=MIN_SIZE_CLASS,
+                 max_size_class=MAX_SIZE_CLASS,
+                 total_allocs=json_array(&metrics.total_allocs),
+                 total_deallocs=json_array(&metrics.total_deallocs),
+                 total_minipages=metrics.total_minipages,
+                 heap_bytes_read=metrics.heap_bytes_read,
+                 heap_bytes_write=metrics.heap_bytes_write,
+                 total_zero_fills=metrics.total_zero_fills,
+                 total_big_headers=metrics.total_big_headers,
+                 big_coalesces=metrics.big_coalesces,
+                 big_splits=metrics.big_splits,
+                 reclaimed_minipages=metrics.reclaimed_minipages,
+                 big_dealloc_header_reads=metrics.big_dealloc_header_reads,
+                 internal_frag_bytes=metrics.internal_frag_bytes,
+                 external_frag_bytes=metrics.external_frag_bytes,
+                 current_used_bytes=metrics.current_used_bytes,
+                 peak_used_bytes=metrics.peak_used_bytes,
+                 current_minipages=metrics.current_minipages,
+                 peak_minipages=metrics.peak_minipages,
+                 max_big_alloc_scan=metrics.max_big_alloc_scan,
+                 dist_counts=match &self.size_dist {
+                     Some(dist) => format!("\"{}\"", dist.counts_spec()),
+                     None => "null".to_string(),
+                 },
+                 total_alloc_fresh=json_array(&ratio.total_alloc_fresh),
+                 total_alloc_reused=json_array(&ratio.total_alloc_reused)
+        );
+    }
+
+    /// Prints a CSV data row based on the current allocator metrics.
+    unsafe fn print_csv_metrics(&mut self) {
         // Return metrics
-        let metrics = match ALLOC.metrics() {
+        let metrics = match self.alloc.metrics() {
             Some(m) => m,
             None => panic!("no metrics found after allocations and deallocations were performed"),
         };
 
-        let ratio = ALLOC.fresh_reused_stats();
+        let ratio = self.alloc.fresh_reused_stats();
         
         // Aggregate per size class metrics into totals
         let mut total_allocs = 0;
@@ -64,7 +185,7 @@ impl RandomReport {
         }
 
         // Print results in a CSV table
-        println!("{iteration},{total_alloc_bytes},{total_minipages},{heap_bytes_write},{heap_bytes_read},{total_allocs},{total_deallocs},{fresh_allocs},{reused_allocs}",
+        println!("{iteration},{total_alloc_bytes},{total_minipages},{heap_bytes_write},{heap_bytes_read},{total_allocs},{total_deallocs},{fresh_allocs},{reused_allocs},{big_coalesces},{big_splits},{internal_frag_bytes},{external_frag_bytes},{current_used_bytes},{peak_used_bytes},{peak_minipages},{max_big_alloc_scan},{alloc_nanos},{dealloc_nanos}",
                  iteration=self.iteration,
                  total_alloc_bytes=self.total_alloc_bytes,
                  total_minipages=metrics.total_minipages,
@@ -73,14 +194,43 @@ impl RandomReport {
                  total_allocs=total_allocs,
                  total_deallocs=total_deallocs,
                  fresh_allocs=fresh_allocs,
-                 reused_allocs=reused_allocs
+                 reused_allocs=reused_allocs,
+                 big_coalesces=metrics.big_coalesces,
+                 big_splits=metrics.big_splits,
+                 internal_frag_bytes=metrics.internal_frag_bytes,
+                 external_frag_bytes=metrics.external_frag_bytes,
+                 current_used_bytes=metrics.current_used_bytes,
+                 peak_used_bytes=metrics.peak_used_bytes,
+                 peak_minipages=metrics.peak_minipages,
+                 max_big_alloc_scan=metrics.max_big_alloc_scan,
+                 alloc_nanos=self.alloc_nanos,
+                 dealloc_nanos=self.dealloc_nanos
         );
     }
 
+    /// Free ptr and record how long it took.
+    unsafe fn timed_dealloc(&mut self, ptr: *mut u8, layout: Layout) {
+        let dealloc_start = self.clock.now_nanos();
+        self.alloc.dealloc(ptr, layout);
+        self.dealloc_nanos += self.clock.now_nanos() - dealloc_start;
+    }
+
     /// Allocate a random size.
     unsafe fn iterate(&mut self) {
         // Choose random size to allocate.
-        let alloc_bytes: u64 = self.rng.gen_range(2_u64.pow(u32::from(self.alloc_range.min))..=2_u64.pow(u32::from(self.alloc_range.max)));
+        let alloc_bytes: u64 = match &mut self.size_dist {
+            Some(dist) => {
+                // A size which falls in the picked size class
+                let i = dist.index.sample(&mut self.rng);
+                dist.counts[i] += 1;
+
+                match u32::from(dist.classes[i]) {
+                    0 => 1,
+                    exp => self.rng.gen_range(2_u64.pow(exp - 1) + 1..=2_u64.pow(exp)),
+                }
+            },
+            None => self.rng.gen_range(2_u64.pow(u32::from(self.alloc_range.min))..=2_u64.pow(u32::from(self.alloc_range.max))),
+        };
         self.total_alloc_bytes += alloc_bytes;
 
         // Create layout which requests the maximum number of bytes possible for this size class
@@ -90,19 +240,24 @@ impl RandomReport {
         };
 
         // Call allocate
-        let ptr = ALLOC.alloc(layout);
+        let alloc_start = self.clock.now_nanos();
+        let ptr = match self.zeroed {
+            true => self.alloc.alloc_zeroed(layout),
+            false => self.alloc.alloc(layout),
+        };
+        self.alloc_nanos += self.clock.now_nanos() - alloc_start;
 
         if ptr.is_null() {
-            panic!("alloc({}) failed: {:?}", alloc_bytes, ALLOC.alloc_failure_cause());
+            panic!("alloc({}) failed: {:?}", alloc_bytes, self.alloc.alloc_failure_cause());
         }
 
         // Either free immediately or free at a random later iteration
         let should_free_now: u8 = self.rng.gen_range(0..10);
         if should_free_now <= 4 {
             // Don't immediately free ~40% of allocations.
-            self.free_later.push(ptr);
+            self.free_later.push((ptr, layout));
         } else {
-            ALLOC.dealloc(ptr, layout);
+            self.timed_dealloc(ptr, layout);
         }
 
         // Determine if we should free one of the addresses which we left around to free at another random time
@@ -110,9 +265,8 @@ impl RandomReport {
         if self.free_later.len() > 0 && should_free_other_old <= 1 {
             // Free stuff from free_later about 40% of the time
             let free_idx: usize = self.rng.gen_range(0..self.free_later.len());
-            let free_ptr = self.free_later[free_idx];
-            ALLOC.dealloc(free_ptr, layout); // Using the wrong layout shouldn't matter
-            self.free_later.remove(free_idx);
+            let (free_ptr, free_layout) = self.free_later.remove(free_idx);
+            self.timed_dealloc(free_ptr, free_layout);
         }
 
         self.iteration += 1;
@@ -120,18 +274,17 @@ impl RandomReport {
 
     /// Cleanup any remaining allocations which were left. Then print a final line of metrics so we can confirm everything is clean.
     unsafe fn cleanup(&mut self) {
-        // Used when a layout needs to be passed but it doesn't matter what its value is
-        let dummy_layout = match Layout::from_size_align(8, 1) {
-            Ok(l) => l,
-            Err(e) => panic!("error making dummy Layout: {}", e),
-        };
-        
-        // Free the memory we intentionally left laying around.
-        for ptr in self.free_later.iter() {
-            ALLOC.dealloc(*ptr, dummy_layout);
+        // Free the memory we intentionally left laying around. With the allocation's Layout, which internal_frag_bytes relies on
+        for (ptr, layout) in self.free_later.iter() {
+            self.alloc.dealloc(*ptr, *layout);
         }
 
         self.print_metrics();
+
+        // Show the distribution was followed, JSON reports already include the counts
+        if let (Some(dist), ReportFormat::CSV) = (&self.size_dist, self.format) {
+            println!("# dist counts={}", dist.counts_spec());
+        }
     }
 }
 
@@ -144,6 +297,16 @@ enum PrintCSVHeader {
     Exit,
 }
 
+/// How metric reports are printed.
+#[derive(Copy, Clone, PartialEq)]
+enum ReportFormat {
+    /// One CSV row of totals per report.
+    CSV,
+
+    /// One JSON object per line per report, with per size class breakdowns.
+    JSON,
+}
+
 /// An inclusive range.
 struct InclusiveRange<T> {
     /// Minimum.
@@ -170,8 +333,23 @@ struct Args {
     /// Defines the range of size classes to allocate.
     alloc_range: Option<InclusiveRange<u8>>,
 
+    /// Weighted distribution of size classes to allocate, (size class, weight) pairs.
+    size_dist: Option<Vec<(u8, u32)>>,
+
     /// If program should print a dot graphviz representation of the allocator internal state.
     print_dot_graph: Option<()>,
+
+    /// If allocations should be made with alloc_zeroed.
+    alloc_zeroed: Option<()>,
+
+    /// Seed for the random number generator.
+    seed: Option<u64>,
+
+    /// How reports are printed.
+    format: Option<ReportFormat>,
+
+    /// If Some the allocator aims for this ratio of fresh to reused MiniPage allocations instead of the default.
+    fresh_reused_ratio: Option<f64>,
 }
 
 impl Args {
@@ -183,7 +361,12 @@ impl Args {
             report_interval: None,
             print_csv_header: None,
             alloc_range: None,
+            size_dist: None,
             print_dot_graph: None,
+            alloc_zeroed: None,
+            seed: None,
+            format: None,
+            fresh_reused_ratio: None,
         };
         
         while !args.is_empty() {
@@ -204,8 +387,29 @@ impl Args {
                     min: args.pop().unwrap().parse().unwrap(),
                     max: args.pop().unwrap().parse().unwrap(),
                 });
+            } else if arg == "--dist" {
+                let spec = args.pop().unwrap();
+                parsed.size_dist = Some(spec.split(',').map(|pair| {
+                    match pair.split_once(':') {
+                        Some((class, weight)) => (class.parse().unwrap(), weight.parse().unwrap()),
+                        None => panic!("size class distribution entry {} must be <size class>:<weight>", pair),
+                    }
+                }).collect());
             } else if arg == "-d" || arg == "dot-graph" {
                 parsed.print_dot_graph = Some(());
+            } else if arg == "-z" || arg == "--zeroed" {
+                parsed.alloc_zeroed = Some(());
+            } else if arg == "-s" || arg == "--seed" {
+                parsed.seed = Some(args.pop().unwrap().parse().unwrap());
+            } else if arg == "-f" || arg == "--format" {
+                let format = args.pop().unwrap();
+                parsed.format = match format.as_str() {
+                    "csv" => Some(ReportFormat::CSV),
+                    "json" => Some(ReportFormat::JSON),
+                    _ => panic!("unknown format: {}, must be csv or json", format),
+                };
+            } else if arg == "--fresh-reused-ratio" {
+                parsed.fresh_reused_ratio = Some(args.pop().unwrap().parse().unwrap());
             } else {
                 panic!("unknown argument: {}", arg);
             }
@@ -220,6 +424,10 @@ impl Args {
             parsed.report_interval = Some(100);
         }
 
+        if parsed.format.is_none() {
+            parsed.format = Some(ReportFormat::CSV);
+        }
+
         if parsed.alloc_range.is_none() {
             parsed.alloc_range = Some(InclusiveRange::<u8>{
                 min: MIN_SIZE_CLASS,
@@ -236,7 +444,7 @@ impl Args {
 
 USAGE
 
-    bench-alloc-report.rs [-h] [-i,--max-iterations <num>] [-r,--report-interval <num>] [-d,--dot-graph] [-c,--csv-header] [-C,--only-csv-header] [-a,--alloc <min> <max>]
+    bench-alloc-report.rs [-h] [-i,--max-iterations <num>] [-r,--report-interval <num>] [-d,--dot-graph] [-z,--zeroed] [-s,--seed <u64>] [-f,--format <csv|json>] [-c,--csv-header] [-C,--only-csv-header] [-a,--alloc <min> <max>] [--dist <spec>] [--fresh-reused-ratio <f64>]
 
 OPTIONS
 
@@ -244,13 +452,20 @@ OPTIONS
     -i,--max-iteration <num>      Number of iterations to run (default 1000)
     -r,--report-interval <num>    The interval on which to print CSV metric rows (default 100)
     -d,--dot-graph                Print a dot graph of the allocator state.
+    -z,--zeroed                   Allocate with alloc_zeroed, to compare heap_bytes_write with and without the zero-tracking feature
+    -s,--seed <u64>               Seed for the random number generator, runs with the same seed perform the same allocations (default random)
     -a,--alloc <min> <max>        The, inclusive, minimum and maximum size class which can be randomly allocated (default {min_size_class} {max_size_class})
+    -f,--format <csv|json>        Print reports as CSV rows or as one JSON object per line, JSON includes per size class breakdowns (default csv)
+    --dist <spec>                 Allocate sizes from a weighted distribution of size classes instead of the --alloc range. spec is a comma separated list of <size class>:<weight> pairs, ie., 3:50,4:30,10:1 allocates 8 byte size class sizes 50 times as often as 1024 byte size class sizes
+    --fresh-reused-ratio <f64>    Number of allocations from fresh MiniPages the allocator aims for per allocation from a reused MiniPage, 0 always reuses and inf always takes fresh MiniPages (default 1)
     -c,--csv-header               Print CSV header row first
     -C,--only-csv-header          Print CSV header row and exit
 
 BEHAVIOR
 
-    Randomly allocates bytes and outputs metrics as CSV table rows.
+    Randomly allocates bytes and outputs metrics as CSV table rows. The seed used is printed in a comment before the CSV header, or to stderr if the header is not printed, so a run can be repeated. JSON reports include the seed in every object.
+
+    With --dist the number of allocations made for each size class is printed after the last CSV row, in a comment formatted like the spec. JSON reports include them in dist_counts.
 
 ", min_size_class=MIN_SIZE_CLASS, max_size_class=MAX_SIZE_CLASS);
     }
@@ -273,8 +488,23 @@ fn main() {
         }
     }
 
+    // Without a seed pick one from the thread random number generator, so every run is random but can still be repeated
+    let seed = match parsed_args.seed {
+        Some(s) => s,
+        None => thread_rng().gen(),
+    };
+
+    let format = parsed_args.format.unwrap();
+
+    match (format, &parsed_args.print_csv_header) {
+        (ReportFormat::JSON, _) => {},
+        (_, Some(PrintCSVHeader::Continue)) => println!("# seed={}", seed),
+        (_, Some(PrintCSVHeader::Exit)) => {},
+        (_, None) => eprintln!("seed={}", seed),
+    }
+
     if let Some(status) = parsed_args.print_csv_header {
-        println!("iteration,total_alloc_bytes,total_minipages,heap_bytes_write,heap_bytes_read,total_allocs,total_deallocs,fresh_allocs,reused_allocs");
+        println!("iteration,total_alloc_bytes,total_minipages,heap_bytes_write,heap_bytes_read,total_allocs,total_deallocs,fresh_allocs,reused_allocs,big_coalesces,big_splits,internal_frag_bytes,external_frag_bytes,current_used_bytes,peak_used_bytes,peak_minipages,max_big_alloc_scan,alloc_nanos,dealloc_nanos");
         
         match status {
             PrintCSVHeader::Exit => exit(0),
@@ -282,13 +512,27 @@ fn main() {
         }
     }
 
+    // The allocator is leaked so it lives as long as the static ALLOC
+    let alloc: &'static AlligatorAlloc<HeapType> = match parsed_args.fresh_reused_ratio {
+        Some(ratio) => Box::leak(Box::new(AlligatorAlloc::with_fresh_reused_ratio(ratio))),
+        None => &ALLOC,
+    };
+
     // Run benchmark
     let mut benchmark = RandomReport{
-        rng: thread_rng(),
+        alloc: alloc,
+        rng: StdRng::seed_from_u64(seed),
         free_later: vec!(),
         iteration: 0,
         total_alloc_bytes: 0,
         alloc_range: parsed_args.alloc_range.unwrap(),
+        size_dist: parsed_args.size_dist.as_ref().map(|spec| SizeDist::new(spec)),
+        zeroed: parsed_args.alloc_zeroed.is_some(),
+        clock: BenchClock::new(),
+        alloc_nanos: 0,
+        dealloc_nanos: 0,
+        seed: seed,
+        format: format,
     };
 
     for _i in 0..=parsed_args.max_iterations.unwrap() {
@@ -309,8 +553,11 @@ fn main() {
     }
 
     if let Some(_v) = parsed_args.print_dot_graph {
+        let mut dot_graph = String::new();
         unsafe {
-            println!("dot graph:\n{}", ALLOC.dot_graph());
+            benchmark.alloc.dot_graph(&mut dot_graph).unwrap();
         }
+        
+        println!("dot graph:\n{}", dot_graph);
     }
 }
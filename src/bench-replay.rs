@@ -0,0 +1,346 @@
+mod alloc;
+mod clock;
+use clock::{Clock,BenchClock};
+use alloc::{AlligatorAlloc,SizeClass,MIN_SIZE_CLASS,MAX_SIZE_CLASS};
+use alloc::heap::HeapType;
+use core::alloc::Layout;
+use std::alloc::GlobalAlloc;
+use std::collections::HashMap;
+use std::env;
+use std::fs::File;
+use std::io::{self,BufRead,BufReader};
+use std::process::exit;
+
+/*
+ * What follows is the benchmark program. It replays an
+ * allocation trace captured from a real program, so
+ * Alligator can be tuned against real allocation
+ * patterns instead of random ones. ALLOC is not the
+ * global allocator so the bookkeeping below does not use
+ * Alligator.
+ */
+static ALLOC: AlligatorAlloc<HeapType> = AlligatorAlloc::INIT;
+
+/// Allocation made while replaying a trace.
+struct LiveAlloc {
+    /// Pointer returned by alloc.
+    ptr: *mut u8,
+
+    /// Layout used to allocate ptr.
+    layout: Layout,
+}
+
+/// Replays trace operations through ALLOC.
+struct Replay {
+    /// Live allocations, keyed by their id in the trace.
+    live: HashMap<u64, LiveAlloc>,
+
+    /// The number of trace operations which have been replayed.
+    iteration: u64,
+
+    /// The total number of bytes which have been allocated.
+    total_alloc_bytes: u64,
+
+    /// If true fill every allocation with a pattern, and check it is intact when it is freed.
+    validate: bool,
+
+    /// Times alloc and dealloc calls.
+    clock: BenchClock,
+
+    /// Total nanoseconds spent in alloc calls.
+    alloc_nanos: u64,
+
+    /// Total nanoseconds spent in dealloc calls.
+    dealloc_nanos: u64,
+}
+
+impl Replay {
+    /// Prints a CSV data row based on the current allocator metrics. Same columns as bench-random-report.
+    unsafe fn print_metrics(&mut self) {
+        // Return metrics
+        let metrics = match ALLOC.metrics() {
+            Some(m) => m,
+            None => panic!("no metrics found after allocations and deallocations were performed"),
+        };
+
+        let ratio = ALLOC.fresh_reused_stats();
+
+        // Aggregate per size class metrics into totals
+        let mut total_allocs = 0;
+        let mut total_deallocs = 0;
+
+        let mut fresh_allocs = 0;
+        let mut reused_allocs = 0;
+
+        for i in MIN_SIZE_CLASS..=MAX_SIZE_CLASS {
+            let size_class = SizeClass::new(i);
+
+            total_allocs += metrics.total_allocs[size_class.exp_as_idx()];
+            total_deallocs += metrics.total_deallocs[size_class.exp_as_idx()];
+
+            fresh_allocs += ratio.total_alloc_fresh[size_class.exp_as_idx()];
+            reused_allocs += ratio.total_alloc_reused[size_class.exp_as_idx()];
+        }
+
+        // Print results in a CSV table
+        println!("{iteration},{total_alloc_bytes},{total_minipages},{heap_bytes_write},{heap_bytes_read},{total_allocs},{total_deallocs},{fresh_allocs},{reused_allocs},{big_coalesces},{big_splits},{internal_frag_bytes},{external_frag_bytes},{current_used_bytes},{peak_used_bytes},{peak_minipages},{max_big_alloc_scan},{alloc_nanos},{dealloc_nanos}",
+                 iteration=self.iteration,
+                 total_alloc_bytes=self.total_alloc_bytes,
+                 total_minipages=metrics.total_minipages,
+                 heap_bytes_write=metrics.heap_bytes_write,
+                 heap_bytes_read=metrics.heap_bytes_read,
+                 total_allocs=total_allocs,
+                 total_deallocs=total_deallocs,
+                 fresh_allocs=fresh_allocs,
+                 reused_allocs=reused_allocs,
+                 big_coalesces=metrics.big_coalesces,
+                 big_splits=metrics.big_splits,
+                 internal_frag_bytes=metrics.internal_frag_bytes,
+                 external_frag_bytes=metrics.external_frag_bytes,
+                 current_used_bytes=metrics.current_used_bytes,
+                 peak_used_bytes=metrics.peak_used_bytes,
+                 peak_minipages=metrics.peak_minipages,
+                 max_big_alloc_scan=metrics.max_big_alloc_scan,
+                 alloc_nanos=self.alloc_nanos,
+                 dealloc_nanos=self.dealloc_nanos
+        );
+    }
+
+    /// Byte the allocation with id is filled with when validating.
+    fn pattern(id: u64, i: usize) -> u8 {
+        (id as u8) ^ (i as u8)
+    }
+
+    /// Allocate size bytes for id. line is the trace line number, used in error messages.
+    unsafe fn alloc(&mut self, line: usize, id: u64, size: usize) {
+        if self.live.contains_key(&id) {
+            panic!("line {}: id {} is allocated again before it was freed", line, id);
+        }
+
+        let layout = match Layout::from_size_align(size, 1) {
+            Ok(l) => l,
+            Err(e) => panic!("line {}: error making Layout::from_size_align({}, 1): {}", line, size, e),
+        };
+
+        let alloc_start = self.clock.now_nanos();
+        let ptr = ALLOC.alloc(layout);
+        self.alloc_nanos += self.clock.now_nanos() - alloc_start;
+
+        if ptr.is_null() {
+            panic!("line {}: alloc({}) for id {} failed: {:?}", line, size, id, ALLOC.alloc_failure_cause());
+        }
+
+        if self.validate {
+            for i in 0..size {
+                *ptr.add(i) = Replay::pattern(id, i);
+            }
+        }
+
+        self.total_alloc_bytes += size as u64;
+        self.live.insert(id, LiveAlloc{
+            ptr: ptr,
+            layout: layout,
+        });
+    }
+
+    /// Free the allocation for id. line is the trace line number, used in error messages.
+    unsafe fn dealloc(&mut self, line: usize, id: u64) {
+        let freed = match self.live.remove(&id) {
+            Some(a) => a,
+            None => panic!("line {}: id {} is freed but it is not allocated", line, id),
+        };
+
+        if self.validate {
+            for i in 0..freed.layout.size() {
+                let found = *freed.ptr.add(i);
+                if found != Replay::pattern(id, i) {
+                    panic!("line {}: byte {} of id {} ({:?}, {} bytes) was overwritten: expected {}, found {}. Another allocation must overlap it", line, i, id, freed.ptr, freed.layout.size(), Replay::pattern(id, i), found);
+                }
+            }
+        }
+
+        let dealloc_start = self.clock.now_nanos();
+        ALLOC.dealloc(freed.ptr, freed.layout);
+        self.dealloc_nanos += self.clock.now_nanos() - dealloc_start;
+    }
+
+    /// Replay one line of the trace. Blank lines and lines starting with # are skipped. line_num is the 1 based line number, used in error messages.
+    unsafe fn replay_line(&mut self, line_num: usize, line: &str) {
+        let mut fields = line.split_whitespace();
+        let op = match fields.next() {
+            Some(op) => op,
+            None => return,
+        };
+
+        if op.starts_with('#') {
+            return;
+        }
+
+        let mut next_num = |name: &str| -> u64 {
+            match fields.next().map(|f| f.parse::<u64>()) {
+                Some(Ok(n)) => n,
+                Some(Err(e)) => panic!("line {}: error parsing {} in {:?}: {}", line_num, name, line, e),
+                None => panic!("line {}: missing {} in {:?}", line_num, name, line),
+            }
+        };
+
+        match op {
+            "A" => {
+                let id = next_num("id");
+                let size = next_num("size");
+                self.alloc(line_num, id, size as usize);
+            },
+            "F" => {
+                let id = next_num("id");
+                self.dealloc(line_num, id);
+            },
+            _ => panic!("line {}: unknown operation {:?}, must be A or F", line_num, op),
+        }
+
+        self.iteration += 1;
+    }
+
+    /// Free any allocations the trace never freed. Then print a final line of metrics so we can confirm everything is clean.
+    unsafe fn cleanup(&mut self) {
+        let ids: Vec<u64> = self.live.keys().cloned().collect();
+        for id in ids {
+            self.dealloc(0, id);
+        }
+
+        self.print_metrics();
+    }
+}
+
+/// Program run arguments.
+struct Args {
+    /// If true will print help text and exit.
+    print_usage: bool,
+
+    /// The interval of replayed operations to print reports.
+    report_interval: u64,
+
+    /// If the CSV header should be printed.
+    print_csv_header: bool,
+
+    /// If allocations should be filled with a pattern which is checked when they are freed.
+    validate: bool,
+
+    /// File to read the trace from. None reads from stdin.
+    trace_path: Option<String>,
+}
+
+impl Args {
+    /// Parse arguments from command line input, not including the binary name.
+    fn new(args: Vec<String>) -> Args {
+        let mut parsed = Args{
+            print_usage: false,
+            report_interval: 1000,
+            print_csv_header: false,
+            validate: false,
+            trace_path: None,
+        };
+
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            if arg == "-h" || arg == "--help" {
+                parsed.print_usage = true;
+            } else if arg == "-r" || arg == "--report-interval" {
+                parsed.report_interval = args.next().unwrap().parse().unwrap();
+            } else if arg == "-c" || arg == "--csv-header" {
+                parsed.print_csv_header = true;
+            } else if arg == "-v" || arg == "--validate" {
+                parsed.validate = true;
+            } else if arg.starts_with('-') && arg != "-" {
+                panic!("unknown argument: {}", arg);
+            } else if arg != "-" {
+                parsed.trace_path = Some(arg);
+            }
+        }
+
+        parsed
+    }
+
+    /// Print usage help text.
+    fn print_usage() {
+        println!("bench-replay.rs - Replay an allocation trace and print metrics as CSV rows
+
+USAGE
+
+    bench-replay.rs [-h] [-r,--report-interval <num>] [-c,--csv-header] [-v,--validate] [<trace file>]
+
+OPTIONS
+
+    -h                            Display help text
+    -r,--report-interval <num>    The interval of replayed operations on which to print CSV metric rows (default 1000)
+    -c,--csv-header               Print CSV header row first
+    -v,--validate                 Fill each allocation with a pattern and check it is intact when freed, to catch overlapping allocations
+    <trace file>                  File to read the trace from, reads stdin if not provided or -
+
+TRACE FORMAT
+
+    One operation per line. Blank lines and lines starting with # are ignored.
+
+    A <id> <size>                 Allocate size bytes, later operations refer to the allocation by id
+    F <id>                        Free the allocation with id
+
+BEHAVIOR
+
+    Replays the trace and outputs metrics as CSV table rows, with the same columns as bench-random-report. Allocations the trace never frees are freed at the end, before the last row.
+");
+    }
+}
+
+/// Replay an allocation trace.
+fn main() {
+    let args = Args::new(env::args().skip(1).collect());
+
+    if args.print_usage {
+        Args::print_usage();
+        exit(0);
+    }
+
+    let reader: Box<dyn BufRead> = match &args.trace_path {
+        Some(path) => match File::open(path) {
+            Ok(f) => Box::new(BufReader::new(f)),
+            Err(e) => panic!("error opening trace {}: {}", path, e),
+        },
+        None => Box::new(BufReader::new(io::stdin())),
+    };
+
+    if args.print_csv_header {
+        println!("iteration,total_alloc_bytes,total_minipages,heap_bytes_write,heap_bytes_read,total_allocs,total_deallocs,fresh_allocs,reused_allocs,big_coalesces,big_splits,internal_frag_bytes,external_frag_bytes,current_used_bytes,peak_used_bytes,peak_minipages,max_big_alloc_scan,alloc_nanos,dealloc_nanos");
+    }
+
+    let mut replay = Replay{
+        live: HashMap::new(),
+        iteration: 0,
+        total_alloc_bytes: 0,
+        validate: args.validate,
+        clock: BenchClock::new(),
+        alloc_nanos: 0,
+        dealloc_nanos: 0,
+    };
+
+    for (i, line) in reader.lines().enumerate() {
+        let line = match line {
+            Ok(l) => l,
+            Err(e) => panic!("line {}: error reading trace: {}", i + 1, e),
+        };
+
+        let iteration_before = replay.iteration;
+        unsafe {
+            replay.replay_line(i + 1, &line);
+        }
+
+        // Print metrics
+        if replay.iteration != iteration_before && replay.iteration % args.report_interval == 0 {
+            unsafe {
+                replay.print_metrics();
+            }
+        }
+    }
+
+    unsafe {
+        replay.cleanup();
+    }
+}
@@ -0,0 +1,141 @@
+mod alloc;
+use alloc::AlligatorAlloc;
+use alloc::heap::HeapType;
+use core::alloc::Layout;
+use std::alloc::GlobalAlloc;
+use std::env;
+use std::thread;
+use rand::prelude::*;
+use rand::rngs::StdRng;
+use cfg_if::cfg_if;
+
+/*
+ * What follows is the benchmark program. Several threads
+ * allocate and free from the same allocator at once,
+ * which is only safe with the sync feature. Each
+ * allocation is filled with a byte unique to its thread
+ * and allocation. If two threads are ever handed the same
+ * memory the byte is overwritten, which is caught when the
+ * allocation is checked before it is freed. ALLOC is not
+ * the global allocator so the threads' bookkeeping does
+ * not use Alligator.
+ */
+static ALLOC: AlligatorAlloc<HeapType> = AlligatorAlloc::INIT;
+
+/// Seed used if one is not provided, so runs are reproducible. Thread i uses seed + i.
+const DEFAULT_SEED: u64 = 1274;
+
+/// Number of threads spawned if not provided.
+const DEFAULT_THREADS: u64 = 4;
+
+/// Number of alloc / dealloc operations each thread performs if not provided.
+const DEFAULT_OPERATIONS: u64 = 2000;
+
+/// Maximum number of allocations each thread has live at once.
+const MAX_LIVE: usize = 64;
+
+/// Largest allocation made is 2^MAX_EXP bytes, large enough to include big allocations.
+const MAX_EXP: u32 = 16;
+
+/// Live allocation made by one thread.
+struct LiveAlloc {
+    /// Pointer returned by alloc.
+    ptr: *mut u8,
+
+    /// Layout used to allocate ptr.
+    layout: Layout,
+
+    /// Byte every byte of the allocation was filled with.
+    fill: u8,
+}
+
+impl LiveAlloc {
+    /// Panics if any byte of the allocation is not fill anymore.
+    unsafe fn check(&self, thread_i: u64, op: u64) {
+        for i in 0..self.layout.size() {
+            assert_eq!(*self.ptr.add(i), self.fill, "thread {} op {}: byte {} of allocation {:?} ({} bytes) was overwritten, another thread must have been given the same memory", thread_i, op, i, self.ptr, self.layout.size());
+        }
+    }
+}
+
+/// Randomly allocate and free from ALLOC, filling and checking each allocation. Frees everything before returning.
+unsafe fn hammer(thread_i: u64, seed: u64, operations: u64) {
+    let mut rng = StdRng::seed_from_u64(seed);
+    let mut live: Vec<LiveAlloc> = Vec::with_capacity(MAX_LIVE);
+
+    for op in 0..operations {
+        let should_alloc = live.is_empty() || (live.len() < MAX_LIVE && rng.gen_range(0..10) < 6);
+
+        if should_alloc {
+            // Pick a power of two, then a size up to it, so small sizes are as common as big ones
+            let max_bytes = 2_usize.pow(rng.gen_range(0..=MAX_EXP)).min(usize::from(u16::MAX));
+            let layout = Layout::from_size_align(rng.gen_range(1..=max_bytes), 1).unwrap();
+            let ptr = ALLOC.alloc(layout);
+            assert!(!ptr.is_null(), "thread {} op {}: alloc({}) failed (returned null)", thread_i, op, layout.size());
+
+            let fill = (thread_i as u8).wrapping_mul(61).wrapping_add(op as u8);
+            ptr.write_bytes(fill, layout.size());
+            live.push(LiveAlloc{
+                ptr: ptr,
+                layout: layout,
+                fill: fill,
+            });
+        } else {
+            let free_i = rng.gen_range(0..live.len());
+            let freed = live.swap_remove(free_i);
+            freed.check(thread_i, op);
+            ALLOC.dealloc(freed.ptr, freed.layout);
+        }
+    }
+
+    for freed in live.iter() {
+        freed.check(thread_i, operations);
+        ALLOC.dealloc(freed.ptr, freed.layout);
+    }
+}
+
+/// Check concurrent allocations from several threads are never given the same memory. Usage: bench-threads [-s,--seed <u64>] [-t,--threads <num>] [-i,--operations <num>]
+fn main() {
+    let mut seed = DEFAULT_SEED;
+    let mut threads = DEFAULT_THREADS;
+    let mut operations = DEFAULT_OPERATIONS;
+
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == "-s" || arg == "--seed" {
+            seed = args.next().unwrap().parse().unwrap();
+        } else if arg == "-t" || arg == "--threads" {
+            threads = args.next().unwrap().parse().unwrap();
+        } else if arg == "-i" || arg == "--operations" {
+            operations = args.next().unwrap().parse().unwrap();
+        } else {
+            panic!("unknown argument: {}", arg);
+        }
+    }
+
+    let handles: Vec<thread::JoinHandle<()>> = (0..threads).map(|thread_i| {
+        thread::spawn(move || unsafe {
+            hammer(thread_i, seed + thread_i, operations);
+        })
+    }).collect();
+
+    for handle in handles {
+        handle.join().unwrap();
+    }
+
+    // Every allocation was freed exactly once, so no failures should have been recorded
+    cfg_if! {
+        if #[cfg(feature = "metrics")] {
+            unsafe {
+                let metrics = ALLOC.metrics().unwrap();
+                let allocs: u32 = metrics.total_allocs.iter().sum();
+                let deallocs: u32 = metrics.total_deallocs.iter().sum();
+
+                assert_eq!(allocs, deallocs, "allocations and de-allocations recorded by metrics do not match");
+                assert!(ALLOC.failure_counts().iter().all(|count| *count == 0), "failures were recorded: {:?}", ALLOC.failure_counts());
+            }
+        }
+    }
+
+    println!("seed={} threads={} operations={}: no memory given to two threads at once", seed, threads, operations);
+}
@@ -17,7 +17,7 @@ fn main() {
         for i in 0..1024 {
             v[x].push(true);
             unsafe {
-                greet(&format!("Alligator wasmtime, x={}, i={}, fresh / reused stats={:?}", x, i, ALLOC.fresh_reused_stats()));
+                greet(&format!("Alligator wasmtime, x={}, i={}, fresh / reused stats={:?}, occupancy stats={:?}", x, i, ALLOC.fresh_reused_stats(), ALLOC.occupancy_stats()));
             }
         }
     }
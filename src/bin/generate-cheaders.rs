@@ -3,11 +3,22 @@ use std::env;
 use cbindgen;
 
 
+/// Writes the C header for the functions exported by clib.rs to liballigatorc.h in the current directory. Doc comments on the functions are copied into the header.
 fn main() {
     let crate_dir = env::var("CARGO_MANIFEST_DIR").unwrap();
 
+    // cbindgen 0.18's Builder has no setter for cpp_compat, which wraps the declarations in extern "C" so the C++ fuzzer and c-test can include the header
+    let config = cbindgen::Config{
+        language: cbindgen::Language::C,
+        cpp_compat: true,
+        include_guard: Some(String::from("LIBALLIGATORC_H")),
+        documentation: true,
+        ..Default::default()
+    };
+
     cbindgen::Builder::new()
         .with_crate(crate_dir)
+        .with_config(config)
         .generate()
         .expect("Unable to generate bindings")
         .write_to_file("liballigatorc.h");
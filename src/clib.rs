@@ -7,9 +7,12 @@ use core::alloc::Layout;
 use std::alloc::GlobalAlloc;
 use libc::size_t;
 use std::ffi::c_void;
+use std::ptr::null_mut;
+use cfg_if::cfg_if;
 
 static ALLOC: AlligatorAlloc<HeapType> = AlligatorAlloc::INIT;
 
+/// Allocates size bytes. Returns NULL if the allocation failed. Like malloc() except alligator_alloc(0) always returns NULL.
 #[no_mangle]
 pub unsafe extern "C" fn alligator_alloc(size: size_t) -> *mut c_void {
     let layout = match Layout::from_size_align(size, 1) {
@@ -19,17 +22,76 @@ pub unsafe extern "C" fn alligator_alloc(size: size_t) -> *mut c_void {
     ALLOC.alloc(layout) as *mut c_void
 }
 
+/// Allocates an array of nmemb elements of size bytes each, with every byte set to 0. Returns NULL if the allocation failed or nmemb * size overflows. Like calloc() except a total of 0 bytes always returns NULL.
+#[no_mangle]
+pub unsafe extern "C" fn alligator_calloc(nmemb: size_t, size: size_t) -> *mut c_void {
+    let total = match nmemb.checked_mul(size) {
+        Some(t) => t,
+        None => return null_mut(),
+    };
+    let layout = match Layout::from_size_align(total, 1) {
+        Ok(l) => l,
+        Err(_) => return null_mut(),
+    };
+    ALLOC.alloc_zeroed(layout) as *mut c_void
+}
+
+/// Allocates size bytes aligned to alignment. Returns NULL if the allocation failed, alignment is not a power of two, or size is not a multiple of alignment. Like aligned_alloc(). Alignments up to 32768 bytes are supported for allocations of at most 32768 bytes, larger allocations can be aligned to at most 2048 bytes. Free with alligator_dealloc().
+#[no_mangle]
+pub unsafe extern "C" fn alligator_aligned_alloc(alignment: size_t, size: size_t) -> *mut c_void {
+    if alignment == 0 || size % alignment != 0 {
+        return null_mut();
+    }
+    let layout = match Layout::from_size_align(size, alignment) {
+        Ok(l) => l,
+        Err(_) => return null_mut(),
+    };
+    ALLOC.alloc(layout) as *mut c_void
+}
+
+/// Resizes the allocation ptr to new_size bytes, keeping its contents up to the smaller of the two sizes. Returns the possibly moved allocation, or NULL if it failed, in which case ptr is left untouched. Like realloc(): alligator_realloc(NULL, n) is alligator_alloc(n), and alligator_realloc(ptr, 0) frees ptr and returns NULL.
 #[no_mangle]
 pub unsafe extern "C" fn alligator_realloc(ptr: *mut c_void, new_size: size_t) -> *mut c_void {
-    let layout = match Layout::from_size_align(0, 1) {
+    // Same as C's realloc(): null ptr allocates, 0 new_size frees
+    if ptr.is_null() {
+        return alligator_alloc(new_size);
+    }
+    if new_size == 0 {
+        alligator_dealloc(ptr);
+        return null_mut();
+    }
+
+    // The allocator knows the size of every live allocation, so the old Layout does not have to be tracked here. All old_size bytes belong to the allocation, copying them all is safe.
+    let old_size = ALLOC.usable_size(ptr as *mut u8);
+
+    // If new_size would be allocated exactly like the current block (ie., same size class) don't move
+    if let Some(plan) = AlligatorAlloc::explain(new_size) {
+        if plan.usable_bytes == old_size {
+            return ptr;
+        }
+    }
+    
+    let layout = match Layout::from_size_align(old_size, 1) {
         Ok(l) => l,
         Err(e) => panic!("error making Layout for realloc({}, {}): {}", ptr as u32, new_size, e),
     };
     ALLOC.realloc(ptr as *mut u8, layout, new_size) as *mut c_void
 }
 
+/// Returns the number of bytes which can be used in the allocation ptr, at least the size it was allocated with. Returns 0 if ptr is not a live allocation.
+#[no_mangle]
+pub unsafe extern "C" fn alligator_usable_size(ptr: *mut c_void) -> size_t {
+    ALLOC.usable_size(ptr as *mut u8)
+}
+
+/// Frees the allocation ptr, which must have been returned by alligator_alloc() or alligator_realloc(). Like free(): alligator_dealloc(NULL) does nothing.
 #[no_mangle]
 pub unsafe extern "C" fn alligator_dealloc(ptr: *mut c_void) {
+    // Same as C's free(): null ptr is a no-op, the allocator would look for a MiniPage at address 0
+    if ptr.is_null() {
+        return;
+    }
+
     let layout = match Layout::from_size_align(0, 1) {
         Ok(l) => l,
         Err(e) => panic!("error making Layout for alloc({}): {}", ptr as u32, e),
@@ -37,3 +99,48 @@ pub unsafe extern "C" fn alligator_dealloc(ptr: *mut c_void) {
     ALLOC.dealloc(ptr as *mut u8, layout)
 }
 
+
+/// Returns the offset in the WebAssembly linear memory of the allocator's AllocDebugHeader, 0 if nothing has been allocated yet. See the debug-layout feature.
+#[cfg(feature = "debug-layout")]
+#[no_mangle]
+pub unsafe extern "C" fn alligator_debug_header_offset() -> size_t {
+    ALLOC.debug_header_ptr() as size_t
+}
+
+cfg_if! {
+    if #[cfg(all(target_arch = "wasm32", feature = "js"))] {
+        use wasm_bindgen::prelude::*;
+
+        /// Allocates size bytes for JavaScript. Returns the offset of the allocation in the WebAssembly linear memory, 0 if the allocation failed. Read and write it with `new Uint8Array(memory.buffer, ptr, size)`.
+        ///
+        /// Allocating may grow the WebAssembly memory, which detaches memory.buffer. Any view JavaScript made over the old buffer, including views of other allocations, is then empty and must be re-created. Create views after the last call which may allocate, or re-create them in the callback registered with on_memory_grow_js().
+        #[wasm_bindgen]
+        pub fn alligator_js_alloc(size: usize) -> u32 {
+            let layout = match Layout::from_size_align(size, 1) {
+                Ok(l) => l,
+                Err(_) => return 0,
+            };
+
+            unsafe {
+                ALLOC.alloc(layout) as u32
+            }
+        }
+
+        /// Frees an allocation made by alligator_js_alloc(). ptr is the returned offset and size the size which was requested. Does nothing if ptr is 0, so the result of a failed allocation can be passed. Views JavaScript holds over the allocation must not be used afterwards.
+        #[wasm_bindgen]
+        pub fn alligator_js_free(ptr: u32, size: usize) {
+            if ptr == 0 {
+                return;
+            }
+
+            let layout = match Layout::from_size_align(size, 1) {
+                Ok(l) => l,
+                Err(e) => panic!("error making Layout for alligator_js_free({}, {}): {}", ptr, size, e),
+            };
+
+            unsafe {
+                ALLOC.dealloc(ptr as *mut u8, layout);
+            }
+        }
+    }
+}
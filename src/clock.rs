@@ -0,0 +1,63 @@
+use cfg_if::cfg_if;
+
+/// Source of timestamps for benchmarks.
+pub trait Clock {
+    /// Returns the current time in nanoseconds. Only the difference between two calls is meaningful.
+    fn now_nanos(&self) -> u64;
+}
+
+cfg_if! {
+    if #[cfg(all(target_arch = "wasm32", feature = "js"))] {
+        use wasm_bindgen::prelude::*;
+
+        #[wasm_bindgen]
+        extern "C" {
+            /// The JavaScript host's performance.now(), in milliseconds.
+            #[wasm_bindgen(js_namespace = performance, js_name = now)]
+            fn performance_now() -> f64;
+        }
+
+        /// Clock which calls the JavaScript host's performance.now(). Works on wasm32-unknown-unknown, where std::time::Instant panics.
+        pub struct JsClock;
+
+        impl JsClock {
+            pub fn new() -> JsClock {
+                JsClock
+            }
+        }
+
+        impl Clock for JsClock {
+            fn now_nanos(&self) -> u64 {
+                (performance_now() * 1_000_000.0) as u64
+            }
+        }
+
+        /// Clock the benchmarks use on this target.
+        pub type BenchClock = JsClock;
+    } else {
+        use std::time::Instant;
+
+        /// Clock which uses std::time::Instant.
+        pub struct StdClock {
+            /// When the clock was created, now_nanos() is relative to this.
+            start: Instant,
+        }
+
+        impl StdClock {
+            pub fn new() -> StdClock {
+                StdClock{
+                    start: Instant::now(),
+                }
+            }
+        }
+
+        impl Clock for StdClock {
+            fn now_nanos(&self) -> u64 {
+                self.start.elapsed().as_nanos() as u64
+            }
+        }
+
+        /// Clock the benchmarks use on this target.
+        pub type BenchClock = StdClock;
+    }
+}
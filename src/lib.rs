@@ -1,2 +1,5 @@
+//! The core allocator without the C library or benchmarks around it. Only uses core, so it can be built for #![no_std] targets, see no-std/Cargo.toml.
+#![no_std]
+
 mod alloc;
 pub use crate::alloc::*;
@@ -0,0 +1,122 @@
+#include <stdint.h>
+#include <stdio.h>
+#include <string.h>
+#include "liballigatorc.h"
+
+#define OLD_SIZE 100
+#define NEW_SIZE 3000
+#define CALLOC_NMEMB 100
+
+int main() {
+  // Zero byte allocations always fail
+  if (alligator_alloc(0) != NULL) {
+    printf("alligator_alloc(0) should return NULL\n");
+    return 1;
+  }
+
+  // A NULL ptr makes realloc allocate
+  unsigned char *ptr = (unsigned char *) alligator_realloc(NULL, OLD_SIZE);
+  if (ptr == NULL) {
+    printf("alligator_realloc(NULL, %d) failed\n", OLD_SIZE);
+    return 1;
+  }
+  if (alligator_usable_size(ptr) < OLD_SIZE) {
+    printf("alligator_usable_size(%p) is %zu, less than the %d bytes allocated\n", ptr, alligator_usable_size(ptr), OLD_SIZE);
+    return 1;
+  }
+  memset(ptr, 0xA5, OLD_SIZE);
+
+  // Growing keeps the contents
+  unsigned char *new_ptr = (unsigned char *) alligator_realloc(ptr, NEW_SIZE);
+  if (new_ptr == NULL) {
+    printf("alligator_realloc(%p, %d) failed\n", ptr, NEW_SIZE);
+    return 1;
+  }
+  for (int i = 0; i < OLD_SIZE; i++) {
+    if (new_ptr[i] != 0xA5) {
+      printf("alligator_realloc(%p, %d) lost byte %d\n", ptr, NEW_SIZE, i);
+      return 1;
+    }
+  }
+
+  // A 0 size makes realloc free
+  if (alligator_realloc(new_ptr, 0) != NULL) {
+    printf("alligator_realloc(%p, 0) should free and return NULL\n", new_ptr);
+    return 1;
+  }
+
+  // A NULL ptr makes dealloc do nothing, the allocator must still work afterwards
+  alligator_dealloc(NULL);
+
+  unsigned char *other_ptr = (unsigned char *) alligator_alloc(OLD_SIZE);
+  if (other_ptr == NULL) {
+    printf("alligator_alloc(%d) after alligator_dealloc(NULL) failed\n", OLD_SIZE);
+    return 1;
+  }
+  if (alligator_usable_size(other_ptr) < OLD_SIZE) {
+    printf("alligator_usable_size(%p) is %zu after alligator_dealloc(NULL), less than the %d bytes allocated\n", other_ptr, alligator_usable_size(other_ptr), OLD_SIZE);
+    return 1;
+  }
+  alligator_dealloc(other_ptr);
+
+  // calloc zeroes memory, even when it reuses a dirty allocation
+  unsigned char *dirty_ptr = (unsigned char *) alligator_alloc(CALLOC_NMEMB * sizeof(uint32_t));
+  if (dirty_ptr == NULL) {
+    printf("alligator_alloc(%zu) failed\n", CALLOC_NMEMB * sizeof(uint32_t));
+    return 1;
+  }
+  memset(dirty_ptr, 0xFF, CALLOC_NMEMB * sizeof(uint32_t));
+  alligator_dealloc(dirty_ptr);
+
+  uint32_t *array = (uint32_t *) alligator_calloc(CALLOC_NMEMB, sizeof(uint32_t));
+  if (array == NULL) {
+    printf("alligator_calloc(%d, %zu) failed\n", CALLOC_NMEMB, sizeof(uint32_t));
+    return 1;
+  }
+  unsigned char *array_bytes = (unsigned char *) array;
+  for (size_t i = 0; i < CALLOC_NMEMB * sizeof(uint32_t); i++) {
+    if (array_bytes[i] != 0) {
+      printf("alligator_calloc(%d, %zu) byte %zu is %d, not 0\n", CALLOC_NMEMB, sizeof(uint32_t), i, array_bytes[i]);
+      return 1;
+    }
+  }
+  alligator_dealloc(array);
+
+  // calloc fails instead of wrapping when nmemb * size overflows
+  if (alligator_calloc(SIZE_MAX, 2) != NULL) {
+    printf("alligator_calloc(SIZE_MAX, 2) should overflow and return NULL\n");
+    return 1;
+  }
+  if (alligator_calloc(SIZE_MAX / 2 + 2, 2) != NULL) {
+    printf("alligator_calloc(SIZE_MAX / 2 + 2, 2) wraps to 2 bytes, it should return NULL\n");
+    return 1;
+  }
+
+  // aligned_alloc honors power of two alignments
+  size_t alignments[] = { 16, 64, 4096 };
+  for (size_t i = 0; i < sizeof(alignments) / sizeof(alignments[0]); i++) {
+    void *aligned_ptr = alligator_aligned_alloc(alignments[i], alignments[i]);
+    if (aligned_ptr == NULL) {
+      printf("alligator_aligned_alloc(%zu, %zu) failed\n", alignments[i], alignments[i]);
+      return 1;
+    }
+    if ((uintptr_t) aligned_ptr % alignments[i] != 0) {
+      printf("alligator_aligned_alloc(%zu, %zu) returned %p, which is not aligned\n", alignments[i], alignments[i], aligned_ptr);
+      return 1;
+    }
+    alligator_dealloc(aligned_ptr);
+  }
+
+  // aligned_alloc rejects alignments which are not a power of two, and sizes which are not a multiple of the alignment
+  if (alligator_aligned_alloc(24, 48) != NULL) {
+    printf("alligator_aligned_alloc(24, 48) should return NULL, 24 is not a power of two\n");
+    return 1;
+  }
+  if (alligator_aligned_alloc(64, 100) != NULL) {
+    printf("alligator_aligned_alloc(64, 100) should return NULL, 100 is not a multiple of 64\n");
+    return 1;
+  }
+
+  printf("round trip through liballigatorc.h passed\n");
+  return 0;
+}
@@ -0,0 +1,50 @@
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::process::Command;
+
+/// Generates liballigatorc.h with generate-cheaders, compiles tests/c-roundtrip.c against it with cc, links it to liballigatorc, and runs it. Checks the header's prototypes match the functions clib.rs exports.
+#[test]
+fn c_roundtrip() {
+    let manifest_dir = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+
+    // Test binaries are in target/<profile>/deps
+    let out_dir = env::current_exe().unwrap().parent().unwrap().parent().unwrap().join("c-header-test");
+    fs::create_dir_all(&out_dir).unwrap();
+
+    // Integration tests do not build the cdylib, so build it in its own target directory, which does not wait on the lock cargo test holds. Release because debug builds trip the misaligned pointer checks on the unaligned UnsafeStacks.
+    let target_dir = out_dir.join("target");
+    let status = Command::new(env!("CARGO"))
+        .arg("build")
+        .arg("--lib")
+        .arg("--release")
+        .arg("--manifest-path").arg(manifest_dir.join("Cargo.toml"))
+        .arg("--target-dir").arg(&target_dir)
+        .status()
+        .unwrap();
+    assert!(status.success(), "building liballigatorc failed: {}", status);
+    let lib_dir = target_dir.join("release");
+
+    let status = Command::new(env!("CARGO_BIN_EXE_generate-cheaders"))
+        .current_dir(&out_dir)
+        .status()
+        .unwrap();
+    assert!(status.success(), "generate-cheaders failed: {}", status);
+
+    let program = out_dir.join("c-roundtrip");
+    let status = Command::new(env::var("CC").unwrap_or("cc".to_string()))
+        .arg(manifest_dir.join("tests").join("c-roundtrip.c"))
+        .arg("-I").arg(&out_dir)
+        .arg("-L").arg(&lib_dir)
+        .arg("-lalligatorc")
+        .arg("-o").arg(&program)
+        .status()
+        .unwrap();
+    assert!(status.success(), "compiling c-roundtrip.c against liballigatorc.h failed: {}", status);
+
+    let output = Command::new(&program)
+        .env("LD_LIBRARY_PATH", &lib_dir)
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "c-roundtrip failed: {}{}", String::from_utf8_lossy(&output.stdout), String::from_utf8_lossy(&output.stderr));
+}